@@ -1,10 +1,50 @@
 // Frame receiver worker
+import jpeg from "npm:jpeg-js@0.4.4";
+import { decompress } from "npm:fzstd@0.1.1";
+
 let conn: Deno.Conn | null = null;
 let listener: Deno.Listener | null = null;
 let isConnected = false;
+let streamVersion = 1;
+let streamCodec: typeof CODECS[number] = "raw";
+let streamPixelFormat: typeof PIXEL_FORMATS[number] = "rgba";
+let streamStride = 0;
+let streamChecksum = false;
 
 const worker = self as unknown as Worker;
 
+// Must match the sender's protocol.rs
+const MAGIC = "SCRN";
+const MIN_PROTOCOL_VERSION = 1;
+// Versions 18 and 19 only changed UDP, and 20 added variants, which only a
+// listening sender offers; this worker listens, so it never sees either
+const MAX_PROTOCOL_VERSION = 20;
+// Version 6 appended the row stride to the original 20-byte handshake
+const HANDSHAKE_SIZE_V1 = 20;
+const HANDSHAKE_SIZE = 24;
+const PIXEL_FORMATS = ["rgba", "bgra", "gray", "rgb"] as const;
+const BYTES_PER_PIXEL = { rgba: 4, bgra: 4, gray: 1, rgb: 3 } as const;
+const CODECS = ["raw", "jpeg", "zstd", "delta", "h264", "rle"] as const;
+const DELTA_HEADER_SIZE = 8;
+const NEGOTIATE = 1;
+// A choice or preference naming no pixel format (version >= 14)
+const NO_PIXEL_FORMAT = 0xff;
+const ENCRYPTED = 4;
+const CHECKSUM = 8;
+
+interface Handshake {
+  version: number;
+  pixelFormat: typeof PIXEL_FORMATS[number];
+  codec: typeof CODECS[number];
+  width: number;
+  height: number;
+  fps: number;
+  /** Bytes per row of delivered frames */
+  stride: number;
+  /** Payloads carry a CRC-32 (version >= 13) */
+  checksum: boolean;
+}
+
 async function readExactly(size: number): Promise<Uint8Array | null> {
   if (!conn) return null;
   const buffer = new Uint8Array(size);
@@ -25,15 +65,211 @@ async function readExactly(size: number): Promise<Uint8Array | null> {
   }
 }
 
-async function receiveFrame(): Promise<{ data: Uint8Array; width: number; height: number } | null> {
-  // Read metadata (width, height, size, chunks)
-  const metadata = await readExactly(16); // 4 x 32-bit values
+// Answer a negotiating handshake: take BGRA only when the consumer asked for it,
+// otherwise the sender falls back to RGBA
+async function receiveHandshake(acceptBgra: boolean): Promise<Handshake> {
+  let bytes = await readExactly(HANDSHAKE_SIZE_V1);
+  if (!bytes) throw new Error("Connection closed before handshake");
+  if (bytes[4] >= 6) {
+    const rest = await readExactly(HANDSHAKE_SIZE - HANDSHAKE_SIZE_V1);
+    if (!rest) throw new Error("Connection closed during handshake");
+    const full = new Uint8Array(HANDSHAKE_SIZE);
+    full.set(bytes);
+    full.set(rest, HANDSHAKE_SIZE_V1);
+    bytes = full;
+  }
+
+  const view = new DataView(bytes.buffer);
+  const magic = new TextDecoder().decode(bytes.subarray(0, 4));
+  const version = view.getUint8(4);
+  if (magic !== MAGIC) throw new Error(`Bad handshake magic: ${magic}`);
+  if (version < MIN_PROTOCOL_VERSION || version > MAX_PROTOCOL_VERSION) {
+    throw new Error(`Unsupported protocol version: ${version}`);
+  }
+
+  const pixelFormat = PIXEL_FORMATS[view.getUint8(5)];
+  if (!pixelFormat) throw new Error(`Unknown pixel format: ${view.getUint8(5)}`);
+
+  // Byte 6 was reserved (always 0 = raw) before version 3
+  const codec = CODECS[view.getUint8(6)];
+  if (!codec) throw new Error(`Unknown codec: ${view.getUint8(6)}`);
+  // Opening --psk payloads needs ChaCha20-Poly1305, which this worker doesn't have
+  if (view.getUint8(7) & ENCRYPTED) throw new Error("encrypted streams are not supported by this receiver");
+
+  let agreedFormat: typeof PIXEL_FORMATS[number] = pixelFormat;
+  const negotiate = (view.getUint8(7) & NEGOTIATE) !== 0;
+  if (negotiate && version >= 14) {
+    // Answer the sender's offer with everything but H.264, and BGRA only when the
+    // consumer asked for it (otherwise preferring RGBA)
+    const offer = await readExactly(2);
+    if (!offer) throw new Error("Connection closed during negotiation");
+    const codecs = offer[0] & ~(1 << CODECS.indexOf("h264"));
+    const formats = offer[1] & (acceptBgra ? 0xff : ~(1 << PIXEL_FORMATS.indexOf("bgra")));
+    const preferred = acceptBgra ? NO_PIXEL_FORMAT : PIXEL_FORMATS.indexOf("rgba");
+    await conn!.write(new Uint8Array([codecs, formats, preferred]));
+    const choice = await readExactly(1);
+    if (!choice) throw new Error("Connection closed during negotiation");
+    if (choice[0] === NO_PIXEL_FORMAT) {
+      throw new Error(codec === "h264"
+        ? "h264 streams are not supported by this receiver"
+        : "the sender offered no pixel format this receiver takes");
+    }
+    agreedFormat = PIXEL_FORMATS[choice[0]];
+  } else if (negotiate) {
+    const accept = pixelFormat === "rgba" || (pixelFormat === "bgra" && acceptBgra);
+    await conn!.write(new Uint8Array([accept ? 1 : 0]));
+    agreedFormat = accept ? pixelFormat : "rgba";
+  }
+  // Decoding H.264 needs a video decoder this worker doesn't have
+  if (codec === "h264") throw new Error("h264 streams are not supported by this receiver");
+
+  return {
+    version,
+    pixelFormat: agreedFormat,
+    codec,
+    width: view.getUint32(8, true),
+    height: view.getUint32(12, true),
+    fps: view.getUint32(16, true),
+    stride: version >= 6 ? view.getUint32(20, true) : view.getUint32(8, true) * 4,
+    checksum: version >= 13 && (view.getUint8(7) & CHECKSUM) !== 0,
+  };
+}
+
+interface ReceivedFrame {
+  data: Uint8Array;
+  width: number;
+  height: number;
+  /** Sender sequence number (version >= 2) */
+  seq?: bigint;
+  /** Capture time in ms since the sender started streaming (version >= 2) */
+  timestampMs?: bigint;
+  /** Set when the sender repeated the previous frame (--constant-fps) */
+  repeat?: boolean;
+}
+
+// Returned by receiveFrame when the sender signals a clean end of stream
+const END_OF_STREAM = "end";
+// Returned by receiveFrame for a delta that can't be applied yet
+const SKIPPED = "skip";
+
+// Delta codec state: the last reconstructed frame and its sequence number
+let deltaBase: Uint8Array | null = null;
+let deltaSeq: bigint | undefined;
+// The last frame returned, handed out again for repeats
+let lastFrame: ReceivedFrame | null = null;
+
+// Apply a delta payload (layout in delta.rs) to the previous frame. Returns null
+// when there is no usable base, i.e. until the next keyframe after a gap.
+function applyDelta(payload: Uint8Array, width: number, seq: bigint | undefined): Uint8Array | null {
+  const view = new DataView(payload.buffer, payload.byteOffset);
+  const body = payload.subarray(DELTA_HEADER_SIZE);
+
+  if (view.getUint8(0) === 0) {
+    deltaBase = body.slice();
+  } else {
+    if (!deltaBase || seq === undefined || deltaSeq === undefined || seq !== deltaSeq + 1n) {
+      deltaBase = null;
+      return null;
+    }
+    const tileSize = view.getUint16(2, true);
+    const tilesX = view.getUint16(4, true);
+    const tilesY = view.getUint16(6, true);
+    const bpp = BYTES_PER_PIXEL[streamPixelFormat];
+    const height = deltaBase.length / (width * bpp);
+    const stride = width * bpp;
+    const bitmapLength = Math.ceil((tilesX * tilesY) / 8);
+    let offset = bitmapLength;
+
+    for (let tile = 0; tile < tilesX * tilesY; tile++) {
+      if (!(body[tile >> 3] & (1 << (tile & 7)))) continue;
+      const x0 = (tile % tilesX) * tileSize * bpp;
+      const x1 = Math.min(x0 + tileSize * bpp, stride);
+      const y0 = Math.floor(tile / tilesX) * tileSize;
+      const y1 = Math.min(y0 + tileSize, height);
+      for (let y = y0; y < y1; y++) {
+        const rowLength = x1 - x0;
+        deltaBase.set(body.subarray(offset, offset + rowLength), y * stride + x0);
+        offset += rowLength;
+      }
+    }
+  }
+
+  deltaSeq = seq;
+  return deltaBase.slice();
+}
+
+// CRC-32 (IEEE), as the sender's crc32fast computes it
+const CRC_TABLE = Array.from({ length: 256 }, (_, n) => {
+  let c = n;
+  for (let k = 0; k < 8; k++) c = c & 1 ? 0xedb88320 ^ (c >>> 1) : c >>> 1;
+  return c >>> 0;
+});
+
+function crc32(data: Uint8Array): number {
+  let crc = 0xffffffff;
+  for (const byte of data) crc = CRC_TABLE[(crc ^ byte) & 0xff] ^ (crc >>> 8);
+  return (crc ^ 0xffffffff) >>> 0;
+}
+
+// Expand an RLE payload (layout in rle.rs) of 4-byte pixels into rawSize bytes
+function decodeRle(payload: Uint8Array, rawSize: number): Uint8Array {
+  const out = new Uint8Array(rawSize);
+  let offset = 0;
+  let i = 0;
+  while (i < payload.length) {
+    const header = payload[i++];
+    const pixels = (header & 0x7f) + 1;
+    if (offset + pixels * 4 > rawSize) throw new Error("RLE payload decodes past its raw size");
+    if (header & 0x80) {
+      const pixel = payload.subarray(i, i + 4);
+      for (let p = 0; p < pixels; p++) out.set(pixel, offset + p * 4);
+      i += 4;
+    } else {
+      out.set(payload.subarray(i, i + pixels * 4), offset);
+      i += pixels * 4;
+    }
+    offset += pixels * 4;
+  }
+  if (offset !== rawSize) throw new Error(`RLE payload decoded to ${offset} bytes, expected ${rawSize}`);
+  return out;
+}
+
+async function receiveFrame(): Promise<ReceivedFrame | typeof END_OF_STREAM | typeof SKIPPED | null> {
+  // Read metadata (width, height, size, chunks[, seq, timestamp][, raw size])
+  const metadataSize = streamVersion >= 4 ? 36 : streamVersion >= 2 ? 32 : 16;
+  const metadata = await readExactly(metadataSize);
   if (!metadata) return null;
 
-  const width = new DataView(metadata.buffer).getUint32(0, true);
-  const height = new DataView(metadata.buffer).getUint32(4, true);
-  const totalSize = new DataView(metadata.buffer).getUint32(8, true);
-  const numChunks = new DataView(metadata.buffer).getUint32(12, true);
+  const view = new DataView(metadata.buffer);
+  const width = view.getUint32(0, true);
+  const height = view.getUint32(4, true);
+  const totalSize = view.getUint32(8, true);
+  const numChunks = view.getUint32(12, true);
+  const seq = streamVersion >= 2 ? view.getBigUint64(16, true) : undefined;
+  const timestampMs = streamVersion >= 2 ? view.getBigUint64(24, true) : undefined;
+  const rawSize = streamVersion >= 4 ? view.getUint32(32, true) : width * height * 4;
+
+  // An empty frame is the sender's end-of-stream marker, or from version 15 a
+  // repeat of the last frame when it has a size, or from version 16 a size change
+  // when it also has a raw size
+  if (totalSize === 0 && numChunks === 0) {
+    if (width === 0 || streamVersion < 15) return END_OF_STREAM;
+    if (streamVersion >= 16 && rawSize !== 0) {
+      streamStride = rawSize / height;
+      deltaBase = null;
+      lastFrame = null;
+      worker.postMessage({ type: 'resized', width, height, stride: streamStride });
+      return SKIPPED;
+    }
+    return lastFrame ? { ...lastFrame, timestampMs, repeat: true } : SKIPPED;
+  }
+
+  let checksum: number | undefined;
+  if (streamChecksum) {
+    const bytes = await readExactly(4);
+    if (!bytes) return null;
+    checksum = new DataView(bytes.buffer).getUint32(0, true);
+  }
 
   // Allocate frame buffer
   const frameData = new Uint8Array(totalSize);
@@ -54,20 +290,63 @@ async function receiveFrame(): Promise<{ data: Uint8Array; width: number; height
     offset += chunk.length;
   }
 
-  return { data: frameData, width, height };
+  // A damaged payload is dropped; deltas then wait for the next keyframe
+  if (checksum !== undefined && crc32(frameData) !== checksum) {
+    console.warn(`Frame ${seq} failed its checksum, discarding it`);
+    deltaBase = null;
+    return SKIPPED;
+  }
+
+  // A frame with no dimensions is an audio packet (version >= 8), which this
+  // worker doesn't play, or a cursor packet (version >= 17), which this worker
+  // doesn't draw
+  if (width === 0 && height === 0) return SKIPPED;
+
+  // Compressed payloads are decoded here so consumers always get plain pixels
+  let data = frameData;
+  if (streamCodec === "jpeg") {
+    data = jpeg.decode(frameData, { useTArray: true, formatAsRGBA: true }).data;
+    // Grayscale streams keep one byte per pixel, where every channel holds the
+    // luma, and RGB streams drop the alpha
+    if (streamPixelFormat === "gray") data = data.filter((_, i) => i % 4 === 0);
+    if (streamPixelFormat === "rgb") data = data.filter((_, i) => i % 4 !== 3);
+  } else if (streamCodec === "zstd") {
+    data = decompress(frameData, new Uint8Array(rawSize));
+  } else if (streamCodec === "rle") {
+    data = decodeRle(frameData, rawSize);
+  } else if (streamCodec === "delta") {
+    const frame = applyDelta(frameData, width, seq);
+    if (!frame) return SKIPPED;
+    data = frame;
+  }
+
+  lastFrame = { data, width, height, seq, timestampMs };
+  return lastFrame;
 }
 
 async function startReceiving() {
   while (isConnected) {
     const frameStart = performance.now();
     const frame = await receiveFrame();
-    if (frame) {
+    if (frame === END_OF_STREAM) {
+      isConnected = false;
+      conn?.close();
+      conn = null;
+      worker.postMessage({ type: 'ended' });
+      break;
+    }
+    if (frame && frame !== SKIPPED) {
       const receiveTime = performance.now() - frameStart;
       worker.postMessage({ 
         type: 'frame', 
         data: frame.data,
         width: frame.width,
         height: frame.height,
+        seq: frame.seq,
+        timestampMs: frame.timestampMs,
+        repeat: frame.repeat ?? false,
+        pixelFormat: streamPixelFormat,
+        stride: streamStride,
         receiveTime 
       });
     }
@@ -77,7 +356,7 @@ async function startReceiving() {
 }
 
 worker.onmessage = async (e: MessageEvent) => {
-  const { type, port } = e.data;
+  const { type, port, acceptBgra } = e.data;
   
   if (type === 'connect') {
     try {
@@ -91,8 +370,16 @@ worker.onmessage = async (e: MessageEvent) => {
       // Wait for client connection
       console.log("Waiting for client connection...");
       conn = await listener.accept();
+      const handshake = await receiveHandshake(acceptBgra ?? false);
+      streamVersion = handshake.version;
+      streamCodec = handshake.codec;
+      streamPixelFormat = handshake.pixelFormat;
+      streamStride = handshake.stride;
+      streamChecksum = handshake.checksum;
+      deltaBase = null;
+      lastFrame = null;
       isConnected = true;
-      worker.postMessage({ type: 'connected' });
+      worker.postMessage({ type: 'connected', handshake });
       startReceiving();
     } catch (err) {
       worker.postMessage({ type: 'error', error: (err as Error).message });
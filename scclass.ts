@@ -4,14 +4,22 @@
  * Interface representing a captured frame with its dimensions and timing information
  */
 export interface CapturedFrame {
-  /** Raw RGBA pixel data */
+  /** Raw pixel data, RGBA unless `acceptBgra` was set and the sender agreed */
   data: Uint8Array;
+  /** Channel order of `data` */
+  pixelFormat: "rgba" | "bgra" | "rgb" | "gray";
   /** Frame width in pixels */
   width: number;
   /** Frame height in pixels */
   height: number;
+  /** Bytes per row of `data` */
+  stride: number;
   /** Time taken to receive the frame in milliseconds */
   receiveTime: number;
+  /** Sender sequence number; gaps indicate lost frames (protocol v2+) */
+  seq?: bigint;
+  /** Capture time in ms since the sender started streaming (protocol v2+) */
+  timestampMs?: bigint;
 }
 
 /**
@@ -24,6 +32,18 @@ export interface ScreenCapturerOptions {
   executablePath?: string;
   /** Whether to log debug information. Defaults to false. */
   debug?: boolean;
+  /** Frame encoding used on the wire: "raw" pixels, lossy "jpeg", lossless "zstd", changed-tile "delta" or run-length encoded "rle". Frames are always delivered decoded. Defaults to "raw". */
+  codec?: "raw" | "jpeg" | "zstd" | "delta" | "rle";
+  /** JPEG quality (1-100) when codec is "jpeg". Defaults to 80. */
+  quality?: number;
+  /** zstd compression level (1-22) when codec is "zstd". Defaults to 3. */
+  level?: number;
+  /** Frames between full keyframes when codec is "delta". Defaults to 60. */
+  keyframeInterval?: number;
+  /** Frames per second to capture and send; 0 sends frames as fast as they are captured, which can saturate CPU and network. Defaults to 60. */
+  fps?: number;
+  /** Accept BGRA frames so the sender can skip its color conversion. Check `pixelFormat` on each frame. Defaults to false. */
+  acceptBgra?: boolean;
   /** Callback for frame statistics (FPS, latency). Called every 30 frames if provided. */
   onStats?: (stats: { fps: number; avgLatency: number }) => void;
 }
@@ -69,6 +89,12 @@ export class ScreenCapturer {
       port: options.port ?? 12345,
       executablePath: options.executablePath ?? "./screen-streamer",
       debug: options.debug ?? false,
+      codec: options.codec ?? "raw",
+      quality: options.quality ?? 80,
+      level: options.level ?? 3,
+      keyframeInterval: options.keyframeInterval ?? 60,
+      fps: options.fps ?? 60,
+      acceptBgra: options.acceptBgra ?? false,
       onStats: options.onStats ?? (() => {}),
     };
   }
@@ -120,14 +146,19 @@ export class ScreenCapturer {
       if (!this.worker) return reject(new Error("Worker not initialized"));
 
       this.worker.onmessage = (e: MessageEvent) => {
-        const { type, data, width, height, receiveTime, error } = e.data;
+        const { type, data, width, height, stride, seq, timestampMs, pixelFormat, receiveTime, error, handshake } = e.data;
         if (type === 'listening') {
           this.log("TCP server started on worker");
           resolve();
         } else if (type === 'connected') {
-          this.log("Client connected to worker");
+          this.log("Client connected to worker:", handshake);
+        } else if (type === 'resized') {
+          // Later frames carry the new width, height and stride themselves
+          this.log(`Stream resized to ${width}x${height}`);
+        } else if (type === 'ended') {
+          this.log("Capture process ended the stream");
         } else if (type === 'frame') {
-          this.frameData = { data, width, height, receiveTime };
+          this.frameData = { data, pixelFormat, width, height, stride, receiveTime, seq, timestampMs };
           this.frameCount++;
           this.totalReceiveTime += receiveTime;
 
@@ -145,11 +176,19 @@ export class ScreenCapturer {
       };
 
       // Tell worker to start TCP server
-      this.worker.postMessage({ type: 'connect', port: this.options.port });
+      this.worker.postMessage({ type: 'connect', port: this.options.port, acceptBgra: this.options.acceptBgra });
     });
 
     // Start the Rust process after worker is ready
     const command = new Deno.Command(this.options.executablePath, {
+      args: [
+        "--port", String(this.options.port),
+        "--codec", this.options.codec,
+        "--quality", String(this.options.quality),
+        "--level", String(this.options.level),
+        "--keyframe-interval", String(this.options.keyframeInterval),
+        "--fps", String(this.options.fps),
+      ],
       stdout: "piped",
       stderr: "piped",
     });
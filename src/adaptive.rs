@@ -0,0 +1,97 @@
+use std::time::Duration;
+
+// Weight of the newest send time in the moving average
+const SMOOTHING: f64 = 0.1;
+// Frames between adjustments, so each change has time to show in the average
+const ADJUST_EVERY: u32 = 15;
+// Quality steps: drop fast when sends overrun, recover slowly
+const STEP_DOWN: u8 = 5;
+const STEP_UP: u8 = 2;
+
+/// Lowers JPEG quality while sends take longer than the frame budget and raises it
+/// again once there is headroom, staying within `min..=max`
+pub struct QualityController {
+  min: u8,
+  max: u8,
+  quality: u8,
+  budget: Duration,
+  /// Moving average of send time, in seconds
+  average: f64,
+  since_change: u32,
+}
+
+impl QualityController {
+  pub fn new(min: u8, max: u8, budget: Duration) -> Self {
+    QualityController {
+      min,
+      max,
+      quality: max,
+      budget,
+      average: 0.0,
+      since_change: 0,
+    }
+  }
+
+  pub fn quality(&self) -> u8 {
+    self.quality
+  }
+
+  pub fn budget(&self) -> Duration {
+    self.budget
+  }
+
+  /// Record how long a frame took to send. Returns the new quality when it changes.
+  pub fn record(&mut self, send_time: Duration) -> Option<u8> {
+    self.average += (send_time.as_secs_f64() - self.average) * SMOOTHING;
+    self.since_change += 1;
+    if self.since_change < ADJUST_EVERY {
+      return None;
+    }
+
+    // Back off above 90% of the budget; only climb again below half of it
+    let budget = self.budget.as_secs_f64();
+    let quality = if self.average > budget * 0.9 {
+      self.quality.saturating_sub(STEP_DOWN).max(self.min)
+    } else if self.average < budget * 0.5 {
+      self.quality.saturating_add(STEP_UP).min(self.max)
+    } else {
+      self.quality
+    };
+    if quality == self.quality {
+      return None;
+    }
+    self.quality = quality;
+    self.since_change = 0;
+    Some(quality)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn backs_off_under_pressure_and_recovers() {
+    let budget = Duration::from_millis(16);
+    let mut controller = QualityController::new(30, 80, budget);
+
+    for _ in 0..1000 {
+      controller.record(budget * 2);
+    }
+    assert_eq!(controller.quality(), 30);
+
+    for _ in 0..1000 {
+      controller.record(Duration::from_millis(2));
+    }
+    assert_eq!(controller.quality(), 80);
+  }
+
+  #[test]
+  fn holds_steady_within_the_budget() {
+    let budget = Duration::from_millis(16);
+    let mut controller = QualityController::new(30, 80, budget);
+    for _ in 0..1000 {
+      assert_eq!(controller.record(budget * 7 / 10), None);
+    }
+  }
+}
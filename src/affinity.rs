@@ -0,0 +1,23 @@
+// --pin-cores: keep each stream's capture and send threads on cores of their own,
+// so the scheduler moving them around doesn't show up as jitter in the FPS and
+// latency numbers. Cores are numbered in the order the OS lists them; a stream
+// whose cores run past the last one wraps around to the first.
+
+use std::sync::Once;
+
+use log::{debug, warn};
+
+static UNSUPPORTED: Once = Once::new();
+
+/// Pin the calling thread to core `index`, named `role` in the logs. Where
+/// affinity isn't supported this warns once and leaves the thread where it is.
+pub fn pin_current(index: usize, role: &str) {
+  let cores = core_affinity::get_core_ids().unwrap_or_default();
+  let core = (!cores.is_empty()).then(|| cores[index % cores.len()]);
+  match core.filter(|&core| core_affinity::set_for_current(core)) {
+    Some(core) => debug!("📌 Pinned the {} thread to core {}", role, core.id),
+    None => {
+      UNSUPPORTED.call_once(|| warn!("⚠️ Can't pin threads to cores here, ignoring --pin-cores"))
+    }
+  }
+}
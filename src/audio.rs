@@ -0,0 +1,126 @@
+// Audio packet payload layout, used with --audio:
+//
+//   payload := sample_rate:u32 channels:u16 samples
+//
+// `samples` is interleaved signed 16-bit little-endian PCM, `channels` values per
+// sample frame. Every packet repeats the format, so a receiver can start playing
+// from any packet.
+
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::time::Instant;
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{Device, SampleFormat, SizedSample, Stream, StreamConfig};
+use log::error;
+
+use crate::protocol::FrameInfo;
+
+// Buffers waiting for the send loop; past this the newest audio is dropped
+const QUEUE_DEPTH: usize = 32;
+
+const HEADER_SIZE: usize = 6;
+
+/// One callback's worth of captured samples, already laid out as a payload
+pub struct AudioBuffer {
+  pub data: Vec<u8>,
+  pub captured_at: Instant,
+}
+
+impl AudioBuffer {
+  /// Metadata for sending this buffer as an audio packet. `seq` counts audio
+  /// packets; frames keep their own numbering.
+  pub fn info(&self, seq: u64, stream_start: Instant) -> FrameInfo {
+    FrameInfo {
+      width: 0,
+      height: 0,
+      seq,
+      timestamp_ms: self
+        .captured_at
+        .saturating_duration_since(stream_start)
+        .as_millis() as u64,
+      raw_size: self.data.len() as u32,
+      checksum: None,
+      nonce: None,
+    }
+  }
+}
+
+/// PCM capture of what the system is playing where the platform offers loopback
+/// (WASAPI on Windows), otherwise of the default input device, which can be a
+/// PulseAudio or PipeWire monitor source. Samples arrive on cpal's audio thread.
+pub struct AudioCapture {
+  // Capture stops when the stream is dropped
+  _stream: Stream,
+  pub buffers: Receiver<AudioBuffer>,
+  pub device: String,
+  pub sample_rate: u32,
+  pub channels: u16,
+}
+
+impl AudioCapture {
+  pub fn start() -> Result<AudioCapture, String> {
+    let host = cpal::default_host();
+    let (device, supported) = if cfg!(windows) {
+      let device = host
+        .default_output_device()
+        .ok_or("no audio output device")?;
+      let supported = device.default_output_config().map_err(|e| e.to_string())?;
+      (device, supported)
+    } else {
+      let device = host.default_input_device().ok_or("no audio input device")?;
+      let supported = device.default_input_config().map_err(|e| e.to_string())?;
+      (device, supported)
+    };
+
+    let config = supported.config();
+    let mut header = [0u8; HEADER_SIZE];
+    header[0..4].copy_from_slice(&config.sample_rate.0.to_le_bytes());
+    header[4..6].copy_from_slice(&config.channels.to_le_bytes());
+
+    let (tx, buffers) = mpsc::sync_channel(QUEUE_DEPTH);
+    let stream = match supported.sample_format() {
+      SampleFormat::F32 => build(&device, &config, tx, header, |sample: f32| {
+        (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16
+      }),
+      SampleFormat::I16 => build(&device, &config, tx, header, |sample: i16| sample),
+      format => Err(format!("unsupported sample format {:?}", format)),
+    }?;
+    stream.play().map_err(|e| e.to_string())?;
+
+    Ok(AudioCapture {
+      _stream: stream,
+      buffers,
+      device: device.name().unwrap_or_default(),
+      sample_rate: config.sample_rate.0,
+      channels: config.channels,
+    })
+  }
+}
+
+fn build<T: SizedSample>(
+  device: &Device,
+  config: &StreamConfig,
+  tx: SyncSender<AudioBuffer>,
+  header: [u8; HEADER_SIZE],
+  convert: fn(T) -> i16,
+) -> Result<Stream, String> {
+  device
+    .build_input_stream(
+      config,
+      move |samples: &[T], _: &cpal::InputCallbackInfo| {
+        let mut data = Vec::with_capacity(HEADER_SIZE + samples.len() * 2);
+        data.extend_from_slice(&header);
+        for &sample in samples {
+          data.extend_from_slice(&convert(sample).to_le_bytes());
+        }
+        // A full queue means the send loop is behind; never block the audio thread
+        let _ = tx.try_send(AudioBuffer {
+          data,
+          captured_at: Instant::now(),
+        });
+      },
+      |e| error!("❌ Audio capture error: {}", e),
+      None,
+    )
+    .map_err(|e| e.to_string())
+}
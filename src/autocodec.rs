@@ -0,0 +1,202 @@
+// --codec auto: which codec a stream should use, judged by how the link keeps up.
+// The codecs sit on a ladder from the cheapest to encode to the smallest on the
+// wire: raw, zstd, JPEG, then H.264 where it's built in. The stream steps down it
+// while sends overrun the frame time, noting what the full link carried, and back
+// up once the lighter codec's frames would cross that link well within the frame
+// time. Each switch means dialing the receiver again with a new handshake, so
+// they're held apart: none comes within MIN_HOLD of the last, and a step up that
+// had to be taken back makes the next one wait twice as long.
+
+use std::time::{Duration, Instant};
+
+use crate::protocol::Codec;
+
+// Weight of the newest frame in the moving averages
+const SMOOTHING: f64 = 0.05;
+// Frames sent at a codec before its averages are trusted
+const SETTLE_FRAMES: u32 = 30;
+// Least time between switches, and the longest a step up is held off
+const MIN_HOLD: Duration = Duration::from_secs(10);
+const MAX_HOLD: Duration = Duration::from_secs(320);
+
+/// Picks the codec from the time frames take to send, against the frame time
+pub struct CodecSwitcher {
+  ladder: Vec<Codec>,
+  step: usize,
+  budget: Duration,
+  /// Moving averages of the current codec's send time, in seconds, and frame
+  /// size, in bytes
+  send_time: f64,
+  size: f64,
+  /// Frames sent since the last switch
+  frames: u32,
+  /// Each step's average frame size, as of when the stream last left it
+  sizes: Vec<Option<f64>>,
+  /// Bytes per second the link carried the last time it was full, and when
+  capacity: Option<(f64, Instant)>,
+  switched_at: Instant,
+  stepped_up: bool,
+  /// Time at one codec before stepping up from it
+  hold: Duration,
+}
+
+impl CodecSwitcher {
+  /// Start at the top of `ladder`, with sends having `budget` each
+  pub fn new(ladder: Vec<Codec>, budget: Duration, now: Instant) -> Self {
+    CodecSwitcher {
+      sizes: vec![None; ladder.len()],
+      ladder,
+      step: 0,
+      budget,
+      send_time: 0.0,
+      size: 0.0,
+      frames: 0,
+      capacity: None,
+      switched_at: now,
+      stepped_up: false,
+      hold: MIN_HOLD,
+    }
+  }
+
+  pub fn codec(&self) -> Codec {
+    self.ladder[self.step]
+  }
+
+  /// Record a frame of `bytes` that took `send_time` to send
+  pub fn record(&mut self, bytes: usize, send_time: Duration) {
+    // The first frame at a codec sets the averages rather than pulling them from 0
+    let weight = if self.frames == 0 { 1.0 } else { SMOOTHING };
+    self.send_time += (send_time.as_secs_f64() - self.send_time) * weight;
+    self.size += (bytes as f64 - self.size) * weight;
+    self.frames += 1;
+  }
+
+  /// The codec to switch to, if it's time for another
+  pub fn decide(&mut self, now: Instant) -> Option<Codec> {
+    let since = now.duration_since(self.switched_at);
+    if self.frames < SETTLE_FRAMES || since < MIN_HOLD {
+      return None;
+    }
+    let budget = self.budget.as_secs_f64();
+    if self.send_time > budget * 0.9 && self.step + 1 < self.ladder.len() {
+      // A full link carries what it can, so this is its capacity
+      self.capacity = Some((self.size / self.send_time, now));
+      if self.stepped_up && since < self.hold * 2 {
+        self.hold = (self.hold * 2).min(MAX_HOLD);
+      } else {
+        self.hold = MIN_HOLD;
+      }
+      return Some(self.switch(self.step + 1, now, false));
+    }
+    if self.send_time < budget * 0.5 && self.step > 0 && since >= self.hold {
+      // The link may have got faster since it was last full, so a measurement
+      // that old no longer holds the stream back from trying
+      let fits = match (self.sizes[self.step - 1], self.capacity) {
+        (Some(size), Some((capacity, at))) if now.duration_since(at) < MAX_HOLD => {
+          size / capacity < budget * 0.9
+        }
+        _ => true,
+      };
+      if fits {
+        return Some(self.switch(self.step - 1, now, true));
+      }
+    }
+    None
+  }
+
+  /// The receiver can't decode the codec just stepped down to: leave it off the
+  /// ladder and go back to the one before, or `None` once it refused them all.
+  /// The last codec stays on the ladder so there's always one to name.
+  pub fn refuse(&mut self, now: Instant) -> Option<Codec> {
+    if self.ladder.len() == 1 {
+      return None;
+    }
+    self.ladder.remove(self.step);
+    self.sizes.remove(self.step);
+    let back = self.step.saturating_sub(1);
+    self.step = back.min(self.ladder.len() - 1);
+    // Nothing was sent with it, so there's no size to note
+    self.frames = 0;
+    Some(self.switch(self.step, now, false))
+  }
+
+  fn switch(&mut self, step: usize, now: Instant, up: bool) -> Codec {
+    if self.frames > 0 {
+      self.sizes[self.step] = Some(self.size);
+    }
+    self.step = step;
+    self.send_time = 0.0;
+    self.size = 0.0;
+    self.frames = 0;
+    self.switched_at = now;
+    self.stepped_up = up;
+    self.ladder[step]
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const BUDGET: Duration = Duration::from_millis(16);
+
+  fn send(switcher: &mut CodecSwitcher, bytes: usize, ms: u64) {
+    for _ in 0..SETTLE_FRAMES {
+      switcher.record(bytes, Duration::from_millis(ms));
+    }
+  }
+
+  #[test]
+  fn steps_down_while_sends_overrun_and_back_once_they_would_fit() {
+    let start = Instant::now();
+    let at = |secs| start + Duration::from_secs(secs);
+    let ladder = vec![Codec::Raw, Codec::Zstd, Codec::Jpeg];
+    let mut switcher = CodecSwitcher::new(ladder, BUDGET, start);
+
+    // 100 kB frames taking 20ms: the link carries 5 MB/s
+    send(&mut switcher, 100_000, 20);
+    assert_eq!(switcher.decide(at(5)), None, "switched too soon");
+    assert_eq!(switcher.decide(at(10)), Some(Codec::Zstd));
+
+    // Raw frames would still take 20ms, however quick zstd's are
+    send(&mut switcher, 25_000, 5);
+    assert_eq!(switcher.decide(at(30)), None);
+    // Once that measurement is old, raw is tried again
+    assert_eq!(switcher.decide(at(10 + 320)), Some(Codec::Raw));
+
+    // Taken back at once, so the next step up waits twice as long
+    send(&mut switcher, 100_000, 20);
+    assert_eq!(switcher.decide(at(340)), Some(Codec::Zstd));
+    assert_eq!(switcher.hold, MIN_HOLD * 2);
+  }
+
+  #[test]
+  fn holds_steady_within_the_budget() {
+    let start = Instant::now();
+    let mut switcher = CodecSwitcher::new(vec![Codec::Raw, Codec::Zstd], BUDGET, start);
+    send(&mut switcher, 100_000, 12);
+    assert_eq!(switcher.decide(start + Duration::from_secs(60)), None);
+    assert_eq!(switcher.codec(), Codec::Raw);
+  }
+
+  #[test]
+  fn goes_back_from_a_codec_the_receiver_refused() {
+    let start = Instant::now();
+    let ladder = vec![Codec::Raw, Codec::Zstd, Codec::Jpeg];
+    let mut switcher = CodecSwitcher::new(ladder, BUDGET, start);
+    send(&mut switcher, 100_000, 20);
+    assert_eq!(switcher.decide(start + MIN_HOLD), Some(Codec::Zstd));
+    assert_eq!(switcher.refuse(start + MIN_HOLD), Some(Codec::Raw));
+    assert_eq!(switcher.ladder, [Codec::Raw, Codec::Jpeg]);
+  }
+
+  #[test]
+  fn runs_out_once_every_codec_is_refused() {
+    let start = Instant::now();
+    let mut switcher = CodecSwitcher::new(vec![Codec::Raw, Codec::Zstd], BUDGET, start);
+    assert_eq!(switcher.refuse(start), Some(Codec::Zstd));
+    assert_eq!(switcher.refuse(start), None);
+    assert_eq!(switcher.refuse(start), None);
+    assert_eq!(switcher.codec(), Codec::Zstd);
+  }
+}
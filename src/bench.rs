@@ -0,0 +1,130 @@
+// --bench: capture and encode for a while with nothing on the network, then report
+// what that costs on its own. Allocations are counted process-wide by
+// CountingAlloc, which the binary installs as its global allocator; it adds one
+// relaxed atomic add to every allocation.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use log::{error, info};
+
+use crate::capture::CaptureThread;
+use crate::encode::{FrameEncoder, StreamEncoder};
+
+// How often the loop checks for Ctrl-C while no frame is ready
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+/// The system allocator, counting every allocation and reallocation
+pub struct CountingAlloc;
+
+unsafe impl GlobalAlloc for CountingAlloc {
+  unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    System.alloc(layout)
+  }
+
+  unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    System.alloc_zeroed(layout)
+  }
+
+  unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    System.realloc(ptr, layout, new_size)
+  }
+
+  unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    System.dealloc(ptr, layout)
+  }
+}
+
+/// Run the capture thread and `stream_encoder`, if the codec has one, for
+/// `duration` and print the frame rate, encode times and allocations. The
+/// capture thread is pinned to `core` if given.
+pub fn run(
+  capture: CaptureThread,
+  encoder: FrameEncoder,
+  mut stream_encoder: Option<StreamEncoder>,
+  duration: Duration,
+  interrupted: &AtomicBool,
+  core: Option<usize>,
+) {
+  capture.start(encoder, core, None);
+  info!(
+    "⏱️ Benchmarking capture and encoding for {:.1}s...",
+    duration.as_secs_f64()
+  );
+
+  // Only count from the first frame, so starting the capturer doesn't skew the numbers
+  let first = loop {
+    if interrupted.load(Ordering::SeqCst) || capture.failed() {
+      capture.stop();
+      return;
+    }
+    if let Some(frame) = capture.frames.pop_timeout(POLL_INTERVAL) {
+      break frame;
+    }
+  };
+  capture.recycle(first.data);
+  capture.take_encode_stats();
+  capture.take_dropped();
+  let allocations_start = ALLOCATIONS.load(Ordering::Relaxed);
+  let start = Instant::now();
+
+  let mut frames = 0u64;
+  let mut bytes = 0u64;
+  let mut stream_time = Duration::ZERO;
+  while start.elapsed() < duration && !interrupted.load(Ordering::SeqCst) && !capture.failed() {
+    let Some(frame) = capture.frames.pop_timeout(POLL_INTERVAL) else {
+      continue;
+    };
+    frames += 1;
+    match stream_encoder.as_mut() {
+      Some(stream_encoder) => {
+        let encode_start = Instant::now();
+        match stream_encoder.encode(&frame.data) {
+          Ok(payload) => bytes += payload.len() as u64,
+          Err(e) => error!("❌ Failed to encode frame: {}", e),
+        }
+        stream_time += encode_start.elapsed();
+      }
+      None => bytes += frame.data.len() as u64,
+    }
+    capture.recycle(frame.data);
+  }
+
+  let elapsed = start.elapsed().as_secs_f64();
+  let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations_start;
+  let (encoded, encode_time) = capture.take_encode_stats();
+  let dropped = capture.take_dropped();
+  capture.stop();
+
+  let per_frame = |total: f64| total / frames.max(1) as f64;
+  info!(
+    "🏁 {} frames in {:.1}s: {:.1} fps, {} dropped",
+    frames,
+    elapsed,
+    frames as f64 / elapsed,
+    dropped
+  );
+  info!(
+    "   Convert and encode: {:.2}ms mean over {} frames",
+    encode_time.as_secs_f64() * 1000.0 / encoded.max(1) as f64,
+    encoded
+  );
+  if stream_encoder.is_some() {
+    info!(
+      "   Stream encode: {:.2}ms mean",
+      per_frame(stream_time.as_secs_f64() * 1000.0)
+    );
+  }
+  info!(
+    "   {:.1}KB mean payload, {:.1} allocations per frame ({} total)",
+    per_frame(bytes as f64) / 1024.0,
+    per_frame(allocations as f64),
+    allocations
+  );
+}
@@ -0,0 +1,122 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::protocol::PixelFormat;
+use crate::Stream;
+
+/// Layout of the file written by `--out`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+  /// YUV4MPEG2 with 4:2:0 chroma, readable by ffmpeg and most players
+  Y4m,
+  /// Decoded frames back to back, in the stream's pixel format and stride
+  Raw,
+}
+
+/// Writes every decoded frame of one stream to a file
+pub struct Dump {
+  writer: BufWriter<File>,
+  format: Format,
+  /// Frame size written in the Y4M header, once the first frame has set it
+  size: Option<(u32, u32)>,
+  yuv: Vec<u8>,
+}
+
+impl Dump {
+  pub fn create(path: &Path, format: Format) -> io::Result<Dump> {
+    Ok(Dump {
+      writer: BufWriter::new(File::create(path)?),
+      format,
+      size: None,
+      yuv: Vec::new(),
+    })
+  }
+
+  pub fn write(&mut self, stream: &Stream, pixels: &[u8]) -> io::Result<()> {
+    if self.format == Format::Raw {
+      return self.writer.write_all(pixels);
+    }
+
+    // The stream header comes from the first frame's handshake. Y4M needs a rate,
+    // so unpaced streams are written as 60fps.
+    let size = (stream.width, stream.height);
+    match self.size {
+      None => {
+        let fps = if stream.fps == 0 { 60 } else { stream.fps };
+        writeln!(
+          self.writer,
+          "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C420jpeg",
+          stream.width, stream.height, fps
+        )?;
+        self.size = Some(size);
+      }
+      // Every frame of a Y4M file has the header's size
+      Some((width, height)) if size != (width, height) => {
+        return Err(io::Error::new(
+          io::ErrorKind::InvalidData,
+          format!(
+            "the stream was resized to {}x{}, but Y4M frames stay {}x{}; use --raw",
+            stream.width, stream.height, width, height
+          ),
+        ));
+      }
+      Some(_) => {}
+    }
+    to_yuv420(stream, pixels, &mut self.yuv);
+    self.writer.write_all(b"FRAME\n")?;
+    self.writer.write_all(&self.yuv)
+  }
+
+  /// Flush whatever is still buffered; the file is complete afterwards
+  pub fn finish(mut self) -> io::Result<()> {
+    self.writer.flush()
+  }
+}
+
+/// BT.601 limited-range conversion into Y, U and V planes. Chroma averages each
+/// 2x2 block, with the last column or row repeated when a dimension is odd.
+fn to_yuv420(stream: &Stream, pixels: &[u8], out: &mut Vec<u8>) {
+  let (width, height, stride) = (
+    stream.width as usize,
+    stream.height as usize,
+    stream.stride as usize,
+  );
+  let bpp = stream.pixel_format.bytes_per_pixel() as usize;
+  // Channel offsets of red, green and blue; all the same byte for grayscale
+  let (red, green, blue) = match stream.pixel_format {
+    PixelFormat::Rgba | PixelFormat::Rgb => (0, 1, 2),
+    PixelFormat::Bgra => (2, 1, 0),
+    PixelFormat::Gray => (0, 0, 0),
+  };
+  let rgb = |x: usize, y: usize| {
+    let px = &pixels[y * stride + x * bpp..];
+    (px[red] as i32, px[green] as i32, px[blue] as i32)
+  };
+
+  let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+  out.clear();
+  out.reserve(width * height + 2 * chroma_width * chroma_height);
+
+  for y in 0..height {
+    for x in 0..width {
+      let (r, g, b) = rgb(x, y);
+      out.push((((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8);
+    }
+  }
+
+  let mut v_plane = Vec::with_capacity(chroma_width * chroma_height);
+  for cy in 0..chroma_height {
+    for cx in 0..chroma_width {
+      let (mut r, mut g, mut b) = (0, 0, 0);
+      for (x, y) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+        let (pr, pg, pb) = rgb((cx * 2 + x).min(width - 1), (cy * 2 + y).min(height - 1));
+        (r, g, b) = (r + pr, g + pg, b + pb);
+      }
+      let (r, g, b) = (r / 4, g / 4, b / 4);
+      out.push((((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8);
+      v_plane.push((((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8);
+    }
+  }
+  out.extend_from_slice(&v_plane);
+}
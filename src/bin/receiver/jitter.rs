@@ -0,0 +1,147 @@
+// Reordering for UDP streams. Datagrams can overtake each other, so frames can
+// complete out of order. Each frame waits here up to --jitter-ms for the ones
+// before it, then frames go out in sequence order. One completing after a later
+// frame already went out is too late to show and is dropped.
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+pub struct JitterBuffer<T> {
+  delay: Duration,
+  /// Frames waiting to go out, by seq, with when each completed
+  held: BTreeMap<u64, (Instant, T)>,
+  /// Lowest seq that can still go out, once one has
+  next: Option<u64>,
+  /// Frames that completed after a later one and were put back in order
+  pub reordered: u64,
+  /// Frames that completed after a later one had already gone out
+  pub late: u64,
+}
+
+impl<T> JitterBuffer<T> {
+  pub fn new(delay: Duration) -> Self {
+    JitterBuffer {
+      delay,
+      held: BTreeMap::new(),
+      next: None,
+      reordered: 0,
+      late: 0,
+    }
+  }
+
+  /// Hold frame `seq`, which completed at `now`
+  pub fn push(&mut self, seq: u64, frame: T, now: Instant) {
+    if self.next.is_some_and(|next| seq < next) {
+      self.late += 1;
+      return;
+    }
+    if self
+      .held
+      .last_key_value()
+      .is_some_and(|(&last, _)| seq < last)
+    {
+      self.reordered += 1;
+    }
+    self.held.insert(seq, (now, frame));
+  }
+
+  /// The next frame in order, once it's the one expected or a frame has waited
+  /// out the delay for the gap before it to fill
+  pub fn pop(&mut self, now: Instant) -> Option<(u64, T)> {
+    let deadline = self.deadline()?;
+    if deadline > now {
+      return None;
+    }
+    let (seq, (_, frame)) = self.held.pop_first()?;
+    self.next = Some(seq + 1);
+    Some((seq, frame))
+  }
+
+  /// Everything still held, in order, for the end of the stream
+  pub fn drain(&mut self) -> impl Iterator<Item = (u64, T)> + '_ {
+    if let Some((&last, _)) = self.held.last_key_value() {
+      self.next = Some(last + 1);
+    }
+    std::mem::take(&mut self.held)
+      .into_iter()
+      .map(|(seq, (_, frame))| (seq, frame))
+  }
+
+  /// When `pop` will next have a frame, if any is held
+  pub fn deadline(&self) -> Option<Instant> {
+    let (&first, &(arrived, _)) = self.held.first_key_value()?;
+    if self.next == Some(first) {
+      return Some(arrived);
+    }
+    // The longest-waiting frame decides, since the gap holds all of them up
+    let oldest = self.held.values().map(|&(arrived, _)| arrived).min()?;
+    Some(oldest + self.delay)
+  }
+
+  /// Lowest seq that can still go out; frames below it are no use any more
+  pub fn next(&self) -> Option<u64> {
+    self.next
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.held.is_empty()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const DELAY: Duration = Duration::from_millis(20);
+
+  #[test]
+  fn puts_frames_back_in_order() {
+    let start = Instant::now();
+    let mut jitter = JitterBuffer::new(DELAY);
+    jitter.push(1, "b", start);
+    jitter.push(0, "a", start + Duration::from_millis(2));
+    assert_eq!(jitter.reordered, 1);
+    // The first frame waits out the delay, since nothing says it is first
+    assert_eq!(jitter.pop(start + Duration::from_millis(5)), None);
+    let later = start + DELAY;
+    assert_eq!(jitter.pop(later), Some((0, "a")));
+    // Then the rest follow at once while they're in order
+    assert_eq!(jitter.pop(later), Some((1, "b")));
+    jitter.push(2, "c", later);
+    assert_eq!(jitter.deadline(), Some(later));
+    assert_eq!(jitter.pop(later), Some((2, "c")));
+    assert!(jitter.is_empty());
+  }
+
+  #[test]
+  fn skips_a_gap_after_the_delay_and_drops_late_frames() {
+    let start = Instant::now();
+    let mut jitter = JitterBuffer::new(DELAY);
+    jitter.push(0, "a", start);
+    assert_eq!(jitter.pop(start + DELAY), Some((0, "a")));
+
+    // Frame 1 is missing, so 2 waits for it, then goes without it
+    jitter.push(2, "c", start + DELAY);
+    assert_eq!(jitter.pop(start + DELAY), None);
+    assert_eq!(jitter.deadline(), Some(start + DELAY * 2));
+    assert_eq!(jitter.pop(start + DELAY * 2), Some((2, "c")));
+
+    jitter.push(1, "b", start + DELAY * 3);
+    assert_eq!(jitter.late, 1);
+    assert!(jitter.is_empty());
+    assert_eq!(jitter.next(), Some(3));
+  }
+
+  #[test]
+  fn drains_in_order() {
+    let start = Instant::now();
+    let mut jitter = JitterBuffer::new(DELAY);
+    for seq in [5, 3, 4] {
+      jitter.push(seq, seq, start);
+    }
+    let drained: Vec<_> = jitter.drain().map(|(seq, _)| seq).collect();
+    assert_eq!(drained, [3, 4, 5]);
+    jitter.push(4, 4, start);
+    assert_eq!(jitter.late, 1);
+  }
+}
@@ -0,0 +1,901 @@
+//! Minimal receiver for the TCP stream: accepts the streamer's connection,
+//! reassembles each frame from its chunks, checks the sizes add up and prints
+//! FPS/throughput. With `--udp` it takes the UDP transport instead, reordering
+//! frames that arrive out of order. Mirrors the framing described in
+//! protocol.rs. Built with the `preview` feature it can also show the decoded
+//! frames in a window, and with `--out` it writes them to a Y4M or raw file.
+//! With `--connect` it dials a streamer started with `--listen` instead.
+
+// Only the wire constants and types are used here
+#[allow(dead_code)]
+#[path = "../../protocol.rs"]
+mod protocol;
+
+// The closing summary is only printed by the streamer
+#[allow(dead_code)]
+#[path = "../../logging.rs"]
+mod logging;
+
+// Only the server side is used here
+#[allow(dead_code)]
+#[path = "../../tls.rs"]
+mod tls;
+
+// Only opening is used here
+#[allow(dead_code)]
+#[path = "../../crypto.rs"]
+mod crypto;
+
+// Only decoding is used here
+#[allow(dead_code)]
+#[path = "../../rle.rs"]
+mod rle;
+
+// Only reading and drawing is used here
+#[allow(dead_code)]
+#[path = "../../cursor.rs"]
+mod cursor;
+
+// Parity slices are made by the streamer and used here
+#[path = "../../fec.rs"]
+mod fec;
+
+// Shared with the streamer's --preview
+#[path = "../../decode.rs"]
+mod decode;
+#[cfg(feature = "preview")]
+#[path = "../../preview.rs"]
+mod preview;
+
+mod dump;
+mod jitter;
+mod udp;
+
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::ops::Range;
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use crypto::Cipher;
+use cursor::Cursor;
+use decode::Decoder;
+use dump::{Dump, Format};
+use log::{error, info, warn, LevelFilter};
+use protocol::{
+  Capabilities, Codec, FrameInfo, PixelFormat, Variant, AUDIO, CHECKSUM, CURSOR, ENCRYPTED, FEC,
+  HANDSHAKE_SIZE, MAGIC, NEGOTIATE, NO_PIXEL_FORMAT, PROTOCOL_VERSION, VARIANTS,
+};
+use tls::TlsConfig;
+use udp::UdpListener;
+
+// Same defaults as the streamer, so both can be started without arguments
+const DEFAULT_HOST: &str = "127.0.0.1";
+const DEFAULT_PORT: u16 = 12345;
+const DEFAULT_JITTER: Duration = Duration::from_millis(20);
+
+// Handshake length before version 6 added the stride
+const HANDSHAKE_SIZE_V1: usize = 20;
+
+// How often --connect dials a streamer that isn't listening yet
+const REDIAL_INTERVAL: Duration = Duration::from_millis(500);
+
+const USAGE: &str = "\
+Usage: receiver [OPTIONS]
+
+Options:
+  --host <HOST>    Address to listen on, or with --connect to dial (default:
+                   127.0.0.1)
+  --port <PORT>    Port to listen on, or with --connect to dial (default: 12345)
+  --connect        Dial a streamer started with --listen, rather than waiting for
+                   one to connect, and dial it again after each stream
+  --want <CODEC[:QUALITY][@WIDTHxHEIGHT]>
+                   With --connect, ask a streamer with --variants for the variant
+                   of its stream closest to this, e.g. jpeg:60@1280x720 or
+                   @640x360 (default: its main stream)
+  --path <PATH>    Listen on a Unix domain socket at PATH instead, for a streamer
+                   on the same machine with --transport unix (Unix only); the
+                   file is removed on exit
+  --udp            Take a streamer started with --transport udp. Start the
+                   receiver first: a stream can't be picked up without its
+                   handshake
+  --jitter-ms <MS> With --udp, hold frames up to MS milliseconds for ones that
+                   were overtaken on the way, adding that much latency only when
+                   one is missing; later stragglers are dropped (default: 20, 0
+                   to show frames as they complete)
+  --preview        Show the frames in a window (needs the `preview` feature)
+  --out <PATH>     Write the frames of one stream to PATH as Y4M, then exit
+  --raw            With --out, write the decoded frames back to back instead of
+                   converting them to Y4M, which can't follow the stream through
+                   a size change
+  --tls            Expect a streamer started with --tls, presenting --cert
+  --cert <PATH>    With --tls, the PEM certificate chain to present
+  --key <PATH>     With --tls, the PEM private key for --cert
+  --psk <KEY>      Decrypt payloads sealed with the streamer's --psk
+  --hide-cursor    Leave out the cursor a streamer with --cursor-layer sends
+                   beside its frames, rather than drawing it into them
+  -h, --help       Print this help and exit";
+
+/// Command-line options for the receiver
+struct Args {
+  host: String,
+  port: u16,
+  /// Unix socket file to listen on instead of --host/--port
+  path: Option<PathBuf>,
+  /// Dial --host/--port rather than listening there
+  connect: bool,
+  /// The variant of the stream to ask a streamer with --variants for
+  want: Variant,
+  udp: bool,
+  /// How long UDP frames wait for the ones before them
+  jitter: Duration,
+  preview: bool,
+  out: Option<PathBuf>,
+  format: Format,
+  tls: bool,
+  cert: Option<PathBuf>,
+  key: Option<PathBuf>,
+  psk: Option<String>,
+  /// Don't draw --cursor-layer cursors into shown and written frames
+  hide_cursor: bool,
+}
+
+/// What the handshake told us about the stream
+struct Stream {
+  version: u8,
+  width: u32,
+  height: u32,
+  pixel_format: PixelFormat,
+  codec: Codec,
+  fps: u32,
+  stride: u32,
+  /// Audio packets may arrive between frames
+  audio: bool,
+  /// Payloads are sealed with a pre-shared key
+  encrypted: bool,
+  /// Payloads carry a CRC-32
+  checksum: bool,
+  /// Cursor packets come before frames, which leave the cursor out
+  cursor: bool,
+  /// UDP frames carry parity slices
+  fec: bool,
+}
+
+fn main() {
+  logging::init(LevelFilter::Info);
+  let args = match parse_args() {
+    Ok(args) => args,
+    Err(e) => {
+      error!("❌ {}\n\n{}", e, USAGE);
+      std::process::exit(2);
+    }
+  };
+
+  let cipher = args.psk.as_deref().map(Cipher::new);
+  let tls = match (&args.cert, &args.key) {
+    (Some(cert), Some(key)) => match TlsConfig::server(cert, key) {
+      Ok(tls) => Some(tls),
+      Err(e) => {
+        error!("❌ {}", e);
+        std::process::exit(1);
+      }
+    },
+    _ => None,
+  };
+
+  let listener = match Listener::bind(&args) {
+    Ok(listener) => listener,
+    Err(e) => {
+      error!("❌ Failed to listen on {}: {}", listen_addr(&args), e);
+      std::process::exit(1);
+    }
+  };
+  if args.connect {
+    info!("🔌 Dialing the streamer at {}", listen_addr(&args));
+  } else {
+    info!("👂 Waiting for the streamer on {}", listen_addr(&args));
+  }
+
+  // Kept across connections so a reconnecting streamer reuses the same window
+  #[cfg(feature = "preview")]
+  let mut preview = args
+    .preview
+    .then(|| preview::Preview::new("screen-streamer preview"));
+
+  // Created up front so a bad path fails before the streamer connects
+  let mut dump = match &args.out {
+    Some(path) => match Dump::create(path, args.format) {
+      Ok(dump) => Some(dump),
+      Err(e) => {
+        error!("❌ Failed to create {}: {}", path.display(), e);
+        std::process::exit(1);
+      }
+    },
+    None => None,
+  };
+
+  // Frames with the cursor drawn in, reused from frame to frame
+  let mut composited = Vec::new();
+
+  // The streamer reconnects after errors, so keep serving one connection at a time
+  loop {
+    let (stream, peer) = match listener.accept() {
+      Ok(accepted) => accepted,
+      Err(e) => {
+        error!("❌ Accept failed: {}", e);
+        continue;
+      }
+    };
+    info!("✅ Streamer connected from {}", peer);
+
+    let decode = args.preview || dump.is_some();
+    let mut show = |stream: &Stream, pixels: &[u8], cursor: Option<&Cursor>| {
+      // --cursor-layer streams leave drawing the cursor to us
+      let pixels = match cursor.filter(|_| !args.hide_cursor) {
+        Some(cursor) => {
+          composited.clear();
+          composited.extend_from_slice(pixels);
+          let size = [stream.width, stream.height];
+          cursor.draw(
+            &mut composited,
+            stream.stride as usize,
+            size,
+            stream.pixel_format,
+          );
+          &composited[..]
+        }
+        None => pixels,
+      };
+      if let Some(dump) = dump.as_mut() {
+        dump
+          .write(stream, pixels)
+          .map_err(|e| format!("Failed to write frame: {}", e))?;
+      }
+      #[cfg(feature = "preview")]
+      if let Some(preview) = preview.as_mut() {
+        let size = [stream.width, stream.height];
+        return preview.show(pixels, size, stream.stride, stream.pixel_format);
+      }
+      Ok(true)
+    };
+    // The TLS handshake runs as the streamer's handshake is read
+    let result = match (stream, &tls) {
+      (Incoming::Tcp(stream), Some(tls)) => tls
+        .wrap(stream)
+        .and_then(|mut stream| receive(&mut stream, &args, cipher.as_ref(), decode, &mut show)),
+      (Incoming::Tcp(mut stream), None) => {
+        receive(&mut stream, &args, cipher.as_ref(), decode, &mut show)
+      }
+      #[cfg(unix)]
+      (Incoming::Unix(mut stream), _) => {
+        receive(&mut stream, &args, cipher.as_ref(), decode, &mut show)
+      }
+      (Incoming::Udp(incoming), _) => receive_udp(incoming, cipher.as_ref(), decode, &mut show),
+    };
+    match &result {
+      Ok(true) => {}
+      Ok(false) => info!("👋 Preview closed"),
+      Err(e) => error!("❌ Connection error: {}", e),
+    }
+
+    // A dump holds exactly one stream, however it ended
+    if let Some(dump) = dump.take() {
+      match dump.finish() {
+        Ok(()) => info!("💾 Wrote {}", args.out.as_ref().unwrap().display()),
+        Err(e) => error!(
+          "❌ Failed to finish {}: {}",
+          args.out.as_ref().unwrap().display(),
+          e
+        ),
+      }
+      return;
+    }
+    if let Ok(false) = result {
+      return;
+    }
+  }
+}
+
+/// Where streamers connect, or where --connect dials, as `--path` or
+/// --host/--port laid out
+fn listen_addr(args: &Args) -> String {
+  match &args.path {
+    Some(path) => path.display().to_string(),
+    None if args.udp => format!("{}:{} (UDP)", args.host, args.port),
+    None => format!("{}:{}", args.host, args.port),
+  }
+}
+
+/// Where streamers connect: a TCP or UDP port, or a Unix socket file that is
+/// removed again on exit. With --connect, the address of a streamer to dial.
+enum Listener {
+  Tcp(TcpListener),
+  #[cfg(unix)]
+  Unix(UnixListener, SocketFile),
+  Udp(UdpListener),
+  Connect(String),
+}
+
+/// A streamer's connection, from any kind of listener
+enum Incoming {
+  Tcp(TcpStream),
+  #[cfg(unix)]
+  Unix(UnixStream),
+  Udp(udp::Incoming),
+}
+
+impl Listener {
+  fn bind(args: &Args) -> io::Result<Listener> {
+    match &args.path {
+      #[cfg(unix)]
+      Some(path) => {
+        let listener = UnixListener::bind(path).map_err(|e| match e.kind() {
+          io::ErrorKind::AddrInUse => io::Error::new(
+            e.kind(),
+            "the file already exists; remove it if no receiver is using it",
+          ),
+          _ => e,
+        })?;
+        let file = SocketFile(path.clone());
+        // Ctrl-C would skip the guard's drop, so it removes the file itself
+        let on_interrupt = path.clone();
+        if let Err(e) = ctrlc::set_handler(move || {
+          let _ = std::fs::remove_file(&on_interrupt);
+          std::process::exit(130);
+        }) {
+          warn!("⚠️ {} won't be removed on Ctrl-C: {}", path.display(), e);
+        }
+        Ok(Listener::Unix(listener, file))
+      }
+      #[cfg(not(unix))]
+      Some(_) => unreachable!("--path is refused off Unix"),
+      None if args.udp => UdpListener::bind(&args.host, args.port, args.jitter).map(Listener::Udp),
+      None if args.connect => Ok(Listener::Connect(format!("{}:{}", args.host, args.port))),
+      None => TcpListener::bind((args.host.as_str(), args.port)).map(Listener::Tcp),
+    }
+  }
+
+  /// Wait for the next streamer, returning its connection and what to call it.
+  /// --connect dials until the streamer answers.
+  fn accept(&self) -> io::Result<(Incoming, String)> {
+    match self {
+      Listener::Connect(addr) => loop {
+        match TcpStream::connect(addr.as_str()) {
+          Ok(stream) => {
+            let peer = stream.peer_addr()?;
+            return Ok((Incoming::Tcp(stream), peer.to_string()));
+          }
+          // Not listening yet, or between streams
+          Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => sleep(REDIAL_INTERVAL),
+          Err(e) => return Err(e),
+        }
+      },
+      Listener::Tcp(listener) => {
+        let (stream, addr) = listener.accept()?;
+        Ok((Incoming::Tcp(stream), addr.to_string()))
+      }
+      // Connecting sockets are unnamed, so the file stands in for the peer
+      #[cfg(unix)]
+      Listener::Unix(listener, file) => {
+        let (stream, _) = listener.accept()?;
+        Ok((Incoming::Unix(stream), file.0.display().to_string()))
+      }
+      Listener::Udp(listener) => {
+        let incoming = listener.accept()?;
+        let peer = incoming.peer.to_string();
+        Ok((Incoming::Udp(incoming), peer))
+      }
+    }
+  }
+}
+
+/// Removes the socket file when the receiver exits normally
+#[cfg(unix)]
+struct SocketFile(PathBuf);
+
+#[cfg(unix)]
+impl Drop for SocketFile {
+  fn drop(&mut self) {
+    let _ = std::fs::remove_file(&self.0);
+  }
+}
+
+fn parse_args() -> Result<Args, String> {
+  let mut parsed = Args {
+    host: DEFAULT_HOST.to_string(),
+    port: DEFAULT_PORT,
+    path: None,
+    connect: false,
+    want: Variant::default(),
+    udp: false,
+    jitter: DEFAULT_JITTER,
+    preview: false,
+    out: None,
+    format: Format::Y4m,
+    tls: false,
+    cert: None,
+    key: None,
+    psk: None,
+    hide_cursor: false,
+  };
+  let mut jitter = None;
+  let mut want = None;
+  let mut args = std::env::args().skip(1);
+  while let Some(arg) = args.next() {
+    let mut value = || args.next().ok_or(format!("Missing value for {}", arg));
+    match arg.as_str() {
+      "--host" => parsed.host = value()?,
+      "--port" => {
+        let value = value()?;
+        parsed.port = value
+          .parse()
+          .map_err(|_| format!("Invalid value for --port: '{}'", value))?;
+      }
+      "--path" if cfg!(unix) => parsed.path = Some(value()?.into()),
+      "--path" => return Err("--path needs a Unix platform".to_string()),
+      "--udp" => parsed.udp = true,
+      "--connect" => parsed.connect = true,
+      "--want" => want = Some(value()?.parse()?),
+      "--jitter-ms" => {
+        let value = value()?;
+        let ms = value
+          .parse()
+          .map_err(|_| format!("Invalid value for --jitter-ms: '{}'", value))?;
+        jitter = Some(Duration::from_millis(ms));
+      }
+      "--preview" if cfg!(feature = "preview") => parsed.preview = true,
+      "--preview" => {
+        return Err("--preview needs the receiver built with `--features preview`".to_string())
+      }
+      "--out" => parsed.out = Some(value()?.into()),
+      "--raw" => parsed.format = Format::Raw,
+      "--tls" => parsed.tls = true,
+      "--cert" => parsed.cert = Some(value()?.into()),
+      "--key" => parsed.key = Some(value()?.into()),
+      "--psk" => parsed.psk = Some(value()?),
+      "--hide-cursor" => parsed.hide_cursor = true,
+      "-h" | "--help" => {
+        println!("{}", USAGE);
+        std::process::exit(0);
+      }
+      _ => return Err(format!("Unknown argument: {}", arg)),
+    }
+  }
+  if parsed.format == Format::Raw && parsed.out.is_none() {
+    return Err("--raw only applies with --out".to_string());
+  }
+  if parsed.psk.as_deref() == Some("") {
+    return Err("--psk can't be empty".to_string());
+  }
+  if parsed.tls != (parsed.cert.is_some() && parsed.key.is_some()) {
+    return Err("--tls needs --cert and --key, and they need --tls".to_string());
+  }
+  // A Unix socket never leaves the machine, so there is nothing to encrypt it against
+  if parsed.tls && parsed.path.is_some() {
+    return Err("--tls doesn't apply with --path".to_string());
+  }
+  if parsed.udp && parsed.path.is_some() {
+    return Err("--udp and --path can't be combined".to_string());
+  }
+  // The streamer neither encrypts nor sends TLS over UDP
+  if parsed.udp && (parsed.tls || parsed.psk.is_some()) {
+    return Err("--tls and --psk don't apply with --udp".to_string());
+  }
+  match jitter {
+    Some(_) if !parsed.udp => return Err("--jitter-ms only applies with --udp".to_string()),
+    Some(jitter) => parsed.jitter = jitter,
+    None => {}
+  }
+  // Listening streamers only take TCP, and --tls here presents a certificate
+  if parsed.connect && (parsed.udp || parsed.path.is_some() || parsed.tls) {
+    return Err(
+      "--connect dials TCP, so it can't be combined with --udp, --path or --tls".to_string(),
+    );
+  }
+  // Only a listening streamer has variants to offer
+  match want {
+    Some(_) if !parsed.connect => return Err("--want only applies with --connect".to_string()),
+    Some(want) => parsed.want = want,
+    None => {}
+  }
+  Ok(parsed)
+}
+
+/// A decoder for the frames `info` describes
+fn new_decoder(info: &Stream) -> Decoder {
+  let size = [info.width, info.height];
+  Decoder::new(info.codec, info.pixel_format, size, info.stride)
+}
+
+/// Where a stream's frames come from once its handshake is read
+trait FrameSource {
+  /// The next frame into `payload`, `None` at the end of the stream, as
+  /// `protocol::read_frame` returns it
+  fn next_frame(&mut self, info: &Stream, payload: &mut Vec<u8>) -> io::Result<Option<FrameInfo>>;
+
+  /// Byte ranges of the last frame's payload that never arrived
+  fn missing(&self) -> &[Range<usize>] {
+    &[]
+  }
+
+  /// What the transport adds to the stats line
+  fn stats(&self) -> String {
+    String::new()
+  }
+}
+
+/// Frames read off a TCP or Unix socket connection
+struct Connection<'a, S>(&'a mut S);
+
+impl<S: Read> FrameSource for Connection<'_, S> {
+  fn next_frame(&mut self, info: &Stream, payload: &mut Vec<u8>) -> io::Result<Option<FrameInfo>> {
+    protocol::read_frame(self.0, info.version, info.encrypted, info.checksum, payload)
+  }
+}
+
+impl FrameSource for udp::Frames {
+  fn next_frame(&mut self, _info: &Stream, payload: &mut Vec<u8>) -> io::Result<Option<FrameInfo>> {
+    udp::Frames::next_frame(self, payload)
+  }
+
+  fn missing(&self) -> &[Range<usize>] {
+    udp::Frames::missing(self)
+  }
+
+  fn stats(&self) -> String {
+    format!(
+      " | Reordered: {} | Late: {} | Partial: {} | Corrupt slices: {} | Recovered: {}",
+      self.reordered(),
+      self.late(),
+      self.partial_frames(),
+      self.corrupt_slices(),
+      self.recovered_slices()
+    )
+  }
+}
+
+/// Read the handshake, then frames until the end-of-stream marker, opening
+/// encrypted payloads with `cipher`. With `decode` set every frame is decoded and
+/// handed to `show`, with the cursor to draw over it if the stream sends one
+/// apart; returns false if `show` asked to stop.
+fn receive<S, F>(
+  stream: &mut S,
+  args: &Args,
+  cipher: Option<&Cipher>,
+  decode: bool,
+  show: &mut F,
+) -> io::Result<bool>
+where
+  S: Read + Write,
+  F: FnMut(&Stream, &[u8], Option<&Cursor>) -> Result<bool, String>,
+{
+  let info = read_handshake(stream, args.want)?;
+  receive_frames(info, &mut Connection(stream), cipher, decode, show)
+}
+
+/// `receive` for a UDP stream whose handshake datagram has arrived
+fn receive_udp<F>(
+  incoming: udp::Incoming,
+  cipher: Option<&Cipher>,
+  decode: bool,
+  show: &mut F,
+) -> io::Result<bool>
+where
+  F: FnMut(&Stream, &[u8], Option<&Cursor>) -> Result<bool, String>,
+{
+  // UDP handshakes never negotiate, so nothing is written back
+  let handshake = &mut io::Cursor::new(incoming.handshake.clone());
+  let info = read_handshake(handshake, Variant::default())?;
+  let mut frames = incoming.frames(&info);
+  receive_frames(info, &mut frames, cipher, decode, show)
+}
+
+fn receive_frames<F>(
+  mut info: Stream,
+  source: &mut impl FrameSource,
+  cipher: Option<&Cipher>,
+  decode: bool,
+  show: &mut F,
+) -> io::Result<bool>
+where
+  F: FnMut(&Stream, &[u8], Option<&Cursor>) -> Result<bool, String>,
+{
+  info!(
+    "🤝 Protocol v{}: {}x{} @ {}fps, {:?} {:?}, stride {}",
+    info.version, info.width, info.height, info.fps, info.codec, info.pixel_format, info.stride
+  );
+  if info.audio {
+    info!("🔊 Stream carries audio");
+  }
+  // Refuse a plaintext stream too, or anyone could feed us frames
+  match (info.encrypted, cipher) {
+    (true, None) => return Err(invalid("stream is encrypted; pass --psk".to_string())),
+    (false, Some(_)) => {
+      return Err(invalid(
+        "--psk given but the stream isn't encrypted".to_string(),
+      ))
+    }
+    (true, Some(_)) => info!("🔑 Stream is encrypted"),
+    (false, None) => {}
+  }
+  if info.checksum {
+    info!("🧮 Payloads are checksummed");
+  }
+  if info.fec {
+    info!("🛟 Frames carry parity slices");
+  }
+  if info.cursor {
+    info!("🖱️ The cursor arrives apart from the frames");
+  }
+
+  let mut frame = Vec::new();
+  let mut decoder = decode.then(|| new_decoder(&info));
+  let mut frames = 0u64;
+  let mut next_seq = None;
+  let mut lost = 0u64;
+  let mut corrupt = 0u64;
+  let mut audio_packets = 0u64;
+  let mut repeats = 0u64;
+  // Where the latest cursor packet put the cursor
+  let mut cursor = None;
+
+  let mut frame_count = 0u64;
+  let mut bytes_received = 0u64;
+  let mut last_print = Instant::now();
+
+  loop {
+    // Checked before each read, so the line keeps coming on a stream of repeats,
+    // audio or corrupt payloads too
+    if last_print.elapsed().as_secs() >= 1 {
+      let elapsed = last_print.elapsed().as_secs_f64();
+      let audio = if info.audio {
+        format!(" | Audio packets: {}", audio_packets)
+      } else {
+        String::new()
+      };
+      let corrupt = if info.checksum {
+        format!(" | Corrupt: {}", corrupt)
+      } else {
+        String::new()
+      };
+      let repeated = if repeats > 0 {
+        format!(" | Repeats: {}", repeats)
+      } else {
+        String::new()
+      };
+      logging::stats(&format!(
+        "🎬 FPS: {:.1} | {:.1}MB/s | Frames: {} | Lost: {}{}{}{}{}",
+        frame_count as f64 / elapsed,
+        bytes_received as f64 / (1024.0 * 1024.0) / elapsed,
+        frames,
+        lost,
+        source.stats(),
+        repeated,
+        corrupt,
+        audio
+      ));
+      frame_count = 0;
+      bytes_received = 0;
+      last_print = Instant::now();
+    }
+
+    let Some(mut meta) = source.next_frame(&info, &mut frame)? else {
+      info!("👋 Stream ended after {} frames", frames);
+      return Ok(true);
+    };
+    let (width, height, total_size) = (meta.width, meta.height, frame.len());
+    let seq = (info.version >= 2).then_some(meta.seq);
+    if info.version < 4 {
+      meta.raw_size = info.stride * info.height;
+    }
+    let raw_size = meta.raw_size;
+
+    // The sender's target changed size; frames from here on are the new one
+    if frame.is_empty() && meta.is_size_change() {
+      if meta.height == 0 || raw_size % meta.height != 0 {
+        return Err(invalid(format!(
+          "size change to {}x{} with {} bytes per frame",
+          width, height, raw_size
+        )));
+      }
+      if (width, height) == (info.width, info.height) {
+        info!("🔄 The sender restarted capture at frame {}", meta.seq);
+      } else {
+        info!("📐 Stream resized to {}x{}", width, height);
+      }
+      (info.width, info.height) = (width, height);
+      info.stride = raw_size / height;
+      decoder = decode.then(|| new_decoder(&info));
+      continue;
+    }
+    // The only other empty frame is a repeat: show the last frame again, without
+    // counting it as a new one. It counts towards FPS, which is what a constant
+    // rate is about.
+    if frame.is_empty() {
+      repeats += 1;
+      frame_count += 1;
+      if let Some(pixels) = decoder.as_ref().and_then(Decoder::last) {
+        if !show(&info, pixels, cursor.as_ref()).map_err(invalid)? {
+          return Ok(false);
+        }
+      }
+      continue;
+    }
+
+    // A damaged payload is dropped like a lost frame; deltas then wait for a keyframe
+    if !meta.checksum_matches(&frame) {
+      corrupt += 1;
+      warn!("⚠️ Payload {} failed its checksum, discarding it", meta.seq);
+      continue;
+    }
+    if let (Some(cipher), Some(nonce)) = (cipher, meta.nonce) {
+      cipher.open(&meta, &nonce, &mut frame).map_err(invalid)?;
+    }
+    if info.cursor && (width, height, raw_size) == (0, 0, 0) {
+      if decode {
+        cursor = Some(Cursor::from_payload(&frame).map_err(invalid)?);
+      }
+      bytes_received += total_size as u64;
+      continue;
+    }
+    // Audio is only counted; nothing here plays it back
+    if info.audio && (width, height) == (0, 0) {
+      audio_packets += 1;
+      bytes_received += total_size as u64;
+      continue;
+    }
+    if (width, height) != (info.width, info.height) {
+      return Err(invalid(format!(
+        "{}x{} frame in a {}x{} stream",
+        width, height, info.width, info.height
+      )));
+    }
+    if raw_size != info.stride * info.height {
+      return Err(invalid(format!(
+        "decoded size {} doesn't match {} rows of {} bytes",
+        raw_size, info.height, info.stride
+      )));
+    }
+    if info.codec == Codec::Raw && frame.len() != raw_size as usize {
+      return Err(invalid(format!(
+        "raw frame has {} bytes, expected {}",
+        frame.len(),
+        raw_size
+      )));
+    }
+
+    if let Some(seq) = seq {
+      if let Some(expected) = next_seq {
+        lost += seq.saturating_sub(expected);
+      }
+      next_seq = Some(seq + 1);
+    }
+    if let Some(decoder) = decoder.as_mut() {
+      let missing = source.missing();
+      let decoded = match missing.is_empty() {
+        true => decoder.decode(&frame, seq),
+        false => decoder.decode_partial(&frame, missing, seq),
+      };
+      match decoded.map_err(invalid)? {
+        Some(pixels) => {
+          if !show(&info, pixels, cursor.as_ref()).map_err(invalid)? {
+            return Ok(false);
+          }
+        }
+        None => warn!("⏭️ Frame {} skipped until the next keyframe", frames),
+      }
+    }
+
+    frames += 1;
+    frame_count += 1;
+    bytes_received += total_size as u64;
+  }
+}
+
+/// Read the handshake and answer it, asking for the variant `want` if the
+/// sender offers variants
+fn read_handshake<S: Read + Write>(stream: &mut S, want: Variant) -> io::Result<Stream> {
+  let mut bytes = [0u8; HANDSHAKE_SIZE];
+  stream.read_exact(&mut bytes[..HANDSHAKE_SIZE_V1])?;
+  if bytes[0..4] != MAGIC {
+    return Err(invalid("not a screen-streamer connection".to_string()));
+  }
+  let version = bytes[4];
+  if version == 0 || version > PROTOCOL_VERSION {
+    return Err(invalid(format!("unsupported protocol version {}", version)));
+  }
+  if version >= 6 {
+    stream.read_exact(&mut bytes[HANDSHAKE_SIZE_V1..])?;
+  }
+
+  let field = |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+  let (width, height, fps) = (field(8), field(12), field(16));
+  let mut pixel_format = match bytes[5] {
+    0 => PixelFormat::Rgba,
+    1 => PixelFormat::Bgra,
+    2 => PixelFormat::Gray,
+    3 => PixelFormat::Rgb,
+    other => return Err(invalid(format!("unknown pixel format {}", other))),
+  };
+  let codec = match bytes[6] {
+    0 => Codec::Raw,
+    1 => Codec::Jpeg,
+    2 => Codec::Zstd,
+    3 => Codec::Delta,
+    4 => Codec::H264,
+    5 => Codec::Rle,
+    other => return Err(invalid(format!("unknown codec {}", other))),
+  };
+  let stride = if version >= 6 { field(20) } else { width * 4 };
+  let audio = version >= 8 && bytes[7] & AUDIO != 0;
+  let encrypted = version >= 11 && bytes[7] & ENCRYPTED != 0;
+  let checksum = version >= 13 && bytes[7] & CHECKSUM != 0;
+  let cursor = version >= 17 && bytes[7] & CURSOR != 0;
+  let fec = version >= 19 && bytes[7] & FEC != 0;
+  let variants = version >= 20 && bytes[7] & VARIANTS != 0;
+
+  // Only sizes are checked, so whatever pixel format the sender offers is fine
+  if version >= 14 && bytes[7] & NEGOTIATE != 0 {
+    let mut offer = [0u8; 2];
+    stream.read_exact(&mut offer)?;
+    let answer = Capabilities::from_bytes(offer).intersect(decodable());
+    stream.write_all(&answer.to_bytes())?;
+    stream.write_all(&[NO_PIXEL_FORMAT])?;
+    if variants {
+      stream.write_all(&want.to_bytes())?;
+    }
+    stream.flush()?;
+    let mut choice = [0u8; 1];
+    stream.read_exact(&mut choice)?;
+    pixel_format = match protocol::PIXEL_FORMATS.get(choice[0] as usize) {
+      Some(&format) => format,
+      None if !answer.has_codec(codec) => {
+        let message = format!("{} frames can't be decoded by this build", codec.name());
+        return Err(io::Error::new(io::ErrorKind::Unsupported, message));
+      }
+      None => {
+        let message = "the sender offered no pixel format shown here".to_string();
+        return Err(io::Error::new(io::ErrorKind::Unsupported, message));
+      }
+    };
+    // The variant we got has a handshake of its own, which says the rest
+    if variants {
+      let mut variant = vec![0u8; HANDSHAKE_SIZE];
+      stream.read_exact(&mut variant)?;
+      return read_handshake(&mut io::Cursor::new(variant), want);
+    }
+  } else if version >= 5 && bytes[7] & NEGOTIATE != 0 {
+    stream.write_all(&[1])?;
+  }
+
+  Ok(Stream {
+    version,
+    width,
+    height,
+    pixel_format,
+    codec,
+    fps,
+    stride,
+    audio,
+    encrypted,
+    checksum,
+    cursor,
+    fec,
+  })
+}
+
+/// Codecs this build can decode, in every pixel format
+fn decodable() -> Capabilities {
+  let codecs: Vec<Codec> = protocol::CODECS
+    .into_iter()
+    .filter(|&codec| codec != Codec::H264 || cfg!(feature = "h264"))
+    .collect();
+  Capabilities::new(&codecs, &protocol::PIXEL_FORMATS)
+}
+
+fn invalid(message: String) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,617 @@
+// UDP streams for the receiver. A handshake datagram starts each one, since there
+// is no connection to accept. Frames are reassembled from their slices, and a
+// JitterBuffer hands them over in sequence order, as a TCP stream would. A frame
+// still missing slices when the one after it goes out is handed over as it is if
+// its codec can use the slices that arrived, with the byte ranges that didn't,
+// rather than lost outright. With FEC, one slice lost in a run is rebuilt from
+// the run's parity slice as soon as the rest are in. Frame ids are the low 32
+// bits of `seq`, which at 60fps takes over two years to wrap, so they stand in
+// for it here.
+
+use std::cell::Cell;
+use std::collections::{BTreeMap, VecDeque};
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::ops::Range;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use log::warn;
+use socket2::SockRef;
+
+use crate::fec;
+use crate::jitter::JitterBuffer;
+use crate::protocol::{self, Codec, Datagram, FrameInfo, MAGIC, MAX_DATAGRAM_SIZE};
+use crate::Stream;
+
+// A streamer that goes quiet this long is taken to be gone, since UDP never says
+const IDLE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Frames still missing slices that are kept at once; older ones are given up on
+const MAX_PARTIAL: usize = 16;
+
+// A frame's slices go out in one burst, so once none has come for the jitter
+// delay, or this long with a shorter one, the rest are taken to be lost
+const MIN_SLICE_WAIT: Duration = Duration::from_millis(5);
+
+// Receive buffer asked for, as a raw frame's burst of datagrams overflows the usual
+// default before it can be read; the system may grant less
+const RECV_BUFFER_SIZE: usize = 8 * 1024 * 1024;
+
+/// A handshake datagram and who sent it
+struct Start {
+  handshake: Vec<u8>,
+  peer: SocketAddr,
+}
+
+pub struct UdpListener {
+  socket: UdpSocket,
+  delay: Duration,
+  /// A handshake that ended the previous stream, to start the next one with
+  next: Rc<Cell<Option<Start>>>,
+}
+
+/// A stream whose handshake arrived, ready to be read
+pub struct Incoming {
+  socket: UdpSocket,
+  delay: Duration,
+  pub handshake: Vec<u8>,
+  pub peer: SocketAddr,
+  next: Rc<Cell<Option<Start>>>,
+}
+
+impl UdpListener {
+  /// Listen on `host`:`port`, holding frames up to `delay` to reorder them
+  pub fn bind(host: &str, port: u16, delay: Duration) -> io::Result<UdpListener> {
+    let socket = UdpSocket::bind((host, port))?;
+    SockRef::from(&socket).set_recv_buffer_size(RECV_BUFFER_SIZE)?;
+    Ok(UdpListener {
+      socket,
+      delay,
+      next: Rc::new(Cell::new(None)),
+    })
+  }
+
+  /// Wait for a streamer's handshake. Datagrams of a stream whose handshake was
+  /// missed can't be made sense of, so they are skipped.
+  pub fn accept(&self) -> io::Result<Incoming> {
+    let start = match self.next.take() {
+      Some(start) => start,
+      None => {
+        self.socket.set_read_timeout(None)?;
+        let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
+        loop {
+          let (len, peer) = self.socket.recv_from(&mut buf)?;
+          if is_handshake(&buf[..len]) {
+            break Start {
+              handshake: buf[..len].to_vec(),
+              peer,
+            };
+          }
+        }
+      }
+    };
+    Ok(Incoming {
+      socket: self.socket.try_clone()?,
+      delay: self.delay,
+      handshake: start.handshake,
+      peer: start.peer,
+      next: self.next.clone(),
+    })
+  }
+}
+
+impl Incoming {
+  /// Frames of the stream `info` describes
+  pub fn frames(self, info: &Stream) -> Frames {
+    // Before version 18 the checksum covered whole frames, so nothing short of
+    // one could be checked
+    let slice_checksums = info.version >= 18;
+    Frames {
+      jitter: JitterBuffer::new(self.delay),
+      slice_wait: self.delay.max(MIN_SLICE_WAIT),
+      incoming: self,
+      checksummed: info.checksum,
+      fec: info.fec,
+      slice_checksums,
+      // Nor opened, as a sealed payload is only checked as a whole
+      keep_partial: matches!(info.codec, Codec::Raw | Codec::Delta)
+        && (slice_checksums || !info.checksum)
+        && !info.encrypted,
+      bytes_per_pixel: info.pixel_format.bytes_per_pixel(),
+      size: (info.width, info.height),
+      partial: BTreeMap::new(),
+      ready: VecDeque::new(),
+      ended: false,
+      last_datagram: Instant::now(),
+      buf: vec![0u8; MAX_DATAGRAM_SIZE],
+      missing: Vec::new(),
+      partial_frames: 0,
+      corrupt_slices: 0,
+      recovered_slices: 0,
+    }
+  }
+}
+
+/// A frame with slices still to come
+struct Partial {
+  data: Vec<u8>,
+  received: Vec<bool>,
+  missing: usize,
+  /// Length of every slice but the last
+  slice_len: usize,
+  /// The frame's header fields, for handing it over incomplete
+  width: u16,
+  height: u16,
+  timestamp_ms: u64,
+  last_slice: Instant,
+  /// Data slices per parity slice, 0 for none
+  group: usize,
+  parity: Vec<Option<Vec<u8>>>,
+}
+
+impl Partial {
+  /// Put the data slice `index` at `offset`
+  fn place(&mut self, index: usize, offset: usize, bytes: &[u8]) {
+    self.received[index] = true;
+    self.missing -= 1;
+    // The last slice's offset is that of `count - 1` whole slices
+    self.slice_len = match index + 1 == self.received.len() {
+      true => offset / index.max(1),
+      false => bytes.len(),
+    };
+    self.data[offset..offset + bytes.len()].copy_from_slice(bytes);
+  }
+
+  /// Rebuild the slice of parity run `run` that's missing, if it's the only one
+  /// and the parity slice is in
+  fn recover(&mut self, run: usize) -> bool {
+    let (count, total) = (self.received.len(), self.data.len());
+    let slices = fec::run(run, count, self.group);
+    let Some(parity) = &self.parity[run] else {
+      return false;
+    };
+    let mut lost = slices.clone().filter(|&index| !self.received[index]);
+    let (Some(index), None) = (lost.next(), lost.next()) else {
+      return false;
+    };
+    // Parity is as long as the run's first slice, a whole one unless it's the
+    // frame's last
+    let len = parity.len();
+    let last = index + 1 == count;
+    let offset = match slices.len() {
+      1 if last => total.checked_sub(len),
+      _ => index.checked_mul(len),
+    };
+    let end = offset.map(|offset| if last { total } else { offset + len });
+    let (Some(offset), Some(end)) = (offset, end) else {
+      return false;
+    };
+    if end > total || end < offset || end - offset > len {
+      return false;
+    }
+    // A parity longer than the run's slices would reach past the frame
+    let others: Option<Vec<&[u8]>> = (slices.filter(|&other| other != index))
+      .map(|other| self.data.get(other * len..(other * len + len).min(total)))
+      .collect();
+    let Some(others) = others else {
+      return false;
+    };
+    let rebuilt = fec::xor([&parity[..]].into_iter().chain(others), len);
+    self.place(index, offset, &rebuilt[..end - offset]);
+    true
+  }
+}
+
+/// A frame or marker ready to be read, with the byte ranges of its payload that
+/// never arrived
+struct Assembled {
+  info: FrameInfo,
+  data: Vec<u8>,
+  missing: Vec<Range<usize>>,
+}
+
+impl Assembled {
+  fn whole(info: FrameInfo, data: Vec<u8>) -> Self {
+    Assembled {
+      info,
+      data,
+      missing: Vec::new(),
+    }
+  }
+}
+
+/// Reassembled frames of one UDP stream, in order
+pub struct Frames {
+  incoming: Incoming,
+  jitter: JitterBuffer<Assembled>,
+  /// How long a frame waits for more of its slices
+  slice_wait: Duration,
+  checksummed: bool,
+  /// Slices carry `fec_group`, and parity slices follow
+  fec: bool,
+  /// Each slice carries its own checksum, from version 18
+  slice_checksums: bool,
+  /// Frames still missing slices are handed over rather than dropped
+  keep_partial: bool,
+  bytes_per_pixel: u32,
+  /// Frame size so far, to turn a change into a size change marker
+  size: (u32, u32),
+  partial: BTreeMap<u32, Partial>,
+  /// Frames and markers out of the jitter buffer, to be read
+  ready: VecDeque<Assembled>,
+  /// The end of the stream arrived, or another stream began
+  ended: bool,
+  last_datagram: Instant,
+  buf: Vec<u8>,
+  /// What never arrived of the frame read last
+  missing: Vec<Range<usize>>,
+  partial_frames: u64,
+  corrupt_slices: u64,
+  recovered_slices: u64,
+}
+
+impl Frames {
+  /// The next frame, repeat or size change marker into `payload`, or `None` at the
+  /// end of the stream, like `protocol::read_frame`
+  pub fn next_frame(&mut self, payload: &mut Vec<u8>) -> io::Result<Option<FrameInfo>> {
+    loop {
+      if let Some(frame) = self.ready.pop_front() {
+        *payload = frame.data;
+        self.missing = frame.missing;
+        return Ok(Some(frame.info));
+      }
+      let now = Instant::now();
+      let slice_wait = self.slice_wait;
+      self.expire(|partial| partial.last_slice + slice_wait <= now);
+      if let Some((seq, frame)) = self.jitter.pop(now) {
+        self.give_up_before(seq);
+        self.queue(frame);
+        continue;
+      }
+      if self.ended {
+        // Nothing more is coming for the frames still missing slices either
+        self.expire(|_| true);
+        let held: Vec<_> = self.jitter.drain().collect();
+        if held.is_empty() {
+          return Ok(None);
+        }
+        for (_, frame) in held {
+          self.queue(frame);
+        }
+        continue;
+      }
+      self.receive()?;
+    }
+  }
+
+  /// Byte ranges of the last frame read's payload that never arrived, in order;
+  /// empty for a whole frame
+  pub fn missing(&self) -> &[Range<usize>] {
+    &self.missing
+  }
+
+  /// Counts for the stats line
+  pub fn reordered(&self) -> u64 {
+    self.jitter.reordered
+  }
+
+  pub fn late(&self) -> u64 {
+    self.jitter.late
+  }
+
+  /// Frames handed over with slices missing
+  pub fn partial_frames(&self) -> u64 {
+    self.partial_frames
+  }
+
+  pub fn corrupt_slices(&self) -> u64 {
+    self.corrupt_slices
+  }
+
+  /// Lost slices rebuilt from parity
+  pub fn recovered_slices(&self) -> u64 {
+    self.recovered_slices
+  }
+
+  /// Wait for one datagram, or until a held frame is due or a frame has waited
+  /// long enough for its slices
+  fn receive(&mut self) -> io::Result<()> {
+    let given_up_at = (self.partial.values())
+      .filter(|_| self.keep_partial)
+      .map(|partial| partial.last_slice + self.slice_wait)
+      .min();
+    let wait = match self.jitter.deadline().into_iter().chain(given_up_at).min() {
+      Some(deadline) => deadline.saturating_duration_since(Instant::now()),
+      None => IDLE_TIMEOUT,
+    };
+    // A zero timeout would mean blocking for good
+    let socket = &self.incoming.socket;
+    socket.set_read_timeout(Some(wait.max(Duration::from_millis(1))))?;
+    let mut buf = std::mem::take(&mut self.buf);
+    let received = socket.recv_from(&mut buf);
+    let result = received.map(|(len, from)| self.handle(&buf[..len], from));
+    self.buf = buf;
+    match result {
+      Ok(()) => Ok(()),
+      Err(e)
+        if matches!(
+          e.kind(),
+          io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+        ) =>
+      {
+        if self.last_datagram.elapsed() >= IDLE_TIMEOUT {
+          return Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("nothing arrived for {}s", IDLE_TIMEOUT.as_secs()),
+          ));
+        }
+        Ok(())
+      }
+      Err(e) => Err(e),
+    }
+  }
+
+  /// Act on one datagram from `from`
+  fn handle(&mut self, bytes: &[u8], from: SocketAddr) {
+    // A new handshake means a new stream, from a restarted streamer or another one
+    if is_handshake(bytes) {
+      self.incoming.next.set(Some(Start {
+        handshake: bytes.to_vec(),
+        peer: from,
+      }));
+      self.ended = true;
+      return;
+    }
+    if from != self.incoming.peer {
+      return;
+    }
+    self.last_datagram = Instant::now();
+    let datagram = match protocol::parse_datagram(bytes, self.checksummed, self.fec) {
+      Ok(datagram) => datagram,
+      Err(e) => {
+        warn!("⚠️ Skipping a datagram: {}", e);
+        return;
+      }
+    };
+    // A damaged slice counts as lost, leaving the rest of its frame usable
+    if self.slice_checksums
+      && datagram
+        .checksum
+        .is_some_and(|checksum| checksum != crc32fast::hash(datagram.payload))
+    {
+      self.corrupt_slices += 1;
+      return;
+    }
+    if datagram.is_end_of_stream() {
+      self.ended = true;
+    } else if datagram.is_repeat() {
+      // Frames still held are newer than the one repeated, so it isn't shown again
+      if self.jitter.is_empty() {
+        let (width, height) = (datagram.width as u32, datagram.height as u32);
+        let repeat = FrameInfo::repeat(
+          width,
+          height,
+          datagram.frame_id as u64,
+          datagram.timestamp_ms,
+        );
+        self.ready.push_back(Assembled::whole(repeat, Vec::new()));
+      }
+    } else {
+      self.add_slice(&datagram);
+    }
+  }
+
+  /// Place one slice in its frame, handing the frame to the jitter buffer once it
+  /// is whole
+  fn add_slice(&mut self, datagram: &Datagram) {
+    let id = datagram.frame_id;
+    // Frames behind what already went out can't be shown any more
+    let next = self.jitter.next().unwrap_or(0);
+    self.partial.retain(|&held, _| held as u64 >= next);
+    let (index, count, total) = (
+      datagram.chunk_index as usize,
+      datagram.chunk_count as usize,
+      datagram.total_size as usize,
+    );
+    let group = datagram.fec_group as usize;
+    let len = datagram.payload.len();
+    // Every slice but the last is as long as this one, and the last ends the frame.
+    // Parity slices come after those, one per run of `group`.
+    let offset = match index.checked_sub(count) {
+      Some(run) => (run < fec::parity_count(count, group)).then_some(0),
+      None if index + 1 == count => total.checked_sub(len),
+      None => Some(index * len),
+    };
+    let Some(offset) = offset.filter(|&offset| offset + len <= total) else {
+      warn!("⚠️ Skipping a slice that doesn't fit frame {}", id);
+      return;
+    };
+    // Parity for a frame that's whole already, or hasn't begun, is no use
+    if datagram.is_parity() && !self.partial.contains_key(&id) {
+      return;
+    }
+
+    if !self.partial.contains_key(&id) && self.partial.len() >= MAX_PARTIAL {
+      self.partial.pop_first();
+    }
+    let partial = self.partial.entry(id).or_insert_with(|| Partial {
+      data: vec![0; total],
+      received: vec![false; count],
+      missing: count,
+      slice_len: 0,
+      width: datagram.width,
+      height: datagram.height,
+      timestamp_ms: datagram.timestamp_ms,
+      last_slice: Instant::now(),
+      group,
+      parity: vec![None; fec::parity_count(count, group)],
+    });
+    if partial.data.len() != total || partial.received.len() != count || partial.group != group {
+      warn!(
+        "⚠️ Slices of frame {} disagree on its size, dropping it",
+        id
+      );
+      self.partial.remove(&id);
+      return;
+    }
+    let run = match index.checked_sub(count) {
+      Some(run) if partial.parity[run].is_none() => {
+        partial.parity[run] = Some(datagram.payload.to_vec());
+        run
+      }
+      None if !partial.received[index] => {
+        partial.place(index, offset, datagram.payload);
+        index / group.max(1)
+      }
+      _ => return,
+    };
+    partial.last_slice = Instant::now();
+    if group > 0 && partial.recover(run) {
+      self.recovered_slices += 1;
+    }
+    if partial.missing > 0 {
+      return;
+    }
+
+    let partial = self.partial.remove(&id).unwrap();
+    let mut info = self.info(id, &partial);
+    // Checked slice by slice already from version 18
+    if !self.slice_checksums {
+      info.checksum = datagram.checksum;
+    }
+    let frame = Assembled::whole(info, partial.data);
+    self.jitter.push(id as u64, frame, Instant::now());
+  }
+
+  fn info(&self, id: u32, partial: &Partial) -> FrameInfo {
+    let (width, height) = (partial.width as u32, partial.height as u32);
+    FrameInfo {
+      width,
+      height,
+      seq: id as u64,
+      timestamp_ms: partial.timestamp_ms,
+      // Compressed frames decode to this on UDP, whatever their payload size
+      raw_size: width * height * self.bytes_per_pixel,
+      checksum: None,
+      nonce: None,
+    }
+  }
+
+  /// What arrived of a frame whose other slices are given up on
+  fn incomplete(&mut self, id: u32, partial: Partial) -> Assembled {
+    self.partial_frames += 1;
+    let total = partial.data.len();
+    let missing = (partial.received.iter().enumerate())
+      .filter(|(_, &received)| !received)
+      .map(|(index, _)| index * partial.slice_len..((index + 1) * partial.slice_len).min(total))
+      .collect();
+    Assembled {
+      info: self.info(id, &partial),
+      data: partial.data,
+      missing,
+    }
+  }
+
+  /// Hand the frames still missing slices that `given_up` picks to the jitter
+  /// buffer as they are, to go out in order with the whole ones
+  fn expire(&mut self, given_up: impl Fn(&Partial) -> bool) {
+    if !self.keep_partial {
+      return;
+    }
+    let ids: Vec<_> = (self.partial.iter())
+      .filter(|(_, partial)| given_up(partial))
+      .map(|(&id, _)| id)
+      .collect();
+    for id in ids {
+      let partial = self.partial.remove(&id).unwrap();
+      let frame = self.incomplete(id, partial);
+      self.jitter.push(id as u64, frame, Instant::now());
+    }
+  }
+
+  /// Stop waiting for the slices of frames before `seq`, which is about to go
+  /// out, handing over what arrived of them where that can be used
+  fn give_up_before(&mut self, seq: u64) {
+    let later = self.partial.split_off(&(seq as u32));
+    let given_up = std::mem::replace(&mut self.partial, later);
+    if !self.keep_partial {
+      return;
+    }
+    for (id, partial) in given_up {
+      let frame = self.incomplete(id, partial);
+      self.queue(frame);
+    }
+  }
+
+  /// Make a frame the next to be read, behind a size change marker if its size
+  /// differs from the frame before
+  fn queue(&mut self, frame: Assembled) {
+    let info = &frame.info;
+    if (info.width, info.height) != self.size {
+      self.size = (info.width, info.height);
+      let marker = FrameInfo::size_change(info.width, info.height, info.seq, info.raw_size);
+      self.ready.push_back(Assembled::whole(marker, Vec::new()));
+    }
+    self.ready.push_back(frame);
+  }
+}
+
+fn is_handshake(bytes: &[u8]) -> bool {
+  bytes.starts_with(&MAGIC)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// A frame of `slices` awaiting its slices, protected in runs of `group`
+  fn partial(slices: &[&[u8]], group: usize) -> Partial {
+    let total = slices.iter().map(|slice| slice.len()).sum();
+    Partial {
+      data: vec![0; total],
+      received: vec![false; slices.len()],
+      missing: slices.len(),
+      slice_len: 0,
+      width: 1,
+      height: 1,
+      timestamp_ms: 0,
+      last_slice: Instant::now(),
+      group,
+      parity: vec![None; fec::parity_count(slices.len(), group)],
+    }
+  }
+
+  #[test]
+  fn rebuilds_a_lost_slice_from_parity() {
+    let data: Vec<u8> = (0..50).collect();
+    let slices: Vec<&[u8]> = data.chunks(20).collect();
+    for lost in 0..3 {
+      let mut frame = partial(&slices, 2);
+      frame.parity[0] = Some(fec::xor([slices[0], slices[1]], 20));
+      frame.parity[1] = Some(slices[2].to_vec());
+      for (index, slice) in slices
+        .iter()
+        .enumerate()
+        .filter(|&(index, _)| index != lost)
+      {
+        frame.place(index, index * 20, slice);
+      }
+      assert!(frame.recover(lost / 2));
+      assert_eq!((frame.missing, &frame.data), (0, &data));
+    }
+
+    // Two lost in one run can't be told apart
+    let mut frame = partial(&slices, 2);
+    frame.parity[0] = Some(fec::xor([slices[0], slices[1]], 20));
+    frame.place(2, 40, slices[2]);
+    assert!(!frame.recover(0));
+
+    // Parity claiming more than a slice's length places the others past the end
+    let mut frame = partial(&slices, 3);
+    frame.parity[0] = Some(vec![0; 30]);
+    frame.place(1, 20, slices[1]);
+    frame.place(2, 40, slices[2]);
+    assert!(!frame.recover(0));
+  }
+}
@@ -0,0 +1,275 @@
+use std::net::SocketAddr;
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, sleep};
+use std::time::{Duration, Instant};
+
+use log::{error, info};
+
+use crate::events::{EventKind, Events};
+use crate::net::{self, Connection, Listener};
+use crate::protocol::{Capabilities, FrameInfo, Handshake};
+use crate::variants::Ladder;
+
+// Frames buffered per client before that client starts dropping. Kept small so a
+// lagging viewer sees fresh frames once it catches up rather than a backlog.
+const CLIENT_QUEUE_DEPTH: usize = 2;
+
+// How long shutdown waits for clients to drain and receive the end-of-stream marker
+const FINISH_TIMEOUT: Duration = Duration::from_secs(1);
+
+enum Packet {
+  Frame(FrameInfo, Arc<Vec<u8>>),
+  /// A size change marker for the current size, after the capturer was rebuilt
+  Restart(FrameInfo),
+  End(u64),
+}
+
+struct Client {
+  peer: SocketAddr,
+  /// The variant of the stream it gets, 0 being the main one
+  variant: usize,
+  tx: SyncSender<Packet>,
+  done: Receiver<()>,
+}
+
+/// Fans each captured frame out to every connected receiver in listen mode.
+/// Each client gets its own writer thread and bounded queue, so one slow
+/// receiver drops its own frames instead of stalling everyone else. Every client
+/// is greeted with the size the stream started at; its writer announces each size
+/// change before the first frame of that size it sends. With --variants, each
+/// client negotiates which variant of the stream it gets.
+pub struct Broadcaster {
+  clients: Arc<Mutex<Vec<Client>>>,
+  ladder: Option<Ladder>,
+}
+
+impl Broadcaster {
+  /// Take ownership of the listener and keep accepting receivers in the background.
+  /// `first` is a receiver that was already accepted before streaming began.
+  /// Receivers coming and going are reported to `events`. With a `ladder`, the
+  /// first receiver gets variant `first_variant` of it, and later ones pick their
+  /// own out of `offer`.
+  pub fn start(
+    listener: Listener,
+    first: (Connection, SocketAddr),
+    handshake: Handshake,
+    events: Events,
+    variants: Option<(Ladder, Capabilities, usize)>,
+  ) -> Broadcaster {
+    let clients = Arc::new(Mutex::new(Vec::new()));
+    let (conn, peer) = first;
+    let (ladder, offer, first_variant) = match variants {
+      Some((ladder, offer, index)) => (Some(ladder), offer, index),
+      None => (None, Capabilities::default(), 0),
+    };
+    let size = variant_size(ladder.as_ref(), first_variant, &handshake);
+    add_client(&clients, conn, peer, first_variant, size, events.clone());
+    note_wanted(&clients.lock().unwrap(), ladder.as_ref());
+
+    let accept_clients = clients.clone();
+    let accept_ladder = ladder.clone();
+    thread::spawn(move || loop {
+      let accepted = listener.accept(&handshake).and_then(|(mut conn, peer)| {
+        let variant = match &accept_ladder {
+          Some(ladder) => conn.negotiate_variant(&handshake, offer, ladder)?.1,
+          None => 0,
+        };
+        Ok((conn, peer, variant))
+      });
+      match accepted {
+        Ok((conn, peer, variant)) => {
+          add_client(
+            &accept_clients,
+            conn,
+            peer,
+            variant,
+            variant_size(accept_ladder.as_ref(), variant, &handshake),
+            events.clone(),
+          );
+          let clients = accept_clients.lock().unwrap();
+          note_wanted(&clients, accept_ladder.as_ref());
+          let count = clients.len();
+          drop(clients);
+          match variant {
+            0 => info!("✅ Receiver connected from {} ({} connected)", peer, count),
+            _ => info!(
+              "✅ Receiver connected from {} on variant {} ({} connected)",
+              peer, variant, count
+            ),
+          }
+          events.emit(EventKind::Connected {
+            peer: peer.to_string(),
+          });
+        }
+        Err(e) => {
+          error!("❌ Accept failed: {}", e);
+          events.error(format!("Accept failed: {}", e));
+        }
+      }
+    });
+
+    Broadcaster { clients, ladder }
+  }
+
+  pub fn client_count(&self) -> usize {
+    self.clients.lock().unwrap().len()
+  }
+
+  /// Queue a packet for every client without blocking. Clients whose queue is full
+  /// skip this one; clients whose writer has exited are removed.
+  /// Returns how many clients had to drop it.
+  pub fn send(&self, info: FrameInfo, data: Arc<Vec<u8>>) -> usize {
+    self.send_to(None, info, data).1
+  }
+
+  /// `send` a frame of `variant` to just the clients on it, returning how many
+  /// those were and how many of them had to drop it
+  pub fn send_frame(&self, variant: usize, info: FrameInfo, data: Arc<Vec<u8>>) -> (usize, usize) {
+    self.send_to(Some(variant), info, data)
+  }
+
+  fn send_to(&self, variant: Option<usize>, info: FrameInfo, data: Arc<Vec<u8>>) -> (usize, usize) {
+    let (mut sent, mut lagging) = (0, 0);
+    let mut clients = self.clients.lock().unwrap();
+    let count = clients.len();
+    clients.retain(|client| {
+      if variant.is_some_and(|variant| variant != client.variant) {
+        return true;
+      }
+      sent += 1;
+      match client.tx.try_send(Packet::Frame(info, data.clone())) {
+        Ok(()) => true,
+        Err(TrySendError::Full(_)) => {
+          lagging += 1;
+          true
+        }
+        Err(TrySendError::Disconnected(_)) => {
+          info!("👋 Receiver {} disconnected", client.peer);
+          sent -= 1;
+          false
+        }
+      }
+    });
+    if clients.len() < count {
+      note_wanted(&clients, self.ladder.as_ref());
+    }
+    (sent, lagging)
+  }
+
+  /// Tell every client the capturer was rebuilt, with `marker` from
+  /// `FrameInfo::size_change` for the size the stream already has. A client whose
+  /// queue is full misses it, but not the keyframe that follows. Variants are all
+  /// keyframes, so their clients aren't told.
+  pub fn restart(&self, marker: FrameInfo) {
+    for client in self.clients.lock().unwrap().iter() {
+      if client.variant != 0 {
+        continue;
+      }
+      let _ = client.tx.try_send(Packet::Restart(marker));
+    }
+  }
+
+  /// Send the end-of-stream marker to every client and give them a moment to flush
+  /// it. Bounded so a stalled receiver can't hold up shutdown.
+  pub fn finish(&self, seq: u64) {
+    let clients = std::mem::take(&mut *self.clients.lock().unwrap());
+    let deadline = Instant::now() + FINISH_TIMEOUT;
+
+    for client in &clients {
+      let mut packet = Packet::End(seq);
+      while let Err(TrySendError::Full(p)) = client.tx.try_send(packet) {
+        if Instant::now() >= deadline {
+          break;
+        }
+        packet = p;
+        sleep(Duration::from_millis(5));
+      }
+    }
+
+    for client in &clients {
+      let remaining = deadline.saturating_duration_since(Instant::now());
+      let _ = client.done.recv_timeout(remaining);
+    }
+  }
+}
+
+/// The frame size the handshake for `variant` names
+fn variant_size(ladder: Option<&Ladder>, variant: usize, handshake: &Handshake) -> [u32; 2] {
+  let handshake = match ladder {
+    Some(ladder) => ladder.handshake(variant, handshake),
+    None => *handshake,
+  };
+  [handshake.width, handshake.height]
+}
+
+/// Tell the capture thread which variants have clients to encode for
+fn note_wanted(clients: &[Client], ladder: Option<&Ladder>) {
+  if let Some(ladder) = ladder {
+    ladder.set_wanted(clients.iter().map(|client| client.variant));
+  }
+}
+
+/// Start a writer thread for a receiver of `variant` whose handshake said frames
+/// are `size`
+fn add_client(
+  clients: &Arc<Mutex<Vec<Client>>>,
+  mut conn: Connection,
+  peer: SocketAddr,
+  variant: usize,
+  mut size: [u32; 2],
+  events: Events,
+) {
+  let (tx, rx) = mpsc::sync_channel::<Packet>(CLIENT_QUEUE_DEPTH);
+  let (done_tx, done) = mpsc::channel();
+  thread::spawn(move || {
+    for packet in rx {
+      let result = match packet {
+        // Audio packets have no size and repeats no payload, so only frames count
+        Packet::Frame(info, data)
+          if !data.is_empty() && info.width != 0 && [info.width, info.height] != size =>
+        {
+          size = [info.width, info.height];
+          let marker = FrameInfo::size_change(info.width, info.height, info.seq, info.raw_size);
+          conn
+            .send_size_change(&marker)
+            .and_then(|()| conn.send_frame(&info, &data))
+        }
+        // A repeat names the main stream's size, which a variant's may not be
+        Packet::Frame(mut info, data) if data.is_empty() && info.width != 0 => {
+          [info.width, info.height] = size;
+          conn.send_frame(&info, &data)
+        }
+        Packet::Frame(info, data) => conn.send_frame(&info, &data),
+        Packet::Restart(marker) => {
+          size = [marker.width, marker.height];
+          conn.send_size_change(&marker)
+        }
+        Packet::End(seq) => {
+          let _ = conn.send_end_of_stream(seq);
+          break;
+        }
+      };
+      if let Err(e) = result {
+        error!(
+          "❌ Connection to {} lost ({}): {:?}",
+          peer,
+          net::disconnect_reason(&e),
+          e
+        );
+        events.emit(EventKind::Disconnected {
+          peer: peer.to_string(),
+          reason: net::disconnect_reason(&e).to_string(),
+        });
+        break;
+      }
+    }
+    let _ = done_tx.send(());
+  });
+  clients.lock().unwrap().push(Client {
+    peer,
+    variant,
+    tx,
+    done,
+  });
+}
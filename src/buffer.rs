@@ -0,0 +1,93 @@
+use std::collections::VecDeque;
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+/// Latest-frame-wins hand-off between the capture thread and the sender. When the
+/// sender falls behind, pushing discards the oldest buffered frame so what
+/// eventually goes out is as fresh as possible; `push_wait` waits for room instead.
+pub struct FrameBuffer<T> {
+  items: Mutex<VecDeque<T>>,
+  ready: Condvar,
+  /// Signalled when an item is taken, for `push_wait`
+  space: Condvar,
+  capacity: usize,
+}
+
+impl<T> FrameBuffer<T> {
+  pub fn new(capacity: usize) -> Self {
+    FrameBuffer {
+      items: Mutex::new(VecDeque::with_capacity(capacity)),
+      ready: Condvar::new(),
+      space: Condvar::new(),
+      capacity: capacity.max(1),
+    }
+  }
+
+  /// Queue an item, returning the oldest one if it had to be dropped to make room
+  pub fn push(&self, item: T) -> Option<T> {
+    let mut items = self.items.lock().unwrap();
+    let dropped = if items.len() >= self.capacity {
+      items.pop_front()
+    } else {
+      None
+    };
+    items.push_back(item);
+    self.ready.notify_one();
+    dropped
+  }
+
+  /// Queue an item once there's room, waiting up to `timeout` for the sender to
+  /// take one. Hands the item back if the buffer is still full.
+  pub fn push_wait(&self, item: T, timeout: Duration) -> Result<(), T> {
+    let items = self.items.lock().unwrap();
+    let (mut items, _) = self
+      .space
+      .wait_timeout_while(items, timeout, |items| items.len() >= self.capacity)
+      .unwrap();
+    if items.len() >= self.capacity {
+      return Err(item);
+    }
+    items.push_back(item);
+    self.ready.notify_one();
+    Ok(())
+  }
+
+  /// Frames currently waiting; staying at capacity means the sender can't keep up
+  pub fn depth(&self) -> usize {
+    self.items.lock().unwrap().len()
+  }
+
+  pub fn capacity(&self) -> usize {
+    self.capacity
+  }
+
+  /// Wait up to `timeout` for the next item
+  pub fn pop_timeout(&self, timeout: Duration) -> Option<T> {
+    let items = self.items.lock().unwrap();
+    let (mut items, _) = self
+      .ready
+      .wait_timeout_while(items, timeout, |items| items.is_empty())
+      .unwrap();
+    let item = items.pop_front();
+    if item.is_some() {
+      self.space.notify_one();
+    }
+    item
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn push_wait_holds_items_until_there_is_room() {
+    let buffer = FrameBuffer::new(1);
+    assert_eq!(buffer.push_wait(1, Duration::ZERO), Ok(()));
+    assert_eq!(buffer.push_wait(2, Duration::from_millis(10)), Err(2));
+    assert_eq!(buffer.pop_timeout(Duration::ZERO), Some(1));
+    assert_eq!(buffer.push_wait(2, Duration::ZERO), Ok(()));
+    // Plain pushes still make room by dropping the oldest
+    assert_eq!(buffer.push(3), Some(2));
+  }
+}
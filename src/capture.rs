@@ -0,0 +1,954 @@
+// The capture thread: it takes frames from the capturer or the test pattern, then
+// converts, processes and encodes them while the sender is still sending the one
+// before, so conversion and network I/O overlap on separate cores. Frames are
+// handed over through a FrameBuffer and their buffers come back through
+// `recycle`; each Vec has one owner at a time, moved between the two threads, so
+// neither stage ever sees the other's half-written pixels and steady-state
+// streaming converts into the same few allocations over and over.
+
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, sleep};
+use std::time::{Duration, Instant};
+
+use log::{debug, error, info, warn};
+use scap::capturer::{Capturer, Options};
+use scap::frame::{BGRAFrame, Frame, FrameType};
+
+use crate::affinity;
+use crate::buffer::FrameBuffer;
+use crate::convert;
+use crate::encode::FrameEncoder;
+use crate::follow::Follow;
+use crate::idle::{Fingerprint, IdleRate};
+use crate::net::Backoff;
+use crate::overlay::{CursorLayer, Overlay};
+use crate::pacing::{Pacing, Schedule};
+use crate::pattern;
+use crate::profile::{Laps, Profile, Stage};
+use crate::protocol::{Codec, PixelFormat};
+use crate::replay::Recording;
+use crate::resize::{self, Resize};
+use crate::targets;
+use crate::variants::{Ladder, VariantFrame};
+use crate::watermark::Watermark;
+
+// Spare frame buffers kept for reuse. One per buffered frame plus the ones being
+// encoded and sent is enough; anything beyond that is freed.
+const SPARE_BUFFERS: usize = 4;
+
+// How long shutdown waits for the capture thread to notice the stop request
+const STOP_TIMEOUT: Duration = Duration::from_secs(1);
+
+// How often a paused capture thread checks whether to resume or stop
+const PAUSE_POLL: Duration = Duration::from_millis(50);
+
+// Capturer restarts tried in a row, each after a longer pause, before giving up
+const MAX_RESTARTS: u32 = 5;
+
+// At most one frame error is logged per this interval; the rest are counted
+const ERROR_LOG_INTERVAL: Duration = Duration::from_secs(5);
+
+/// What the capture thread waits for before capturing: see `CaptureThread::start`
+type Start = (FrameEncoder, Option<usize>, Option<Arc<Profile>>);
+
+/// Where the capture thread gets its frames
+pub enum Source {
+  Screen(Options),
+  /// Synthetic frames of this size, from pattern.rs
+  TestPattern([u32; 2]),
+  /// A --loop-file recording, from replay.rs
+  Recording(Recording),
+}
+
+/// Frames asked of the capturer with --capture-format. Whatever arrives is
+/// converted to BGRA for everything downstream, so only BGRA skips that step; the
+/// others are for backends where producing BGRA costs more than converting here.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CaptureFormat {
+  #[default]
+  Bgra,
+  /// BGR with an unused fourth byte
+  Bgr0,
+  Rgb,
+  /// NV12, converted with --colorspace and --range
+  Yuv,
+}
+
+impl CaptureFormat {
+  pub fn frame_type(self) -> FrameType {
+    match self {
+      CaptureFormat::Bgra => FrameType::BGRAFrame,
+      CaptureFormat::Bgr0 => FrameType::BGR0,
+      CaptureFormat::Rgb => FrameType::RGB,
+      CaptureFormat::Yuv => FrameType::YUVFrame,
+    }
+  }
+}
+
+impl FromStr for CaptureFormat {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "bgra" => Ok(CaptureFormat::Bgra),
+      "bgr0" => Ok(CaptureFormat::Bgr0),
+      "rgb" => Ok(CaptureFormat::Rgb),
+      "yuv" => Ok(CaptureFormat::Yuv),
+      _ => Err(format!(
+        "Unknown --capture-format '{}' (expected bgra, bgr0, rgb or yuv)",
+        s
+      )),
+    }
+  }
+}
+
+/// What the capture thread does to each frame between converting and encoding it,
+/// in this order
+#[derive(Default)]
+pub struct Processing {
+  /// Keep only the region around the cursor
+  pub follow: Option<Follow>,
+  /// Resample what's kept to another size
+  pub resize: Option<Resize>,
+  /// Mark the cursor
+  pub overlay: Option<Overlay>,
+  /// Burn text into a corner
+  pub watermark: Option<Watermark>,
+  /// Note where the cursor is, for --cursor-layer
+  pub cursor_layer: Option<CursorLayer>,
+  /// Leave frames out while the screen is still, last of all
+  pub idle: Option<IdleRate>,
+  /// Encode --variants beside the stream
+  pub variants: Option<Ladder>,
+}
+
+pub struct CapturedFrame {
+  pub data: Vec<u8>,
+  /// Size of the encoded frame, which changes when the captured target does
+  pub width: u32,
+  pub height: u32,
+  pub captured_at: Instant,
+  /// Where the cursor is in the frame, with --cursor-layer once it has been read
+  pub cursor: Option<(i32, i32)>,
+  /// Codec and pixel format it was encoded with, which --codec auto changes
+  pub format: (Codec, PixelFormat),
+  /// The first frame from a capturer rebuilt after the last one stopped, which
+  /// doesn't follow on from the frame before
+  pub restarted: bool,
+  /// The same frame as each --variants variant with a receiver
+  pub variants: Vec<VariantFrame>,
+}
+
+/// Screen capture and pixel conversion running on their own thread, so a slow send
+/// never delays the next capture and vice versa. The capturer is built on that
+/// thread too, since it isn't `Send` on every platform.
+pub struct CaptureThread {
+  pub width: u32,
+  pub height: u32,
+  pub frames: Arc<FrameBuffer<CapturedFrame>>,
+  /// Frames discarded by pacing or buffer overflow since the last `take_dropped`
+  dropped: Arc<AtomicU64>,
+  /// Frames encoded and the nanoseconds spent encoding them, for --bench
+  encoded: Arc<AtomicU64>,
+  encode_nanos: Arc<AtomicU64>,
+  stop: Arc<AtomicBool>,
+  /// Capture is stopped until this is cleared again
+  paused: Arc<AtomicBool>,
+  /// JPEG quality the capture thread encodes with, adjustable while streaming
+  quality: Arc<AtomicU8>,
+  /// Codec and pixel format the capture thread encodes with, likewise
+  encoding: Arc<Mutex<(Codec, PixelFormat)>>,
+  /// Set when capture failed for good and the thread has exited
+  failed: Arc<AtomicBool>,
+  spare: SyncSender<Vec<u8>>,
+  start: Sender<Start>,
+  stopped: Receiver<()>,
+}
+
+impl CaptureThread {
+  /// Build the capturer and report its output size; capture begins on `start`.
+  /// Frames keep that size until the target itself changes size.
+  /// Accepted frames are paced to one per `frame_time`, either by sleeping until
+  /// each is due or by dropping any that arrive early; without one every captured
+  /// frame is kept. At most `buffer_depth` encoded frames wait for the sender.
+  /// `processing` shapes and draws on frames before they are encoded. `no_drop`
+  /// makes a full buffer hold up capture rather than lose its oldest frame.
+  pub fn spawn(
+    source: Source,
+    frame_time: Option<Duration>,
+    pacing: Pacing,
+    buffer_depth: usize,
+    processing: Processing,
+    no_drop: bool,
+  ) -> Result<Self, String> {
+    let frames = Arc::new(FrameBuffer::new(buffer_depth));
+    let dropped = Arc::new(AtomicU64::new(0));
+    let encoded = Arc::new(AtomicU64::new(0));
+    let encode_nanos = Arc::new(AtomicU64::new(0));
+    let stop = Arc::new(AtomicBool::new(false));
+    let paused = Arc::new(AtomicBool::new(false));
+    let quality = Arc::new(AtomicU8::new(0));
+    let encoding = Arc::new(Mutex::new((Codec::Raw, PixelFormat::Rgba)));
+    let failed = Arc::new(AtomicBool::new(false));
+    let (start_tx, start_rx) = mpsc::channel::<Start>();
+    let (stopped_tx, stopped_rx) = mpsc::channel();
+    let (size_tx, size_rx) = mpsc::channel();
+    let (spare_tx, spare_rx) = mpsc::sync_channel(SPARE_BUFFERS);
+
+    let thread_frames = frames.clone();
+    let thread_dropped = dropped.clone();
+    let thread_encoded = encoded.clone();
+    let thread_encode_nanos = encode_nanos.clone();
+    let thread_stop = stop.clone();
+    let thread_paused = paused.clone();
+    let thread_quality = quality.clone();
+    let thread_encoding = encoding.clone();
+    let thread_spare = spare_tx.clone();
+    let thread_failed = failed.clone();
+    let Processing {
+      mut follow,
+      resize,
+      mut overlay,
+      mut watermark,
+      mut cursor_layer,
+      mut idle,
+      variants,
+    } = processing;
+    // Checked against the first frame, since backends may deliver another layout
+    let mut requested = match &source {
+      Source::Screen(options) => Some(options.output_type),
+      Source::TestPattern(_) | Source::Recording(_) => None,
+    };
+    thread::spawn(move || {
+      let mut capturer = match Producer::build(source, frame_time) {
+        Ok(capturer) => capturer,
+        Err(e) => {
+          let _ = size_tx.send(Err(e));
+          return;
+        }
+      };
+      let mut sizes = Sizes::of(capturer.frame_size(), follow.as_ref(), resize);
+      let _ = size_tx.send(Ok(sizes.output));
+
+      // Dropping the handle before `start` means streaming never began
+      let Ok((mut encoder, core, profile)) = start_rx.recv() else {
+        return;
+      };
+      if let Some(core) = core {
+        affinity::pin_current(core, "capture");
+      }
+      capturer.start_capture();
+
+      let mut schedule = frame_time.map(|frame_time| Schedule::new(Instant::now(), frame_time));
+      let mut warned_size = false;
+      let mut errors = Throttle::new();
+      let mut restarts = Backoff::new(Some(MAX_RESTARTS));
+      // Set once the capturer was rebuilt, until a frame has gone out saying so
+      let mut restarted = false;
+      while !thread_stop.load(Ordering::SeqCst) {
+        if thread_paused.load(Ordering::SeqCst) {
+          capturer.stop_capture();
+          while thread_paused.load(Ordering::SeqCst) && !thread_stop.load(Ordering::SeqCst) {
+            sleep(PAUSE_POLL);
+          }
+          if thread_stop.load(Ordering::SeqCst) {
+            let _ = stopped_tx.send(());
+            return;
+          }
+          capturer.start_capture();
+          // Frame times restart from now rather than catching up on the pause
+          schedule = frame_time.map(|frame_time| Schedule::new(Instant::now(), frame_time));
+          continue;
+        }
+
+        let mut laps = Laps::start(profile.as_deref());
+        if let (Some(schedule), Pacing::Sleep) = (&schedule, pacing) {
+          // Deadlines are absolute, so oversleeping one frame shortens the next wait
+          // rather than pushing every later frame back
+          let wait = schedule
+            .deadline()
+            .saturating_duration_since(Instant::now());
+          if !wait.is_zero() {
+            sleep(wait);
+          }
+          laps.lap(Stage::Sleep);
+        }
+
+        match capturer.next_frame(&spare_rx) {
+          Ok(frame) => {
+            let captured_at = Instant::now();
+            laps.lap(Stage::Capture);
+            restarts.reset();
+
+            let pixels = Pixels::of(frame);
+
+            // macOS reports an unchanged screen as an empty frame; there's nothing to send
+            if pixels.data.is_empty() || pixels.height <= 0 {
+              continue;
+            }
+            if let Some(requested) = requested.take() {
+              if !pixels.is(requested) {
+                warn!(
+                  "⚠️ Asked the capturer for {} frames but it delivers {}, converting those instead",
+                  layout_name(requested),
+                  pixels.name
+                );
+              }
+            }
+
+            // scap doesn't report the row pitch, but backends that pad rows (e.g. GPU
+            // surfaces with aligned pitches on Windows) hand over height * stride bytes
+            let stride = pixels.data.len() / pixels.height as usize;
+            if pixels.width <= 0 || !pixels.fits(stride) {
+              if !warned_size {
+                warn!(
+                  "⚠️ Skipping {}x{} {} frames ({} bytes) too short for their size",
+                  pixels.width,
+                  pixels.height,
+                  pixels.name,
+                  pixels.data.len(),
+                );
+                warned_size = true;
+              }
+              continue;
+            }
+
+            // The display mode changed or the window was resized: carry on at the new
+            // size, which the sender announces to receivers with the first such frame
+            let size = [pixels.width as u32, pixels.height as u32];
+            if size != sizes.frame {
+              let [old_width, old_height] = sizes.frame;
+              info!(
+                "📐 Capture size changed from {}x{} to {}x{}",
+                old_width, old_height, size[0], size[1]
+              );
+              sizes = Sizes::of(size, follow.as_ref(), resize);
+              warned_size = false;
+            }
+            let Sizes {
+              frame: [frame_width, frame_height],
+              region: [region_width, region_height],
+              output: [width, height],
+              resize,
+            } = sizes;
+
+            // After sleeping every frame is due; otherwise drop those ahead of schedule
+            if let Some(schedule) = &mut schedule {
+              if !schedule.due(captured_at) {
+                thread_dropped.fetch_add(1, Ordering::Relaxed);
+                continue;
+              }
+            }
+
+            let encode_start = Instant::now();
+            // Everything downstream takes BGRA, so other layouts are converted first
+            let (bgra, stride) = match &pixels.layout {
+              Layout::Bgra => (pixels.data, stride),
+              Layout::Packed {
+                bytes_per_pixel,
+                rgb,
+              } => {
+                let mut bgra = spare_rx.try_recv().unwrap_or_default();
+                convert::packed_to_bgra_into(
+                  &pixels.data,
+                  frame_width as usize,
+                  stride,
+                  *bytes_per_pixel,
+                  *rgb,
+                  &mut bgra,
+                );
+                (bgra, frame_width as usize * 4)
+              }
+              Layout::Nv12 {
+                chroma,
+                chroma_stride,
+              } => {
+                let mut bgra = spare_rx.try_recv().unwrap_or_default();
+                convert::nv12_to_bgra_into(
+                  &pixels.data,
+                  stride,
+                  chroma,
+                  *chroma_stride,
+                  frame_width as usize,
+                  frame_height as usize,
+                  &encoder.yuv,
+                  &mut bgra,
+                );
+                (bgra, frame_width as usize * 4)
+              }
+            };
+            laps.lap(Stage::Convert);
+
+            // Cut the region around the cursor out of the whole display
+            let mut offset = [0, 0];
+            let (bgra, stride) = match follow.as_mut() {
+              Some(follow) => {
+                let region = [region_width, region_height];
+                let [x, y] = follow.origin([frame_width, frame_height], region);
+                offset = [x, y];
+                let row = region_width as usize * 4;
+                let start = y as usize * stride + x as usize * 4;
+                let end = start + (region_height as usize - 1) * stride + row;
+                let mut region = spare_rx.try_recv().unwrap_or_default();
+                convert::pack_rows_into(&bgra[start..end], row, stride, &mut region);
+                (region, row)
+              }
+              None => (bgra, stride),
+            };
+
+            let (mut bgra, stride) = match resize {
+              Some(resize) => {
+                let mut resized = spare_rx.try_recv().unwrap_or_default();
+                resize::resize_bgra_into(
+                  &bgra,
+                  [region_width as usize, region_height as usize],
+                  stride,
+                  [width as usize, height as usize],
+                  resize.filter,
+                  &mut resized,
+                );
+                let _ = thread_spare.try_send(bgra);
+                (resized, width as usize * 4)
+              }
+              None => (bgra, stride),
+            };
+
+            if let Some(overlay) = overlay.as_mut() {
+              overlay.draw(
+                &mut bgra,
+                stride,
+                [frame_width, frame_height],
+                [offset, [region_width, region_height]],
+                [width, height],
+              );
+            }
+            if let Some(watermark) = watermark.as_mut() {
+              watermark.draw(&mut bgra, stride, [width, height]);
+            }
+            let cursor = cursor_layer.as_mut().and_then(|layer| {
+              layer.locate(
+                [frame_width, frame_height],
+                [offset, [region_width, region_height]],
+                [width, height],
+              )
+            });
+            laps.lap(Stage::Process);
+
+            // --half-rate-on-idle: a still frame before its idle frame time is due
+            // isn't worth encoding. It isn't dropped either, so it isn't counted.
+            if let Some(idle) = idle.as_mut() {
+              let was_idle = idle.idle();
+              let fingerprint = Fingerprint::of(&bgra, [width, height], stride);
+              let keep = idle.keep(fingerprint, cursor, captured_at);
+              match (was_idle, idle.idle()) {
+                (false, true) => debug!("💤 Screen still, slowing down to {:.1} fps", idle.fps()),
+                (true, false) => debug!("🏃 Screen changing again, back to full rate"),
+                _ => {}
+              }
+              if !keep {
+                let _ = thread_spare.try_send(bgra);
+                continue;
+              }
+            }
+
+            // Encode into a recycled buffer so steady-state streaming doesn't allocate
+            let mut data = spare_rx.try_recv().unwrap_or_default();
+            encoder.quality = thread_quality.load(Ordering::Relaxed);
+            (encoder.codec, encoder.pixel_format) = *thread_encoding.lock().unwrap();
+            let variants = match variants.as_ref() {
+              Some(ladder) => match ladder.encode(&bgra, [width, height], stride, &encoder) {
+                Ok(frames) => frames,
+                Err(e) => {
+                  error!("❌ Failed to encode a variant: {}", e);
+                  Vec::new()
+                }
+              },
+              None => Vec::new(),
+            };
+            if let Err(e) = encoder.encode(bgra, width, height, stride, &mut data) {
+              error!("❌ Failed to encode frame: {}", e);
+              continue;
+            }
+            laps.lap(Stage::Encode);
+            let nanos = encode_start.elapsed().as_nanos() as u64;
+            thread_encode_nanos.fetch_add(nanos, Ordering::Relaxed);
+            thread_encoded.fetch_add(1, Ordering::Relaxed);
+            let frame = CapturedFrame {
+              data,
+              width,
+              height,
+              captured_at,
+              cursor,
+              format: (encoder.codec, encoder.pixel_format),
+              restarted: std::mem::take(&mut restarted),
+              variants,
+            };
+            if no_drop {
+              // Capture waits on the sender, which waits on the socket
+              let mut frame = frame;
+              while let Err(waiting) = thread_frames.push_wait(frame, PAUSE_POLL) {
+                if thread_stop.load(Ordering::SeqCst) {
+                  break;
+                }
+                frame = waiting;
+              }
+            } else if let Some(stale) = thread_frames.push(frame) {
+              thread_dropped.fetch_add(1, Ordering::Relaxed);
+              // A dropped frame that said capture restarted leaves that to the next
+              restarted |= stale.restarted;
+              let _ = thread_spare.try_send(stale.data);
+            }
+          }
+          Err(e) => {
+            if let Some(suppressed) = errors.allow(Instant::now()) {
+              if suppressed > 0 {
+                error!("❌ Error getting frame: {} ({} more since)", e, suppressed);
+              } else {
+                error!("❌ Error getting frame: {}", e);
+              }
+            }
+            // scap only fails once its frame channel has closed, so waiting on the
+            // same capturer again would fail at once. Back off, then build a new one;
+            // if that keeps failing the display or permission is gone for good.
+            let Some(delay) = restarts.next_delay() else {
+              error!(
+                "❌ Capture failed {} times in a row, stopping",
+                restarts.attempts()
+              );
+              thread_failed.store(true, Ordering::SeqCst);
+              break;
+            };
+            let resume_at = Instant::now() + delay;
+            while Instant::now() < resume_at && !thread_stop.load(Ordering::SeqCst) {
+              sleep(PAUSE_POLL);
+            }
+            match capturer.restart() {
+              Ok(()) => {
+                info!("🔄 Restarted capture");
+                restarted = true;
+              }
+              Err(e) => warn!("⚠️ Couldn't restart capture: {}", e),
+            }
+          }
+        }
+      }
+
+      capturer.stop_capture();
+      let _ = stopped_tx.send(());
+    });
+
+    let [width, height] = size_rx
+      .recv()
+      .map_err(|_| "Capture thread exited during setup".to_string())??;
+
+    Ok(CaptureThread {
+      width,
+      height,
+      frames,
+      dropped,
+      encoded,
+      encode_nanos,
+      stop,
+      paused,
+      quality,
+      encoding,
+      failed,
+      spare: spare_tx,
+      start: start_tx,
+      stopped: stopped_rx,
+    })
+  }
+
+  /// Begin capturing, with the capture thread pinned to `core` if given and timing
+  /// its stages into `profile`. The encoder is only known once the receiver has
+  /// agreed on a pixel format, which needs the frame size reported by `spawn`.
+  pub fn start(&self, encoder: FrameEncoder, core: Option<usize>, profile: Option<Arc<Profile>>) {
+    self.quality.store(encoder.quality, Ordering::Relaxed);
+    *self.encoding.lock().unwrap() = (encoder.codec, encoder.pixel_format);
+    let _ = self.start.send((encoder, core, profile));
+  }
+
+  /// Stop or restart capturing, e.g. while nobody is receiving. Takes effect once
+  /// the capturer hands over its next frame.
+  pub fn set_paused(&self, paused: bool) {
+    self.paused.store(paused, Ordering::SeqCst);
+  }
+
+  /// Change the JPEG quality from the next frame on
+  pub fn set_quality(&self, quality: u8) {
+    self.quality.store(quality, Ordering::Relaxed);
+  }
+
+  /// Encode with `codec` into `pixel_format` from the next frame on. Frames
+  /// already encoded keep theirs, which their `format` says.
+  pub fn set_encoding(&self, codec: Codec, pixel_format: PixelFormat) {
+    *self.encoding.lock().unwrap() = (codec, pixel_format);
+  }
+
+  /// Whether capture gave up after the capturer kept failing; no more frames come
+  pub fn failed(&self) -> bool {
+    self.failed.load(Ordering::SeqCst)
+  }
+
+  /// Hand a sent frame's buffer back for the capture thread to fill again
+  pub fn recycle(&self, data: Vec<u8>) {
+    let _ = self.spare.try_send(data);
+  }
+
+  /// Number of frames dropped since the previous call
+  pub fn take_dropped(&self) -> u64 {
+    self.dropped.swap(0, Ordering::Relaxed)
+  }
+
+  /// Frames encoded since the previous call, and the time spent converting and
+  /// encoding them
+  pub fn take_encode_stats(&self) -> (u64, Duration) {
+    let nanos = self.encode_nanos.swap(0, Ordering::Relaxed);
+    (
+      self.encoded.swap(0, Ordering::Relaxed),
+      Duration::from_nanos(nanos),
+    )
+  }
+
+  /// Ask the capture thread to stop and wait briefly for it to release the capturer.
+  /// The wait is bounded because `get_next_frame` blocks until the screen changes.
+  pub fn stop(self) {
+    self.stop.store(true, Ordering::SeqCst);
+    let _ = self.stopped.recv_timeout(STOP_TIMEOUT);
+  }
+}
+
+/// The sizes a captured frame goes through on its way to the encoder
+#[derive(Clone, Copy)]
+struct Sizes {
+  /// As captured
+  frame: [u32; 2],
+  /// After cutting out the region around the cursor
+  region: [u32; 2],
+  /// After resampling, as encoded
+  output: [u32; 2],
+  /// Resampling from `region` to `output`, if they differ
+  resize: Option<Resize>,
+}
+
+impl Sizes {
+  fn of(frame: [u32; 2], follow: Option<&Follow>, resize: Option<Resize>) -> Self {
+    let region = match follow {
+      Some(follow) => follow.frame_size(frame),
+      None => frame,
+    };
+    let output = match resize {
+      Some(resize) => resize.scale.size(region),
+      None => region,
+    };
+    Sizes {
+      frame,
+      region,
+      output,
+      // Frames that already have the asked-for size go straight through
+      resize: resize.filter(|_| output != region),
+    }
+  }
+}
+
+/// The screen capturer, or the test pattern or recording standing in for it
+enum Producer {
+  /// The options are kept to build a fresh capturer if this one stops
+  Screen(Capturer, Options),
+  Pattern {
+    size: [u32; 2],
+    /// Number of the next frame drawn
+    index: u64,
+    frame_time: Option<Duration>,
+    /// Pattern frames are ready at once, so they're held to the frame rate here
+    schedule: Option<Schedule>,
+  },
+  /// Held to the frame rate the same way
+  Recording {
+    recording: Recording,
+    frame_time: Option<Duration>,
+    schedule: Option<Schedule>,
+  },
+}
+
+impl Producer {
+  fn build(source: Source, frame_time: Option<Duration>) -> Result<Self, String> {
+    Ok(match source {
+      Source::Screen(options) => Producer::Screen(build_capturer(&options)?, options),
+      Source::TestPattern(size) => Producer::Pattern {
+        size,
+        index: 0,
+        frame_time,
+        schedule: None,
+      },
+      Source::Recording(recording) => Producer::Recording {
+        recording,
+        frame_time,
+        schedule: None,
+      },
+    })
+  }
+
+  fn frame_size(&mut self) -> [u32; 2] {
+    match self {
+      Producer::Screen(capturer, _) => capturer.get_output_frame_size(),
+      Producer::Pattern { size, .. } => *size,
+      Producer::Recording { recording, .. } => recording.size(),
+    }
+  }
+
+  fn start_capture(&mut self) {
+    match self {
+      Producer::Screen(capturer, _) => capturer.start_capture(),
+      Producer::Pattern {
+        frame_time,
+        schedule,
+        ..
+      }
+      | Producer::Recording {
+        frame_time,
+        schedule,
+        ..
+      } => *schedule = frame_time.map(|frame_time| Schedule::new(Instant::now(), frame_time)),
+    }
+  }
+
+  fn stop_capture(&mut self) {
+    if let Producer::Screen(capturer, _) = self {
+      capturer.stop_capture();
+    }
+  }
+
+  /// Swap a stopped capturer for a freshly built and started one, of the target
+  /// as it's listed now
+  fn restart(&mut self) -> Result<(), String> {
+    if let Producer::Screen(capturer, options) = self {
+      capturer.stop_capture();
+      if let Some(target) = &options.target {
+        options.target = Some(
+          targets::refresh(target)
+            .ok_or_else(|| format!("the {} is gone", targets::name(target)))?,
+        );
+      }
+      *capturer = build_capturer(options)?;
+      capturer.start_capture();
+    }
+    Ok(())
+  }
+
+  /// Wait for the next frame. Pattern and recorded frames go into a buffer from
+  /// `spare`.
+  fn next_frame(&mut self, spare: &Receiver<Vec<u8>>) -> Result<Frame, String> {
+    match self {
+      Producer::Screen(capturer, _) => capturer
+        .get_next_frame()
+        .map_err(|_| "the capturer stopped delivering frames".to_string()),
+      Producer::Pattern {
+        size: [width, height],
+        index,
+        schedule,
+        ..
+      } => {
+        hold(schedule);
+        let mut data = spare.try_recv().unwrap_or_default();
+        pattern::render_into(*index, *width, *height, &mut data);
+        *index += 1;
+        Ok(Frame::BGRA(BGRAFrame {
+          display_time: 0,
+          width: *width as i32,
+          height: *height as i32,
+          data,
+        }))
+      }
+      Producer::Recording {
+        recording,
+        schedule,
+        ..
+      } => {
+        hold(schedule);
+        recording
+          .next_frame(spare.try_recv().unwrap_or_default())
+          .map_err(|e| format!("the recording can't be read: {}", e))
+      }
+    }
+  }
+}
+
+/// Sleep until the next frame time, for frames that would otherwise be ready at once
+fn hold(schedule: &mut Option<Schedule>) {
+  if let Some(schedule) = schedule {
+    sleep(
+      schedule
+        .deadline()
+        .saturating_duration_since(Instant::now()),
+    );
+    schedule.due(Instant::now());
+  }
+}
+
+fn build_capturer(options: &Options) -> Result<Capturer, String> {
+  Capturer::build(options.clone()).map_err(|e| format!("Failed to create capturer: {}", e))
+}
+
+/// Lets a repeating error through at most once per ERROR_LOG_INTERVAL, counting
+/// the repeats held back in between
+struct Throttle {
+  last: Option<Instant>,
+  suppressed: u64,
+}
+
+impl Throttle {
+  fn new() -> Self {
+    Throttle {
+      last: None,
+      suppressed: 0,
+    }
+  }
+
+  /// The number of repeats held back since the last one logged, or `None` to hold
+  /// this one back too
+  fn allow(&mut self, now: Instant) -> Option<u64> {
+    if self
+      .last
+      .is_some_and(|last| now.duration_since(last) < ERROR_LOG_INTERVAL)
+    {
+      self.suppressed += 1;
+      return None;
+    }
+    self.last = Some(now);
+    Some(std::mem::take(&mut self.suppressed))
+  }
+}
+
+/// A captured frame's pixels and how they're laid out
+struct Pixels {
+  /// Packed pixels, or the luma plane of NV12
+  data: Vec<u8>,
+  width: i32,
+  height: i32,
+  layout: Layout,
+  /// For logs
+  name: &'static str,
+}
+
+enum Layout {
+  Bgra,
+  /// Red, green and blue at the byte offsets in `rgb`, and any other bytes unused
+  Packed {
+    bytes_per_pixel: usize,
+    rgb: [usize; 3],
+  },
+  Nv12 {
+    chroma: Vec<u8>,
+    chroma_stride: usize,
+  },
+}
+
+impl Pixels {
+  fn of(frame: Frame) -> Self {
+    let packed = |name, data, width, height, bytes_per_pixel, rgb| Pixels {
+      data,
+      width,
+      height,
+      layout: Layout::Packed {
+        bytes_per_pixel,
+        rgb,
+      },
+      name,
+    };
+    match frame {
+      Frame::BGRA(f) => Pixels {
+        data: f.data,
+        width: f.width,
+        height: f.height,
+        layout: Layout::Bgra,
+        name: "BGRA",
+      },
+      Frame::BGRx(f) => packed("BGRx", f.data, f.width, f.height, 4, [2, 1, 0]),
+      Frame::BGR0(f) => packed("BGR0", f.data, f.width, f.height, 4, [2, 1, 0]),
+      Frame::RGBx(f) => packed("RGBx", f.data, f.width, f.height, 4, [0, 1, 2]),
+      Frame::XBGR(f) => packed("xBGR", f.data, f.width, f.height, 4, [3, 2, 1]),
+      Frame::RGB(f) => packed("RGB", f.data, f.width, f.height, 3, [0, 1, 2]),
+      Frame::YUVFrame(f) => Pixels {
+        data: f.luminance_bytes,
+        width: f.width,
+        height: f.height,
+        layout: Layout::Nv12 {
+          chroma: f.chrominance_bytes,
+          chroma_stride: f.chrominance_stride.max(0) as usize,
+        },
+        name: "NV12",
+      },
+    }
+  }
+
+  /// Whether these are the frames `requested` asks the capturer for
+  fn is(&self, requested: FrameType) -> bool {
+    self.name == layout_name(requested)
+      || matches!((requested, self.name), (FrameType::BGR0, "BGRx"))
+  }
+
+  /// Whether rows `stride` bytes apart hold a full row of pixels, and for NV12
+  /// whether the chroma plane covers the whole frame
+  fn fits(&self, stride: usize) -> bool {
+    let width = self.width as usize;
+    match &self.layout {
+      Layout::Bgra => stride >= width * 4,
+      Layout::Packed {
+        bytes_per_pixel, ..
+      } => stride >= width * bytes_per_pixel,
+      Layout::Nv12 {
+        chroma,
+        chroma_stride,
+      } => {
+        let rows = (self.height as usize).div_ceil(2);
+        let row = width.div_ceil(2) * 2;
+        stride >= width && *chroma_stride >= row && chroma.len() >= (rows - 1) * chroma_stride + row
+      }
+    }
+  }
+}
+
+/// How `Pixels` names frames of `frame_type`
+fn layout_name(frame_type: FrameType) -> &'static str {
+  match frame_type {
+    FrameType::BGRAFrame => "BGRA",
+    FrameType::BGR0 => "BGR0",
+    FrameType::RGB => "RGB",
+    FrameType::YUVFrame => "NV12",
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn throttle_counts_what_it_holds_back() {
+    let mut throttle = Throttle::new();
+    let start = Instant::now();
+    assert_eq!(throttle.allow(start), Some(0));
+    assert_eq!(throttle.allow(start + Duration::from_millis(1)), None);
+    assert_eq!(throttle.allow(start + Duration::from_secs(1)), None);
+    assert_eq!(throttle.allow(start + ERROR_LOG_INTERVAL), Some(2));
+    assert_eq!(throttle.allow(start + ERROR_LOG_INTERVAL * 3), Some(0));
+  }
+
+  #[test]
+  fn checks_the_capture_format_was_honoured() {
+    assert_eq!("yuv".parse(), Ok(CaptureFormat::Yuv));
+    // scap has no RGBA frames to ask for
+    assert!("rgba".parse::<CaptureFormat>().is_err());
+    let bgrx = Pixels::of(Frame::BGRx(scap::frame::BGRxFrame {
+      display_time: 0,
+      width: 1,
+      height: 1,
+      data: vec![0; 4],
+    }));
+    assert!(bgrx.is(CaptureFormat::Bgr0.frame_type()));
+    assert!(!bgrx.is(CaptureFormat::Bgra.frame_type()));
+  }
+}
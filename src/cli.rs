@@ -0,0 +1,1442 @@
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use log::LevelFilter;
+use scap::capturer::{Area, Point, Resolution, Size};
+
+use crate::capture::CaptureFormat;
+use crate::convert::{ColorRange, ColorSpace};
+use crate::net::{self, IpVersion, Transport};
+use crate::overlay::Colour;
+use crate::pacing::Pacing;
+use crate::protocol::{self, Codec, PixelFormat, Variant};
+use crate::ratelimit::OverLimit;
+use crate::resize::{Filter, Scale};
+use crate::targets::TargetSelector;
+use crate::tls::TlsConfig;
+use crate::variants::{MAX_VARIANTS, VARIANT_CODECS};
+use crate::watermark::Corner;
+
+pub const DEFAULT_HOST: &str = "127.0.0.1";
+pub const DEFAULT_PORT: u16 = 12345;
+pub const DEFAULT_BUFFER_DEPTH: usize = 2;
+pub const DEFAULT_QUALITY: u8 = 80;
+pub const DEFAULT_LEVEL: i32 = 3;
+pub const DEFAULT_KEYFRAME_INTERVAL: u32 = 60;
+pub const DEFAULT_FPS: u32 = 60;
+pub const DEFAULT_BITRATE_KBPS: u32 = 4000;
+pub const DEFAULT_IDLE_THRESHOLD: f64 = 1.0;
+pub const DEFAULT_IDLE_FPS: f64 = 1.0;
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+pub const DEFAULT_KEEPALIVE: Duration = Duration::from_secs(5);
+pub const DEFAULT_OVERLAY_RADIUS: u32 = 24;
+// Gold, covering half of what's under it
+pub const DEFAULT_OVERLAY_COLOUR: Colour = Colour {
+  red: 0xff,
+  green: 0xd7,
+  blue: 0x00,
+  alpha: 0x80,
+};
+
+// 256KB chunks. Each chunk is written as one size-prefixed block, so with TCP_NODELAY
+// on, smaller chunks mean more, smaller segments on the wire; larger chunks amortize
+// that overhead but delay the first bytes of a frame reaching the receiver. Nagle
+// would batch small writes for us, at the cost of up to one RTT of latency.
+pub const DEFAULT_CHUNK_SIZE: usize = 256 * 1024;
+
+// Keys a --config file may set, each standing in for the flag of the same name
+const CONFIG_KEYS: &[&str] = &[
+  "host",
+  "port",
+  "fps",
+  "resolution",
+  "crop",
+  "codec",
+  "quality",
+  "cursor",
+  "psk",
+];
+
+pub const USAGE: &str = "\
+Usage: screen-streamer [OPTIONS]
+
+Options:
+  --config <PATH>  Read defaults from a TOML file with any of the keys host, port,
+                   fps, resolution, crop, codec, quality, cursor (true/false) and
+                   psk; flags on the command line override them
+  --host <HOST>    Receiver address: IPv4, IPv6 (optionally [bracketed] or with a
+                   %scope for link-local) or hostname (default: 127.0.0.1)
+  --port <PORT>    Receiver port (default: 12345)
+  --ip-version <auto|4|6>
+                   Which of --host's addresses to use: all of them in the
+                   resolver's order, trying each until one connects (default),
+                   or only IPv4 or IPv6 ones
+  --transport <tcp|udp|ws|mjpeg|unix>
+                   Stream over TCP (default), lossy, lower-latency UDP datagrams,
+                   WebSocket binary messages for browser viewers, MJPEG over
+                   HTTP that any browser can show at http://HOST:PORT/ (implies
+                   --codec jpeg), or a Unix domain socket to a receiver on the
+                   same machine (Unix only); ws and mjpeg need --listen
+  --path <PATH>    With --transport unix, the receiver's socket file
+  --listen         Bind --host/--port and wait for a receiver to connect (not
+                   with UDP or a Unix socket)
+  --variants <LIST>
+                   With --listen over TCP, also encode up to 3 other variants of
+                   the stream, comma-separated as CODEC[:QUALITY][@WIDTHxHEIGHT]
+                   (e.g. jpeg:50@1280x720,zstd), and give each receiver the one
+                   closest to what its handshake asks for. CODEC is raw, zstd, jpeg
+                   or rle; frames are scaled down to fit WIDTHxHEIGHT. Only the
+                   variants some receiver is on are encoded
+  --max-retries <N>
+                   Give up after N consecutive failed reconnects (default: retry forever)
+  --no-nodelay     Leave Nagle's algorithm enabled (TCP_NODELAY is set by default)
+  --connect-timeout <SECONDS>
+                   Give up on a connection attempt after SECONDS and retry
+                   (default: 5)
+  --keepalive <SECONDS>
+                   Probe a TCP receiver after SECONDS without traffic, and every
+                   SECONDS after that, so a dead peer is noticed after a few
+                   unanswered probes (default: 5, 0 turns probing off)
+  --chunk-size <BYTES>
+                   Split TCP frames into chunks of at most BYTES (default: 262144),
+                   or send UDP datagrams of at most BYTES including their 24-byte
+                   header (default: 1400, to fit a 1500-byte MTU)
+  --fec <RATIO>    With --transport udp, add parity datagrams worth RATIO of each
+                   frame's (above 0 and at most 1, e.g. 0.25 for one per four),
+                   each letting the receiver rebuild one lost datagram of the run
+                   it covers without waiting for a resend
+  --tls            Encrypt the TCP stream. Without it frames cross the network in
+                   the clear, so anyone on the path can watch the screen
+  --ca <PATH>      With --tls, trust the receiver certificates signed by the PEM
+                   certificates in PATH (default: the public web roots); the
+                   certificate must name --host
+  --cert <PATH>    With --tls --listen, the PEM certificate chain to present
+  --key <PATH>     With --tls --listen, the PEM private key for --cert
+  --psk <KEY>      Encrypt and authenticate every payload with a key shared with
+                   the receiver, without certificates. Metadata such as frame
+                   sizes stays readable. Put it in --config to keep it out of the
+                   process list (not with UDP or mjpeg)
+  --checksum       Send a CRC-32 of every payload so the receiver can discard
+                   frames corrupted on the way (not with mjpeg)
+  --max-mbps <N>   Cap outgoing bandwidth at N megabits per second, counting every
+                   receiver in --listen mode (default: unlimited)
+  --over-limit <delay|drop>
+                   With --max-mbps, hold frames until they fit (default) or drop them
+  --buffer-depth <1|2>
+                   Captured frames held for the sender; older ones are dropped when
+                   it falls behind (default: 2, use 1 for lowest latency)
+  --max-lag-ms <MS>
+                   Drop frames that waited more than MS milliseconds between
+                   capture and sending instead of sending them late. Below a
+                   frame time (17ms at 60fps) even a short hiccup drops frames,
+                   so FPS falls before latency rises (default: send every frame
+                   the buffer holds, however late)
+  --no-drop        Never drop a frame for a slow receiver: when the buffer is full,
+                   capture waits for the TCP socket instead, trading latency for
+                   every frame arriving (not with --listen or UDP)
+  --codec <raw|jpeg|zstd|delta|h264|rle|auto>
+                   Send raw pixels (default), lossy JPEG, lossless zstd, only the
+                   64x64 tiles that changed since the previous frame, H.264 video
+                   (needs a build with the `h264` feature), or run-length encoded
+                   pixels, which shrink flat areas for little CPU. auto starts with
+                   raw and moves to zstd, JPEG and H.264 while sends overrun the
+                   frame time, and back once the link has room, reconnecting at
+                   most every 10s to switch (tcp or unix, without --listen)
+  --quality <1-100>
+                   JPEG quality (default: 80)
+  --min-quality <1-100>
+                   Adapt JPEG quality to the network: drop towards this value while
+                   sends overrun the frame time, and climb back up to --quality
+                   once they keep up (default: fixed quality)
+  --level <1-22>   zstd compression level (default: 3)
+  --keyframe-interval <N>
+                   Send a full frame every N frames with delta or h264 (default: 60)
+  --bitrate <KBPS> H.264 target bitrate in kilobits per second (default: 4000)
+  --colorspace <bt601|bt709>
+                   Matrix for YUV: frames encoded as H.264 and NV12 the OS
+                   captures. Players of the stream need the same; mismatches show
+                   as shifted colours (default: bt601)
+  --range <full|limited>
+                   Whether those YUV values span 0-255 or the 16-235 video range.
+                   Mismatches look washed out or overly dark (default: limited)
+  --capture-format <bgra|bgr0|rgb|yuv>
+                   Frames to ask the capturer for. Everything is converted to
+                   BGRA before processing, so another format only pays off where
+                   it's what the platform produces natively; a warning says if
+                   the capturer delivered something else (default: bgra)
+  --display <INDEX> Capture the display at INDEX (default: the first display)
+  --all-displays   Stream every display at once, each as its own stream on the
+                   next port up from --port in --list-targets order. Audio goes
+                   with the first display only
+  --window <TITLE> Capture the first window whose title contains TITLE
+  --exclude <TITLE>
+                   Leave out every window whose title contains TITLE; repeat to
+                   exclude several
+  --list-targets   Print a table of the displays and windows that can be captured,
+                   with their ids, sizes and the flag that picks each, and exit
+  --test-pattern <WIDTHxHEIGHT>
+                   Stream moving colour bars with a frame counter, the same for
+                   every run, instead of capturing the screen (no display or
+                   permission needed)
+  --loop-file <PATH>
+                   Stream a recording instead of the screen, from the start again
+                   at its end, paced to --fps: a Y4M file the receiver writes with
+                   --out, or raw BGRA frames, such as --out --raw writes from a
+                   stream sent with --pixel-format bgra
+  --loop-size <WIDTHxHEIGHT>
+                   With --loop-file, the size of a raw file's BGRA frames (a Y4M
+                   file's header gives its own)
+  --screenshot <PATH>
+                   Save one frame as a PNG at PATH and exit without streaming
+  --bench <SECONDS>
+                   Capture and encode for SECONDS without connecting anywhere, then
+                   print the frame rate, mean encode time and allocations per
+                   frame. Add --fps 0 to find the highest rate
+  --record <PATH>  Also write the stream to an MP4 file at PATH, finished when
+                   streaming stops. Needs --codec h264 and a build with the
+                   `record` feature; keeps recording while no receiver is connected
+  --duration <SECONDS>
+                   Stop streaming on its own SECONDS after it starts, as Enter or
+                   Ctrl-C would, e.g. for cron jobs and test scripts (default: run
+                   until stopped)
+  --frames <N>     Stop streaming on its own once N frames have gone out, e.g. so
+                   a test knows exactly what the receiver should have (with
+                   --duration, whichever comes first)
+  --resolution <native|480p|720p|1080p|1440p|2160p|4320p>
+                   Scale frames down to at most this width, keeping the aspect
+                   ratio (default: native, the target's own size in pixels; also
+                   spelled captured)
+  --scale <FACTOR> Resample frames to this fraction of their size in each
+                   direction, e.g. 0.5, whatever the display's size
+  --width <PIXELS> Resample frames to this width, keeping the aspect ratio
+  --filter <nearest|bilinear>
+                   How --scale and --width resample (default: bilinear, smoother;
+                   nearest is cheaper)
+  --fps <N>        Frames per second to capture and send (default: 60). 0 sends
+                   every frame as fast as the capturer delivers it, which can
+                   saturate a CPU core and the network
+  --pacing <sleep|drop>
+                   Hold --fps by sleeping until each frame is due (default), or
+                   by capturing continuously and dropping frames that come early
+                   (steadier frame age, more CPU)
+  --constant-fps   Keep receivers at exactly --fps: whenever no new frame is ready
+                   in time, as when the screen is still, send a tiny marker that
+                   repeats the last one (not with mjpeg)
+  --half-rate-on-idle
+                   Send fewer frames while the screen is still: the rate halves
+                   every second nothing changes, down to --idle-fps, and the
+                   first frame that changes brings back --fps at once
+  --idle-threshold <PERCENT>
+                   With --half-rate-on-idle, how much of the screen may change
+                   with it still counting as still, e.g. a blinking cursor
+                   (default: 1)
+  --idle-fps <FPS> With --half-rate-on-idle, the fewest frames a second to send
+                   while still (default: 1)
+  --pin-cores      Run the capture thread on the first CPU core and sending on the
+                   second, for steadier frame times; with --all-displays each
+                   display takes the next two. Ignored with a warning where the
+                   platform can't pin threads
+  --crop <X,Y,W,H> Capture only this region of the target, in pixels from its
+                   top-left corner (default: the whole target)
+  --follow-cursor  Keep the --crop region centred on the mouse cursor as it moves,
+                   within the display. Needs a build with the `follow-cursor`
+                   feature; tracks the cursor correctly on the primary display
+  --no-cursor      Leave the mouse cursor out of captured frames (--cursor restores
+                   the default of drawing it)
+  --highlight      Highlight mouse clicks where the platform supports it
+                   (--no-highlight is the default)
+  --cursor-overlay Draw a translucent disc under the mouse cursor into every frame,
+                   on any platform, e.g. for tutorials. Needs a build with the
+                   `follow-cursor` feature and a display target
+  --cursor-layer   Leave the cursor out of frames and send where it is beside
+                   each one, for the receiver to draw or hide; a cursor moving
+                   over a still screen then changes no pixels. Needs a display
+                   target, and --record keeps frames without the cursor. Draws
+                   it into frames as usual where its position can't be read,
+                   as in builds without the `follow-cursor` feature
+  --overlay-colour <RRGGBB[AA]>
+                   Colour of the --cursor-overlay disc, with optional alpha in hex
+                   (default: FFD70080, half-covering gold)
+  --overlay-radius <PIXELS>
+                   Radius of the --cursor-overlay disc in the frames as sent
+                   (default: 24)
+  --watermark <TEXT>
+                   Burn TEXT into a corner of every frame before it is encoded.
+                   Letters come out in capitals; characters the built-in font
+                   lacks show as '?'
+  --timestamp      Burn the current UTC time into every frame, after --watermark's
+                   text if both are given
+  --watermark-corner <top-left|top-right|bottom-left|bottom-right>
+                   Where --watermark and --timestamp go (default: bottom-right)
+  --audio          Send system audio along with the video (not over UDP, needs a build
+                   with the `audio` feature); streams video only if no audio
+                   device can be opened
+  --pixel-format, --format <auto|rgba|bgra|rgb|gray>
+                   Pixel layout of raw frames. auto (default) sends BGRA without
+                   conversion if the receiver accepts it, RGBA otherwise; rgb
+                   drops the alpha channel to save a quarter of the bandwidth,
+                   and gray sends one luminance byte per pixel
+  --grayscale      Same as --pixel-format gray: a quarter of the raw bandwidth,
+                   good for text and terminals, and works with jpeg too
+  --preview        Also show the frames being sent in a window, decoded the way
+                   the receiver decodes them, to check the crop, scale and
+                   watermark before anyone watches (needs a build with the
+                   `preview` feature). Closing it leaves streaming running
+  --stats-json     Also print the per-second stats to stdout as one JSON object
+                   per line
+  --profile        Time each stage of every frame (sleep, capture, convert,
+                   process, encode, send...) and log their mean and 99th
+                   percentile every 5 seconds, then the session's totals as
+                   folded stacks for flamegraph.pl
+  --metrics-addr <ADDR>
+                   Serve Prometheus metrics at http://ADDR/metrics, e.g.
+                   0.0.0.0:9100 (default: off)
+  -q, --quiet      Only print errors and a closing summary
+  -v, --verbose    Also print the capturer options, the negotiated handshake and
+                   how frames are split into chunks
+  -h, --help       Print this help and exit";
+
+/// Command-line options for the streamer
+#[derive(Debug, Clone)]
+pub struct Args {
+  pub host: String,
+  pub port: u16,
+  pub transport: Transport,
+  /// Which of the host's addresses to use
+  pub ip_version: IpVersion,
+  /// Socket file for --transport unix
+  pub path: Option<PathBuf>,
+  pub listen: bool,
+  /// Other variants of the stream listen-mode receivers may ask for
+  pub variants: Vec<Variant>,
+  pub max_retries: Option<u32>,
+  pub nodelay: bool,
+  pub connect_timeout: Duration,
+  /// Idle time before, and between, TCP keepalive probes; `None` disables them
+  pub keepalive: Option<Duration>,
+  /// TCP chunk or UDP datagram size, defaulted for the transport
+  pub chunk_size: usize,
+  /// UDP data slices per parity slice, from --fec
+  pub fec: Option<u16>,
+  pub tls: bool,
+  /// PEM roots for verifying the receiver; `None` uses the public web roots
+  pub ca: Option<PathBuf>,
+  /// PEM certificate chain and key served in listen mode
+  pub cert: Option<PathBuf>,
+  pub key: Option<PathBuf>,
+  /// Pre-shared key payloads are encrypted with
+  pub psk: Option<String>,
+  /// Carry a CRC-32 with every payload
+  pub checksum: bool,
+  /// Egress cap in megabits per second
+  pub max_mbps: Option<f64>,
+  pub over_limit: OverLimit,
+  pub buffer_depth: usize,
+  /// Oldest a frame may be when its turn to send comes
+  pub max_lag: Option<Duration>,
+  /// Hold up capture instead of dropping frames the sender hasn't taken
+  pub no_drop: bool,
+  pub codec: Codec,
+  /// --codec auto: start with raw and switch as the link allows, `codec` being
+  /// where it starts
+  pub auto_codec: bool,
+  pub quality: u8,
+  /// Lower bound for adaptive JPEG quality; `None` keeps it fixed
+  pub min_quality: Option<u8>,
+  pub level: i32,
+  pub keyframe_interval: u32,
+  /// H.264 target bitrate in kbps
+  pub bitrate: u32,
+  pub colorspace: ColorSpace,
+  pub range: ColorRange,
+  pub capture_format: CaptureFormat,
+  /// `None` negotiates with the receiver
+  pub pixel_format: Option<PixelFormat>,
+  /// `None` captures the first display
+  pub target: Option<TargetSelector>,
+  /// Stream every display, one port each
+  pub all_displays: bool,
+  /// Window titles to leave out of the capture
+  pub exclude: Vec<String>,
+  pub list_targets: bool,
+  /// Stream a synthetic pattern of this size instead of the screen
+  pub test_pattern: Option<[u32; 2]>,
+  /// Stream this recording over and over instead of the screen
+  pub loop_file: Option<PathBuf>,
+  /// Frame size of a raw --loop-file
+  pub loop_size: Option<[u32; 2]>,
+  /// Capture one frame to this PNG instead of streaming
+  pub screenshot: Option<PathBuf>,
+  /// Measure capture and encoding for this long instead of streaming
+  pub bench: Option<Duration>,
+  /// MP4 file to record the sent stream to
+  pub record: Option<PathBuf>,
+  /// Stop streaming after this long; `None` runs until stopped
+  pub duration: Option<Duration>,
+  /// Stop streaming after sending this many frames
+  pub frames: Option<u64>,
+  pub fps: u32,
+  pub pacing: Pacing,
+  /// Repeat the last frame whenever capture misses a frame time
+  pub constant_fps: bool,
+  /// Slow down while the screen is still
+  pub half_rate_on_idle: bool,
+  /// Percent of the screen that may change while it counts as still
+  pub idle_threshold: f64,
+  /// Fewest frames per second sent while still
+  pub idle_fps: f64,
+  /// First of the two cores this stream's capture and send threads are pinned
+  /// to, in that order; `None` leaves them to the scheduler
+  pub pin_cores: Option<usize>,
+  pub resolution: Resolution,
+  /// Software resampling applied after capture and cropping
+  pub scale: Option<Scale>,
+  pub filter: Filter,
+  /// `None` captures the whole target
+  pub crop: Option<Area>,
+  /// Move `crop` with the mouse cursor
+  pub follow_cursor: bool,
+  pub show_cursor: bool,
+  pub show_highlight: bool,
+  /// Draw a disc under the cursor on our side, unlike `show_highlight`
+  pub cursor_overlay: bool,
+  /// Send the cursor's position beside frames instead of drawing it in
+  pub cursor_layer: bool,
+  pub overlay_colour: Colour,
+  pub overlay_radius: u32,
+  /// Text burned into each frame
+  pub watermark: Option<String>,
+  /// Burn the time into each frame
+  pub timestamp: bool,
+  pub watermark_corner: Corner,
+  pub audio: bool,
+  /// Show the sent frames in a local window
+  pub preview: bool,
+  pub stats_json: bool,
+  /// Time each stage of the pipeline
+  pub profile: bool,
+  /// Where to serve Prometheus metrics; `None` turns the endpoint off
+  pub metrics_addr: Option<SocketAddr>,
+  /// Lowest level logged, from --quiet or --verbose; RUST_LOG overrides it
+  pub log_level: LevelFilter,
+}
+
+impl Default for Args {
+  fn default() -> Self {
+    Args {
+      host: DEFAULT_HOST.to_string(),
+      port: DEFAULT_PORT,
+      transport: Transport::Tcp,
+      ip_version: IpVersion::Auto,
+      path: None,
+      listen: false,
+      variants: Vec::new(),
+      max_retries: None,
+      nodelay: true,
+      connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+      keepalive: Some(DEFAULT_KEEPALIVE),
+      chunk_size: DEFAULT_CHUNK_SIZE,
+      fec: None,
+      tls: false,
+      ca: None,
+      cert: None,
+      key: None,
+      psk: None,
+      checksum: false,
+      max_mbps: None,
+      over_limit: OverLimit::Delay,
+      buffer_depth: DEFAULT_BUFFER_DEPTH,
+      max_lag: None,
+      no_drop: false,
+      codec: Codec::Raw,
+      auto_codec: false,
+      quality: DEFAULT_QUALITY,
+      min_quality: None,
+      level: DEFAULT_LEVEL,
+      keyframe_interval: DEFAULT_KEYFRAME_INTERVAL,
+      bitrate: DEFAULT_BITRATE_KBPS,
+      colorspace: ColorSpace::default(),
+      capture_format: CaptureFormat::default(),
+      range: ColorRange::default(),
+      pixel_format: None,
+      target: None,
+      all_displays: false,
+      exclude: Vec::new(),
+      list_targets: false,
+      test_pattern: None,
+      loop_file: None,
+      loop_size: None,
+      screenshot: None,
+      bench: None,
+      record: None,
+      duration: None,
+      frames: None,
+      fps: DEFAULT_FPS,
+      pacing: Pacing::Sleep,
+      constant_fps: false,
+      half_rate_on_idle: false,
+      idle_threshold: DEFAULT_IDLE_THRESHOLD,
+      idle_fps: DEFAULT_IDLE_FPS,
+      pin_cores: None,
+      resolution: Resolution::Captured,
+      scale: None,
+      filter: Filter::Bilinear,
+      crop: None,
+      follow_cursor: false,
+      show_cursor: true,
+      show_highlight: false,
+      cursor_overlay: false,
+      cursor_layer: false,
+      overlay_colour: DEFAULT_OVERLAY_COLOUR,
+      overlay_radius: DEFAULT_OVERLAY_RADIUS,
+      watermark: None,
+      timestamp: false,
+      watermark_corner: Corner::BottomRight,
+      audio: false,
+      preview: false,
+      stats_json: false,
+      profile: false,
+      metrics_addr: None,
+      log_level: LevelFilter::Info,
+    }
+  }
+}
+
+impl Args {
+  /// Parse the process arguments. Prints usage and exits on `--help`.
+  pub fn parse() -> Result<Args, String> {
+    Self::parse_from(std::env::args().skip(1))
+  }
+
+  pub fn parse_from<I: IntoIterator<Item = String>>(args: I) -> Result<Args, String> {
+    let args: Vec<String> = args.into_iter().collect();
+    // Settings from --config go first, so the same flags given later win
+    let mut all = match config_path(&args) {
+      Some(path) => read_config(Path::new(&path))?,
+      None => Vec::new(),
+    };
+    all.extend(args);
+
+    let mut parsed = Args::default();
+    let mut chunk_size = None;
+    let mut fec_ratio = None;
+    let mut idle_set = false;
+    let mut overlay_styled = false;
+    let mut args = all.into_iter();
+
+    while let Some(arg) = args.next() {
+      // Accept both `--flag value` and `--flag=value`
+      let (flag, inline) = match arg.split_once('=') {
+        Some((flag, value)) if flag.starts_with("--") => {
+          (flag.to_string(), Some(value.to_string()))
+        }
+        _ => (arg, None),
+      };
+      let mut value = || {
+        inline
+          .clone()
+          .or_else(|| args.next())
+          .ok_or(format!("Missing value for {}", flag))
+      };
+
+      match flag.as_str() {
+        // Already read before parsing started
+        "--config" => {
+          value()?;
+        }
+        "--host" => parsed.host = value()?,
+        "--port" => parsed.port = parse_num(&flag, &value()?)?,
+        "--transport" => parsed.transport = value()?.parse()?,
+        "--ip-version" => parsed.ip_version = value()?.parse()?,
+        "--path" => parsed.path = Some(PathBuf::from(value()?)),
+        "--listen" => parsed.listen = true,
+        "--variants" => {
+          parsed.variants = value()?
+            .split(',')
+            .map(str::parse)
+            .collect::<Result<_, _>>()?
+        }
+        "--max-retries" => parsed.max_retries = Some(parse_num(&flag, &value()?)?),
+        "--no-nodelay" => parsed.nodelay = false,
+        "--connect-timeout" => {
+          parsed.connect_timeout = parse_seconds(&flag, &value()?)?;
+          if parsed.connect_timeout.is_zero() {
+            return Err("--connect-timeout must be a positive number of seconds".to_string());
+          }
+        }
+        "--keepalive" => {
+          parsed.keepalive = Some(parse_seconds(&flag, &value()?)?).filter(|d| !d.is_zero())
+        }
+        "--chunk-size" => chunk_size = Some(parse_num(&flag, &value()?)?),
+        "--fec" => fec_ratio = Some(parse_num::<f64>(&flag, &value()?)?),
+        "--tls" => parsed.tls = true,
+        "--ca" => parsed.ca = Some(value()?.into()),
+        "--cert" => parsed.cert = Some(value()?.into()),
+        "--key" => parsed.key = Some(value()?.into()),
+        "--psk" => parsed.psk = Some(value()?),
+        "--checksum" => parsed.checksum = true,
+        "--max-mbps" => parsed.max_mbps = Some(parse_num(&flag, &value()?)?),
+        "--over-limit" => parsed.over_limit = value()?.parse()?,
+        "--buffer-depth" => parsed.buffer_depth = parse_num(&flag, &value()?)?,
+        "--max-lag-ms" => {
+          parsed.max_lag = Some(Duration::from_millis(parse_num(&flag, &value()?)?))
+        }
+        "--no-drop" => parsed.no_drop = true,
+        "--codec" => match value()?.as_str() {
+          "auto" => (parsed.codec, parsed.auto_codec) = (Codec::Raw, true),
+          codec => (parsed.codec, parsed.auto_codec) = (codec.parse()?, false),
+        },
+        "--quality" => parsed.quality = parse_num(&flag, &value()?)?,
+        "--min-quality" => parsed.min_quality = Some(parse_num(&flag, &value()?)?),
+        "--level" => parsed.level = parse_num(&flag, &value()?)?,
+        "--keyframe-interval" => parsed.keyframe_interval = parse_num(&flag, &value()?)?,
+        "--bitrate" => parsed.bitrate = parse_num(&flag, &value()?)?,
+        "--colorspace" => parsed.colorspace = value()?.parse()?,
+        "--range" => parsed.range = value()?.parse()?,
+        "--capture-format" => parsed.capture_format = value()?.parse()?,
+        "--pixel-format" | "--format" => {
+          parsed.pixel_format = match value()?.as_str() {
+            "auto" => None,
+            format => Some(format.parse()?),
+          }
+        }
+        "--display" | "--window" if parsed.target.is_some() => {
+          return Err("Only one of --display or --window may be given".to_string());
+        }
+        "--display" => parsed.target = Some(TargetSelector::Display(parse_num(&flag, &value()?)?)),
+        "--window" => parsed.target = Some(TargetSelector::Window(value()?)),
+        "--all-displays" => parsed.all_displays = true,
+        "--exclude" => parsed.exclude.push(value()?),
+        "--list-targets" => parsed.list_targets = true,
+        "--test-pattern" => parsed.test_pattern = Some(parse_size(&flag, &value()?)?),
+        "--loop-file" => parsed.loop_file = Some(value()?.into()),
+        "--loop-size" => parsed.loop_size = Some(parse_size(&flag, &value()?)?),
+        "--screenshot" => parsed.screenshot = Some(value()?.into()),
+        "--bench" => {
+          let duration = parse_seconds(&flag, &value()?)?;
+          if duration.is_zero() {
+            return Err("--bench must be a positive number of seconds".to_string());
+          }
+          parsed.bench = Some(duration);
+        }
+        "--record" => parsed.record = Some(value()?.into()),
+        "--frames" => {
+          let frames = parse_num(&flag, &value()?)?;
+          if frames == 0 {
+            return Err("--frames must be at least 1".to_string());
+          }
+          parsed.frames = Some(frames);
+        }
+        "--duration" => {
+          let duration = parse_seconds(&flag, &value()?)?;
+          if duration.is_zero() {
+            return Err("--duration must be a positive number of seconds".to_string());
+          }
+          parsed.duration = Some(duration);
+        }
+        "--fps" => parsed.fps = parse_num(&flag, &value()?)?,
+        "--pacing" => parsed.pacing = value()?.parse()?,
+        "--constant-fps" => parsed.constant_fps = true,
+        "--half-rate-on-idle" => parsed.half_rate_on_idle = true,
+        "--idle-threshold" => {
+          parsed.idle_threshold = parse_num(&flag, &value()?)?;
+          idle_set = true;
+        }
+        "--idle-fps" => {
+          parsed.idle_fps = parse_num(&flag, &value()?)?;
+          idle_set = true;
+        }
+        "--pin-cores" => parsed.pin_cores = Some(0),
+        "--resolution" => parsed.resolution = parse_resolution(&value()?)?,
+        "--scale" | "--width" => {
+          if parsed.scale.is_some() {
+            return Err("--scale and --width both set the size; use one".to_string());
+          }
+          parsed.scale = Some(if flag == "--scale" {
+            let factor: f64 = parse_num(&flag, &value()?)?;
+            if !(factor > 0.0 && factor <= 1.0) {
+              return Err("--scale must be above 0 and at most 1".to_string());
+            }
+            Scale::Factor(factor)
+          } else {
+            let width: u32 = parse_num(&flag, &value()?)?;
+            if width < 2 {
+              return Err("--width must be at least 2 pixels".to_string());
+            }
+            Scale::Width(width)
+          });
+        }
+        "--filter" => parsed.filter = value()?.parse()?,
+        "--cursor" => parsed.show_cursor = true,
+        "--no-cursor" => parsed.show_cursor = false,
+        "--highlight" => parsed.show_highlight = true,
+        "--no-highlight" => parsed.show_highlight = false,
+        "--cursor-overlay" => parsed.cursor_overlay = true,
+        "--cursor-layer" => parsed.cursor_layer = true,
+        "--overlay-colour" => {
+          parsed.overlay_colour = value()?.parse()?;
+          overlay_styled = true;
+        }
+        "--watermark" => parsed.watermark = Some(value()?),
+        "--timestamp" => parsed.timestamp = true,
+        "--watermark-corner" => parsed.watermark_corner = value()?.parse()?,
+        "--overlay-radius" => {
+          parsed.overlay_radius = parse_num(&flag, &value()?)?;
+          overlay_styled = true;
+        }
+        "--audio" => parsed.audio = true,
+        "--grayscale" => parsed.pixel_format = Some(PixelFormat::Gray),
+        "--crop" => parsed.crop = Some(parse_crop(&value()?)?),
+        "--follow-cursor" => parsed.follow_cursor = true,
+        "--preview" if cfg!(feature = "preview") => parsed.preview = true,
+        "--preview" => return Err("--preview needs a build with `--features preview`".to_string()),
+        "--stats-json" => parsed.stats_json = true,
+        "--profile" => parsed.profile = true,
+        "--metrics-addr" => parsed.metrics_addr = Some(parse_num(&flag, &value()?)?),
+        "-q" | "--quiet" => parsed.log_level = LevelFilter::Error,
+        "-v" | "--verbose" => parsed.log_level = LevelFilter::Debug,
+        "-h" | "--help" => {
+          println!("{}", USAGE);
+          std::process::exit(0);
+        }
+        _ => return Err(format!("Unknown argument: {}", flag)),
+      }
+    }
+
+    if parsed.all_displays {
+      let conflict = [
+        ("--display or --window", parsed.target.is_some()),
+        ("--screenshot", parsed.screenshot.is_some()),
+        ("--bench", parsed.bench.is_some()),
+        ("--record", parsed.record.is_some()),
+        ("--metrics-addr", parsed.metrics_addr.is_some()),
+        ("--follow-cursor", parsed.follow_cursor),
+        ("--cursor-overlay", parsed.cursor_overlay),
+        ("--cursor-layer", parsed.cursor_layer),
+        ("--preview", parsed.preview),
+      ]
+      .into_iter()
+      .find(|(_, given)| *given);
+      if let Some((flag, _)) = conflict {
+        return Err(format!("--all-displays can't be combined with {}", flag));
+      }
+    }
+
+    if parsed.loop_size.is_some() && parsed.loop_file.is_none() {
+      return Err("--loop-size only applies with --loop-file".to_string());
+    }
+    let stand_in = match (&parsed.test_pattern, &parsed.loop_file) {
+      (Some(_), Some(_)) => {
+        return Err("--test-pattern and --loop-file can't be combined".to_string())
+      }
+      (Some(_), None) => Some("--test-pattern"),
+      (None, Some(_)) => Some("--loop-file"),
+      (None, None) => None,
+    };
+    // The pattern and a recording have no target, so anything that picks or
+    // shapes one is out
+    if let Some(stand_in) = stand_in {
+      let conflict = [
+        ("--display or --window", parsed.target.is_some()),
+        ("--all-displays", parsed.all_displays),
+        ("--exclude", !parsed.exclude.is_empty()),
+        ("--list-targets", parsed.list_targets),
+        ("--screenshot", parsed.screenshot.is_some()),
+        ("--crop", parsed.crop.is_some()),
+        ("--cursor-overlay", parsed.cursor_overlay),
+        ("--cursor-layer", parsed.cursor_layer),
+        (
+          "--capture-format",
+          parsed.capture_format != CaptureFormat::default(),
+        ),
+        (
+          "--resolution",
+          !matches!(parsed.resolution, Resolution::Captured),
+        ),
+      ]
+      .into_iter()
+      .find(|(_, given)| *given);
+      if let Some((flag, _)) = conflict {
+        return Err(format!("{} can't be combined with {}", stand_in, flag));
+      }
+    }
+
+    if parsed.duration.is_some() && (parsed.bench.is_some() || parsed.screenshot.is_some()) {
+      return Err("--duration only applies when streaming; --bench takes its own".to_string());
+    }
+    if parsed.frames.is_some() && (parsed.bench.is_some() || parsed.screenshot.is_some()) {
+      return Err("--frames only applies when streaming".to_string());
+    }
+    if parsed.preview && (parsed.bench.is_some() || parsed.screenshot.is_some()) {
+      return Err("--preview only applies when streaming".to_string());
+    }
+    // --bench reports its own encode times
+    if parsed.profile && (parsed.bench.is_some() || parsed.screenshot.is_some()) {
+      return Err("--profile only applies when streaming".to_string());
+    }
+
+    if parsed.listen && parsed.transport == Transport::Udp {
+      return Err("--listen isn't supported with --transport udp".to_string());
+    }
+    if parsed.path.is_some() != parsed.transport.is_unix() {
+      return Err("--path goes with --transport unix, which needs it".to_string());
+    }
+    // The receiver owns the socket file, so the streamer can only dial it
+    if parsed.transport.is_unix() && (parsed.listen || parsed.all_displays) {
+      return Err(
+        "--transport unix connects to one receiver; drop --listen and --all-displays".to_string(),
+      );
+    }
+    if parsed.tls && parsed.transport != Transport::Tcp {
+      return Err("--tls is only supported with --transport tcp".to_string());
+    }
+    if !parsed.tls && (parsed.ca.is_some() || parsed.cert.is_some() || parsed.key.is_some()) {
+      return Err("--ca, --cert and --key only apply with --tls".to_string());
+    }
+    if parsed.tls && parsed.listen && (parsed.cert.is_none() || parsed.key.is_none()) {
+      return Err("--tls --listen needs --cert and --key".to_string());
+    }
+    if parsed.listen && parsed.ca.is_some() {
+      return Err("--ca verifies the receiver, so it doesn't apply with --listen".to_string());
+    }
+    if !parsed.listen && (parsed.cert.is_some() || parsed.key.is_some()) {
+      return Err("--cert and --key are only used with --listen".to_string());
+    }
+    // Receivers ask for their variant while negotiating, which needs TCP
+    if !parsed.variants.is_empty() {
+      if !parsed.listen || parsed.transport != Transport::Tcp {
+        return Err("--variants needs --listen with --transport tcp".to_string());
+      }
+      if parsed.variants.len() > MAX_VARIANTS {
+        return Err(format!(
+          "--variants takes at most {} variants",
+          MAX_VARIANTS
+        ));
+      }
+      for variant in &parsed.variants {
+        match variant.codec {
+          Some(codec) if VARIANT_CODECS.contains(&codec) => {}
+          Some(codec) => {
+            return Err(format!(
+              "--variants can't use {}, whose frames build on earlier ones; use raw, zstd, jpeg or rle",
+              codec.name()
+            ))
+          }
+          None => return Err("every one of --variants needs a codec".to_string()),
+        }
+        if variant.quality.is_some() && variant.codec != Some(Codec::Jpeg) {
+          return Err("only JPEG variants take a quality".to_string());
+        }
+      }
+      // Cursor positions are in the main stream's pixels
+      if parsed.cursor_layer {
+        return Err("--variants and --cursor-layer can't be combined".to_string());
+      }
+    }
+
+    let browser = match parsed.transport {
+      Transport::Ws => Some("ws"),
+      Transport::Mjpeg => Some("mjpeg"),
+      _ => None,
+    };
+    if let Some(transport) = browser.filter(|_| !parsed.listen) {
+      return Err(format!(
+        "--transport {} needs --listen; browsers connect to the streamer",
+        transport
+      ));
+    }
+    // A switch renegotiates the pixel format, which needs the two-way connection
+    if parsed.auto_codec {
+      if parsed.listen || !parsed.transport.is_stream() {
+        return Err(
+          "--codec auto needs a --transport tcp or unix connection without --listen".to_string(),
+        );
+      }
+      if parsed.fps == 0 {
+        return Err("--codec auto needs a frame rate to keep up with; set --fps".to_string());
+      }
+      if parsed.pixel_format.is_some() {
+        return Err("--codec auto picks the pixel format along with the codec".to_string());
+      }
+      if parsed.bench.is_some() || parsed.screenshot.is_some() {
+        return Err("--codec auto only applies when streaming".to_string());
+      }
+    }
+    // MJPEG is nothing but JPEG frames, so that's what an unset --codec becomes
+    if parsed.transport == Transport::Mjpeg {
+      match parsed.codec {
+        Codec::Raw => parsed.codec = Codec::Jpeg,
+        Codec::Jpeg => {}
+        _ => return Err("--transport mjpeg only sends --codec jpeg".to_string()),
+      }
+    }
+
+    if parsed.checksum && parsed.transport == Transport::Mjpeg {
+      return Err("--checksum doesn't apply with --transport mjpeg".to_string());
+    }
+
+    // One parity slice per so many data slices, rounded to the nearest ratio
+    if let Some(ratio) = fec_ratio {
+      if parsed.transport != Transport::Udp {
+        return Err("--fec only applies with --transport udp".to_string());
+      }
+      if !(ratio > 0.0 && ratio <= 1.0) {
+        return Err("--fec must be above 0 and at most 1".to_string());
+      }
+      parsed.fec = Some(((1.0 / ratio).round() as u16).max(1));
+    }
+
+    // The limits come from the wire: a u32 chunk size prefix on TCP, and the header
+    // plus at least one byte in a datagram that fits the UDP length field
+    let datagram_header = protocol::datagram_header_size(parsed.checksum, parsed.fec.is_some());
+    parsed.chunk_size = match (parsed.transport, chunk_size) {
+      (transport, Some(0)) if transport.is_stream() => {
+        return Err("--chunk-size must be at least 1".to_string());
+      }
+      (transport, Some(size)) if transport.is_stream() && size > u32::MAX as usize => {
+        return Err(format!("--chunk-size must be at most {}", u32::MAX));
+      }
+      (Transport::Udp, Some(size))
+        if !(datagram_header + 1..=protocol::MAX_DATAGRAM_SIZE).contains(&size) =>
+      {
+        return Err(format!(
+          "--chunk-size must be between {} and {} with --transport udp",
+          datagram_header + 1,
+          protocol::MAX_DATAGRAM_SIZE
+        ));
+      }
+      (transport, Some(size)) if transport.is_stream() || transport == Transport::Udp => size,
+      (_, Some(_)) => {
+        return Err("--chunk-size only applies with --transport tcp, udp or unix".to_string());
+      }
+      (Transport::Udp, None) => protocol::DEFAULT_DATAGRAM_SIZE,
+      (_, None) => DEFAULT_CHUNK_SIZE,
+    };
+
+    if parsed.audio && matches!(parsed.transport, Transport::Udp | Transport::Mjpeg) {
+      return Err("--audio is only supported with --transport tcp or ws".to_string());
+    }
+    if parsed.cursor_layer && matches!(parsed.transport, Transport::Udp | Transport::Mjpeg) {
+      return Err("--cursor-layer is only supported with --transport tcp or ws".to_string());
+    }
+    if parsed.psk.is_some() && matches!(parsed.transport, Transport::Udp | Transport::Mjpeg) {
+      return Err("--psk is only supported with --transport tcp or ws".to_string());
+    }
+    if parsed.psk.as_deref() == Some("") {
+      return Err("--psk can't be empty".to_string());
+    }
+    if parsed.follow_cursor {
+      if parsed.crop.is_none() {
+        return Err("--follow-cursor needs --crop for the size of the region".to_string());
+      }
+      if matches!(parsed.target, Some(TargetSelector::Window(_))) {
+        return Err("--follow-cursor only works when capturing a display".to_string());
+      }
+      if cfg!(not(feature = "follow-cursor")) {
+        return Err("--follow-cursor needs a build with `--features follow-cursor`".to_string());
+      }
+    }
+    if parsed.cursor_layer {
+      if matches!(parsed.target, Some(TargetSelector::Window(_))) {
+        return Err("--cursor-layer only works when capturing a display".to_string());
+      }
+      if !parsed.show_cursor {
+        return Err("--cursor-layer and --no-cursor can't be combined".to_string());
+      }
+    }
+    if parsed.cursor_overlay {
+      if matches!(parsed.target, Some(TargetSelector::Window(_))) {
+        return Err("--cursor-overlay only works when capturing a display".to_string());
+      }
+      if cfg!(not(feature = "follow-cursor")) {
+        return Err("--cursor-overlay needs a build with `--features follow-cursor`".to_string());
+      }
+      if parsed.overlay_radius == 0 {
+        return Err("--overlay-radius must be at least 1".to_string());
+      }
+    } else if overlay_styled {
+      return Err(
+        "--overlay-colour and --overlay-radius only apply with --cursor-overlay".to_string(),
+      );
+    }
+    if parsed.watermark.as_deref() == Some("") {
+      return Err("--watermark can't be empty".to_string());
+    }
+    if parsed.watermark_corner != Corner::BottomRight
+      && parsed.watermark.is_none()
+      && !parsed.timestamp
+    {
+      return Err("--watermark-corner only applies with --watermark or --timestamp".to_string());
+    }
+    if cfg!(not(feature = "audio")) && parsed.audio {
+      return Err("--audio needs a build with `--features audio`".to_string());
+    }
+
+    if parsed
+      .max_mbps
+      .is_some_and(|mbps| !(mbps > 0.0 && mbps.is_finite()))
+    {
+      return Err("--max-mbps must be a positive number".to_string());
+    }
+
+    if parsed.scale.is_some() && !matches!(parsed.resolution, Resolution::Captured) {
+      return Err(
+        "--scale and --width resample the native size; leave out --resolution".to_string(),
+      );
+    }
+
+    if !(1..=2).contains(&parsed.buffer_depth) {
+      return Err("--buffer-depth must be 1 or 2".to_string());
+    }
+    // Listen mode drops frames for whichever receiver lags, and UDP loses them anyway
+    if parsed.no_drop && (parsed.listen || !parsed.transport.is_stream()) {
+      return Err(
+        "--no-drop needs a --transport tcp or unix connection without --listen".to_string(),
+      );
+    }
+    if parsed.no_drop && parsed.max_mbps.is_some() && parsed.over_limit == OverLimit::Drop {
+      return Err("--no-drop can't be combined with --over-limit drop".to_string());
+    }
+    if parsed.max_lag.is_some_and(|lag| lag.is_zero()) {
+      return Err("--max-lag-ms must be at least 1".to_string());
+    }
+    if parsed.no_drop && parsed.max_lag.is_some() {
+      return Err("--no-drop can't be combined with --max-lag-ms".to_string());
+    }
+
+    if !(1..=100).contains(&parsed.quality) {
+      return Err("--quality must be between 1 and 100".to_string());
+    }
+    if let Some(min) = parsed.min_quality {
+      if parsed.codec != Codec::Jpeg {
+        return Err("--min-quality only applies with --codec jpeg".to_string());
+      }
+      if !(1..=parsed.quality).contains(&min) {
+        return Err("--min-quality must be between 1 and --quality".to_string());
+      }
+    }
+    if !(1..=22).contains(&parsed.level) {
+      return Err("--level must be between 1 and 22".to_string());
+    }
+    if parsed.fps == 0 && parsed.min_quality.is_some() {
+      return Err("--min-quality needs a frame rate to adapt to; set --fps".to_string());
+    }
+    if parsed.fps == 0 && parsed.constant_fps {
+      return Err("--constant-fps needs a frame rate to hold; set --fps".to_string());
+    }
+    if idle_set && !parsed.half_rate_on_idle {
+      return Err(
+        "--idle-threshold and --idle-fps only apply with --half-rate-on-idle".to_string(),
+      );
+    }
+    if parsed.half_rate_on_idle {
+      if parsed.fps == 0 {
+        return Err(
+          "--half-rate-on-idle needs a frame rate to slow down from; set --fps".to_string(),
+        );
+      }
+      if !(0.0..=100.0).contains(&parsed.idle_threshold) {
+        return Err("--idle-threshold must be a percentage from 0 to 100".to_string());
+      }
+      // A rate so low its frame time won't fit a Duration is no rate at all
+      let frame_time = Duration::try_from_secs_f64(1.0 / parsed.idle_fps);
+      if frame_time.is_err() || parsed.idle_fps >= parsed.fps as f64 {
+        return Err("--idle-fps must be above 0 and below --fps".to_string());
+      }
+      if parsed.bench.is_some() || parsed.screenshot.is_some() {
+        return Err("--half-rate-on-idle only applies when streaming".to_string());
+      }
+    }
+    // MJPEG viewers just keep showing the last part, so there is nothing to repeat
+    if parsed.constant_fps && parsed.transport == Transport::Mjpeg {
+      return Err("--constant-fps doesn't apply with --transport mjpeg".to_string());
+    }
+    if parsed.fps == 0 && parsed.codec == Codec::H264 {
+      return Err("--codec h264 needs a frame rate; set --fps".to_string());
+    }
+    if cfg!(not(feature = "h264")) && parsed.codec == Codec::H264 {
+      return Err("--codec h264 needs a build with `--features h264`".to_string());
+    }
+    if parsed.codec == Codec::H264
+      && matches!(
+        parsed.pixel_format,
+        Some(PixelFormat::Gray | PixelFormat::Rgb)
+      )
+    {
+      return Err("--codec h264 always sends RGBA".to_string());
+    }
+    if parsed.codec == Codec::Rle
+      && matches!(
+        parsed.pixel_format,
+        Some(PixelFormat::Gray | PixelFormat::Rgb)
+      )
+    {
+      return Err("--codec rle only sends 4-byte RGBA or BGRA pixels".to_string());
+    }
+    if parsed.record.is_some() && parsed.codec != Codec::H264 {
+      return Err("--record needs --codec h264".to_string());
+    }
+    if cfg!(not(feature = "record")) && parsed.record.is_some() {
+      return Err("--record needs a build with `--features record`".to_string());
+    }
+    if parsed.bitrate == 0 {
+      return Err("--bitrate must be at least 1".to_string());
+    }
+    if parsed.keyframe_interval == 0 {
+      return Err("--keyframe-interval must be at least 1".to_string());
+    }
+
+    Ok(parsed)
+  }
+
+  /// Resolve `--host`/`--port` into the socket addresses --ip-version allows,
+  /// accepting IP literals or DNS names
+  pub fn server_addrs(&self) -> Result<Vec<SocketAddr>, String> {
+    net::resolve(&self.host, self.port, self.ip_version)
+  }
+
+  /// Load the certificates --tls needs: our own in listen mode, otherwise the
+  /// roots the receiver's certificate is checked against
+  pub fn tls_config(&self) -> Result<Option<TlsConfig>, String> {
+    if !self.tls {
+      return Ok(None);
+    }
+    match (&self.cert, &self.key) {
+      (Some(cert), Some(key)) => TlsConfig::server(cert, key).map(Some),
+      _ => TlsConfig::client(&self.host, self.ca.as_deref()).map(Some),
+    }
+  }
+}
+
+/// The value of the first --config flag, in either `--config PATH` or
+/// `--config=PATH` form
+fn config_path(args: &[String]) -> Option<String> {
+  args.iter().enumerate().find_map(|(i, arg)| {
+    if arg == "--config" {
+      args.get(i + 1).cloned()
+    } else {
+      arg.strip_prefix("--config=").map(str::to_string)
+    }
+  })
+}
+
+fn read_config(path: &Path) -> Result<Vec<String>, String> {
+  let text = std::fs::read_to_string(path)
+    .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+  config_args(&text).map_err(|e| format!("Invalid config {}: {}", path.display(), e))
+}
+
+/// Translate a TOML config into the flags it stands for
+fn config_args(text: &str) -> Result<Vec<String>, String> {
+  let table = toml::from_str::<toml::Table>(text).map_err(|e| e.to_string())?;
+  let mut args = Vec::new();
+  for (key, value) in &table {
+    if !CONFIG_KEYS.contains(&key.as_str()) {
+      return Err(format!("Unknown key '{}'", key));
+    }
+    let value = match (key.as_str(), value) {
+      ("cursor", toml::Value::Boolean(true)) => {
+        args.push("--cursor".to_string());
+        continue;
+      }
+      ("cursor", toml::Value::Boolean(false)) => {
+        args.push("--no-cursor".to_string());
+        continue;
+      }
+      // crop can be written like the flag or as an array of four numbers
+      ("crop", toml::Value::Array(parts)) => parts
+        .iter()
+        .map(|part| part.to_string())
+        .collect::<Vec<_>>()
+        .join(","),
+      ("cursor", _) | (_, toml::Value::Boolean(_) | toml::Value::Array(_)) => {
+        return Err(format!("Key '{}' can't be a {}", key, value.type_str()));
+      }
+      (_, toml::Value::String(value)) => value.clone(),
+      (_, value) => value.to_string(),
+    };
+    args.push(format!("--{}={}", key, value));
+  }
+  Ok(args)
+}
+
+fn parse_resolution(value: &str) -> Result<Resolution, String> {
+  match value {
+    "native" | "captured" => Ok(Resolution::Captured),
+    "480p" => Ok(Resolution::_480p),
+    "720p" => Ok(Resolution::_720p),
+    "1080p" => Ok(Resolution::_1080p),
+    "1440p" => Ok(Resolution::_1440p),
+    "2160p" => Ok(Resolution::_2160p),
+    "4320p" => Ok(Resolution::_4320p),
+    other => Err(format!("Unknown resolution: {} (see --help)", other)),
+  }
+}
+
+/// `WIDTHxHEIGHT`, both at least 1
+fn parse_size(flag: &str, value: &str) -> Result<[u32; 2], String> {
+  let invalid = || {
+    format!(
+      "Invalid value for {}: '{}' (expected e.g. 1280x720)",
+      flag, value
+    )
+  };
+  let (width, height) = value.split_once('x').ok_or_else(invalid)?;
+  match (width.parse(), height.parse()) {
+    (Ok(width @ 1..), Ok(height @ 1..)) => Ok([width, height]),
+    _ => Err(invalid()),
+  }
+}
+
+fn parse_crop(value: &str) -> Result<Area, String> {
+  let parts = value
+    .split(',')
+    .map(|part| parse_num::<u32>("--crop", part.trim()))
+    .collect::<Result<Vec<_>, _>>()?;
+  let [x, y, width, height] = parts[..] else {
+    return Err(format!("--crop expects X,Y,W,H, got '{}'", value));
+  };
+  if width == 0 || height == 0 {
+    return Err("--crop width and height must be at least 1".to_string());
+  }
+  Ok(Area {
+    origin: Point {
+      x: x as f64,
+      y: y as f64,
+    },
+    size: Size {
+      width: width as f64,
+      height: height as f64,
+    },
+  })
+}
+
+fn parse_num<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+  value
+    .parse()
+    .map_err(|_| format!("Invalid value for {}: '{}'", flag, value))
+}
+
+/// A duration given in (possibly fractional) seconds, which may be zero
+fn parse_seconds(flag: &str, value: &str) -> Result<Duration, String> {
+  let seconds: f64 = parse_num(flag, value)?;
+  Duration::try_from_secs_f64(seconds)
+    .map_err(|_| format!("{} must be a number of seconds, not '{}'", flag, value))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn parse(args: &[&str]) -> Result<Args, String> {
+    Args::parse_from(args.iter().map(|arg| arg.to_string()))
+  }
+
+  #[test]
+  fn config_keys_become_flags() {
+    let args =
+      config_args("host = \"10.0.0.2\"\nport = 9000\ncrop = [0, 0, 640, 480]\ncursor = false\n")
+        .unwrap();
+    let parsed = Args::parse_from(args).unwrap();
+    assert_eq!(parsed.host, "10.0.0.2");
+    assert_eq!(parsed.port, 9000);
+    assert_eq!(parsed.crop.unwrap().size.width, 640.0);
+    assert!(!parsed.show_cursor);
+  }
+
+  #[test]
+  fn flags_override_the_config_file() {
+    let path = std::env::temp_dir().join("screen-streamer-config-test.toml");
+    std::fs::write(&path, "fps = 30\nquality = 50\n").unwrap();
+    let args = ["--quality", "90", "--config", path.to_str().unwrap()];
+    let parsed = Args::parse_from(args.map(String::from)).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(parsed.fps, 30);
+    assert_eq!(parsed.quality, 90);
+  }
+
+  #[test]
+  fn chunk_size_defaults_to_the_transport() {
+    assert_eq!(parse(&[]).unwrap().chunk_size, DEFAULT_CHUNK_SIZE);
+    assert_eq!(
+      parse(&["--transport", "udp"]).unwrap().chunk_size,
+      protocol::DEFAULT_DATAGRAM_SIZE
+    );
+    assert_eq!(
+      parse(&["--chunk-size", "9000", "--transport=udp"])
+        .unwrap()
+        .chunk_size,
+      9000
+    );
+    assert!(parse(&["--chunk-size", "0"]).is_err());
+    assert!(parse(&["--transport", "udp", "--chunk-size", "24"]).is_err());
+  }
+
+  #[test]
+  fn auto_codec_starts_raw_on_a_connection() {
+    let args = parse(&["--codec", "auto"]).unwrap();
+    assert!(args.auto_codec);
+    assert_eq!(args.codec, Codec::Raw);
+    // A later --codec wins, as with every flag
+    assert!(
+      !parse(&["--codec", "auto", "--codec", "jpeg"])
+        .unwrap()
+        .auto_codec
+    );
+    assert!(parse(&["--codec", "auto", "--listen"]).is_err());
+    assert!(parse(&["--codec", "auto", "--transport", "udp"]).is_err());
+    assert!(parse(&["--codec", "auto", "--pixel-format", "bgra"]).is_err());
+  }
+
+  #[test]
+  fn idle_limits_need_half_rate_on_idle() {
+    let args = parse(&["--half-rate-on-idle", "--idle-fps", "0.5"]).unwrap();
+    assert!(args.half_rate_on_idle);
+    assert_eq!(args.idle_fps, 0.5);
+    assert_eq!(args.idle_threshold, DEFAULT_IDLE_THRESHOLD);
+    assert!(parse(&["--idle-threshold", "5"]).is_err());
+    assert!(parse(&["--half-rate-on-idle", "--idle-fps", "60"]).is_err());
+    assert!(parse(&["--half-rate-on-idle", "--idle-fps", "0"]).is_err());
+    assert!(parse(&["--half-rate-on-idle", "--idle-fps", "1e-20"]).is_err());
+    assert!(parse(&["--half-rate-on-idle", "--idle-threshold", "101"]).is_err());
+    assert!(parse(&["--half-rate-on-idle", "--fps", "0"]).is_err());
+  }
+
+  #[test]
+  fn variants_need_listen_and_standalone_codecs() {
+    let args = parse(&["--listen", "--variants", "jpeg:50@1280x720,zstd"]).unwrap();
+    assert_eq!(args.variants.len(), 2);
+    assert_eq!(args.variants[1].codec, Some(Codec::Zstd));
+    assert!(parse(&["--variants", "zstd"]).is_err());
+    assert!(parse(&["--listen", "--transport", "ws", "--variants", "zstd"]).is_err());
+    assert!(parse(&["--listen", "--variants", "delta"]).is_err());
+    assert!(parse(&["--listen", "--variants", "@640x360"]).is_err());
+    assert!(parse(&["--listen", "--variants", "zstd:50"]).is_err());
+    assert!(parse(&["--listen", "--variants", "raw,raw,raw,raw"]).is_err());
+  }
+
+  #[test]
+  fn fec_ratio_becomes_a_run_length() {
+    assert_eq!(parse(&["--transport", "udp"]).unwrap().fec, None);
+    assert_eq!(
+      parse(&["--transport", "udp", "--fec", "0.25"]).unwrap().fec,
+      Some(4)
+    );
+    assert_eq!(
+      parse(&["--transport", "udp", "--fec", "0.3"]).unwrap().fec,
+      Some(3)
+    );
+    assert_eq!(
+      parse(&["--transport", "udp", "--fec", "1"]).unwrap().fec,
+      Some(1)
+    );
+    assert!(parse(&["--transport", "udp", "--fec", "0"]).is_err());
+    assert!(parse(&["--transport", "udp", "--fec", "1.5"]).is_err());
+    assert!(parse(&["--fec", "0.25"]).is_err());
+    // The fec_group field makes the header 26 bytes
+    assert!(parse(&["--transport", "udp", "--fec", "0.5", "--chunk-size", "26"]).is_err());
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn unix_transport_needs_a_path() {
+    let parsed = parse(&["--transport", "unix", "--path", "/tmp/stream.sock"]).unwrap();
+    assert!(parsed.transport.is_stream());
+    assert_eq!(parsed.chunk_size, DEFAULT_CHUNK_SIZE);
+    assert!(parse(&["--transport", "unix"]).is_err());
+    assert!(parse(&["--path", "/tmp/stream.sock"]).is_err());
+    assert!(parse(&["--transport", "unix", "--path", "/tmp/s.sock", "--listen"]).is_err());
+  }
+
+  #[test]
+  fn keepalive_zero_turns_probing_off() {
+    let defaults = parse(&[]).unwrap();
+    assert_eq!(defaults.connect_timeout, DEFAULT_CONNECT_TIMEOUT);
+    assert_eq!(defaults.keepalive, Some(DEFAULT_KEEPALIVE));
+    let parsed = parse(&["--keepalive", "0", "--connect-timeout", "1.5"]).unwrap();
+    assert_eq!(parsed.keepalive, None);
+    assert_eq!(parsed.connect_timeout, Duration::from_millis(1500));
+    assert!(parse(&["--connect-timeout", "0"]).is_err());
+    assert!(parse(&["--keepalive", "-1"]).is_err());
+  }
+
+  #[test]
+  fn loop_file_stands_in_for_the_screen() {
+    let args = parse(&["--loop-file", "frames.raw", "--loop-size", "640x360"]).unwrap();
+    assert_eq!(args.loop_file, Some(PathBuf::from("frames.raw")));
+    assert_eq!(args.loop_size, Some([640, 360]));
+    assert!(parse(&["--loop-size", "640x360"]).is_err());
+    assert!(parse(&["--loop-file", "a.y4m", "--test-pattern", "640x360"]).is_err());
+    assert!(parse(&["--loop-file", "a.y4m", "--display", "1"]).is_err());
+  }
+
+  #[test]
+  fn test_pattern_takes_a_size() {
+    let parsed = parse(&["--test-pattern", "640x360"]).unwrap();
+    assert_eq!(parsed.test_pattern, Some([640, 360]));
+    assert!(parse(&["--test-pattern", "640"]).is_err());
+    assert!(parse(&["--test-pattern", "0x360"]).is_err());
+    assert!(parse(&["--test-pattern", "640x360", "--display", "1"]).is_err());
+  }
+
+  #[test]
+  fn quiet_and_verbose_set_the_log_level() {
+    assert_eq!(parse(&[]).unwrap().log_level, LevelFilter::Info);
+    assert_eq!(parse(&["-q"]).unwrap().log_level, LevelFilter::Error);
+    // The last one given wins
+    assert_eq!(
+      parse(&["--quiet", "--verbose"]).unwrap().log_level,
+      LevelFilter::Debug
+    );
+  }
+
+  #[test]
+  fn scale_and_width_pick_one_output_size() {
+    let parsed = parse(&["--scale", "0.5", "--filter", "nearest"]).unwrap();
+    assert_eq!(parsed.scale, Some(Scale::Factor(0.5)));
+    assert_eq!(parsed.filter, Filter::Nearest);
+    assert_eq!(
+      parse(&["--width", "960"]).unwrap().scale,
+      Some(Scale::Width(960))
+    );
+    assert!(parse(&["--scale", "2"]).is_err());
+    assert!(parse(&["--scale", "0"]).is_err());
+    assert!(parse(&["--scale", "0.5", "--width", "960"]).is_err());
+    assert!(parse(&["--width", "960", "--resolution", "720p"]).is_err());
+  }
+
+  #[test]
+  fn duration_stops_streaming_only() {
+    assert_eq!(
+      parse(&["--duration", "2.5"]).unwrap().duration,
+      Some(Duration::from_millis(2500))
+    );
+    assert!(parse(&["--duration", "0"]).is_err());
+    assert!(parse(&["--duration", "5", "--bench", "5"]).is_err());
+    assert_eq!(parse(&["--frames", "30"]).unwrap().frames, Some(30));
+    assert!(parse(&["--frames", "0"]).is_err());
+    assert!(parse(&["--frames", "30", "--bench", "5"]).is_err());
+    assert!(parse(&["--profile"]).unwrap().profile);
+    assert!(parse(&["--profile", "--screenshot", "shot.png"]).is_err());
+  }
+
+  #[test]
+  fn max_lag_is_whole_milliseconds() {
+    assert_eq!(parse(&[]).unwrap().max_lag, None);
+    assert_eq!(
+      parse(&["--max-lag-ms", "8"]).unwrap().max_lag,
+      Some(Duration::from_millis(8))
+    );
+    assert!(parse(&["--max-lag-ms", "0"]).is_err());
+    assert!(parse(&["--max-lag-ms", "2.5"]).is_err());
+    assert!(parse(&["--max-lag-ms", "8", "--no-drop"]).is_err());
+  }
+
+  #[test]
+  fn config_rejects_unknown_keys_and_bad_toml() {
+    assert!(config_args("colour = \"blue\"").is_err());
+    assert!(config_args("port = ").is_err());
+    assert!(config_args("quality = true").is_err());
+  }
+}
@@ -0,0 +1,56 @@
+use std::io::{self, BufRead};
+use std::thread;
+
+/// What the user asked for on stdin, one command per line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+  /// Stop sending frames but keep receivers connected
+  Pause,
+  Resume,
+  Quit,
+}
+
+impl Command {
+  /// `p` pauses, `r` resumes, and `q` or an empty line quits. Anything else is
+  /// ignored.
+  pub fn parse(line: &str) -> Option<Command> {
+    match line.trim() {
+      "p" => Some(Command::Pause),
+      "r" => Some(Command::Resume),
+      "" | "q" => Some(Command::Quit),
+      _ => None,
+    }
+  }
+}
+
+/// Read commands from stdin on their own thread and hand each one to `send`.
+/// Closing stdin counts as quitting, as does failing to read it.
+pub fn spawn(send: impl Fn(Command) + Send + 'static) {
+  thread::spawn(move || {
+    for line in io::stdin().lock().lines() {
+      let Ok(line) = line else {
+        break;
+      };
+      match Command::parse(&line) {
+        Some(Command::Quit) => break,
+        Some(command) => send(command),
+        None => {}
+      }
+    }
+    send(Command::Quit);
+  });
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parses_commands() {
+    assert_eq!(Command::parse("p\n"), Some(Command::Pause));
+    assert_eq!(Command::parse(" r "), Some(Command::Resume));
+    assert_eq!(Command::parse(""), Some(Command::Quit));
+    assert_eq!(Command::parse("q"), Some(Command::Quit));
+    assert_eq!(Command::parse("x"), None);
+  }
+}
@@ -0,0 +1,846 @@
+use std::str::FromStr;
+
+/// Swap the red and blue channels of a BGRA buffer into `out`, using SSSE3/AVX2
+/// shuffles when the CPU supports them. `out` is cleared first and its allocation
+/// reused once it has grown to frame size. Output is identical to
+/// `bgra_to_rgba_scalar`.
+pub fn bgra_to_rgba_into(bgra: &[u8], out: &mut Vec<u8>) {
+  out.clear();
+  if !bgra.len().is_multiple_of(4) {
+    out.extend(bgra_to_rgba_scalar(bgra));
+    return;
+  }
+  out.resize(bgra.len(), 0);
+  swap_into(bgra, out);
+}
+
+/// `bgra_to_rgba_into` for frames whose rows are `stride` bytes apart. Only the
+/// first `width` pixels of each row are converted, so row padding is dropped.
+pub fn bgra_to_rgba_strided_into(bgra: &[u8], width: usize, stride: usize, out: &mut Vec<u8>) {
+  let row = width * 4;
+  if stride == row {
+    return bgra_to_rgba_into(bgra, out);
+  }
+  out.clear();
+  out.resize(row_count(bgra, row, stride) * row, 0);
+  convert_rows(bgra, stride, out, row);
+}
+
+/// Copy the first `row` bytes of every `stride`-byte row into `out`, so padded
+/// frames come out tightly packed
+pub fn pack_rows_into(src: &[u8], row: usize, stride: usize, out: &mut Vec<u8>) {
+  out.clear();
+  out.reserve(row_count(src, row, stride) * row);
+  for line in src.chunks(stride) {
+    out.extend_from_slice(&line[..row]);
+  }
+}
+
+/// Drop the alpha channel of a BGRA frame whose rows are `stride` bytes apart,
+/// writing tightly packed RGB, 3 bytes per pixel, into `out`
+pub fn bgra_to_rgb_into(bgra: &[u8], width: usize, stride: usize, out: &mut Vec<u8>) {
+  let row = width * 4;
+  out.clear();
+  out.reserve(row_count(bgra, row, stride) * width * 3);
+  for line in bgra.chunks(stride) {
+    for px in line[..row].chunks_exact(4) {
+      out.extend_from_slice(&[px[2], px[1], px[0]]);
+    }
+  }
+}
+
+/// Convert a BGRA frame whose rows are `stride` bytes apart into one luminance byte
+/// per pixel in `out`, tightly packed. Uses the full-range BT.601 weights
+/// (0.299 R + 0.587 G + 0.114 B), so black stays 0 and white 255.
+pub fn bgra_to_gray_into(bgra: &[u8], width: usize, stride: usize, out: &mut Vec<u8>) {
+  let row = width * 4;
+  out.clear();
+  out.reserve(row_count(bgra, row, stride) * width);
+  for line in bgra.chunks(stride) {
+    out.extend(line[..row].chunks_exact(4).map(|px| {
+      let (b, g, r) = (px[0] as u32, px[1] as u32, px[2] as u32);
+      // Weights scaled by 65536; they sum to exactly 65536
+      ((19595 * r + 38470 * g + 7471 * b + 32768) >> 16) as u8
+    }));
+  }
+}
+
+/// Which matrix YUV frames use, as given to --colorspace
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorSpace {
+  /// Standard definition video, and what most encoders assume when not told
+  #[default]
+  Bt601,
+  /// HD video
+  Bt709,
+}
+
+impl FromStr for ColorSpace {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "bt601" => Ok(ColorSpace::Bt601),
+      "bt709" => Ok(ColorSpace::Bt709),
+      _ => Err(format!(
+        "Unknown --colorspace '{}' (expected bt601 or bt709)",
+        s
+      )),
+    }
+  }
+}
+
+/// Which values Y, U and V span, as given to --range
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorRange {
+  /// 0-255, as in JPEG
+  Full,
+  /// Y in 16-235 and chroma in 16-240, as video usually is
+  #[default]
+  Limited,
+}
+
+impl FromStr for ColorRange {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "full" => Ok(ColorRange::Full),
+      "limited" => Ok(ColorRange::Limited),
+      _ => Err(format!(
+        "Unknown --range '{}' (expected full or limited)",
+        s
+      )),
+    }
+  }
+}
+
+/// RGB to YUV coefficients and back for one colour space and range, in fixed point
+/// scaled by 256. The default, BT.601 limited range, is what the conversions
+/// always used before these were selectable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Yuv {
+  /// Red, green and blue weights of Y, U and V
+  y: [i32; 3],
+  u: [i32; 3],
+  v: [i32; 3],
+  /// Y of black: 16 in limited range
+  black: i32,
+  /// Back to RGB: Y's weight, V in red, U and V in green, U in blue
+  from_y: i32,
+  from_v: [i32; 2],
+  from_u: [i32; 2],
+}
+
+impl Default for Yuv {
+  fn default() -> Self {
+    Yuv::new(ColorSpace::default(), ColorRange::default())
+  }
+}
+
+impl Yuv {
+  pub fn new(space: ColorSpace, range: ColorRange) -> Self {
+    // Red and blue's share of luma; green has the rest
+    let (kr, kb) = match space {
+      ColorSpace::Bt601 => (0.299, 0.114),
+      ColorSpace::Bt709 => (0.2126, 0.0722),
+    };
+    let kg = 1.0 - kr - kb;
+    let (luma_scale, chroma_scale, black) = match range {
+      ColorRange::Full => (1.0, 1.0, 0),
+      ColorRange::Limited => (219.0 / 255.0, 224.0 / 255.0, 16),
+    };
+    let fixed = |x: f64| (x * 256.0).round() as i32;
+    // Green takes the rounding error, so white lands exactly on the top of the
+    // range and greys carry no chroma
+    let balance = |r: i32, b: i32, total: i32| [r, total - r - b, b];
+    let chroma = |r: f64, b: f64, scale: f64| balance(fixed(r * scale), fixed(b * scale), 0);
+    let u_scale = chroma_scale / (2.0 * (1.0 - kb));
+    let v_scale = chroma_scale / (2.0 * (1.0 - kr));
+    Yuv {
+      y: balance(
+        fixed(kr * luma_scale),
+        fixed(kb * luma_scale),
+        fixed(luma_scale),
+      ),
+      u: chroma(-kr, 1.0 - kb, u_scale),
+      v: chroma(1.0 - kr, -kb, v_scale),
+      black,
+      from_y: fixed(1.0 / luma_scale),
+      from_v: [
+        fixed(2.0 * (1.0 - kr) / chroma_scale),
+        fixed(2.0 * (1.0 - kr) * kr / kg / chroma_scale),
+      ],
+      from_u: [
+        fixed(2.0 * (1.0 - kb) * kb / kg / chroma_scale),
+        fixed(2.0 * (1.0 - kb) / chroma_scale),
+      ],
+    }
+  }
+
+  fn luma(&self, (r, g, b): (i32, i32, i32)) -> u8 {
+    (((self.y[0] * r + self.y[1] * g + self.y[2] * b + 128) >> 8) + self.black).clamp(0, 255) as u8
+  }
+
+  fn chroma(weights: [i32; 3], (r, g, b): (i32, i32, i32)) -> u8 {
+    (((weights[0] * r + weights[1] * g + weights[2] * b + 128) >> 8) + 128).clamp(0, 255) as u8
+  }
+
+  /// Y, U and V back to blue, green and red
+  fn bgr(&self, y: u8, u: u8, v: u8) -> [u8; 3] {
+    let c = self.from_y * (y as i32 - self.black);
+    let (d, e) = (u as i32 - 128, v as i32 - 128);
+    let clamp = |v: i32| ((v + 128) >> 8).clamp(0, 255) as u8;
+    [
+      clamp(c + self.from_u[1] * d),
+      clamp(c - self.from_u[0] * d - self.from_v[1] * e),
+      clamp(c + self.from_v[0] * e),
+    ]
+  }
+}
+
+/// Convert a BGRA frame whose rows are `stride` bytes apart into planar YUV420 in
+/// `out`: a full-size Y plane followed by U and V planes of
+/// `width.div_ceil(2) x height.div_ceil(2)`, all tightly packed, using `yuv`'s
+/// coefficients.
+pub fn bgra_to_yuv420_into(
+  bgra: &[u8],
+  width: usize,
+  height: usize,
+  stride: usize,
+  yuv: &Yuv,
+  out: &mut Vec<u8>,
+) {
+  let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+  let luma = width * height;
+  let chroma = chroma_width * chroma_height;
+  out.clear();
+  out.resize(luma + 2 * chroma, 0);
+  let (y_plane, uv) = out.split_at_mut(luma);
+  let (u_plane, v_plane) = uv.split_at_mut(chroma);
+
+  let pixel = |x: usize, y: usize| {
+    let px = &bgra[y * stride + x * 4..];
+    (px[2] as i32, px[1] as i32, px[0] as i32)
+  };
+  for y in 0..height {
+    for x in 0..width {
+      y_plane[y * width + x] = yuv.luma(pixel(x, y));
+    }
+  }
+  for cy in 0..chroma_height {
+    for cx in 0..chroma_width {
+      // Chroma averages each 2x2 block; on odd sizes the last column or row
+      // stands in for its missing neighbour
+      let (mut r, mut g, mut b) = (0, 0, 0);
+      for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+        let (pr, pg, pb) = pixel((cx * 2 + dx).min(width - 1), (cy * 2 + dy).min(height - 1));
+        (r, g, b) = (r + pr, g + pg, b + pb);
+      }
+      let average = ((r + 2) / 4, (g + 2) / 4, (b + 2) / 4);
+      u_plane[cy * chroma_width + cx] = Yuv::chroma(yuv.u, average);
+      v_plane[cy * chroma_width + cx] = Yuv::chroma(yuv.v, average);
+    }
+  }
+}
+
+/// Repack a frame of `bytes_per_pixel`-byte pixels whose red, green and blue sit at
+/// the byte offsets in `rgb`, with rows `stride` bytes apart, into tightly packed
+/// BGRA in `out`. Alpha comes out opaque, since these layouts' padding bytes don't
+/// hold any.
+pub fn packed_to_bgra_into(
+  src: &[u8],
+  width: usize,
+  stride: usize,
+  bytes_per_pixel: usize,
+  rgb: [usize; 3],
+  out: &mut Vec<u8>,
+) {
+  let row = width * bytes_per_pixel;
+  out.clear();
+  out.reserve(row_count(src, row, stride) * width * 4);
+  for line in src.chunks(stride) {
+    for px in line[..row].chunks_exact(bytes_per_pixel) {
+      out.extend_from_slice(&[px[rgb[2]], px[rgb[1]], px[rgb[0]], 255]);
+    }
+  }
+}
+
+/// Convert NV12, a full-size luma plane followed by a half-size plane of
+/// interleaved U and V, into tightly packed BGRA in `out`. The inverse of
+/// `bgra_to_yuv420_into` with the same `yuv`.
+#[allow(clippy::too_many_arguments)]
+pub fn nv12_to_bgra_into(
+  luma: &[u8],
+  luma_stride: usize,
+  chroma: &[u8],
+  chroma_stride: usize,
+  width: usize,
+  height: usize,
+  yuv: &Yuv,
+  out: &mut Vec<u8>,
+) {
+  out.clear();
+  out.reserve(width * height * 4);
+  for y in 0..height {
+    let luma_row = &luma[y * luma_stride..][..width];
+    let chroma_row = &chroma[y / 2 * chroma_stride..];
+    for (x, &l) in luma_row.iter().enumerate() {
+      let [b, g, r] = yuv.bgr(l, chroma_row[x / 2 * 2], chroma_row[x / 2 * 2 + 1]);
+      out.extend_from_slice(&[b, g, r, 255]);
+    }
+  }
+}
+
+// Rows converted per rayon task: enough work to outweigh scheduling overhead while
+// still giving every core a share of a 720p frame
+#[cfg(feature = "rayon")]
+const ROWS_PER_TASK: usize = 16;
+
+/// Same as `bgra_to_rgba_strided_into`, but splits the frame into bands of whole
+/// rows and converts them on the rayon thread pool
+#[cfg(feature = "rayon")]
+pub fn bgra_to_rgba_par_into(bgra: &[u8], width: usize, stride: usize, out: &mut Vec<u8>) {
+  use rayon::prelude::*;
+
+  // A frame that fits in one band gains nothing from the pool
+  let row = width * 4;
+  if row == 0 || bgra.len() <= stride * ROWS_PER_TASK {
+    return bgra_to_rgba_strided_into(bgra, width, stride, out);
+  }
+  out.clear();
+  out.resize(row_count(bgra, row, stride) * row, 0);
+  out
+    .par_chunks_mut(row * ROWS_PER_TASK)
+    .zip(bgra.par_chunks(stride * ROWS_PER_TASK))
+    .for_each(|(dst, src)| convert_rows(src, stride, dst, row));
+}
+
+// The last row may stop right after its pixels, without trailing padding
+fn row_count(src: &[u8], row: usize, stride: usize) -> usize {
+  (src.len() + stride - row) / stride
+}
+
+fn convert_rows(src: &[u8], stride: usize, dst: &mut [u8], row: usize) {
+  if stride == row {
+    return swap_into(src, dst);
+  }
+  for (src, dst) in src.chunks(stride).zip(dst.chunks_exact_mut(row)) {
+    swap_into(&src[..row], dst);
+  }
+}
+
+pub fn bgra_to_rgba_scalar(bgra: &[u8]) -> Vec<u8> {
+  let mut rgba = Vec::with_capacity(bgra.len());
+  for chunk in bgra.chunks(4) {
+    rgba.push(chunk[2]); // R
+    rgba.push(chunk[1]); // G
+    rgba.push(chunk[0]); // B
+    rgba.push(chunk[3]); // A
+  }
+  rgba
+}
+
+// Converts `src` into the equally sized `dst`, both a whole number of pixels
+fn swap_into(src: &[u8], dst: &mut [u8]) {
+  #[cfg(target_arch = "x86_64")]
+  let done = x86::swap(src, dst);
+  #[cfg(not(target_arch = "x86_64"))]
+  let done = 0;
+
+  for (src, dst) in src[done..]
+    .chunks_exact(4)
+    .zip(dst[done..].chunks_exact_mut(4))
+  {
+    dst.copy_from_slice(&[src[2], src[1], src[0], src[3]]);
+  }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+  use std::arch::x86_64::*;
+
+  // pshufb control: within every 4-byte pixel take bytes 2, 1, 0, 3
+  const SHUFFLE: [u8; 16] = [2, 1, 0, 3, 6, 5, 4, 7, 10, 9, 8, 11, 14, 13, 12, 15];
+
+  /// Convert as much as the CPU's widest shuffle allows, returning the bytes done.
+  /// The remaining tail (under 16 bytes) is left to the caller.
+  pub fn swap(src: &[u8], dst: &mut [u8]) -> usize {
+    if is_x86_feature_detected!("avx2") {
+      // SAFETY: AVX2 (and so SSSE3) support was just checked
+      unsafe { swap_avx2(src, dst) }
+    } else if is_x86_feature_detected!("ssse3") {
+      // SAFETY: SSSE3 support was just checked
+      unsafe { swap_ssse3(src, dst) }
+    } else {
+      0
+    }
+  }
+
+  /// Converts whole 16-byte blocks and returns how many bytes were written
+  #[target_feature(enable = "ssse3")]
+  unsafe fn swap_ssse3(src: &[u8], dst: &mut [u8]) -> usize {
+    let mask = _mm_loadu_si128(SHUFFLE.as_ptr() as *const __m128i);
+    let blocks = src.len() / 16;
+    for i in 0..blocks {
+      let pixels = _mm_loadu_si128(src.as_ptr().add(i * 16) as *const __m128i);
+      let swapped = _mm_shuffle_epi8(pixels, mask);
+      _mm_storeu_si128(dst.as_mut_ptr().add(i * 16) as *mut __m128i, swapped);
+    }
+    blocks * 16
+  }
+
+  /// Converts whole 32-byte blocks, then one 16-byte block if it fits
+  #[target_feature(enable = "avx2")]
+  unsafe fn swap_avx2(src: &[u8], dst: &mut [u8]) -> usize {
+    // vpshufb shuffles each 128-bit lane separately, so the same control repeats
+    let lane = _mm_loadu_si128(SHUFFLE.as_ptr() as *const __m128i);
+    let mask = _mm256_broadcastsi128_si256(lane);
+    let blocks = src.len() / 32;
+    for i in 0..blocks {
+      let pixels = _mm256_loadu_si256(src.as_ptr().add(i * 32) as *const __m256i);
+      let swapped = _mm256_shuffle_epi8(pixels, mask);
+      _mm256_storeu_si256(dst.as_mut_ptr().add(i * 32) as *mut __m256i, swapped);
+    }
+    let done = blocks * 32;
+    done + swap_ssse3(&src[done..], &mut dst[done..])
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::time::Instant;
+
+  fn bgra_to_rgba(bgra: &[u8]) -> Vec<u8> {
+    let mut rgba = Vec::new();
+    bgra_to_rgba_into(bgra, &mut rgba);
+    rgba
+  }
+
+  fn bgra_to_rgb(bgra: &[u8]) -> Vec<u8> {
+    let mut rgb = Vec::new();
+    bgra_to_rgb_into(bgra, bgra.len() / 4, bgra.len(), &mut rgb);
+    rgb
+  }
+
+  fn bgra_to_yuv420(bgra: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let mut yuv = Vec::new();
+    bgra_to_yuv420_into(bgra, width, height, width * 4, &Yuv::default(), &mut yuv);
+    yuv
+  }
+
+  fn test_frame(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 31 % 251) as u8).collect()
+  }
+
+  #[test]
+  fn swaps_red_and_blue_and_keeps_alpha() {
+    // BGRA: opaque red, half-transparent green, transparent blue, mixed
+    let bgra = [0, 0, 255, 255, 0, 255, 0, 128, 255, 0, 0, 0, 10, 20, 30, 40];
+    let rgba = [255, 0, 0, 255, 0, 255, 0, 128, 0, 0, 255, 0, 30, 20, 10, 40];
+    assert_eq!(bgra_to_rgba_scalar(&bgra), rgba);
+    // Repeated past 32 bytes so the SIMD paths run too
+    let mut out = Vec::new();
+    bgra_to_rgba_into(&bgra.repeat(5), &mut out);
+    assert_eq!(out, rgba.repeat(5));
+  }
+
+  #[test]
+  fn simd_matches_scalar() {
+    // Odd pixel counts exercise the 32-byte, 16-byte and per-pixel tails
+    for pixels in [0, 1, 3, 4, 5, 8, 9, 12, 13, 1000, 1280 * 720] {
+      let bgra = test_frame(pixels * 4);
+      assert_eq!(
+        bgra_to_rgba(&bgra),
+        bgra_to_rgba_scalar(&bgra),
+        "{pixels} pixels"
+      );
+    }
+  }
+
+  #[cfg(feature = "rayon")]
+  #[test]
+  fn parallel_matches_scalar() {
+    // Widths whose row bands don't line up with the SIMD block sizes
+    for (width, height) in [(1, 1), (7, 3), (33, 40), (1280, 720), (1366, 768)] {
+      let bgra = test_frame(width * height * 4);
+      let mut rgba = Vec::new();
+      bgra_to_rgba_par_into(&bgra, width, width * 4, &mut rgba);
+      assert_eq!(rgba, bgra_to_rgba_scalar(&bgra), "{width}x{height}");
+    }
+  }
+
+  #[test]
+  fn into_reuses_and_grows_buffer() {
+    let small = test_frame(16);
+    let large = test_frame(4096);
+
+    // Starting below the needed capacity must grow rather than truncate
+    let mut out = Vec::with_capacity(8);
+    bgra_to_rgba_into(&large, &mut out);
+    assert_eq!(out, bgra_to_rgba_scalar(&large));
+
+    // Shorter frames reuse the allocation without leaving stale bytes behind
+    let ptr = out.as_ptr();
+    bgra_to_rgba_into(&small, &mut out);
+    assert_eq!(out, bgra_to_rgba_scalar(&small));
+    assert_eq!(out.as_ptr(), ptr);
+  }
+
+  #[test]
+  fn strided_rows_drop_padding() {
+    let (width, height, stride) = (5, 3, 5 * 4 + 12);
+    let padded = test_frame(stride * height);
+    let packed: Vec<u8> = padded
+      .chunks(stride)
+      .flat_map(|row| row[..width * 4].to_vec())
+      .collect();
+
+    let mut out = Vec::new();
+    pack_rows_into(&padded, width * 4, stride, &mut out);
+    assert_eq!(out, packed);
+
+    bgra_to_rgba_strided_into(&padded, width, stride, &mut out);
+    assert_eq!(out, bgra_to_rgba_scalar(&packed));
+
+    // Some backends leave the padding off the final row
+    bgra_to_rgba_strided_into(&padded[..stride * 2 + width * 4], width, stride, &mut out);
+    assert_eq!(out, bgra_to_rgba_scalar(&packed));
+
+    #[cfg(feature = "rayon")]
+    {
+      let (width, height, stride) = (1280, 72, 1280 * 4 + 64);
+      let padded = test_frame(stride * height);
+      let mut packed = Vec::new();
+      pack_rows_into(&padded, width * 4, stride, &mut packed);
+      bgra_to_rgba_par_into(&padded, width, stride, &mut out);
+      assert_eq!(out, bgra_to_rgba_scalar(&packed));
+    }
+  }
+
+  #[test]
+  fn rgb_drops_alpha() {
+    assert_eq!(bgra_to_rgb(&[1, 2, 3, 255, 4, 5, 6, 0]), [3, 2, 1, 6, 5, 4]);
+
+    // Same channels as the RGBA conversion, minus every fourth byte
+    let bgra = test_frame(4 * 37);
+    let expected: Vec<u8> = bgra_to_rgba_scalar(&bgra)
+      .chunks(4)
+      .flat_map(|px| px[..3].to_vec())
+      .collect();
+    assert_eq!(bgra_to_rgb(&bgra), expected);
+
+    let (width, stride) = (5, 5 * 4 + 12);
+    let padded = test_frame(stride * 2 + width * 4);
+    let mut out = Vec::new();
+    bgra_to_rgb_into(&padded, width, stride, &mut out);
+    assert_eq!(out.len(), width * 3 * 3);
+    assert_eq!(
+      out[width * 3..width * 3 + 3],
+      [padded[stride + 2], padded[stride + 1], padded[stride]]
+    );
+  }
+
+  #[test]
+  fn gray_known_luminance() {
+    // BGRA: black, white, red, green, blue, mid grey
+    let bgra = [
+      0, 0, 0, 255, 255, 255, 255, 255, 0, 0, 255, 255, 0, 255, 0, 255, 255, 0, 0, 255, 128, 128,
+      128, 255,
+    ];
+    let mut gray = Vec::new();
+    bgra_to_gray_into(&bgra, 6, 6 * 4, &mut gray);
+    assert_eq!(gray, [0, 255, 76, 150, 29, 128]);
+
+    // Padding is dropped, including a missing pad on the last row
+    let padded = [&bgra[..8], &[9; 8], &bgra[8..16]].concat();
+    bgra_to_gray_into(&padded, 2, 16, &mut gray);
+    assert_eq!(gray, [0, 255, 76, 150]);
+  }
+
+  // Reference BT.601 limited-range inverse, in floating point
+  fn yuv_to_rgb(y: u8, u: u8, v: u8) -> [f64; 3] {
+    let (y, u, v) = (
+      (y as f64 - 16.0) * 255.0 / 219.0,
+      (u as f64 - 128.0) * 255.0 / 224.0,
+      (v as f64 - 128.0) * 255.0 / 224.0,
+    );
+    [
+      y + 1.402 * v,
+      y - 0.344136 * u - 0.714136 * v,
+      y + 1.772 * u,
+    ]
+  }
+
+  #[test]
+  fn yuv420_known_colors() {
+    // (B, G, R) in, (Y, U, V) out, per the BT.601 limited-range definition
+    for (bgr, yuv) in [
+      ([0, 0, 0], [16, 128, 128]),
+      ([255, 255, 255], [235, 128, 128]),
+      ([0, 0, 255], [82, 90, 240]),
+      ([0, 255, 0], [144, 54, 34]),
+      ([255, 0, 0], [41, 240, 110]),
+    ] {
+      let bgra = [bgr[0], bgr[1], bgr[2], 255].repeat(4);
+      assert_eq!(
+        bgra_to_yuv420(&bgra, 2, 2),
+        [[yuv[0]; 4].as_slice(), &yuv[1..]].concat()
+      );
+    }
+  }
+
+  #[test]
+  fn yuv420_round_trips() {
+    // Flat 2x2 blocks lose nothing to chroma subsampling, so converting back must
+    // land within rounding of the original
+    let (width, height) = (64, 32);
+    let mut bgra = vec![0u8; width * height * 4];
+    for (i, px) in bgra.chunks_exact_mut(4).enumerate() {
+      let block = (i % width / 2 + i / width / 2 * width) as u32;
+      let color = block.wrapping_mul(2654435761).to_le_bytes();
+      px.copy_from_slice(&[color[0], color[1], color[2], 255]);
+    }
+
+    let yuv = bgra_to_yuv420(&bgra, width, height);
+    let (u_plane, v_plane) = yuv[width * height..].split_at(width * height / 4);
+    for (i, px) in bgra.chunks_exact(4).enumerate() {
+      let (x, y) = (i % width, i / width);
+      let chroma = y / 2 * width / 2 + x / 2;
+      let rgb = yuv_to_rgb(yuv[i], u_plane[chroma], v_plane[chroma]);
+      for (got, want) in rgb.iter().zip([px[2], px[1], px[0]]) {
+        assert!(
+          (got - want as f64).abs() <= 3.0,
+          "pixel {x},{y}: {rgb:?} vs {px:?}"
+        );
+      }
+    }
+  }
+
+  // Y, U and V straight from a colour space's definition, in floating point
+  fn reference_yuv(space: ColorSpace, range: ColorRange, [r, g, b]: [u8; 3]) -> [f64; 3] {
+    let (kr, kb) = match space {
+      ColorSpace::Bt601 => (0.299, 0.114),
+      ColorSpace::Bt709 => (0.2126, 0.0722),
+    };
+    let (r, g, b) = (r as f64 / 255.0, g as f64 / 255.0, b as f64 / 255.0);
+    let y = kr * r + (1.0 - kr - kb) * g + kb * b;
+    let (u, v) = ((b - y) / (2.0 * (1.0 - kb)), (r - y) / (2.0 * (1.0 - kr)));
+    match range {
+      ColorRange::Full => [255.0 * y, 128.0 + 255.0 * u, 128.0 + 255.0 * v],
+      ColorRange::Limited => [16.0 + 219.0 * y, 128.0 + 224.0 * u, 128.0 + 224.0 * v],
+    }
+  }
+
+  #[test]
+  fn yuv_matrices_match_reference() {
+    // Published BT.709 limited-range values for the primaries, as (R, G, B) and
+    // (Y, U, V)
+    let bt709 = Yuv::new(ColorSpace::Bt709, ColorRange::Limited);
+    for (rgb, want) in [
+      ([255, 0, 0], [63, 102, 240]),
+      ([0, 255, 0], [173, 42, 26]),
+      ([0, 0, 255], [32, 240, 118]),
+    ] {
+      let bgra = [rgb[2], rgb[1], rgb[0], 255].repeat(4);
+      let mut yuv = Vec::new();
+      bgra_to_yuv420_into(&bgra, 2, 2, 8, &bt709, &mut yuv);
+      for (got, want) in [yuv[0], yuv[4], yuv[5]].iter().zip(want) {
+        assert!(got.abs_diff(want) <= 1, "{rgb:?}: {yuv:?} vs {want:?}");
+      }
+    }
+
+    let colours = [
+      [0, 0, 0],
+      [255, 255, 255],
+      [128, 128, 128],
+      [255, 0, 0],
+      [0, 255, 0],
+      [0, 0, 255],
+      [255, 255, 0],
+      [12, 200, 140],
+    ];
+    for space in [ColorSpace::Bt601, ColorSpace::Bt709] {
+      for range in [ColorRange::Full, ColorRange::Limited] {
+        let yuv = Yuv::new(space, range);
+        for rgb in colours {
+          let got = [
+            yuv.luma((rgb[0] as i32, rgb[1] as i32, rgb[2] as i32)),
+            Yuv::chroma(yuv.u, (rgb[0] as i32, rgb[1] as i32, rgb[2] as i32)),
+            Yuv::chroma(yuv.v, (rgb[0] as i32, rgb[1] as i32, rgb[2] as i32)),
+          ];
+          let want = reference_yuv(space, range, rgb);
+          for (got, want) in got.iter().zip(want) {
+            // Full-range chroma of pure blue or red is 255.5, clamped
+            assert!(
+              (*got as f64 - want.min(255.0)).abs() <= 1.0,
+              "{space:?} {range:?} {rgb:?}: {got:?} vs {want:?}"
+            );
+          }
+        }
+        // Black, white and greys land exactly, with no chroma
+        let (black, white) = match range {
+          ColorRange::Full => (0, 255),
+          ColorRange::Limited => (16, 235),
+        };
+        assert_eq!(yuv.luma((0, 0, 0)), black, "{space:?} {range:?}");
+        assert_eq!(yuv.luma((255, 255, 255)), white, "{space:?} {range:?}");
+        for grey in [0, 77, 255] {
+          let grey = (grey, grey, grey);
+          assert_eq!(Yuv::chroma(yuv.u, grey), 128);
+          assert_eq!(Yuv::chroma(yuv.v, grey), 128);
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn yuv_matrices_round_trip() {
+    for space in [ColorSpace::Bt601, ColorSpace::Bt709] {
+      for range in [ColorRange::Full, ColorRange::Limited] {
+        let yuv = Yuv::new(space, range);
+        for bgr in [[40, 200, 90], [250, 10, 130], [0, 0, 0], [255, 255, 255]] {
+          let rgb = (bgr[2] as i32, bgr[1] as i32, bgr[0] as i32);
+          let back = yuv.bgr(
+            yuv.luma(rgb),
+            Yuv::chroma(yuv.u, rgb),
+            Yuv::chroma(yuv.v, rgb),
+          );
+          for (got, want) in back.iter().zip(bgr) {
+            assert!(
+              got.abs_diff(want) <= 3,
+              "{space:?} {range:?}: {back:?} vs {bgr:?}"
+            );
+          }
+        }
+      }
+    }
+    // The wrong matrix is visibly off, which is what --colorspace is for
+    let rgb = (255, 0, 0);
+    let bt601 = Yuv::default();
+    let bt709 = Yuv::new(ColorSpace::Bt709, ColorRange::Limited);
+    let red = bt709.bgr(
+      bt601.luma(rgb),
+      Yuv::chroma(bt601.u, rgb),
+      Yuv::chroma(bt601.v, rgb),
+    );
+    assert!(red[1] > 20, "{red:?}");
+  }
+
+  #[test]
+  fn parses_colour_settings() {
+    assert_eq!("bt709".parse(), Ok(ColorSpace::Bt709));
+    assert_eq!("full".parse(), Ok(ColorRange::Full));
+    assert!("bt2020".parse::<ColorSpace>().is_err());
+    assert!("tv".parse::<ColorRange>().is_err());
+  }
+
+  #[test]
+  fn packed_layouts_become_bgra() {
+    // One pure red pixel, then blue, in each layout, with a padding byte per row
+    let rgbx = [255, 0, 0, 0, 0, 0, 255, 0, 9];
+    let xbgr = [0, 0, 0, 255, 0, 255, 0, 0, 9];
+    let rgb = [255, 0, 0, 0, 0, 255, 9];
+    let want = [0, 0, 255, 255, 255, 0, 0, 255];
+    for (src, bytes_per_pixel, offsets) in [
+      (&rgbx[..], 4, [0, 1, 2]),
+      (&xbgr[..], 4, [3, 2, 1]),
+      (&rgb[..], 3, [0, 1, 2]),
+    ] {
+      let mut out = Vec::new();
+      packed_to_bgra_into(src, 2, src.len(), bytes_per_pixel, offsets, &mut out);
+      assert_eq!(out, want);
+    }
+  }
+
+  #[test]
+  fn nv12_round_trips() {
+    // Two flat 2x2 blocks, so chroma subsampling loses nothing
+    let (width, height) = (4, 2);
+    let colors = [[40, 200, 90, 255], [250, 10, 130, 255]];
+    let bgra: Vec<u8> = (0..width * height)
+      .flat_map(|i| colors[i % width / 2])
+      .collect();
+
+    let yuv = bgra_to_yuv420(&bgra, width, height);
+    let (y_plane, uv) = yuv.split_at(width * height);
+    let (u_plane, v_plane) = uv.split_at(width * height / 4);
+    let nv12: Vec<u8> = u_plane
+      .iter()
+      .zip(v_plane)
+      .flat_map(|(&u, &v)| [u, v])
+      .collect();
+
+    let mut out = Vec::new();
+    let yuv = Yuv::default();
+    nv12_to_bgra_into(y_plane, width, &nv12, width, width, height, &yuv, &mut out);
+    for (got, want) in out.iter().zip(&bgra) {
+      assert!(
+        (*got as i32 - *want as i32).abs() <= 3,
+        "{out:?} vs {bgra:?}"
+      );
+    }
+  }
+
+  #[test]
+  fn yuv420_odd_sizes_and_padding() {
+    let (width, height) = (5, 3);
+    let bgra = test_frame(width * height * 4);
+    let yuv = bgra_to_yuv420(&bgra, width, height);
+    assert_eq!(yuv.len(), width * height + 2 * 3 * 2);
+
+    // Row padding is skipped
+    let stride = width * 4 + 8;
+    let mut padded = vec![0u8; stride * height];
+    for (dst, src) in padded.chunks_mut(stride).zip(bgra.chunks(width * 4)) {
+      dst[..width * 4].copy_from_slice(src);
+    }
+    let mut out = Vec::new();
+    bgra_to_yuv420_into(&padded, width, height, stride, &Yuv::default(), &mut out);
+    assert_eq!(out, yuv);
+  }
+
+  // Run with `cargo test --release -- --ignored --nocapture`
+  #[test]
+  #[ignore]
+  fn bench_bgra_to_rgba() {
+    let bgra = test_frame(1280 * 720 * 4);
+    let runs = 200;
+
+    let start = Instant::now();
+    for _ in 0..runs {
+      std::hint::black_box(bgra_to_rgba_scalar(std::hint::black_box(&bgra)));
+    }
+    let scalar = start.elapsed() / runs;
+
+    let start = Instant::now();
+    for _ in 0..runs {
+      std::hint::black_box(bgra_to_rgba(std::hint::black_box(&bgra)));
+    }
+    let simd = start.elapsed() / runs;
+
+    println!(
+      "720p frame: scalar {:?}, simd {:?} ({:.1}x)",
+      scalar,
+      simd,
+      scalar.as_secs_f64() / simd.as_secs_f64()
+    );
+
+    #[cfg(feature = "rayon")]
+    {
+      let mut out = Vec::new();
+      let start = Instant::now();
+      for _ in 0..runs {
+        bgra_to_rgba_par_into(std::hint::black_box(&bgra), 1280, 1280 * 4, &mut out);
+        std::hint::black_box(&out);
+      }
+      let parallel = start.elapsed() / runs;
+      println!(
+        "720p frame: rayon {:?} ({:.1}x scalar)",
+        parallel,
+        scalar.as_secs_f64() / parallel.as_secs_f64()
+      );
+    }
+  }
+}
@@ -0,0 +1,111 @@
+// Payload encryption with a pre-shared key, used with --psk:
+//
+// Both ends derive a 256-bit key as SHA-256 of KEY_CONTEXT followed by the key
+// text. Every payload (frames and audio packets) is sealed with ChaCha20-Poly1305
+// under a fresh random 96-bit nonce, which follows the metadata block, and the
+// metadata fields below are authenticated with it:
+//
+//   aad := width:u32 height:u32 seq:u64 timestamp_ms:u64 raw_size:u32
+//
+// so a payload that was altered, or moved to another frame or between frames and
+// audio, fails to open. `total_size` counts the ciphertext, which is the payload
+// plus a 16-byte tag. Nonces are random rather than counted so that streams
+// started with the same key don't repeat each other's.
+
+use chacha20poly1305::aead::{AeadCore, AeadInPlace, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use sha2::{Digest, Sha256};
+
+use crate::protocol::{FrameInfo, NONCE_SIZE};
+
+// Ties derived keys to this use, so the same passphrase elsewhere gives another key
+const KEY_CONTEXT: &[u8] = b"screen-streamer psk v1";
+
+/// ChaCha20-Poly1305 keyed from a pre-shared key
+pub struct Cipher {
+  aead: ChaCha20Poly1305,
+}
+
+impl Cipher {
+  pub fn new(psk: &str) -> Self {
+    let key = Sha256::new()
+      .chain_update(KEY_CONTEXT)
+      .chain_update(psk.as_bytes())
+      .finalize();
+    Cipher {
+      aead: ChaCha20Poly1305::new(Key::from_slice(&key)),
+    }
+  }
+
+  /// Encrypt `data` in place and return the nonce the receiver needs to open it
+  pub fn seal(&self, info: &FrameInfo, data: &mut Vec<u8>) -> Result<[u8; NONCE_SIZE], String> {
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    self
+      .aead
+      .encrypt_in_place(&nonce, &associated_data(info), data)
+      .map_err(|_| "payload too large to encrypt".to_string())?;
+    Ok(nonce.into())
+  }
+
+  /// Decrypt `data` in place, failing if it or its metadata was tampered with or
+  /// the key is wrong
+  pub fn open(
+    &self,
+    info: &FrameInfo,
+    nonce: &[u8; NONCE_SIZE],
+    data: &mut Vec<u8>,
+  ) -> Result<(), String> {
+    self
+      .aead
+      .decrypt_in_place(Nonce::from_slice(nonce), &associated_data(info), data)
+      .map_err(|_| "payload failed authentication (wrong --psk or tampered)".to_string())
+  }
+}
+
+fn associated_data(info: &FrameInfo) -> [u8; 28] {
+  let mut aad = [0u8; 28];
+  aad[0..4].copy_from_slice(&info.width.to_le_bytes());
+  aad[4..8].copy_from_slice(&info.height.to_le_bytes());
+  aad[8..16].copy_from_slice(&info.seq.to_le_bytes());
+  aad[16..24].copy_from_slice(&info.timestamp_ms.to_le_bytes());
+  aad[24..28].copy_from_slice(&info.raw_size.to_le_bytes());
+  aad
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn info(seq: u64) -> FrameInfo {
+    FrameInfo {
+      width: 4,
+      height: 2,
+      seq,
+      timestamp_ms: 1000,
+      raw_size: 32,
+      checksum: None,
+      nonce: None,
+    }
+  }
+
+  #[test]
+  fn round_trips_and_detects_tampering() {
+    let cipher = Cipher::new("hunter2");
+    let mut data = vec![7u8; 32];
+    let nonce = cipher.seal(&info(5), &mut data).unwrap();
+    assert_eq!(data.len(), 32 + 16);
+    assert_ne!(data[..32], [7u8; 32]);
+
+    let mut flipped = data.clone();
+    flipped[3] ^= 1;
+    assert!(cipher.open(&info(5), &nonce, &mut flipped).is_err());
+    // The ciphertext is bound to its metadata
+    assert!(cipher.open(&info(6), &nonce, &mut data.clone()).is_err());
+    assert!(Cipher::new("hunter3")
+      .open(&info(5), &nonce, &mut data.clone())
+      .is_err());
+
+    cipher.open(&info(5), &nonce, &mut data).unwrap();
+    assert_eq!(data, vec![7u8; 32]);
+  }
+}
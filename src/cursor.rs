@@ -0,0 +1,219 @@
+// Cursor packet payload layout, used with --cursor-layer:
+//
+//   payload := x:i32 y:i32 hotspot_x:u16 hotspot_y:u16 width:u16 height:u16 pixels
+//
+// (`x`, `y`) is where the cursor's hotspot lies in the frame sent right after the
+// packet, in that frame's pixels; it can be outside the frame when the pointer
+// left the captured area. `pixels` is the `width` x `height` cursor image as
+// straight-alpha RGBA, rows packed. Every packet repeats the image, so a receiver
+// can start compositing from any packet. The mouse_position crate only reports
+// where the cursor is, not what it looks like, so the image is always an arrow.
+
+use crate::protocol::{FrameInfo, PixelFormat};
+
+const HEADER_SIZE: usize = 16;
+
+// The arrow, hotspot at its tip: X is the outline, . the fill
+const ARROW: [&str; 19] = [
+  "X",
+  "XX",
+  "X.X",
+  "X..X",
+  "X...X",
+  "X....X",
+  "X.....X",
+  "X......X",
+  "X.......X",
+  "X........X",
+  "X.........X",
+  "X......XXXXX",
+  "X...X..X",
+  "X..XX..X",
+  "X.X  X..X",
+  "XX   X..X",
+  "X     X..X",
+  "      X..X",
+  "       XX",
+];
+
+/// The cursor image and where it is in one frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cursor {
+  pub x: i32,
+  pub y: i32,
+  /// The pixel of the image at (`x`, `y`)
+  pub hotspot: (u16, u16),
+  pub width: u16,
+  pub height: u16,
+  /// Straight-alpha RGBA, `width` x `height`
+  pub pixels: Vec<u8>,
+}
+
+impl Cursor {
+  /// The arrow with its tip at (`x`, `y`)
+  pub fn arrow(x: i32, y: i32) -> Self {
+    let width = ARROW.iter().map(|row| row.len()).max().unwrap_or(0);
+    let mut pixels = vec![0u8; width * ARROW.len() * 4];
+    for (row, line) in ARROW.iter().enumerate() {
+      for (column, mark) in line.bytes().enumerate() {
+        let colour = match mark {
+          b'X' => [0, 0, 0, 255],
+          b'.' => [255, 255, 255, 255],
+          _ => continue,
+        };
+        let at = (row * width + column) * 4;
+        pixels[at..at + 4].copy_from_slice(&colour);
+      }
+    }
+    Cursor {
+      x,
+      y,
+      hotspot: (0, 0),
+      width: width as u16,
+      height: ARROW.len() as u16,
+      pixels,
+    }
+  }
+
+  /// Metadata for sending a cursor ahead of frame `seq`, captured at
+  /// `timestamp_ms`
+  pub fn info(seq: u64, timestamp_ms: u64) -> FrameInfo {
+    FrameInfo {
+      width: 0,
+      height: 0,
+      seq,
+      timestamp_ms,
+      // Unlike an audio packet's, so the two can be told apart
+      raw_size: 0,
+      checksum: None,
+      nonce: None,
+    }
+  }
+
+  pub fn to_payload(&self) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(HEADER_SIZE + self.pixels.len());
+    payload.extend_from_slice(&self.x.to_le_bytes());
+    payload.extend_from_slice(&self.y.to_le_bytes());
+    payload.extend_from_slice(&self.hotspot.0.to_le_bytes());
+    payload.extend_from_slice(&self.hotspot.1.to_le_bytes());
+    payload.extend_from_slice(&self.width.to_le_bytes());
+    payload.extend_from_slice(&self.height.to_le_bytes());
+    payload.extend_from_slice(&self.pixels);
+    payload
+  }
+
+  // The streamer only sends cursors; reading and drawing them is for the receiver
+  #[allow(dead_code)]
+  pub fn from_payload(payload: &[u8]) -> Result<Self, String> {
+    let Some(header) = payload.get(..HEADER_SIZE) else {
+      return Err(format!("{}-byte cursor packet", payload.len()));
+    };
+    let i32_at = |offset: usize| i32::from_le_bytes(header[offset..offset + 4].try_into().unwrap());
+    let u16_at = |offset: usize| u16::from_le_bytes(header[offset..offset + 2].try_into().unwrap());
+    let (width, height) = (u16_at(12), u16_at(14));
+    let pixels = &payload[HEADER_SIZE..];
+    if pixels.len() != width as usize * height as usize * 4 {
+      return Err(format!(
+        "{}x{} cursor with {} bytes of pixels",
+        width,
+        height,
+        pixels.len()
+      ));
+    }
+    Ok(Cursor {
+      x: i32_at(0),
+      y: i32_at(4),
+      hotspot: (u16_at(8), u16_at(10)),
+      width,
+      height,
+      pixels: pixels.to_vec(),
+    })
+  }
+
+  /// Blend the cursor over a `size` frame of `format` pixels whose rows are
+  /// `stride` bytes apart, clipped to the frame
+  #[allow(dead_code)]
+  pub fn draw(
+    &self,
+    frame: &mut [u8],
+    stride: usize,
+    [width, height]: [u32; 2],
+    format: PixelFormat,
+  ) {
+    let bpp = format.bytes_per_pixel() as usize;
+    let left = self.x as i64 - self.hotspot.0 as i64;
+    let top = self.y as i64 - self.hotspot.1 as i64;
+    for row in 0..self.height as i64 {
+      let y = top + row;
+      if y < 0 || y >= height as i64 {
+        continue;
+      }
+      for column in 0..self.width as i64 {
+        let x = left + column;
+        if x < 0 || x >= width as i64 {
+          continue;
+        }
+        let at = (row * self.width as i64 + column) as usize * 4;
+        let [red, green, blue, alpha] = self.pixels[at..at + 4].try_into().unwrap();
+        if alpha == 0 {
+          continue;
+        }
+        let alpha = alpha as u32;
+        let blend = |under: u8, over: u8| {
+          ((under as u32 * (255 - alpha) + over as u32 * alpha + 127) / 255) as u8
+        };
+        let pixel = &mut frame[y as usize * stride + x as usize * bpp..][..bpp];
+        match format {
+          PixelFormat::Rgba | PixelFormat::Rgb => {
+            pixel[0] = blend(pixel[0], red);
+            pixel[1] = blend(pixel[1], green);
+            pixel[2] = blend(pixel[2], blue);
+          }
+          PixelFormat::Bgra => {
+            pixel[0] = blend(pixel[0], blue);
+            pixel[1] = blend(pixel[1], green);
+            pixel[2] = blend(pixel[2], red);
+          }
+          PixelFormat::Gray => {
+            // BT.601 luminance, as grayscale frames are converted with
+            let luma = (red as u32 * 77 + green as u32 * 150 + blue as u32 * 29 + 128) >> 8;
+            pixel[0] = blend(pixel[0], luma as u8);
+          }
+        }
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn payloads_round_trip() {
+    let cursor = Cursor::arrow(-3, 40);
+    assert_eq!((cursor.width, cursor.height), (12, 19));
+    assert_eq!(
+      Cursor::from_payload(&cursor.to_payload()),
+      Ok(cursor.clone())
+    );
+    let payload = cursor.to_payload();
+    assert!(Cursor::from_payload(&payload[..payload.len() - 1]).is_err());
+    assert!(Cursor::from_payload(&payload[..10]).is_err());
+  }
+
+  #[test]
+  fn draws_clipped_to_the_frame() {
+    // 4x3 white BGRA frame with a padded stride, tip in the last column
+    let stride = 20;
+    let mut frame = vec![255u8; stride * 3];
+    Cursor::arrow(3, 1).draw(&mut frame, stride, [4, 3], PixelFormat::Bgra);
+    let pixel = |x: usize, y: usize| &frame[y * stride + x * 4..][..4];
+    // The outline is black, and nothing left of the tip or past the frame changes
+    assert_eq!(pixel(3, 1), [0, 0, 0, 255]);
+    assert_eq!(pixel(3, 2), [0, 0, 0, 255]);
+    assert_eq!(pixel(2, 2), [255; 4]);
+    assert_eq!(pixel(3, 0), [255; 4]);
+    assert!(frame[16..20].iter().all(|&b| b == 255));
+  }
+}
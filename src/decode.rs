@@ -0,0 +1,326 @@
+use std::ops::Range;
+
+use crate::protocol::{Codec, PixelFormat};
+use crate::rle;
+
+// Delta payload header, as written by the streamer's delta.rs
+const DELTA_HEADER_SIZE: usize = 8;
+// First payload byte of a delta or H.264 keyframe (see delta.rs and video.rs)
+const KEYFRAME: u8 = 0;
+
+/// Turns frame payloads back into pixels in the stream's pixel format, `stride`
+/// bytes per row, for the receiver and for the streamer's --preview. Delta frames
+/// are applied on top of the previous frame.
+pub struct Decoder {
+  codec: Codec,
+  pixel_format: PixelFormat,
+  width: usize,
+  height: usize,
+  stride: usize,
+  pixels: Vec<u8>,
+  /// `seq` the next delta must have to apply cleanly; `None` until a keyframe
+  next_seq: Option<u64>,
+  #[cfg(feature = "h264")]
+  h264: Option<openh264::decoder::Decoder>,
+}
+
+impl Decoder {
+  /// A decoder for `codec` payloads of `size` frames
+  pub fn new(codec: Codec, pixel_format: PixelFormat, size: [u32; 2], stride: u32) -> Self {
+    Decoder {
+      codec,
+      pixel_format,
+      width: size[0] as usize,
+      height: size[1] as usize,
+      stride: stride as usize,
+      pixels: Vec::new(),
+      next_seq: None,
+      #[cfg(feature = "h264")]
+      h264: None,
+    }
+  }
+
+  /// Decode one payload. Returns `None` for a delta that can't be applied because
+  /// frames were lost since the last keyframe.
+  pub fn decode(&mut self, payload: &[u8], seq: Option<u64>) -> Result<Option<&[u8]>, String> {
+    let size = self.stride * self.height;
+    match self.codec {
+      Codec::Raw => {
+        self.pixels.clear();
+        self.pixels.extend_from_slice(payload);
+      }
+      Codec::Zstd => {
+        self.pixels = zstd::bulk::decompress(payload, size).map_err(|e| e.to_string())?;
+      }
+      Codec::Jpeg => self.decode_jpeg(payload)?,
+      Codec::Rle => rle::rle_decode(payload, size, &mut self.pixels)?,
+      Codec::Delta => {
+        let expected = self.next_seq.take();
+        let applied = self.apply_delta(payload, seq.is_none() || seq == expected, &[])?;
+        if !applied {
+          return Ok(None);
+        }
+        self.next_seq = seq.map(|seq| seq + 1);
+      }
+      Codec::H264 => {
+        // Same rules as deltas: after a gap, wait for the next IDR frame
+        let expected = self.next_seq.take();
+        let keyframe = payload.first() == Some(&KEYFRAME);
+        if !keyframe && seq.is_some() && seq != expected {
+          return Ok(None);
+        }
+        if !self.decode_h264(payload.get(1..).unwrap_or_default())? {
+          return Ok(None);
+        }
+        self.next_seq = seq.map(|seq| seq + 1);
+      }
+    }
+
+    if self.pixels.len() != size {
+      return Err(format!(
+        "decoded {} bytes, expected {}",
+        self.pixels.len(),
+        size
+      ));
+    }
+    Ok(Some(&self.pixels))
+  }
+
+  /// Decode a frame that arrived with the byte ranges of its payload in `missing`,
+  /// in order, lost on the way, drawing what did arrive over the previous frame
+  /// (see protocol.rs). Returns `None` when that can't be done: the codec
+  /// compresses whole frames, there is no previous frame, or the part locating
+  /// the rest is missing, after which deltas wait for a keyframe.
+  pub fn decode_partial(
+    &mut self,
+    payload: &[u8],
+    missing: &[Range<usize>],
+    seq: Option<u64>,
+  ) -> Result<Option<&[u8]>, String> {
+    let expected = self.next_seq.take();
+    let intact = self.pixels.len() == self.stride * self.height;
+    let applied = match self.codec {
+      Codec::Raw if intact => {
+        copy_arrived(&mut self.pixels, payload, 0, missing)?;
+        true
+      }
+      Codec::Delta => self.apply_delta(payload, seq.is_none() || seq == expected, missing)?,
+      _ => false,
+    };
+    if !applied {
+      return Ok(None);
+    }
+    self.next_seq = seq.map(|seq| seq + 1);
+    Ok(Some(&self.pixels))
+  }
+
+  /// The last frame decoded, if there is a whole one, for showing it again
+  pub fn last(&self) -> Option<&[u8]> {
+    (self.pixels.len() == self.stride * self.height).then_some(self.pixels.as_slice())
+  }
+
+  // JPEG decodes to whatever the handshake reports for this codec: RGBA, RGB, or
+  // grayscale for single-channel images
+  fn decode_jpeg(&mut self, payload: &[u8]) -> Result<(), String> {
+    let mut decoder = jpeg_decoder::Decoder::new(payload);
+    let decoded = decoder.decode().map_err(|e| e.to_string())?;
+    let format = decoder.info().map(|info| info.pixel_format);
+
+    self.pixels.clear();
+    match format {
+      Some(jpeg_decoder::PixelFormat::L8) if self.pixel_format == PixelFormat::Gray => {
+        self.pixels = decoded;
+      }
+      Some(jpeg_decoder::PixelFormat::RGB24) if self.pixel_format == PixelFormat::Rgb => {
+        self.pixels = decoded;
+      }
+      Some(jpeg_decoder::PixelFormat::RGB24) => {
+        for rgb in decoded.chunks_exact(3) {
+          self
+            .pixels
+            .extend_from_slice(&[rgb[0], rgb[1], rgb[2], 255]);
+        }
+      }
+      Some(jpeg_decoder::PixelFormat::L8) => {
+        for &l in &decoded {
+          self.pixels.extend_from_slice(&[l, l, l, 255]);
+        }
+      }
+      other => return Err(format!("unsupported JPEG pixel format {:?}", other)),
+    }
+    Ok(())
+  }
+
+  // Returns false while the decoder has no picture to show yet
+  #[cfg(feature = "h264")]
+  fn decode_h264(&mut self, access_unit: &[u8]) -> Result<bool, String> {
+    let decoder = match &mut self.h264 {
+      Some(decoder) => decoder,
+      None => self
+        .h264
+        .insert(openh264::decoder::Decoder::new().map_err(|e| e.to_string())?),
+    };
+    let Some(yuv) = decoder.decode(access_unit).map_err(|e| e.to_string())? else {
+      return Ok(false);
+    };
+    // H.264 always decodes to tightly packed RGBA
+    self.pixels.resize(self.width * self.height * 4, 0);
+    yuv.write_rgba8(&mut self.pixels);
+    Ok(true)
+  }
+
+  #[cfg(not(feature = "h264"))]
+  fn decode_h264(&mut self, _access_unit: &[u8]) -> Result<bool, String> {
+    Err("H.264 needs a build with `--features h264`".to_string())
+  }
+
+  /// Apply a delta payload to the previous frame, or replace it with a keyframe,
+  /// leaving out the byte ranges of the payload in `missing`. Returns false when
+  /// a delta arrives without an intact base to apply it to, or too little of it
+  /// arrived to tell where the rest goes.
+  fn apply_delta(
+    &mut self,
+    payload: &[u8],
+    in_sequence: bool,
+    missing: &[Range<usize>],
+  ) -> Result<bool, String> {
+    if payload.len() < DELTA_HEADER_SIZE {
+      return Err("delta payload is shorter than its header".to_string());
+    }
+    if !arrived(missing, 0..DELTA_HEADER_SIZE) {
+      return Ok(false);
+    }
+    let field = |offset: usize| u16::from_le_bytes([payload[offset], payload[offset + 1]]) as usize;
+    let (tile_size, tiles_x, tiles_y) = (field(2), field(4), field(6));
+    let body = &payload[DELTA_HEADER_SIZE..];
+    let intact = self.pixels.len() == self.stride * self.height;
+
+    if payload[0] == KEYFRAME {
+      if missing.is_empty() {
+        self.pixels.clear();
+        self.pixels.extend_from_slice(body);
+        return Ok(true);
+      }
+      // Part of a keyframe is as good as part of a raw frame
+      if intact {
+        copy_arrived(&mut self.pixels, body, DELTA_HEADER_SIZE, missing)?;
+      }
+      return Ok(intact);
+    }
+    if !in_sequence || !intact {
+      return Ok(false);
+    }
+
+    let tiles = tiles_x * tiles_y;
+    let bitmap = body
+      .get(..tiles.div_ceil(8))
+      .ok_or("delta payload is shorter than its bitmap")?;
+    // Where the next changed tile's pixels start in the payload
+    let mut at = DELTA_HEADER_SIZE + bitmap.len();
+    if !arrived(missing, DELTA_HEADER_SIZE..at) {
+      return Ok(false);
+    }
+    let bpp = self.pixel_format.bytes_per_pixel() as usize;
+    let row = self.width * bpp;
+    for tile in (0..tiles).filter(|tile| bitmap[tile / 8] & (1 << (tile % 8)) != 0) {
+      let x0 = tile % tiles_x * tile_size * bpp;
+      let x1 = (x0 + tile_size * bpp).min(row);
+      let y0 = tile / tiles_x * tile_size;
+      let y1 = (y0 + tile_size).min(self.height);
+      let len = (x1 - x0) * (y1 - y0);
+      let pixels = payload
+        .get(at..at + len)
+        .ok_or("delta payload ends inside a tile")?;
+      // Each tile holds its pixels outright, so the ones that arrived can be drawn
+      // whatever became of the others
+      if arrived(missing, at..at + len) {
+        for (y, line) in (y0..y1).zip(pixels.chunks_exact(x1 - x0)) {
+          let start = y * self.stride + x0;
+          self.pixels[start..start + line.len()].copy_from_slice(line);
+        }
+      }
+      at += len;
+    }
+    Ok(true)
+  }
+}
+
+/// Whether none of `range` is in `missing`
+fn arrived(missing: &[Range<usize>], range: Range<usize>) -> bool {
+  !missing
+    .iter()
+    .any(|gap| gap.start < range.end && range.start < gap.end)
+}
+
+/// Copy `src`, which starts `offset` bytes into its payload, over `dst`, except
+/// for the payload's byte ranges in `missing`, which are in order
+fn copy_arrived(
+  dst: &mut [u8],
+  src: &[u8],
+  offset: usize,
+  missing: &[Range<usize>],
+) -> Result<(), String> {
+  if src.len() != dst.len() {
+    return Err(format!(
+      "frame of {} bytes, expected {}",
+      src.len(),
+      dst.len()
+    ));
+  }
+  let mut from = 0;
+  for gap in missing {
+    let start = gap.start.saturating_sub(offset).min(src.len());
+    if from < start {
+      dst[from..start].copy_from_slice(&src[from..start]);
+    }
+    from = from.max(gap.end.saturating_sub(offset).min(src.len()));
+  }
+  dst[from..].copy_from_slice(&src[from..]);
+  Ok(())
+}
+
+// `&[a..b]` here is one missing range, not the bytes in it
+#[cfg(test)]
+#[allow(clippy::single_range_in_vec_init)]
+mod tests {
+  use super::*;
+
+  // A 4x2 grayscale frame in two 2x2 tiles
+  fn header(kind: u8) -> Vec<u8> {
+    vec![kind, 0, 2, 0, 2, 0, 1, 0]
+  }
+
+  #[test]
+  fn applies_the_tiles_that_arrived() {
+    let mut decoder = Decoder::new(Codec::Delta, PixelFormat::Gray, [4, 2], 4);
+    let mut keyframe = header(KEYFRAME);
+    keyframe.extend(1..=8);
+    assert_eq!(
+      decoder.decode(&keyframe, Some(0)),
+      Ok(Some(&[1, 2, 3, 4, 5, 6, 7, 8][..]))
+    );
+
+    // Both tiles changed, but the first one's pixels never arrived
+    let mut delta = header(1);
+    delta.push(0b11);
+    delta.extend([10, 11, 12, 13, 20, 21, 22, 23]);
+    assert_eq!(
+      decoder.decode_partial(&delta, &[9..13], Some(1)),
+      Ok(Some(&[1, 2, 20, 21, 5, 6, 22, 23][..]))
+    );
+    // Without the bitmap nothing says where the tiles go
+    assert_eq!(decoder.decode_partial(&delta, &[8..9], Some(2)), Ok(None));
+    assert_eq!(decoder.decode(&delta, Some(3)), Ok(None));
+  }
+
+  #[test]
+  fn keeps_the_previous_frame_where_a_raw_one_has_gaps() {
+    let mut decoder = Decoder::new(Codec::Raw, PixelFormat::Gray, [4, 2], 4);
+    assert_eq!(decoder.decode_partial(&[9; 8], &[0..4], Some(0)), Ok(None));
+    decoder.decode(&[1, 2, 3, 4, 5, 6, 7, 8], Some(0)).unwrap();
+    assert_eq!(
+      decoder.decode_partial(&[9; 8], &[2..4, 6..8], Some(1)),
+      Ok(Some(&[9, 9, 3, 4, 9, 9, 7, 8][..]))
+    );
+  }
+}
@@ -0,0 +1,101 @@
+// Delta payload layout (all integers little-endian), used by the delta codec:
+//
+//   payload  := kind:u8 reserved:u8 tile_size:u16 tiles_x:u16 tiles_y:u16 body
+//   keyframe := body is the full frame (kind 0)
+//   delta    := body is bitmap tile* (kind 1)
+//
+// The frame is split into `tile_size` square tiles in row-major order; tiles on
+// the right and bottom edges are clipped to the frame. The bitmap has one bit
+// per tile (bit `i % 8` of byte `i / 8`) set when that tile changed, followed by
+// the pixels of each changed tile in order, row by row. A delta applies to the
+// frame with the previous `seq`, so after a gap the receiver must wait for the
+// next keyframe.
+
+pub const TILE_SIZE: u32 = 64;
+pub const DELTA_HEADER_SIZE: usize = 8;
+
+const KEYFRAME: u8 = 0;
+const DELTA: u8 = 1;
+
+/// Diffs each frame against the previous one and keeps only the tiles that changed
+pub struct DeltaEncoder {
+  width: u32,
+  height: u32,
+  bytes_per_pixel: u32,
+  keyframe_interval: u32,
+  since_keyframe: u32,
+  prev: Vec<u8>,
+}
+
+impl DeltaEncoder {
+  /// `keyframe_interval` is the number of frames between full frames
+  pub fn new(width: u32, height: u32, bytes_per_pixel: u32, keyframe_interval: u32) -> Self {
+    DeltaEncoder {
+      width,
+      height,
+      bytes_per_pixel,
+      keyframe_interval,
+      since_keyframe: 0,
+      prev: Vec::new(),
+    }
+  }
+
+  /// Make the next frame a keyframe, e.g. when a receiver (re)joins mid-stream
+  pub fn force_keyframe(&mut self) {
+    self.prev.clear();
+  }
+
+  pub fn tiles(&self) -> (u32, u32) {
+    (
+      self.width.div_ceil(TILE_SIZE),
+      self.height.div_ceil(TILE_SIZE),
+    )
+  }
+
+  pub fn encode(&mut self, frame: &[u8]) -> Vec<u8> {
+    let (tiles_x, tiles_y) = self.tiles();
+    let keyframe = self.prev.len() != frame.len() || self.since_keyframe >= self.keyframe_interval;
+
+    let mut out = Vec::with_capacity(if keyframe {
+      DELTA_HEADER_SIZE + frame.len()
+    } else {
+      DELTA_HEADER_SIZE + 4096
+    });
+    out.push(if keyframe { KEYFRAME } else { DELTA });
+    out.push(0);
+    out.extend_from_slice(&(TILE_SIZE as u16).to_le_bytes());
+    out.extend_from_slice(&(tiles_x as u16).to_le_bytes());
+    out.extend_from_slice(&(tiles_y as u16).to_le_bytes());
+
+    if keyframe {
+      out.extend_from_slice(frame);
+      self.since_keyframe = 1;
+    } else {
+      let bitmap_start = out.len();
+      let bitmap_len = (tiles_x * tiles_y).div_ceil(8) as usize;
+      out.resize(bitmap_start + bitmap_len, 0);
+
+      let bpp = self.bytes_per_pixel as usize;
+      let stride = self.width as usize * bpp;
+      for tile in 0..tiles_x * tiles_y {
+        let x0 = (tile % tiles_x * TILE_SIZE) as usize * bpp;
+        let x1 = (x0 + TILE_SIZE as usize * bpp).min(stride);
+        let y0 = (tile / tiles_x * TILE_SIZE) as usize;
+        let y1 = (y0 + TILE_SIZE as usize).min(self.height as usize);
+        let rows = (y0..y1).map(|y| y * stride + x0..y * stride + x1);
+
+        if rows.clone().any(|row| frame[row.clone()] != self.prev[row]) {
+          out[bitmap_start + tile as usize / 8] |= 1 << (tile % 8);
+          for row in rows {
+            out.extend_from_slice(&frame[row]);
+          }
+        }
+      }
+      self.since_keyframe += 1;
+    }
+
+    self.prev.clear();
+    self.prev.extend_from_slice(frame);
+    out
+  }
+}
@@ -0,0 +1,137 @@
+use jpeg_encoder::{ColorType, Encoder};
+
+use crate::convert::{self, Yuv};
+use crate::delta::DeltaEncoder;
+use crate::protocol::{Codec, PixelFormat};
+use crate::rle;
+#[cfg(feature = "h264")]
+use crate::video::H264Encoder;
+
+/// Turns captured BGRA frames into the payload sent on the wire
+#[derive(Debug, Clone, Copy)]
+pub struct FrameEncoder {
+  pub codec: Codec,
+  /// JPEG quality, 1-100
+  pub quality: u8,
+  /// zstd compression level, 1-22
+  pub level: i32,
+  /// Pixels raw, zstd, delta and RLE frames are converted to before sending. JPEG
+  /// only looks at whether it is grayscale; receivers decode colour JPEGs to
+  /// whichever format the handshake names.
+  pub pixel_format: PixelFormat,
+  /// Matrix and range of YUV frames, those H.264 encodes and NV12 captures
+  pub yuv: Yuv,
+}
+
+impl FrameEncoder {
+  /// Encode a frame into `out`, whose allocation is reused where the codec allows.
+  /// Rows of `frame` are `stride` bytes apart; any padding is dropped on the way.
+  pub fn encode(
+    &self,
+    frame: Vec<u8>,
+    width: u32,
+    height: u32,
+    stride: usize,
+    out: &mut Vec<u8>,
+  ) -> Result<(), String> {
+    let row = width as usize * 4;
+    // Captured frames can go out untouched when they are already tight BGRA
+    let passthrough = self.pixel_format == PixelFormat::Bgra && stride == row;
+    match self.codec {
+      // Delta frames are diffed later by the sender, against raw pixels
+      Codec::Raw | Codec::Delta if passthrough => *out = frame,
+      Codec::Raw | Codec::Delta => self.convert_into(&frame, width, stride, out),
+      Codec::Zstd => {
+        let pixels = if passthrough {
+          frame.as_slice()
+        } else {
+          self.convert_into(&frame, width, stride, out);
+          out.as_slice()
+        };
+        *out = zstd::bulk::compress(pixels, self.level).map_err(|e| e.to_string())?;
+      }
+      Codec::Rle if passthrough => rle::rle_encode(&frame, out),
+      Codec::Rle => {
+        let mut packed = Vec::new();
+        self.convert_into(&frame, width, stride, &mut packed);
+        rle::rle_encode(&packed, out);
+      }
+      // Encoded later by the sender's H264Encoder, which wants planar YUV
+      Codec::H264 => {
+        let (width, height) = (width as usize, height as usize);
+        convert::bgra_to_yuv420_into(&frame, width, height, stride, &self.yuv, out)
+      }
+      Codec::Jpeg => {
+        let (width, height) = match (u16::try_from(width), u16::try_from(height)) {
+          (Ok(w), Ok(h)) => (w, h),
+          _ => return Err(format!("{}x{} is too large for JPEG", width, height)),
+        };
+        let mut packed = Vec::new();
+        let (pixels, color) = if self.pixel_format == PixelFormat::Gray {
+          convert::bgra_to_gray_into(&frame, width as usize, stride, &mut packed);
+          (packed.as_slice(), ColorType::Luma)
+        } else if stride == row {
+          (frame.as_slice(), ColorType::Bgra)
+        } else {
+          convert::pack_rows_into(&frame, row, stride, &mut packed);
+          (packed.as_slice(), ColorType::Bgra)
+        };
+        // The encoder reads BGRA directly, so no separate channel swap is needed
+        out.clear();
+        Encoder::new(&mut *out, self.quality)
+          .encode(pixels, width, height, color)
+          .map_err(|e| e.to_string())?;
+      }
+    }
+    Ok(())
+  }
+
+  /// Convert `frame` to `pixel_format` with rows packed
+  fn convert_into(&self, frame: &[u8], width: u32, stride: usize, out: &mut Vec<u8>) {
+    match self.pixel_format {
+      PixelFormat::Rgba => to_rgba_into(frame, width, stride, out),
+      PixelFormat::Bgra => convert::pack_rows_into(frame, width as usize * 4, stride, out),
+      PixelFormat::Rgb => convert::bgra_to_rgb_into(frame, width as usize, stride, out),
+      PixelFormat::Gray => convert::bgra_to_gray_into(frame, width as usize, stride, out),
+    }
+  }
+}
+
+/// Codecs whose frames build on the one sent before. They run on the sending side
+/// rather than the capture thread, since only it knows which frames went out.
+pub enum StreamEncoder {
+  Delta(DeltaEncoder),
+  #[cfg(feature = "h264")]
+  H264(H264Encoder),
+}
+
+impl StreamEncoder {
+  /// Make the next frame one a receiver can start from
+  pub fn force_keyframe(&mut self) {
+    match self {
+      StreamEncoder::Delta(delta) => delta.force_keyframe(),
+      #[cfg(feature = "h264")]
+      StreamEncoder::H264(h264) => h264.force_keyframe(),
+    }
+  }
+
+  /// Encode a frame prepared by `FrameEncoder` into its wire payload
+  pub fn encode(&mut self, frame: &[u8]) -> Result<Vec<u8>, String> {
+    match self {
+      StreamEncoder::Delta(delta) => Ok(delta.encode(frame)),
+      #[cfg(feature = "h264")]
+      StreamEncoder::H264(h264) => h264.encode(frame),
+    }
+  }
+}
+
+// Large frames are split across cores when built with the rayon feature
+#[cfg(feature = "rayon")]
+fn to_rgba_into(bgra: &[u8], width: u32, stride: usize, out: &mut Vec<u8>) {
+  convert::bgra_to_rgba_par_into(bgra, width as usize, stride, out)
+}
+
+#[cfg(not(feature = "rayon"))]
+fn to_rgba_into(bgra: &[u8], width: u32, stride: usize, out: &mut Vec<u8>) {
+  convert::bgra_to_rgba_strided_into(bgra, width as usize, stride, out)
+}
@@ -0,0 +1,97 @@
+// Lifecycle events for apps embedding a Streamer, alongside the stats line rather
+// than parsed out of it. Sending never blocks the pipeline: with nobody
+// subscribed events go nowhere, and once a subscriber falls EVENT_QUEUE_DEPTH
+// events behind, newer ones are dropped until it catches up.
+
+use std::sync::mpsc::SyncSender;
+use std::time::Duration;
+
+/// Events held for a subscriber that isn't keeping up
+pub const EVENT_QUEUE_DEPTH: usize = 256;
+
+/// Something that happened to one stream
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+  /// The display's index with --all-displays, otherwise 0
+  pub stream: usize,
+  pub kind: EventKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EventKind {
+  /// A receiver connected or reconnected; in listen mode, one more of them
+  Connected { peer: String },
+  /// A receiver's connection broke, for `reason`
+  Disconnected { peer: String, reason: String },
+  /// The capture thread handed over a frame of `bytes` bytes, to go out as `seq`
+  FrameCaptured { seq: u64, bytes: usize },
+  /// `count` frames were discarded by pacing, a full buffer or --max-mbps
+  FrameDropped { count: u64 },
+  /// Frame `seq` went out, `bytes` of it on the wire, `latency` after capture.
+  /// In listen mode that's once it was queued for every receiver.
+  FrameSent {
+    seq: u64,
+    bytes: usize,
+    latency: Duration,
+  },
+  /// Something failed; streaming goes on unless the message says otherwise
+  Error { message: String },
+}
+
+/// Where one stream's events go, if anyone subscribed
+#[derive(Clone, Default)]
+pub struct Events {
+  tx: Option<SyncSender<Event>>,
+  stream: usize,
+}
+
+impl Events {
+  pub fn new(tx: Option<SyncSender<Event>>, stream: usize) -> Self {
+    Events { tx, stream }
+  }
+
+  /// Queue `kind` without blocking, dropping it if the subscriber is behind or gone
+  pub fn emit(&self, kind: EventKind) {
+    if let Some(tx) = &self.tx {
+      let _ = tx.try_send(Event {
+        stream: self.stream,
+        kind,
+      });
+    }
+  }
+
+  pub fn error(&self, message: String) {
+    self.emit(EventKind::Error { message });
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::sync::mpsc;
+
+  #[test]
+  fn drops_events_once_the_queue_is_full() {
+    let (tx, rx) = mpsc::sync_channel(2);
+    let events = Events::new(Some(tx), 3);
+    for seq in 0..5 {
+      events.emit(EventKind::FrameCaptured { seq, bytes: 10 });
+    }
+    let seqs: Vec<_> = rx
+      .try_iter()
+      .map(|event| match event {
+        Event {
+          stream: 3,
+          kind: EventKind::FrameCaptured { seq, .. },
+        } => seq,
+        other => panic!("unexpected {:?}", other),
+      })
+      .collect();
+    assert_eq!(seqs, [0, 1]);
+
+    // Nobody subscribed, or the subscriber went away: nothing happens
+    Events::default().error("ignored".to_string());
+    drop(rx);
+    events.emit(EventKind::FrameDropped { count: 1 });
+  }
+}
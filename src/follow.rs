@@ -0,0 +1,122 @@
+// --follow-cursor: the --crop region moves with the mouse. scap can't move a crop
+// once capture has started, so the capturer delivers the whole display and the
+// capture thread cuts the region out of every frame. Cursor positions are taken
+// as pixels from the display's top-left corner, which holds for the primary
+// display at 100% scaling.
+
+use scap::capturer::Area;
+
+/// A fixed-size region kept centred on the cursor and inside the display
+pub struct Follow {
+  /// Region size, in the display's own pixels like --crop
+  size: (f64, f64),
+  /// Full display size in the same units
+  display: (f64, f64),
+  /// Last known cursor position, kept while it can't be read
+  cursor: (f64, f64),
+}
+
+impl Follow {
+  /// Follow with the size of `crop`, starting where it is on a display of
+  /// `display` pixels
+  pub fn new(crop: &Area, display: [u32; 2]) -> Self {
+    Follow {
+      size: (crop.size.width, crop.size.height),
+      display: (display[0] as f64, display[1] as f64),
+      cursor: (
+        crop.origin.x + crop.size.width / 2.0,
+        crop.origin.y + crop.size.height / 2.0,
+      ),
+    }
+  }
+
+  /// Size of the region in captured frames of `frame` pixels, which --resolution
+  /// may have scaled. Rounded down to even numbers so every codec takes it.
+  pub fn frame_size(&self, frame: [u32; 2]) -> [u32; 2] {
+    let scale = |size: f64, display: f64, frame: u32| {
+      let scaled = (size * frame as f64 / display) as u32;
+      (scaled & !1).clamp(2.min(frame), frame)
+    };
+    [
+      scale(self.size.0, self.display.0, frame[0]),
+      scale(self.size.1, self.display.1, frame[1]),
+    ]
+  }
+
+  /// Top-left corner of the `region` in a frame of `frame` pixels, after moving it
+  /// to the cursor's current position
+  pub fn origin(&mut self, frame: [u32; 2], region: [u32; 2]) -> [u32; 2] {
+    if let Some(cursor) = cursor() {
+      self.cursor = cursor;
+    }
+    let place = |cursor: f64, display: f64, frame: u32, region: u32| {
+      let centre = cursor * frame as f64 / display;
+      let max = frame.saturating_sub(region);
+      // Even offsets keep NV12 chroma and 2x2 blocks aligned
+      ((centre - region as f64 / 2.0).max(0.0) as u32).min(max) & !1
+    };
+    [
+      place(self.cursor.0, self.display.0, frame[0], region[0]),
+      place(self.cursor.1, self.display.1, frame[1], region[1]),
+    ]
+  }
+}
+
+/// Cursor position in display pixels, if it can be read
+#[cfg(feature = "follow-cursor")]
+pub fn cursor() -> Option<(f64, f64)> {
+  use mouse_position::mouse_position::Mouse;
+
+  match Mouse::get_mouse_position() {
+    Mouse::Position { x, y } => Some((x as f64, y as f64)),
+    Mouse::Error => None,
+  }
+}
+
+// --follow-cursor and --cursor-overlay are refused without the feature, so
+// nothing moves
+#[cfg(not(feature = "follow-cursor"))]
+pub fn cursor() -> Option<(f64, f64)> {
+  None
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use scap::capturer::{Point, Size};
+
+  fn follow(cursor: (f64, f64)) -> Follow {
+    let crop = Area {
+      origin: Point { x: 0.0, y: 0.0 },
+      size: Size {
+        width: 640.0,
+        height: 360.0,
+      },
+    };
+    Follow {
+      cursor,
+      ..Follow::new(&crop, [1920, 1080])
+    }
+  }
+
+  #[test]
+  fn centres_on_the_cursor() {
+    let mut follow = follow((1000.0, 500.0));
+    assert_eq!(follow.origin([1920, 1080], [640, 360]), [680, 320]);
+  }
+
+  #[test]
+  fn stays_inside_the_display() {
+    let mut follow = follow((10.0, 1075.0));
+    assert_eq!(follow.origin([1920, 1080], [640, 360]), [0, 720]);
+  }
+
+  #[test]
+  fn scales_with_the_captured_frame() {
+    // --resolution 720p scales a 1080p display to two thirds
+    let mut follow = follow((1000.0, 500.0));
+    let region = follow.frame_size([1280, 720]);
+    assert_eq!(region, [426, 240]);
+    assert_eq!(follow.origin([1280, 720], region), [452, 212]);
+  }
+}
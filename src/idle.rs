@@ -0,0 +1,185 @@
+// --half-rate-on-idle: capture keeps running at --fps, but while the screen is
+// still, fewer of its frames are encoded and sent. Every frame gets a fingerprint,
+// a hash of a sparse sample of each cell of a GRID x GRID grid, and the share of
+// cells whose hash differs from the last frame sent says how much changed. Once
+// that has stayed at or under the threshold for HALVE_EVERY, the rate halves,
+// and halves again after every HALVE_EVERY more, down to --idle-fps. The first
+// frame that changes more goes out at once and brings back the full rate, so
+// whatever moved isn't held back behind an idle frame time.
+
+use std::time::{Duration, Instant};
+
+/// Cells across and down the frame
+const GRID: usize = 16;
+/// Samples across and down each cell
+const SAMPLES: usize = 8;
+/// Stillness that halves the rate once more
+const HALVE_EVERY: Duration = Duration::from_secs(1);
+
+/// One hash per cell of a frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fingerprint {
+  size: [u32; 2],
+  cells: Vec<u64>,
+}
+
+impl Fingerprint {
+  /// Sample a BGRA frame whose rows are `stride` bytes apart
+  pub fn of(bgra: &[u8], [width, height]: [u32; 2], stride: usize) -> Self {
+    let (width, height) = (width as usize, height as usize);
+    let mut cells = vec![0xcbf2_9ce4_8422_2325u64; GRID * GRID];
+    // Frames smaller than the samples take every pixel
+    let step_x = (width / (GRID * SAMPLES)).max(1);
+    let step_y = (height / (GRID * SAMPLES)).max(1);
+    for y in (0..height).step_by(step_y) {
+      let row = &bgra[y * stride..];
+      let cell_row = y * GRID / height * GRID;
+      for x in (0..width).step_by(step_x) {
+        let pixel = u32::from_le_bytes(row[x * 4..x * 4 + 4].try_into().unwrap());
+        let cell = &mut cells[cell_row + x * GRID / width];
+        // FNV-1a over whole pixels
+        *cell = (*cell ^ pixel as u64).wrapping_mul(0x100_0000_01b3);
+      }
+    }
+    Fingerprint {
+      size: [width as u32, height as u32],
+      cells,
+    }
+  }
+
+  /// Share of cells that differ from `other`'s, 1 for another size
+  pub fn changed(&self, other: &Fingerprint) -> f64 {
+    if self.size != other.size {
+      return 1.0;
+    }
+    let changed = self
+      .cells
+      .iter()
+      .zip(&other.cells)
+      .filter(|(a, b)| a != b)
+      .count();
+    changed as f64 / self.cells.len() as f64
+  }
+}
+
+/// Decides which captured frames go out while the screen is still
+pub struct IdleRate {
+  /// Share of cells that may change with the screen still counting as still
+  threshold: f64,
+  frame_time: Duration,
+  /// Longest time between frames sent, from --idle-fps
+  slowest: Duration,
+  /// The last frame sent, its fingerprint and where the cursor was in it
+  last: Option<(Fingerprint, Option<(i32, i32)>)>,
+  sent_at: Option<Instant>,
+  still_since: Option<Instant>,
+  interval: Duration,
+}
+
+impl IdleRate {
+  /// Frames come every `frame_time`; while still, at least one every `slowest`
+  pub fn new(threshold: f64, frame_time: Duration, slowest: Duration) -> Self {
+    IdleRate {
+      threshold,
+      frame_time,
+      slowest: slowest.max(frame_time),
+      last: None,
+      sent_at: None,
+      still_since: None,
+      interval: frame_time,
+    }
+  }
+
+  /// Whether the rate is down from the full one
+  pub fn idle(&self) -> bool {
+    self.interval > self.frame_time
+  }
+
+  /// The current rate, in frames per second
+  pub fn fps(&self) -> f64 {
+    1.0 / self.interval.as_secs_f64()
+  }
+
+  /// Whether to send a frame captured at `now`; a cursor sent beside the frames
+  /// moving counts as change too
+  pub fn keep(
+    &mut self,
+    fingerprint: Fingerprint,
+    cursor: Option<(i32, i32)>,
+    now: Instant,
+  ) -> bool {
+    let still = self.last.as_ref().is_some_and(|(last, last_cursor)| {
+      *last_cursor == cursor && fingerprint.changed(last) <= self.threshold
+    });
+    let keep = if still {
+      let still_since = *self.still_since.get_or_insert(now);
+      // 2^16 times slower is far past any --idle-fps
+      let halvings = ((now - still_since).as_millis() / HALVE_EVERY.as_millis()).min(16);
+      self.interval = (self.frame_time * (1 << halvings)).min(self.slowest);
+      // Half a frame time of slack, as captured frames don't arrive exactly on time
+      self
+        .sent_at
+        .is_none_or(|at| now - at + self.frame_time / 2 >= self.interval)
+    } else {
+      self.still_since = None;
+      self.interval = self.frame_time;
+      true
+    };
+    if keep {
+      self.last = Some((fingerprint, cursor));
+      self.sent_at = Some(now);
+    }
+    keep
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const FRAME_TIME: Duration = Duration::from_millis(100);
+
+  fn frame(shade: u8) -> Vec<u8> {
+    vec![shade; 320 * 240 * 4]
+  }
+
+  #[test]
+  fn only_changed_cells_count() {
+    let still = Fingerprint::of(&frame(0), [320, 240], 320 * 4);
+    let mut moved = frame(0);
+    // One row of pixels in the top left cell, covering a sampled row
+    moved[..20 * 4].fill(255);
+    let moved = Fingerprint::of(&moved, [320, 240], 320 * 4);
+    assert_eq!(moved.changed(&still), 1.0 / (GRID * GRID) as f64);
+    assert_eq!(
+      still.changed(&Fingerprint::of(&frame(0), [240, 320], 240 * 4)),
+      1.0
+    );
+  }
+
+  #[test]
+  fn slows_down_while_still_and_catches_up_on_motion() {
+    let start = Instant::now();
+    let mut rate = IdleRate::new(0.01, FRAME_TIME, Duration::from_millis(400));
+    let mut sent = Vec::new();
+    for index in 0..60u32 {
+      let now = start + FRAME_TIME * index;
+      // Still for 5s, then moving
+      let shade = if index < 50 { 0 } else { index as u8 };
+      let fingerprint = Fingerprint::of(&frame(shade), [320, 240], 320 * 4);
+      if rate.keep(fingerprint, None, now) {
+        sent.push(index);
+      }
+    }
+    // Every frame to start with, then every other, then one in four at the
+    // slowest, and every frame again from the first that moved
+    assert_eq!(&sent[..12], [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 12]);
+    assert!(sent
+      .windows(2)
+      .filter(|w| w[1] < 50)
+      .all(|w| w[1] - w[0] <= 4));
+    assert!(sent.contains(&44) && !sent.contains(&46));
+    assert_eq!(sent[sent.len() - 10..], (50..60).collect::<Vec<_>>()[..]);
+    assert!(!rate.idle());
+  }
+}
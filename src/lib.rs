@@ -0,0 +1,85 @@
+//! Screen capture streamed over TCP, UDP, WebSocket, MJPEG or a Unix socket. The
+//! `screen-streamer` binary is a thin command line over `Streamer`, which other
+//! apps can embed the same way:
+//!
+//! ```no_run
+//! use screen_streamer::{cli::Args, Streamer};
+//!
+//! let args = ["--test-pattern", "1280x720", "--codec", "jpeg"];
+//! let config = Args::parse_from(args.map(String::from)).unwrap();
+//! let mut streamer = Streamer::new(config);
+//! streamer.on_frame(|frame| println!("frame {}: {} bytes", frame.info.seq, frame.data.len()));
+//! streamer.start().unwrap();
+//! std::thread::sleep(std::time::Duration::from_secs(5));
+//! println!("{} frames sent so far", streamer.stats().frames_sent);
+//! streamer.stop().unwrap();
+//! ```
+
+mod adaptive;
+mod affinity;
+#[cfg(feature = "audio")]
+mod audio;
+mod autocodec;
+pub mod bench;
+mod broadcast;
+mod buffer;
+mod capture;
+pub mod cli;
+pub mod controls;
+mod convert;
+// Sealing happens here; opening is the receiver's half
+#[allow(dead_code)]
+mod crypto;
+mod cursor;
+// The receiver's decoder, which --preview decodes sent frames with
+#[cfg(feature = "preview")]
+#[allow(dead_code)]
+mod decode;
+mod delta;
+mod encode;
+mod events;
+mod fec;
+mod follow;
+mod idle;
+pub mod logging;
+mod metrics;
+mod mirror;
+mod net;
+mod overlay;
+mod pacing;
+// Public so a receiver or test can draw a frame again to compare
+pub mod pattern;
+#[cfg(feature = "preview")]
+mod preview;
+mod profile;
+pub mod protocol;
+mod ratelimit;
+#[cfg(feature = "record")]
+mod record;
+mod replay;
+mod resize;
+mod rle;
+mod screenshot;
+mod streamer;
+mod targets;
+mod tls;
+mod variants;
+#[cfg(feature = "h264")]
+mod video;
+mod watermark;
+
+pub use events::{Event, EventKind, EVENT_QUEUE_DEPTH};
+pub use metrics::StatsSnapshot;
+pub use streamer::{EncodedFrame, Error, FrameCallback, Handle, Streamer};
+
+// Types the settings in `cli::Args` are made of
+pub use capture::CaptureFormat;
+pub use convert::{ColorRange, ColorSpace};
+pub use net::Transport;
+pub use overlay::Colour;
+pub use pacing::Pacing;
+pub use protocol::{Codec, FrameInfo, PixelFormat, Variant};
+pub use ratelimit::OverLimit;
+pub use resize::{Filter, Scale};
+pub use targets::TargetSelector;
+pub use watermark::Corner;
@@ -0,0 +1,37 @@
+use std::io::{self, IsTerminal, Write};
+
+use env_logger::{Builder, Env};
+use log::{debug, LevelFilter};
+
+/// Log to stderr at `level` unless RUST_LOG says otherwise. On a terminal each
+/// line is just the message, clearing the stats line it lands on first; redirected
+/// output gets env_logger's usual timestamp and level.
+pub fn init(level: LevelFilter) {
+  let mut builder = Builder::from_env(Env::default().default_filter_or(level.as_str()));
+  if io::stderr().is_terminal() {
+    builder.format(|buf, record| writeln!(buf, "\r\x1b[K{}", record.args()));
+  }
+  builder.init();
+}
+
+/// Redraw the live stats line on a terminal; headless runs log it at debug level.
+/// Logging below info level (--quiet) leaves it out.
+pub fn stats(line: &str) {
+  if log::max_level() < LevelFilter::Info {
+    return;
+  }
+  let mut stderr = io::stderr();
+  if stderr.is_terminal() {
+    let _ = write!(stderr, "\r{}    ", line);
+    let _ = stderr.flush();
+  } else {
+    debug!("{}", line);
+  }
+}
+
+/// Print a closing line on stderr at any log level, so even --quiet runs end with it
+pub fn summary(line: &str) {
+  let mut stderr = io::stderr();
+  let clear = if stderr.is_terminal() { "\r\x1b[K" } else { "" };
+  let _ = writeln!(stderr, "{}{}", clear, line);
+}
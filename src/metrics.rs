@@ -0,0 +1,291 @@
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{self, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use log::{error, info};
+
+// A scraper that connects and never sends its request can't hold up the next one
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// One stream's counts at a moment, from `Streamer::stats`
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct StatsSnapshot {
+  /// Frames handed to the network
+  pub frames_sent: u64,
+  /// Frames dropped before sending, by pacing, a full buffer or the limits
+  pub frames_dropped: u64,
+  /// Payload bytes sent, counting every receiver in --listen mode
+  pub bytes_sent: u64,
+  /// Frames sent per second over the last second
+  pub fps: f64,
+  /// Receivers currently connected; 0 or 1 unless in --listen mode
+  pub receivers: u64,
+}
+
+impl StatsSnapshot {
+  /// Several streams' counts together, as with --all-displays
+  pub fn sum(snapshots: impl IntoIterator<Item = StatsSnapshot>) -> Self {
+    snapshots
+      .into_iter()
+      .fold(StatsSnapshot::default(), |sum, stats| StatsSnapshot {
+        frames_sent: sum.frames_sent + stats.frames_sent,
+        frames_dropped: sum.frames_dropped + stats.frames_dropped,
+        bytes_sent: sum.bytes_sent + stats.bytes_sent,
+        fps: sum.fps + stats.fps,
+        receivers: sum.receivers + stats.receivers,
+      })
+  }
+}
+
+/// A stream's counts, written by its send loop and read by anyone: the stats
+/// line, the metrics server and `Streamer::stats`. It's a sequence lock, so a
+/// reader never waits on the writer and always gets counts from one moment: a
+/// reader that overlapped an update just reads again.
+#[derive(Default)]
+pub struct Metrics {
+  /// Odd while an update is under way
+  version: AtomicU64,
+  frames_sent: AtomicU64,
+  frames_dropped: AtomicU64,
+  bytes_sent: AtomicU64,
+  receivers: AtomicU64,
+  /// As f64 bits
+  fps: AtomicU64,
+}
+
+impl Metrics {
+  /// Change the counts as one. Only the stream's send loop calls this, so there
+  /// is never a second writer to race.
+  pub fn update(&self, change: impl FnOnce(&mut StatsSnapshot)) {
+    let mut stats = self.read();
+    change(&mut stats);
+    let version = self.version.load(Ordering::Relaxed);
+    self.version.store(version + 1, Ordering::Relaxed);
+    atomic::fence(Ordering::Release);
+    self.frames_sent.store(stats.frames_sent, Ordering::Relaxed);
+    self
+      .frames_dropped
+      .store(stats.frames_dropped, Ordering::Relaxed);
+    self.bytes_sent.store(stats.bytes_sent, Ordering::Relaxed);
+    self.receivers.store(stats.receivers, Ordering::Relaxed);
+    self.fps.store(stats.fps.to_bits(), Ordering::Relaxed);
+    self.version.store(version + 2, Ordering::Release);
+  }
+
+  /// A copy of every count as of the same moment
+  pub fn snapshot(&self) -> StatsSnapshot {
+    loop {
+      let version = self.version.load(Ordering::Acquire);
+      if version % 2 == 1 {
+        std::hint::spin_loop();
+        continue;
+      }
+      let stats = self.read();
+      atomic::fence(Ordering::Acquire);
+      if self.version.load(Ordering::Relaxed) == version {
+        return stats;
+      }
+    }
+  }
+
+  fn read(&self) -> StatsSnapshot {
+    StatsSnapshot {
+      frames_sent: self.frames_sent.load(Ordering::Relaxed),
+      frames_dropped: self.frames_dropped.load(Ordering::Relaxed),
+      bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+      fps: f64::from_bits(self.fps.load(Ordering::Relaxed)),
+      receivers: self.receivers.load(Ordering::Relaxed),
+    }
+  }
+
+  /// Everything in the Prometheus text exposition format
+  fn render(&self) -> String {
+    let stats = self.snapshot();
+    let metrics = [
+      (
+        "frames_sent_total",
+        "counter",
+        "Frames handed to the network",
+        stats.frames_sent as f64,
+      ),
+      (
+        "frames_dropped_total",
+        "counter",
+        "Frames dropped before sending",
+        stats.frames_dropped as f64,
+      ),
+      (
+        "bytes_sent_total",
+        "counter",
+        "Payload bytes sent, counting every receiver",
+        stats.bytes_sent as f64,
+      ),
+      (
+        "fps",
+        "gauge",
+        "Frames sent per second over the last second",
+        stats.fps,
+      ),
+      (
+        "receivers",
+        "gauge",
+        "Receivers currently connected",
+        stats.receivers as f64,
+      ),
+    ];
+
+    let mut out = String::new();
+    for (name, kind, help, value) in metrics {
+      let _ = writeln!(out, "# HELP screen_streamer_{} {}", name, help);
+      let _ = writeln!(out, "# TYPE screen_streamer_{} {}", name, kind);
+      let _ = writeln!(out, "screen_streamer_{} {}", name, value);
+    }
+    out
+  }
+}
+
+/// Lifetime counts for the closing summary
+#[derive(Debug, Default)]
+pub struct Totals {
+  pub frames: u64,
+  pub dropped: u64,
+  pub bytes: u64,
+}
+
+impl From<StatsSnapshot> for Totals {
+  fn from(stats: StatsSnapshot) -> Self {
+    Totals {
+      frames: stats.frames_sent,
+      dropped: stats.frames_dropped,
+      bytes: stats.bytes_sent,
+    }
+  }
+}
+
+impl Totals {
+  /// One line describing a session that lasted `elapsed`
+  pub fn summary(&self, elapsed: Duration) -> String {
+    let offered = self.frames + self.dropped;
+    let drop_rate = match offered {
+      0 => 0.0,
+      offered => self.dropped as f64 / offered as f64 * 100.0,
+    };
+    let seconds = elapsed.as_secs_f64();
+    let fps = if seconds > 0.0 {
+      self.frames as f64 / seconds
+    } else {
+      0.0
+    };
+    format!(
+      "📊 Session: {} frames sent, {} dropped ({:.1}%), {:.1}MB in {:.1}s, {:.1} FPS average",
+      self.frames,
+      self.dropped,
+      drop_rate,
+      self.bytes as f64 / (1024.0 * 1024.0),
+      seconds,
+      fps
+    )
+  }
+}
+
+/// Serve `GET /metrics` on `addr` from a background thread. Binding happens up
+/// front so a taken port is reported before streaming starts.
+pub fn serve(addr: SocketAddr, metrics: Arc<Metrics>) -> io::Result<()> {
+  let listener = TcpListener::bind(addr)?;
+  info!("📈 Metrics on http://{}/metrics", listener.local_addr()?);
+  thread::spawn(move || {
+    for stream in listener.incoming() {
+      let result = stream.and_then(|stream| respond(stream, &metrics));
+      if let Err(e) = result {
+        error!("❌ Metrics request failed: {}", e);
+      }
+    }
+  });
+  Ok(())
+}
+
+fn respond(stream: TcpStream, metrics: &Metrics) -> io::Result<()> {
+  stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+  let mut reader = BufReader::new(stream);
+  let mut request_line = String::new();
+  reader.read_line(&mut request_line)?;
+  // Skip the headers; nothing in them changes the answer
+  let mut header = String::new();
+  while reader.read_line(&mut header)? > 2 {
+    header.clear();
+  }
+
+  let path = request_line.split_whitespace().nth(1).unwrap_or("");
+  let (status, body) = if path == "/metrics" {
+    ("200 OK", metrics.render())
+  } else {
+    ("404 Not Found", "Not found\n".to_string())
+  };
+  write!(
+    reader.get_mut(),
+    "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+    status,
+    body.len(),
+    body
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn renders_prometheus_text() {
+    let metrics = Metrics::default();
+    metrics.update(|stats| {
+      stats.frames_sent = 42;
+      stats.fps = 59.5;
+    });
+    let text = metrics.render();
+    assert!(text.contains("# TYPE screen_streamer_frames_sent_total counter\n"));
+    assert!(text.contains("\nscreen_streamer_frames_sent_total 42\n"));
+    assert!(text.contains("\nscreen_streamer_fps 59.5\n"));
+    assert!(text.contains("\nscreen_streamer_receivers 0\n"));
+  }
+
+  #[test]
+  fn snapshots_see_whole_updates() {
+    let metrics = Metrics::default();
+    std::thread::scope(|scope| {
+      scope.spawn(|| {
+        for _ in 0..10_000 {
+          metrics.update(|stats| {
+            stats.frames_sent += 1;
+            stats.bytes_sent += 100;
+          });
+        }
+      });
+      for _ in 0..10_000 {
+        let stats = metrics.snapshot();
+        assert_eq!(stats.bytes_sent, stats.frames_sent * 100);
+      }
+    });
+    assert_eq!(metrics.snapshot().frames_sent, 10_000);
+  }
+
+  #[test]
+  fn summarises_a_session() {
+    let totals = Totals {
+      frames: 300,
+      dropped: 100,
+      bytes: 3 * 1024 * 1024,
+    };
+    assert_eq!(
+      totals.summary(Duration::from_secs(10)),
+      "📊 Session: 300 frames sent, 100 dropped (25.0%), 3.0MB in 10.0s, 30.0 FPS average"
+    );
+    // Stopping before the first frame divides by nothing
+    assert!(Totals::default()
+      .summary(Duration::ZERO)
+      .contains("0 dropped (0.0%), 0.0MB in 0.0s, 0.0 FPS"));
+  }
+}
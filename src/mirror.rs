@@ -0,0 +1,104 @@
+// --preview: the frames being sent, decoded again the way a receiver decodes them
+// and shown in a local window, so a wrong crop, scale or watermark shows before
+// anyone else sees it. The send loop only copies each encoded frame into a short
+// queue; decoding and drawing happen on the thread showing the window. A frame
+// that finds the queue full is skipped rather than waited for, so a slow window
+// never holds up sending, and deltas and H.264 pick up again at the next keyframe.
+
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+
+use crate::protocol::{Codec, FrameInfo, PixelFormat};
+
+/// Frames waiting for the window at most
+const QUEUE_DEPTH: usize = 4;
+
+/// One sent frame, with what it takes to decode it
+#[cfg_attr(not(feature = "preview"), allow(dead_code))]
+pub struct Mirrored {
+  info: FrameInfo,
+  codec: Codec,
+  pixel_format: PixelFormat,
+  data: Vec<u8>,
+  /// Where --cursor-layer put the cursor, which the window draws in as a
+  /// receiver would
+  cursor: Option<(i32, i32)>,
+}
+
+/// The send loop's end of the queue
+pub struct Feed(SyncSender<Mirrored>);
+
+impl Feed {
+  /// Queue a copy of one encoded frame unless the window is behind. Returns
+  /// false once the window is gone, after which there's no point calling again.
+  pub fn send(
+    &self,
+    info: &FrameInfo,
+    (codec, pixel_format): (Codec, PixelFormat),
+    data: &[u8],
+    cursor: Option<(i32, i32)>,
+  ) -> bool {
+    let frame = Mirrored {
+      info: *info,
+      codec,
+      pixel_format,
+      data: data.to_vec(),
+      cursor,
+    };
+    !matches!(self.0.try_send(frame), Err(TrySendError::Disconnected(_)))
+  }
+}
+
+pub fn channel() -> (Feed, Receiver<Mirrored>) {
+  let (tx, rx) = mpsc::sync_channel(QUEUE_DEPTH);
+  (Feed(tx), rx)
+}
+
+/// Show `frames` until the stream ends or the user closes the window
+#[cfg(feature = "preview")]
+pub fn show(frames: Receiver<Mirrored>) -> Result<(), String> {
+  use log::{info, warn};
+
+  use crate::cursor::Cursor;
+  use crate::decode::Decoder;
+  use crate::preview::Preview;
+
+  let mut window = Preview::new("screen-streamer: sending");
+  // Remade whenever the codec, pixel format or size changes
+  let mut decoder = None;
+  let mut made_for = None;
+  let mut composited = Vec::new();
+  // Ends once the stream drops its Feed
+  for frame in frames {
+    let size = [frame.info.width, frame.info.height];
+    let stride = size[0] * frame.pixel_format.bytes_per_pixel();
+    let key = (frame.codec, frame.pixel_format, size);
+    if made_for != Some(key) {
+      decoder = Some(Decoder::new(frame.codec, frame.pixel_format, size, stride));
+      made_for = Some(key);
+    }
+    let decoder = decoder.as_mut().unwrap();
+    let pixels = match decoder.decode(&frame.data, Some(frame.info.seq)) {
+      Ok(Some(pixels)) => pixels,
+      // A delta after a skipped frame, waiting for the next keyframe
+      Ok(None) => continue,
+      Err(e) => {
+        warn!("⚠️ Preview couldn't decode frame {}: {}", frame.info.seq, e);
+        continue;
+      }
+    };
+    let pixels = match frame.cursor {
+      Some((x, y)) => {
+        composited.clear();
+        composited.extend_from_slice(pixels);
+        Cursor::arrow(x, y).draw(&mut composited, stride as usize, size, frame.pixel_format);
+        &composited[..]
+      }
+      None => pixels,
+    };
+    if !window.show(pixels, size, stride, frame.pixel_format)? {
+      info!("👋 Preview closed, streaming goes on");
+      return Ok(());
+    }
+  }
+  Ok(())
+}
@@ -0,0 +1,722 @@
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::net::{
+  Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6, TcpListener, TcpStream, ToSocketAddrs, UdpSocket,
+};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+#[cfg(unix)]
+use socket2::SockAddr;
+use socket2::{Domain, Protocol, SockRef, Socket, TcpKeepalive, Type};
+use tungstenite::{Message, WebSocket};
+
+use crate::protocol::{
+  self, Capabilities, FrameInfo, Handshake, PixelFormat, Variant, METADATA_SIZE, NO_PIXEL_FORMAT,
+};
+use crate::tls::{TlsConfig, TlsStream};
+use crate::variants::Ladder;
+
+pub const INITIAL_BACKOFF: Duration = Duration::from_millis(250);
+pub const MAX_BACKOFF: Duration = Duration::from_secs(8);
+
+// A receiver that asked for negotiation answers right after reading the handshake
+const NEGOTIATE_TIMEOUT: Duration = Duration::from_secs(2);
+
+// A browser sends its request as soon as it connects
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Unanswered keepalive probes before the system drops the connection, where it
+// can be set (Windows always sends 10)
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+const KEEPALIVE_RETRIES: u32 = 3;
+
+// Separates the JPEG parts of an MJPEG response
+const MJPEG_BOUNDARY: &str = "frame";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+  Tcp,
+  Udp,
+  /// WebSocket, accepted in listen mode so browsers can connect
+  Ws,
+  /// Motion JPEG over HTTP, viewable by opening the address in a browser
+  Mjpeg,
+  /// The TCP framing over a Unix domain socket, for a receiver on the same machine
+  #[cfg(unix)]
+  Unix,
+}
+
+impl Transport {
+  /// Whether frames go out with the TCP framing on a two-way byte stream, which
+  /// also carries negotiation: TCP itself or a Unix domain socket
+  pub fn is_stream(self) -> bool {
+    self == Transport::Tcp || self.is_unix()
+  }
+
+  /// Whether this is a Unix domain socket, which is always false off Unix
+  pub fn is_unix(self) -> bool {
+    match self {
+      #[cfg(unix)]
+      Transport::Unix => true,
+      _ => false,
+    }
+  }
+}
+
+impl FromStr for Transport {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "tcp" => Ok(Transport::Tcp),
+      "udp" => Ok(Transport::Udp),
+      "ws" => Ok(Transport::Ws),
+      "mjpeg" => Ok(Transport::Mjpeg),
+      #[cfg(unix)]
+      "unix" => Ok(Transport::Unix),
+      #[cfg(not(unix))]
+      "unix" => Err("--transport unix needs a Unix platform".to_string()),
+      _ => Err(format!(
+        "Unknown transport '{}' (expected tcp, udp, ws, mjpeg or unix)",
+        s
+      )),
+    }
+  }
+}
+
+/// Which addresses of a host to use
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpVersion {
+  /// Both, in the order the system resolver prefers
+  Auto,
+  V4,
+  V6,
+}
+
+impl IpVersion {
+  fn allows(self, addr: &SocketAddr) -> bool {
+    match self {
+      IpVersion::Auto => true,
+      IpVersion::V4 => addr.is_ipv4(),
+      IpVersion::V6 => addr.is_ipv6(),
+    }
+  }
+}
+
+impl FromStr for IpVersion {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "auto" => Ok(IpVersion::Auto),
+      "4" => Ok(IpVersion::V4),
+      "6" => Ok(IpVersion::V6),
+      _ => Err(format!(
+        "Unknown IP version '{}' (expected auto, 4 or 6)",
+        s
+      )),
+    }
+  }
+}
+
+/// Resolve a host (IPv4/IPv6 literal or DNS name) and port into every socket
+/// address of the `version` asked for, in the resolver's order. IPv6 literals
+/// may be bracketed and carry a link-local scope id (`fe80::1%2`); interface-name
+/// scopes (`fe80::1%eth0`) are left to the system resolver.
+pub fn resolve(host: &str, port: u16, version: IpVersion) -> Result<Vec<SocketAddr>, String> {
+  let host = host
+    .strip_prefix('[')
+    .and_then(|h| h.strip_suffix(']'))
+    .unwrap_or(host);
+
+  let addrs: Vec<SocketAddr> = match host.split_once('%').and_then(|(ip, scope)| {
+    Some(SocketAddrV6::new(ip.parse().ok()?, port, 0, scope.parse().ok()?).into())
+  }) {
+    Some(addr) => vec![addr],
+    None => (host, port)
+      .to_socket_addrs()
+      .map_err(|e| format!("Invalid host '{}': {}", host, e))?
+      .collect(),
+  };
+  if addrs.is_empty() {
+    return Err(format!("Host '{}' did not resolve to any address", host));
+  }
+  let allowed: Vec<_> = addrs
+    .into_iter()
+    .filter(|addr| version.allows(addr))
+    .collect();
+  if allowed.is_empty() {
+    let family = if version == IpVersion::V4 {
+      "IPv4"
+    } else {
+      "IPv6"
+    };
+    return Err(format!(
+      "Host '{}' has no {} address (--ip-version)",
+      host, family
+    ));
+  }
+  Ok(allowed)
+}
+
+/// Connect to the first of `addrs` that accepts within `timeout` each
+fn connect(addrs: &[SocketAddr], timeout: Duration) -> io::Result<TcpStream> {
+  let mut failures = Vec::new();
+  let mut last = None;
+  for &addr in addrs {
+    match TcpStream::connect_timeout(&addr, timeout) {
+      Ok(stream) => return Ok(stream),
+      Err(e) => {
+        let e = match e.kind() {
+          io::ErrorKind::TimedOut => io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!(
+              "connect timed out after {:.1}s (--connect-timeout)",
+              timeout.as_secs_f64()
+            ),
+          ),
+          _ => e,
+        };
+        failures.push(format!("{}: {}", addr, e));
+        last = Some(e);
+      }
+    }
+  }
+  let last = last.unwrap_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address"));
+  // One address's error reads as it is; several are listed, keeping the last kind
+  Err(match failures.len() {
+    0 | 1 => last,
+    _ => io::Error::new(last.kind(), failures.join("; ")),
+  })
+}
+
+/// Settings shared by outgoing connections and accepted receivers
+#[derive(Clone)]
+pub struct LinkOptions {
+  pub transport: Transport,
+  /// Disable Nagle's algorithm so each frame goes out as soon as it's flushed
+  pub nodelay: bool,
+  /// How long to wait for a TCP connection to be accepted
+  pub connect_timeout: Duration,
+  /// Idle time before, and between, TCP keepalive probes; `None` sends none
+  pub keepalive: Option<Duration>,
+  /// Largest chunk of a frame on TCP, or whole datagram on UDP
+  pub chunk_size: usize,
+  /// UDP data slices per parity slice; `None` sends no parity
+  pub fec: Option<u16>,
+  /// Encrypt TCP connections; `None` sends everything in the clear
+  pub tls: Option<TlsConfig>,
+  /// Socket file to connect to with --transport unix
+  pub path: Option<PathBuf>,
+}
+
+/// A stream carrying the TCP framing: TCP in the clear or behind TLS, or a Unix
+/// domain socket
+pub enum TcpLink {
+  Plain(TcpStream),
+  Tls(Box<TlsStream>),
+  #[cfg(unix)]
+  Unix(UnixStream),
+}
+
+impl TcpLink {
+  fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+    match self {
+      TcpLink::Plain(stream) => stream.set_read_timeout(timeout),
+      TcpLink::Tls(stream) => stream.socket().set_read_timeout(timeout),
+      #[cfg(unix)]
+      TcpLink::Unix(stream) => stream.set_read_timeout(timeout),
+    }
+  }
+}
+
+impl Read for TcpLink {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    match self {
+      TcpLink::Plain(stream) => stream.read(buf),
+      TcpLink::Tls(stream) => stream.read(buf),
+      #[cfg(unix)]
+      TcpLink::Unix(stream) => stream.read(buf),
+    }
+  }
+}
+
+impl Write for TcpLink {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    match self {
+      TcpLink::Plain(stream) => stream.write(buf),
+      TcpLink::Tls(stream) => stream.write(buf),
+      #[cfg(unix)]
+      TcpLink::Unix(stream) => stream.write(buf),
+    }
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    match self {
+      TcpLink::Plain(stream) => stream.flush(),
+      TcpLink::Tls(stream) => stream.flush(),
+      #[cfg(unix)]
+      TcpLink::Unix(stream) => stream.flush(),
+    }
+  }
+}
+
+/// An open link to the receiver over any transport
+pub enum Connection {
+  Tcp {
+    stream: BufWriter<TcpLink>,
+    chunk_size: usize,
+  },
+  Udp {
+    socket: UdpSocket,
+    datagram_size: usize,
+    fec: Option<u16>,
+  },
+  Ws(WebSocket<TcpStream>),
+  /// A `multipart/x-mixed-replace` HTTP response with one JPEG part per frame
+  Mjpeg(BufWriter<TcpStream>),
+}
+
+impl Connection {
+  /// Open a connection and send the handshake, so every (re)connect starts a fresh
+  /// stream. TCP tries each of `addrs` in turn; UDP, which can't tell whether
+  /// anyone is listening, sends to the first.
+  pub fn open(
+    addrs: &[SocketAddr],
+    options: &LinkOptions,
+    handshake: &Handshake,
+  ) -> io::Result<Connection> {
+    let mut conn = match options.transport {
+      Transport::Tcp => Connection::tcp(connect(addrs, options.connect_timeout)?, options)?,
+      // Local only, so there's no Nagle, keepalive or TLS to set up
+      #[cfg(unix)]
+      Transport::Unix => {
+        let path = options.path.as_deref().unwrap_or(Path::new(""));
+        let socket = Socket::new(Domain::UNIX, Type::STREAM, None)?;
+        socket.connect(&SockAddr::unix(path)?)?;
+        Connection::Tcp {
+          stream: BufWriter::with_capacity(tcp_buffer_size(options), TcpLink::Unix(socket.into())),
+          chunk_size: options.chunk_size,
+        }
+      }
+      Transport::Udp => {
+        let Some(&addr) = addrs.first() else {
+          return Err(io::Error::new(io::ErrorKind::InvalidInput, "no address"));
+        };
+        let local: SocketAddr = match addr {
+          SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+          SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(addr)?;
+        Connection::Udp {
+          socket,
+          datagram_size: options.chunk_size,
+          fec: options.fec,
+        }
+      }
+      Transport::Ws | Transport::Mjpeg => {
+        return Err(io::Error::new(
+          io::ErrorKind::InvalidInput,
+          "browser receivers connect to the streamer; use --listen",
+        ))
+      }
+    };
+    conn.send_handshake(handshake)?;
+    Ok(conn)
+  }
+
+  /// The address a connection to the receiver reached, which a Unix socket doesn't
+  /// have
+  pub fn peer_addr(&self) -> Option<SocketAddr> {
+    match self {
+      Connection::Tcp { stream, .. } => match stream.get_ref() {
+        TcpLink::Plain(stream) => stream.peer_addr().ok(),
+        TcpLink::Tls(stream) => stream.socket().peer_addr().ok(),
+        #[cfg(unix)]
+        TcpLink::Unix(_) => None,
+      },
+      Connection::Udp { socket, .. } => socket.peer_addr().ok(),
+      // Browsers dial in, and `Listener::accept` says where from
+      Connection::Ws(_) | Connection::Mjpeg(_) => None,
+    }
+  }
+
+  fn tcp(stream: TcpStream, options: &LinkOptions) -> io::Result<Connection> {
+    configure(&stream, options)?;
+    let stream = match &options.tls {
+      Some(tls) => TcpLink::Tls(Box::new(tls.wrap(stream)?)),
+      None => TcpLink::Plain(stream),
+    };
+    Ok(Connection::Tcp {
+      stream: BufWriter::with_capacity(tcp_buffer_size(options), stream),
+      chunk_size: options.chunk_size,
+    })
+  }
+
+  fn send_handshake(&mut self, handshake: &Handshake) -> io::Result<()> {
+    let bytes = handshake.to_bytes();
+    match self {
+      Connection::Tcp { stream, .. } => {
+        stream.write_all(&bytes)?;
+        stream.flush()
+      }
+      Connection::Udp { socket, .. } => socket.send(&bytes).map(|_| ()),
+      Connection::Ws(socket) => socket
+        .send(Message::Text(handshake.to_json()))
+        .map_err(ws_error),
+      // Browsers have no use for the handshake; the response headers stand in for it
+      Connection::Mjpeg(stream) => {
+        write!(
+          stream,
+          "HTTP/1.1 200 OK\r\nContent-Type: multipart/x-mixed-replace; boundary={}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+          MJPEG_BOUNDARY
+        )?;
+        stream.flush()
+      }
+    }
+  }
+
+  /// Agree with the receiver on the pixel format, out of the ones in `offer`, and
+  /// check it can decode the handshake's codec. Unsupported errors mean the two
+  /// have nothing in common, so connecting again won't help.
+  pub fn negotiate(
+    &mut self,
+    handshake: &Handshake,
+    offer: Capabilities,
+  ) -> io::Result<PixelFormat> {
+    if !handshake.negotiate {
+      return Ok(handshake.pixel_format);
+    }
+    let Connection::Tcp { stream, .. } = self else {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "pixel format negotiation needs TCP",
+      ));
+    };
+
+    stream.write_all(&offer.to_bytes())?;
+    stream.flush()?;
+    let socket = stream.get_mut();
+    socket.set_read_timeout(Some(NEGOTIATE_TIMEOUT))?;
+    let mut answer = [0u8; 3];
+    socket.read_exact(&mut answer)?;
+    socket.set_read_timeout(None)?;
+
+    let answer_caps = Capabilities::from_bytes([answer[0], answer[1]]);
+    let chosen = protocol::choose_pixel_format(
+      handshake.codec,
+      handshake.pixel_format,
+      offer,
+      answer_caps,
+      answer[2],
+    );
+    let choice = chosen
+      .as_ref()
+      .map_or(NO_PIXEL_FORMAT, |&format| format as u8);
+    stream.write_all(&[choice])?;
+    stream.flush()?;
+    chosen.map_err(|e| io::Error::new(io::ErrorKind::Unsupported, e))
+  }
+
+  /// Negotiate as above on a stream whose handshake has VARIANTS, where the
+  /// receiver also says which variant it wants and gets the closest of the main
+  /// stream and `ladder`'s. Returns the main stream's pixel format, which is only
+  /// settled here while `handshake.negotiate` is set, and the variant the receiver
+  /// gets, 0 being the main stream.
+  pub fn negotiate_variant(
+    &mut self,
+    handshake: &Handshake,
+    offer: Capabilities,
+    ladder: &Ladder,
+  ) -> io::Result<(PixelFormat, usize)> {
+    let Connection::Tcp { stream, .. } = self else {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "variants need TCP",
+      ));
+    };
+
+    stream.write_all(&offer.to_bytes())?;
+    stream.flush()?;
+    let socket = stream.get_mut();
+    socket.set_read_timeout(Some(NEGOTIATE_TIMEOUT))?;
+    let mut answer = [0u8; 3 + protocol::WANT_SIZE];
+    socket.read_exact(&mut answer)?;
+    socket.set_read_timeout(None)?;
+
+    let answer_caps = Capabilities::from_bytes([answer[0], answer[1]]);
+    let want = Variant::from_bytes(answer[3..].try_into().unwrap());
+    // Once the first receiver has settled the main stream's format, later ones
+    // take it as it is or not at all
+    let main = if handshake.negotiate {
+      protocol::choose_pixel_format(
+        handshake.codec,
+        handshake.pixel_format,
+        offer,
+        answer_caps,
+        answer[2],
+      )
+    } else {
+      let fixed = Capabilities::new(&[handshake.codec], &[handshake.pixel_format]);
+      if fixed.intersect(answer_caps) == fixed {
+        Ok(handshake.pixel_format)
+      } else {
+        Err(format!(
+          "the receiver can't show {} frames in {:?}",
+          handshake.codec.name(),
+          handshake.pixel_format
+        ))
+      }
+    };
+    let main_format = *main.as_ref().unwrap_or(&handshake.pixel_format);
+    let main_stream = Handshake {
+      pixel_format: main_format,
+      ..*handshake
+    };
+    let Some(index) = ladder.pick(&main_stream, main.is_ok(), answer_caps, want) else {
+      stream.write_all(&[NO_PIXEL_FORMAT])?;
+      stream.flush()?;
+      let reason = main.err().unwrap_or_default();
+      return Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{}, nor any of the variants", reason),
+      ));
+    };
+    let variant = ladder.handshake(index, &main_stream);
+    stream.write_all(&[variant.pixel_format as u8])?;
+    stream.write_all(&variant.to_bytes())?;
+    stream.flush()?;
+    Ok((main_format, index))
+  }
+
+  pub fn send_frame(&mut self, info: &FrameInfo, data: &[u8]) -> io::Result<()> {
+    match self {
+      Connection::Tcp { stream, chunk_size } => {
+        protocol::send_frame(stream, info, data, *chunk_size)
+      }
+      Connection::Udp {
+        socket,
+        datagram_size,
+        fec,
+      } => protocol::send_frame_datagrams(socket, info, data, *datagram_size, *fec),
+      Connection::Ws(socket) => socket
+        .send(Message::Binary(protocol::frame_message(info, data)))
+        .map_err(ws_error),
+      Connection::Mjpeg(stream) => {
+        write!(
+          stream,
+          "--{}\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
+          MJPEG_BOUNDARY,
+          data.len()
+        )?;
+        stream.write_all(data)?;
+        stream.write_all(b"\r\n")?;
+        stream.flush()
+      }
+    }
+  }
+
+  /// Tell the receiver the frames after this are a new size, with a marker from
+  /// `FrameInfo::size_change`. UDP datagrams carry their frame's size anyway, and
+  /// MJPEG parts are images of any size.
+  pub fn send_size_change(&mut self, marker: &FrameInfo) -> io::Result<()> {
+    match self {
+      Connection::Tcp { stream, chunk_size } => {
+        protocol::send_frame(stream, marker, &[], *chunk_size)
+      }
+      Connection::Ws(socket) => socket
+        .send(Message::Binary(protocol::frame_message(marker, &[])))
+        .map_err(ws_error),
+      Connection::Udp { .. } | Connection::Mjpeg(_) => Ok(()),
+    }
+  }
+
+  /// Send the end-of-stream marker; `seq` is the number the next frame would have had
+  pub fn send_end_of_stream(&mut self, seq: u64) -> io::Result<()> {
+    match self {
+      Connection::Tcp { stream, .. } => protocol::send_end_of_stream(stream, seq),
+      Connection::Udp { socket, .. } => protocol::send_end_of_stream_datagram(socket, seq),
+      Connection::Ws(socket) => {
+        let marker = protocol::frame_message(&FrameInfo::end_of_stream(seq), &[]);
+        socket.send(Message::Binary(marker)).map_err(ws_error)?;
+        socket.close(None).map_err(ws_error)?;
+        socket.flush().map_err(ws_error)
+      }
+      Connection::Mjpeg(stream) => {
+        write!(stream, "--{}--\r\n", MJPEG_BOUNDARY)?;
+        stream.flush()
+      }
+    }
+  }
+}
+
+/// Why an open connection failed, judging by the error its last send returned
+pub fn disconnect_reason(e: &io::Error) -> &'static str {
+  match e.kind() {
+    // Also what a send reports once keepalive probes have gone unanswered
+    io::ErrorKind::TimedOut => "keepalive or retransmit timeout, the receiver stopped answering",
+    io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted => {
+      "the receiver reset the connection"
+    }
+    io::ErrorKind::BrokenPipe | io::ErrorKind::UnexpectedEof => {
+      "the receiver closed the connection"
+    }
+    io::ErrorKind::ConnectionRefused => "the receiver is no longer listening",
+    _ => "send failed",
+  }
+}
+
+// Sized to hold a full chunk plus the metadata and chunk-size prefixes, so each
+// chunk leaves in one write() instead of separate writes for the 4-byte size and
+// the data. A 720p BGRA frame (3.5MB, 15 chunks) drops from 31 syscalls to ~16;
+// a smaller buffer would be bypassed entirely by the large chunk writes.
+fn tcp_buffer_size(options: &LinkOptions) -> usize {
+  options.chunk_size + METADATA_SIZE + 4
+}
+
+/// Apply TCP_NODELAY and keepalive to a TCP connection in either direction
+fn configure(stream: &TcpStream, options: &LinkOptions) -> io::Result<()> {
+  stream.set_nodelay(options.nodelay)?;
+  if let Some(idle) = options.keepalive {
+    let keepalive = TcpKeepalive::new().with_time(idle).with_interval(idle);
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    let keepalive = keepalive.with_retries(KEEPALIVE_RETRIES);
+    SockRef::from(stream).set_tcp_keepalive(&keepalive)?;
+  }
+  Ok(())
+}
+
+/// Read and discard an HTTP request; every path gets the same stream
+fn read_http_request(stream: &TcpStream) -> io::Result<()> {
+  stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+  let mut reader = BufReader::new(stream);
+  let mut line = String::new();
+  // The request line and headers end at the first empty line
+  while reader.read_line(&mut line)? > 2 {
+    line.clear();
+  }
+  stream.set_read_timeout(None)
+}
+
+fn ws_error(e: tungstenite::Error) -> io::Error {
+  match e {
+    tungstenite::Error::Io(e) => e,
+    e => io::Error::other(e),
+  }
+}
+
+/// Server side of `--listen`: receivers connect to us instead of the other way round
+pub struct Listener {
+  listener: TcpListener,
+  options: LinkOptions,
+}
+
+impl Listener {
+  /// Bind with SO_REUSEADDR so a restarted streamer can reclaim the port immediately
+  pub fn bind(addr: SocketAddr, options: &LinkOptions) -> io::Result<Listener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(16)?;
+    Ok(Listener {
+      listener: socket.into(),
+      options: options.clone(),
+    })
+  }
+
+  pub fn local_addr(&self) -> io::Result<SocketAddr> {
+    self.listener.local_addr()
+  }
+
+  /// Block until a receiver connects, then send it the handshake. Browser
+  /// receivers get their HTTP request answered first.
+  pub fn accept(&self, handshake: &Handshake) -> io::Result<(Connection, SocketAddr)> {
+    let (stream, peer) = self.listener.accept()?;
+    let mut conn = match self.options.transport {
+      Transport::Ws => {
+        configure(&stream, &self.options)?;
+        let socket = tungstenite::accept(stream).map_err(|e| io::Error::other(e.to_string()))?;
+        Connection::Ws(socket)
+      }
+      Transport::Mjpeg => {
+        configure(&stream, &self.options)?;
+        read_http_request(&stream)?;
+        Connection::Mjpeg(BufWriter::new(stream))
+      }
+      _ => Connection::tcp(stream, &self.options)?,
+    };
+    conn.send_handshake(handshake)?;
+    Ok((conn, peer))
+  }
+}
+
+/// Exponential backoff between reconnect attempts: 250ms doubling up to 8s
+pub struct Backoff {
+  delay: Duration,
+  attempts: u32,
+  max_retries: Option<u32>,
+}
+
+impl Backoff {
+  /// `max_retries` caps consecutive failed attempts; `None` retries forever
+  pub fn new(max_retries: Option<u32>) -> Self {
+    Backoff {
+      delay: INITIAL_BACKOFF,
+      attempts: 0,
+      max_retries,
+    }
+  }
+
+  /// Call after a successful connect so the next outage starts from the shortest delay
+  pub fn reset(&mut self) {
+    self.delay = INITIAL_BACKOFF;
+    self.attempts = 0;
+  }
+
+  /// Delay to wait before the next attempt, or `None` once retries are exhausted
+  pub fn next_delay(&mut self) -> Option<Duration> {
+    if self.max_retries.is_some_and(|max| self.attempts >= max) {
+      return None;
+    }
+    self.attempts += 1;
+    let delay = self.delay;
+    self.delay = (self.delay * 2).min(MAX_BACKOFF);
+    Some(delay)
+  }
+
+  pub fn attempts(&self) -> u32 {
+    self.attempts
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn resolve_keeps_the_ip_version_asked_for() {
+    let v6 = resolve("[::1]", 9000, IpVersion::Auto).unwrap();
+    assert_eq!(v6, ["[::1]:9000".parse::<SocketAddr>().unwrap()]);
+    assert!(resolve("::1", 9000, IpVersion::V4).is_err());
+    assert!(resolve("127.0.0.1", 9000, IpVersion::V4).is_ok());
+  }
+
+  #[test]
+  fn connect_falls_through_to_an_address_that_answers() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    // A port nothing listens on any more, refused at once
+    let gone = TcpListener::bind("127.0.0.1:0")
+      .unwrap()
+      .local_addr()
+      .unwrap();
+    let live = listener.local_addr().unwrap();
+    let stream = connect(&[gone, live], Duration::from_secs(1)).unwrap();
+    assert_eq!(stream.peer_addr().unwrap(), live);
+    let e = connect(&[gone, gone], Duration::from_secs(1)).unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::ConnectionRefused);
+  }
+}
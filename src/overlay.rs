@@ -0,0 +1,275 @@
+// --cursor-overlay: a translucent disc drawn under the mouse cursor into every
+// frame, after cropping and resizing, so tutorials and recorded demos show where
+// the pointer is even with --no-cursor or a cursor too small to follow. It is
+// composited here rather than by the capturer, so it works the same on every
+// platform. The mouse_position crate only reports where the cursor is, not its
+// buttons, so the disc marks the pointer all the time rather than only clicks.
+// Positions are mapped like --follow-cursor's, from the display's own pixels.
+// --cursor-layer tracks the cursor the same way but sends where it is beside the
+// frames instead, as described in cursor.rs.
+
+use std::str::FromStr;
+
+use scap::capturer::Area;
+
+use crate::follow;
+
+/// Colour with alpha, as given to --overlay-colour
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Colour {
+  pub red: u8,
+  pub green: u8,
+  pub blue: u8,
+  /// How much of the colour covers the frame, from 0 (none) to 255 (opaque)
+  pub alpha: u8,
+}
+
+impl FromStr for Colour {
+  type Err = String;
+
+  /// `RRGGBB` or `RRGGBBAA` in hex, optionally after a `#`
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    let invalid = || {
+      format!(
+        "Invalid --overlay-colour '{}' (expected RRGGBB or RRGGBBAA)",
+        s
+      )
+    };
+    if !matches!(hex.len(), 6 | 8) || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+      return Err(invalid());
+    }
+    let byte = |i: usize| {
+      hex
+        .get(i * 2..i * 2 + 2)
+        .map_or(Ok(255), |pair| u8::from_str_radix(pair, 16))
+        .map_err(|_| invalid())
+    };
+    Ok(Colour {
+      red: byte(0)?,
+      green: byte(1)?,
+      blue: byte(2)?,
+      alpha: byte(3)?,
+    })
+  }
+}
+
+/// Maps display pixels onto the frames of the captured area as sent
+#[derive(Debug, Clone, Copy)]
+struct Placement {
+  /// The captured area in display pixels: --crop, or the whole display
+  origin: (f64, f64),
+  size: (f64, f64),
+}
+
+impl Placement {
+  fn new(area: &Area) -> Self {
+    Placement {
+      origin: (area.origin.x, area.origin.y),
+      size: (area.size.width, area.size.height),
+    }
+  }
+
+  /// Where a cursor at display pixel `cursor` lands in the output frame. The area
+  /// was captured at `frame` pixels, and `region` pixels from `offset` on were
+  /// kept of that before resizing to `output`.
+  fn position(
+    &self,
+    cursor: (f64, f64),
+    frame: [u32; 2],
+    [offset, region]: [[u32; 2]; 2],
+    output: [u32; 2],
+  ) -> (f64, f64) {
+    let map = |cursor: f64, origin: f64, size: f64, axis: usize| {
+      let in_frame = (cursor - origin) * frame[axis] as f64 / size;
+      (in_frame - offset[axis] as f64) * output[axis] as f64 / region[axis] as f64
+    };
+    (
+      map(cursor.0, self.origin.0, self.size.0, 0),
+      map(cursor.1, self.origin.1, self.size.1, 1),
+    )
+  }
+}
+
+/// A disc drawn under the cursor into frames of the captured area
+pub struct Overlay {
+  placement: Placement,
+  radius: u32,
+  colour: Colour,
+  /// Last known cursor position, kept while it can't be read
+  cursor: Option<(f64, f64)>,
+}
+
+impl Overlay {
+  /// Mark the cursor in frames of `area` with a disc of `radius` pixels, measured
+  /// in the frames as sent
+  pub fn new(area: &Area, radius: u32, colour: Colour) -> Self {
+    Overlay {
+      placement: Placement::new(area),
+      radius,
+      colour,
+      cursor: None,
+    }
+  }
+
+  /// Draw the disc into a BGRA frame of `output` pixels whose rows are `stride`
+  /// bytes apart. It was captured at `frame` pixels, and `region` pixels from
+  /// `offset` on were kept of that before resizing to `output`.
+  pub fn draw(
+    &mut self,
+    bgra: &mut [u8],
+    stride: usize,
+    frame: [u32; 2],
+    [offset, region]: [[u32; 2]; 2],
+    output: [u32; 2],
+  ) {
+    if let Some(cursor) = follow::cursor() {
+      self.cursor = Some(cursor);
+    }
+    let Some(cursor) = self.cursor else {
+      return;
+    };
+    let centre = self
+      .placement
+      .position(cursor, frame, [offset, region], output);
+    fill_disc(
+      bgra,
+      stride,
+      output,
+      centre,
+      self.radius as f64,
+      self.colour,
+    );
+  }
+}
+
+/// Where the cursor is in frames of the captured area, for --cursor-layer
+pub struct CursorLayer {
+  placement: Placement,
+  /// Last known cursor position, kept while it can't be read
+  cursor: Option<(f64, f64)>,
+}
+
+impl CursorLayer {
+  pub fn new(area: &Area) -> Self {
+    CursorLayer {
+      placement: Placement::new(area),
+      cursor: None,
+    }
+  }
+
+  /// The cursor's pixel in an output frame sized as for `Overlay::draw`, once its
+  /// position has been read at all
+  pub fn locate(
+    &mut self,
+    frame: [u32; 2],
+    [offset, region]: [[u32; 2]; 2],
+    output: [u32; 2],
+  ) -> Option<(i32, i32)> {
+    if let Some(cursor) = follow::cursor() {
+      self.cursor = Some(cursor);
+    }
+    let (x, y) = self
+      .placement
+      .position(self.cursor?, frame, [offset, region], output);
+    Some((x.floor() as i32, y.floor() as i32))
+  }
+}
+
+/// Blend `colour` over every pixel whose centre lies within `radius` of `centre`,
+/// clipped to the `size` frame
+fn fill_disc(
+  bgra: &mut [u8],
+  stride: usize,
+  [width, height]: [u32; 2],
+  (x, y): (f64, f64),
+  radius: f64,
+  colour: Colour,
+) {
+  let span = |centre: f64, size: u32| {
+    let first = (centre - radius).floor().max(0.0) as usize;
+    let last = ((centre + radius).ceil().max(0.0) as usize).min(size as usize);
+    first..last
+  };
+  let alpha = colour.alpha as u32;
+  let blend =
+    |under: u8, over: u8| ((under as u32 * (255 - alpha) + over as u32 * alpha + 127) / 255) as u8;
+  for row in span(y, height) {
+    let dy = row as f64 + 0.5 - y;
+    for column in span(x, width) {
+      let dx = column as f64 + 0.5 - x;
+      if dx * dx + dy * dy > radius * radius {
+        continue;
+      }
+      let pixel = &mut bgra[row * stride + column * 4..][..3];
+      pixel[0] = blend(pixel[0], colour.blue);
+      pixel[1] = blend(pixel[1], colour.green);
+      pixel[2] = blend(pixel[2], colour.red);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use scap::capturer::{Point, Size};
+
+  fn placement(x: f64, y: f64, width: f64, height: f64) -> Placement {
+    Placement::new(&Area {
+      origin: Point { x, y },
+      size: Size { width, height },
+    })
+  }
+
+  #[test]
+  fn parses_colours() {
+    assert_eq!(
+      "#FFD700".parse(),
+      Ok(Colour {
+        red: 255,
+        green: 215,
+        blue: 0,
+        alpha: 255
+      })
+    );
+    assert_eq!("00000040".parse::<Colour>().map(|c| c.alpha), Ok(64));
+    assert!("fff".parse::<Colour>().is_err());
+    assert!("gg0000".parse::<Colour>().is_err());
+  }
+
+  #[test]
+  fn follows_crop_scale_and_region() {
+    // A 1920x1080 display captured at half size, then 640x360 of it cut out at
+    // 100,50 and scaled up to 1280x720
+    let display = placement(0.0, 0.0, 1920.0, 1080.0);
+    let region = [[100, 50], [640, 360]];
+    assert_eq!(
+      display.position((400.0, 300.0), [960, 540], region, [1280, 720]),
+      (200.0, 200.0)
+    );
+    // --crop moves the origin, in display pixels
+    let crop = placement(200.0, 100.0, 800.0, 600.0);
+    let whole = [[0, 0], [800, 600]];
+    assert_eq!(
+      crop.position((210.0, 110.0), [800, 600], whole, [800, 600]),
+      (10.0, 10.0)
+    );
+  }
+
+  #[test]
+  fn blends_a_clipped_disc() {
+    // 4x4 black frame with a padded stride, disc centred on the top-left corner
+    let stride = 20;
+    let mut frame = vec![0u8; stride * 4];
+    let colour = "ff000080".parse().unwrap();
+    fill_disc(&mut frame, stride, [4, 4], (0.0, 0.0), 2.0, colour);
+    let pixel = |x: usize, y: usize| &frame[y * stride + x * 4..][..4];
+    // Red at half coverage, in BGRA order, leaving alpha alone
+    assert_eq!(pixel(0, 0), [0, 0, 128, 0]);
+    assert_eq!(pixel(1, 0), [0, 0, 128, 0]);
+    assert_eq!(pixel(1, 1), [0, 0, 0, 0]);
+    assert_eq!(pixel(2, 0), [0, 0, 0, 0]);
+    assert_eq!(pixel(3, 3), [0, 0, 0, 0]);
+    assert!(frame[16..20].iter().all(|&b| b == 0));
+  }
+}
@@ -0,0 +1,106 @@
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// How the capture thread holds captured frames to the target rate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pacing {
+  /// Sleep until each frame is due, then take the next frame the capturer has
+  Sleep,
+  /// Keep pulling frames and drop the ones that arrive ahead of schedule
+  Drop,
+}
+
+impl FromStr for Pacing {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "sleep" => Ok(Pacing::Sleep),
+      "drop" => Ok(Pacing::Drop),
+      _ => Err(format!("Unknown --pacing '{}' (expected sleep or drop)", s)),
+    }
+  }
+}
+
+/// Frame deadlines at whole multiples of the frame time from a fixed start, so
+/// rounding and late frames never accumulate into drift
+pub struct Schedule {
+  start: Instant,
+  frame_time: Duration,
+  /// Index of the next frame slot
+  next: u64,
+}
+
+impl Schedule {
+  pub fn new(start: Instant, frame_time: Duration) -> Self {
+    Schedule {
+      start,
+      frame_time,
+      next: 0,
+    }
+  }
+
+  /// When the next frame is due
+  pub fn deadline(&self) -> Instant {
+    let offset = self.frame_time.as_nanos() * self.next as u128;
+    self.start + Duration::from_nanos(offset as u64)
+  }
+
+  /// Whether a frame captured at `at` should be kept, claiming its slot if so. A
+  /// quarter frame of slack keeps capture jitter at the target rate from dropping
+  /// every other frame.
+  pub fn due(&mut self, at: Instant) -> bool {
+    if at + self.frame_time / 4 < self.deadline() {
+      return false;
+    }
+    // After a stall, move on to the slot after `at` instead of accepting a burst
+    let elapsed = at.saturating_duration_since(self.start).as_nanos();
+    let slot = (elapsed / self.frame_time.as_nanos()) as u64 + 1;
+    self.next = (self.next + 1).max(slot);
+    true
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const FRAME: Duration = Duration::from_millis(10);
+
+  #[test]
+  fn deadlines_do_not_drift() {
+    let start = Instant::now();
+    let mut schedule = Schedule::new(start, Duration::from_nanos(16_666_667));
+    for _ in 0..600 {
+      let deadline = schedule.deadline();
+      assert!(schedule.due(deadline));
+    }
+    // Ten seconds at 60fps lands on the 600th slot exactly, not a sum of roundings
+    assert_eq!(
+      schedule.deadline() - start,
+      Duration::from_nanos(600 * 16_666_667)
+    );
+  }
+
+  #[test]
+  fn drops_early_frames_and_allows_jitter() {
+    let start = Instant::now();
+    let mut schedule = Schedule::new(start, FRAME);
+    assert!(schedule.due(start));
+    assert!(!schedule.due(start + Duration::from_millis(5)));
+    // Within a quarter frame of the deadline counts as on time
+    assert!(schedule.due(start + Duration::from_millis(8)));
+    assert_eq!(schedule.deadline(), start + FRAME * 2);
+  }
+
+  #[test]
+  fn skips_missed_slots_after_a_stall() {
+    let start = Instant::now();
+    let mut schedule = Schedule::new(start, FRAME);
+    assert!(schedule.due(start));
+    assert!(schedule.due(start + Duration::from_millis(55)));
+    // The grid keeps its phase: the next frame is due at 60ms, not 65ms
+    assert_eq!(schedule.deadline(), start + FRAME * 6);
+    assert!(!schedule.due(start + Duration::from_millis(56)));
+  }
+}
@@ -0,0 +1,110 @@
+// --test-pattern: synthetic frames standing in for the screen, so the whole
+// pipeline runs with no display or capture permission (CI, demos). Frame `n` of a
+// given size depends on nothing else, so a receiver can draw it again and compare:
+//
+//   - eight vertical colour bars (white, yellow, cyan, green, magenta, red, blue,
+//     black) across the top three quarters, scrolled left by SCROLL pixels a frame
+//   - a gray ramp from black on the left to white on the right below them
+//   - the low 32 bits of `n` in the top-left corner, most significant bit first, as
+//     a row of COUNTER_CELL-pixel squares, white for 1 and black for 0, clipped
+//     to the frame
+//
+// Every pixel is opaque.
+
+/// Pixels the bars move left by from one frame to the next
+pub const SCROLL: u64 = 4;
+/// Side of each square of the frame counter
+pub const COUNTER_CELL: usize = 8;
+pub const COUNTER_BITS: usize = 32;
+
+const WHITE: [u8; 4] = [255, 255, 255, 255];
+const BLACK: [u8; 4] = [0, 0, 0, 255];
+// In BGRA order
+const BARS: [[u8; 4]; 8] = [
+  WHITE,
+  [0, 255, 255, 255],
+  [255, 255, 0, 255],
+  [0, 255, 0, 255],
+  [255, 0, 255, 255],
+  [0, 0, 255, 255],
+  [255, 0, 0, 255],
+  BLACK,
+];
+
+/// Draw frame `index` of a `width` x `height` pattern into `out` as packed BGRA
+pub fn render_into(index: u64, width: u32, height: u32, out: &mut Vec<u8>) {
+  let (width, height) = (width as usize, height as usize);
+  out.clear();
+  out.resize(width * height * 4, 0);
+  if width == 0 {
+    return;
+  }
+
+  let ramp_from = height - height / 4;
+  let offset = (index * SCROLL % width as u64) as usize;
+  for (y, row) in out.chunks_exact_mut(width * 4).enumerate() {
+    for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+      let colour = if y >= ramp_from {
+        let level = (x * 255 / (width - 1).max(1)) as u8;
+        [level, level, level, 255]
+      } else {
+        BARS[(x + offset) % width * BARS.len() / width]
+      };
+      pixel.copy_from_slice(&colour);
+    }
+  }
+
+  let counter = index as u32;
+  for bit in 0..COUNTER_BITS {
+    let x0 = bit * COUNTER_CELL;
+    if x0 >= width {
+      break;
+    }
+    let set = counter >> (COUNTER_BITS - 1 - bit) & 1 == 1;
+    let colour = if set { WHITE } else { BLACK };
+    let x1 = (x0 + COUNTER_CELL).min(width);
+    for y in 0..COUNTER_CELL.min(height) {
+      for pixel in out[(y * width + x0) * 4..(y * width + x1) * 4].chunks_exact_mut(4) {
+        pixel.copy_from_slice(&colour);
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn pixel(frame: &[u8], width: usize, x: usize, y: usize) -> [u8; 4] {
+    frame[(y * width + x) * 4..][..4].try_into().unwrap()
+  }
+
+  #[test]
+  fn is_deterministic_and_moves() {
+    let (mut a, mut b) = (Vec::new(), Vec::new());
+    render_into(7, 320, 240, &mut a);
+    render_into(7, 320, 240, &mut b);
+    assert_eq!(a, b);
+    assert_eq!(a.len(), 320 * 240 * 4);
+    render_into(8, 320, 240, &mut b);
+    assert_ne!(a, b);
+
+    // Bars are 40 pixels wide; frame 8 has scrolled them 32 pixels left
+    assert_eq!(pixel(&b, 320, 0, 100), BARS[0]);
+    assert_eq!(pixel(&b, 320, 8, 100), BARS[1]);
+    assert_eq!(pixel(&b, 320, 319, 239), WHITE);
+    assert_eq!(pixel(&b, 320, 0, 239), BLACK);
+  }
+
+  #[test]
+  fn draws_the_frame_counter() {
+    let mut frame = Vec::new();
+    // A multiple of 80 frames, so the bars are back where they started
+    render_into(0x8000_0070, 320, 240, &mut frame);
+    let bit = |bit: usize| pixel(&frame, 320, bit * COUNTER_CELL + 3, 3) == WHITE;
+    let bits: Vec<usize> = (0..COUNTER_BITS).filter(|&i| bit(i)).collect();
+    assert_eq!(bits, [0, 25, 26, 27]);
+    // The bars carry on below the counter
+    assert_eq!(pixel(&frame, 320, 3, COUNTER_CELL), BARS[0]);
+  }
+}
@@ -0,0 +1,66 @@
+use minifb::{ScaleMode, Window, WindowOptions};
+
+use crate::protocol::PixelFormat;
+
+/// Window showing each decoded frame. It's opened at the stream's resolution and
+/// reopened when a new stream arrives with a different one; the user can resize
+/// it freely and frames are scaled to fit.
+pub struct Preview {
+  title: &'static str,
+  window: Option<Window>,
+  size: (usize, usize),
+  buffer: Vec<u32>,
+}
+
+impl Preview {
+  pub fn new(title: &'static str) -> Self {
+    Preview {
+      title,
+      window: None,
+      size: (0, 0),
+      buffer: Vec::new(),
+    }
+  }
+
+  /// Blit one `size` frame of `format` pixels whose rows are `stride` bytes apart.
+  /// Returns false once the user has closed the window.
+  pub fn show(
+    &mut self,
+    pixels: &[u8],
+    size: [u32; 2],
+    stride: u32,
+    format: PixelFormat,
+  ) -> Result<bool, String> {
+    let (width, height) = (size[0] as usize, size[1] as usize);
+    if self.window.is_none() || self.size != (width, height) {
+      let options = WindowOptions {
+        resize: true,
+        scale_mode: ScaleMode::AspectRatioStretch,
+        ..WindowOptions::default()
+      };
+      let window = Window::new(self.title, width, height, options)
+        .map_err(|e| format!("Failed to open preview window: {}", e))?;
+      self.window = Some(window);
+      self.size = (width, height);
+    }
+
+    // minifb wants one 0RGB u32 per pixel
+    let bpp = format.bytes_per_pixel() as usize;
+    self.buffer.clear();
+    for row in pixels.chunks(stride as usize).take(height) {
+      self
+        .buffer
+        .extend(row[..width * bpp].chunks_exact(bpp).map(|px| match format {
+          PixelFormat::Rgba | PixelFormat::Rgb => u32::from_be_bytes([0, px[0], px[1], px[2]]),
+          PixelFormat::Bgra => u32::from_be_bytes([0, px[2], px[1], px[0]]),
+          PixelFormat::Gray => u32::from_be_bytes([0, px[0], px[0], px[0]]),
+        }));
+    }
+
+    let window = self.window.as_mut().unwrap();
+    window
+      .update_with_buffer(&self.buffer, width, height)
+      .map_err(|e| format!("Failed to draw preview: {}", e))?;
+    Ok(window.is_open())
+  }
+}
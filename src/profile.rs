@@ -0,0 +1,204 @@
+// --profile: how long each frame spends in every stage between the capturer and
+// the socket. The capture thread and the send loop each time their stages with
+// `Laps` and record them here; the send loop prints the mean and 99th percentile
+// of every stage now and then, and the whole session's totals at the end as
+// folded stacks, the input flamegraph.pl and inferno take. Without --profile
+// there is no `Profile`, and `Laps` never reads the clock.
+
+use std::fmt::Write as _;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// One step of a frame's trip, in order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+  /// Capture thread pacing, waiting for the next frame time
+  Sleep,
+  /// Waiting for the capturer to hand over a frame
+  Capture,
+  /// Turning the capturer's layout into BGRA
+  Convert,
+  /// --follow-cursor, resizing, the cursor overlay and the watermark
+  Process,
+  /// Pixel format conversion and the codec, on the capture thread
+  Encode,
+  /// Deltas and H.264, on the send loop
+  StreamEncode,
+  /// --checksum and --psk
+  Seal,
+  /// Held back by --max-mbps
+  Throttle,
+  /// Writing to the socket, or handing over to the --listen clients' threads
+  Send,
+}
+
+const STAGES: [Stage; 9] = [
+  Stage::Sleep,
+  Stage::Capture,
+  Stage::Convert,
+  Stage::Process,
+  Stage::Encode,
+  Stage::StreamEncode,
+  Stage::Seal,
+  Stage::Throttle,
+  Stage::Send,
+];
+
+impl Stage {
+  fn name(self) -> &'static str {
+    match self {
+      Stage::Sleep => "sleep",
+      Stage::Capture => "capture",
+      Stage::Convert => "convert",
+      Stage::Process => "process",
+      Stage::Encode => "encode",
+      Stage::StreamEncode => "stream-encode",
+      Stage::Seal => "seal",
+      Stage::Throttle => "throttle",
+      Stage::Send => "send",
+    }
+  }
+
+  /// The thread the stage runs on, the root of its folded stack
+  fn thread(self) -> &'static str {
+    match self {
+      Stage::Sleep | Stage::Capture | Stage::Convert | Stage::Process | Stage::Encode => {
+        "capture-thread"
+      }
+      Stage::StreamEncode | Stage::Seal | Stage::Throttle | Stage::Send => "send-loop",
+    }
+  }
+}
+
+#[derive(Default)]
+struct Samples {
+  /// Times since the last `report`, per stage
+  recent: [Vec<Duration>; STAGES.len()],
+  /// Time spent over the whole session, per stage
+  total: [Duration; STAGES.len()],
+}
+
+/// Stage times recorded by both threads of one stream
+#[derive(Default)]
+pub struct Profile {
+  samples: Mutex<Samples>,
+}
+
+impl Profile {
+  pub fn record(&self, stage: Stage, took: Duration) {
+    let mut samples = self.samples.lock().unwrap();
+    samples.recent[stage as usize].push(took);
+    samples.total[stage as usize] += took;
+  }
+
+  /// Mean and 99th percentile of every stage recorded since the last report, in
+  /// milliseconds, then start over; `None` if nothing was recorded
+  pub fn report(&self) -> Option<String> {
+    let mut samples = self.samples.lock().unwrap();
+    let mut line = String::new();
+    for stage in STAGES {
+      let times = &mut samples.recent[stage as usize];
+      if times.is_empty() {
+        continue;
+      }
+      times.sort_unstable();
+      let mean = times.iter().sum::<Duration>() / times.len() as u32;
+      let p99 = times[(times.len() * 99).div_ceil(100) - 1];
+      if !line.is_empty() {
+        line.push_str(" | ");
+      }
+      let _ = write!(
+        line,
+        "{} {:.2}/{:.2}",
+        stage.name(),
+        mean.as_secs_f64() * 1000.0,
+        p99.as_secs_f64() * 1000.0
+      );
+      times.clear();
+    }
+    (!line.is_empty()).then_some(line)
+  }
+
+  /// The session's time per stage as folded stacks, one `thread;stage
+  /// microseconds` line each
+  pub fn folded(&self) -> String {
+    let samples = self.samples.lock().unwrap();
+    let mut out = String::new();
+    for stage in STAGES {
+      let total = samples.total[stage as usize];
+      if !total.is_zero() {
+        let _ = writeln!(
+          out,
+          "{};{} {}",
+          stage.thread(),
+          stage.name(),
+          total.as_micros()
+        );
+      }
+    }
+    out
+  }
+}
+
+/// Times consecutive stages on one thread: each `lap` charges the time since the
+/// one before to a stage
+pub struct Laps<'a> {
+  profile: Option<&'a Profile>,
+  last: Option<Instant>,
+}
+
+impl<'a> Laps<'a> {
+  /// Start timing now, or not at all without a profile
+  pub fn start(profile: Option<&'a Profile>) -> Self {
+    Laps {
+      profile,
+      last: profile.map(|_| Instant::now()),
+    }
+  }
+
+  pub fn lap(&mut self, stage: Stage) {
+    if let (Some(profile), Some(last)) = (self.profile, self.last) {
+      let now = Instant::now();
+      profile.record(stage, now - last);
+      self.last = Some(now);
+    }
+  }
+
+  /// Leave the time since the last lap out, for work that's no stage's
+  pub fn skip(&mut self) {
+    if self.last.is_some() {
+      self.last = Some(Instant::now());
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn reports_mean_and_p99_per_stage() {
+    let profile = Profile::default();
+    for ms in 1..=100 {
+      profile.record(Stage::Encode, Duration::from_millis(ms));
+    }
+    profile.record(Stage::Send, Duration::from_millis(2));
+    assert_eq!(
+      profile.report().as_deref(),
+      Some("encode 50.50/99.00 | send 2.00/2.00")
+    );
+    // Each report covers only what came after the one before
+    assert_eq!(profile.report(), None);
+    assert_eq!(
+      profile.folded(),
+      "capture-thread;encode 5050000\nsend-loop;send 2000\n"
+    );
+  }
+
+  #[test]
+  fn laps_without_a_profile_do_nothing() {
+    let mut laps = Laps::start(None);
+    assert_eq!(laps.last, None);
+    laps.lap(Stage::Capture);
+  }
+}
@@ -0,0 +1,1259 @@
+// Every connection starts with a 24-byte little-endian handshake describing the
+// stream, sent once before the first frame (as a standalone datagram on UDP):
+//
+//   offset  size  field
+//   0       4     magic        (b"SCRN")
+//   4       1     version      (PROTOCOL_VERSION)
+//   5       1     pixel_format (0 = RGBA, 1 = BGRA, 2 = 8-bit grayscale (version >= 9),
+//                                3 = RGB without alpha (version >= 10))
+//   6       1     codec        (0 = raw, 1 = JPEG (version >= 3), 2 = zstd, 3 = delta
+//                                (version >= 4), 4 = H.264 (version >= 7),
+//                                5 = RLE (version >= 12))
+//   7       1     flags        (bit 0: NEGOTIATE, version >= 5;
+//                                bit 1: AUDIO, version >= 8;
+//                                bit 2: ENCRYPTED, version >= 11;
+//                                bit 3: CHECKSUM, version >= 13;
+//                                bit 4: CURSOR, version >= 17;
+//                                bit 5: FEC, version >= 19;
+//                                bit 6: VARIANTS, version >= 20)
+//   8       4     width        (u32)
+//   12      4     height       (u32)
+//   16      4     fps          (u32, target frame rate; 0 = unpaced)
+//   20      4     stride       (u32, bytes per row of decoded frames; version >= 6)
+//
+// Receivers should reject a connection whose magic or version they don't know.
+// Senders before version 6 stop after fps (20 bytes) and always pack rows tightly.
+// With NEGOTIATE set, `pixel_format` is only the sender's first choice (TCP only)
+// and the two sides exchange what they support before the first frame, each as a
+// pair of bit sets where bit n stands for codec or pixel format n:
+//
+//   offer  := codecs:u8 pixel_formats:u8                (sender to receiver)
+//   answer := codecs:u8 pixel_formats:u8 preferred:u8   (receiver to sender)
+//   choice := pixel_format:u8                           (sender to receiver)
+//
+// The offer holds every codec the sender can encode and the pixel formats it can
+// send with `codec`; the answer holds the ones of those the receiver can decode,
+// and the pixel format it would rather have (0xff for no preference). The stream
+// keeps `codec` and uses the receiver's preferred format if it was offered, or
+// else `pixel_format` if the receiver supports it, or else the first format both
+// sides support in the order RGBA, BGRA, gray, RGB. When the receiver can't
+// decode `codec` or shares no format, the choice is 0xff and the sender closes the
+// connection. Before version 14 the receiver answers with one byte instead, 1 to
+// accept `pixel_format` or 0 to get RGBA, and there is no choice. Without the flag
+// `pixel_format` is final.
+// VARIANTS comes with NEGOTIATE from a listen-mode sender that encodes other
+// variants of the stream beside its own (--variants), and lets each receiver say
+// which it would rather have:
+//
+//   answer  := codecs:u8 pixel_formats:u8 preferred:u8 want
+//   want    := codec:u8 quality:u8 max_width:u16 max_height:u16
+//   choice  := pixel_format:u8 handshake
+//
+// `want` names a codec (0xff for any), a JPEG quality (0 for any) and the largest
+// frames it takes (0 for any size). The sender picks the variant that comes
+// closest and follows the choice with that variant's handshake, without
+// NEGOTIATE or VARIANTS, which replaces the first one. Its pixel format is the
+// choice, and its codec and size may differ from the first handshake's. A choice
+// of 0xff has no handshake after it.
+// With the JPEG codec each frame payload is one baseline JPEG image, and with zstd
+// it is one zstd frame holding the raw pixels; `pixel_format` then describes the
+// decoded pixels rather than the bytes on the wire. Grayscale frames carry one
+// BT.601 luminance byte per pixel; with JPEG they are single-channel images. RGB
+// frames carry 3 bytes per pixel with the alpha channel dropped. The
+// delta codec's payload layout is described in delta.rs, the RLE codec's in
+// rle.rs, and the H.264 codec's in video.rs; H.264 frames always decode to RGBA
+// and RLE frames always carry 4-byte pixels.
+//
+// TCP wire format (all integers little-endian):
+//
+//   stream   := handshake frame*
+//   frame    := metadata chunk*
+//   metadata := width:u32 height:u32 total_size:u32 num_chunks:u32
+//               seq:u64 timestamp_ms:u64                  (version >= 2)
+//               raw_size:u32                              (version >= 4)
+//               crc32:u32                         (CHECKSUM, version >= 13)
+//               nonce[12]                        (ENCRYPTED, version >= 11)
+//   chunk    := chunk_size:u32 data[chunk_size]
+//
+// `seq` increases by one per frame sent on the stream, so a gap means frames were
+// lost; `timestamp_ms` is the capture time in milliseconds since streaming started.
+// `raw_size` is the payload size once decoded, so a receiver can allocate the
+// output buffer up front; for the raw codec it equals total_size.
+// Version 1 senders omit seq and timestamp_ms (16-byte metadata) and versions
+// before 4 omit raw_size (32-byte metadata).
+// Chunks are at most `chunk_size` bytes; only the last one may be shorter.
+// Real frames are never empty. A metadata block with total_size == 0 and no
+// chunks is a control marker instead, without a checksum or nonce, told apart
+// by its other fields:
+//
+//   width == 0                      end of stream; the sender closes the
+//                                   connection right after it
+//   width != 0, raw_size == 0       repeat (version >= 15, --constant-fps): show
+//                                   the frame numbered `seq` again at
+//                                   `timestamp_ms`; it isn't a new frame for gaps
+//   width != 0, raw_size != 0       size change (version >= 16): frames from
+//                                   `seq` on are width x height and decode to
+//                                   raw_size bytes, raw_size / height per row
+//
+// A size change comes right before the first frame of the new size, e.g. after
+// the captured window was resized, and replaces the handshake's width, height
+// and stride; decoders then start over, as deltas wait for the next keyframe.
+// One that repeats the current size says the sender rebuilt its capturer, and
+// the frames after it don't follow on from those before, though `seq` does.
+// With AUDIO set in the handshake, a metadata block with width == height == 0 and
+// a non-empty payload is an audio packet instead of a frame: `seq` counts audio
+// packets on their own, `timestamp_ms` runs on the same clock as the frames, and
+// the payload layout is described in audio.rs. Audio is never sent over UDP.
+// With CURSOR set (--cursor-layer) frames leave the mouse cursor out, and once
+// its position is known every frame comes right after a cursor packet: a
+// metadata block with width == height == raw_size == 0 and a non-empty payload,
+// with the `seq` and `timestamp_ms` of that frame, laid out as described in
+// cursor.rs. Audio packets never have raw_size == 0. Cursor packets are never
+// sent over UDP.
+// With ENCRYPTED set every payload is sealed with a pre-shared key as described
+// in crypto.rs, and every metadata block except the control markers carries the
+// payload's nonce. Encryption is never used over UDP.
+// With CHECKSUM set every metadata block except the control markers carries the
+// CRC-32 (IEEE) of the payload as sent, after any encryption, so a receiver
+// can discard payloads corrupted on the way.
+//
+// UDP wire format: every datagram carries one slice of one frame behind a
+// 24-byte little-endian header, so a receiver can reassemble frames and give up
+// on any frame that is still missing slices when a newer frame id arrives.
+//
+//   offset  size  field
+//   0       4     frame_id     (u32, low 32 bits of `seq`)
+//   4       2     chunk_index  (u16, 0-based)
+//   6       2     chunk_count  (u16)
+//   8       2     width        (u16)
+//   10      2     height       (u16)
+//   12      4     total_size   (u32, frame payload bytes)
+//   16      8     timestamp_ms (u64)
+//   24      4     crc32        (u32, CHECKSUM only: of this datagram's payload
+//                                from version 18, of the whole frame payload before)
+//   24/28   2     fec_group    (u16, FEC only: data slices per parity slice, 0 = no
+//                                parity for this frame)
+//   24-30   ...   payload
+//
+// Every payload except the last has the same length, 1376 bytes (1372 with
+// CHECKSUM) unless the sender was given another --chunk-size, so slice `i` lands
+// at offset `i` times that length in the frame and the last slice ends at
+// total_size. A 24-byte datagram with chunk_count == 0 marks the end of the
+// stream, or from version 15 repeats frame `frame_id` when width and height
+// aren't 0. The decoded size of a compressed frame is always width * height *
+// bytes per pixel on UDP. There is no size change datagram: every datagram
+// carries its frame's size, so receivers follow that instead.
+// With FEC set (--fec) a frame's data slices are followed by parity slices, with
+// chunk_index from chunk_count up, as described in fec.rs; they carry the same
+// header, and don't count in chunk_count. FEC is only used over UDP.
+// Since a slice's place in the payload follows from its header, the slices of a
+// frame that did arrive can still be used where the codec allows it, which a
+// per-slice checksum makes safe: a raw frame, or the body of a delta keyframe, is
+// the new pixels at the slices' offsets, and every changed tile of a delta whose
+// header and bitmap arrived lies at an offset the bitmap gives, so each tile that
+// arrived in full can be drawn. What's missing stays as it was in the previous
+// frame until it changes again or the next keyframe repaints it. Other codecs
+// compress the frame as a whole, so their frames are all or nothing.
+//
+// WebSocket wire format (--transport ws, for browsers): the first message is text
+// holding the handshake as a JSON object
+//
+//   {"version", "width", "height", "pixel_format", "codec", "fps", "stride", "audio",
+//    "encrypted", "checksum", "cursor"}
+//
+// with `pixel_format` and `codec` by name ("rgba", "jpeg", ...). Every frame, audio
+// packet, cursor packet and control marker is then one binary message: the
+// metadata block above (with its checksum and nonce when set) with num_chunks == 1
+// (0 when the payload is empty), followed by the whole payload unchunked. With the
+// JPEG codec a page can show each payload directly as an image/jpeg Blob. Pixel
+// formats aren't negotiated.
+//
+// --transport mjpeg skips all of the above: the receiver gets a plain HTTP
+// `multipart/x-mixed-replace` response with one image/jpeg part per frame, so no
+// handshake, metadata or audio reaches it.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::UdpSocket;
+use std::str::FromStr;
+
+use crate::fec;
+
+pub const MAGIC: [u8; 4] = *b"SCRN";
+pub const PROTOCOL_VERSION: u8 = 20;
+pub const HANDSHAKE_SIZE: usize = 24;
+pub const NEGOTIATE: u8 = 1;
+pub const AUDIO: u8 = 2;
+pub const ENCRYPTED: u8 = 4;
+pub const CHECKSUM: u8 = 8;
+pub const CURSOR: u8 = 16;
+pub const FEC: u8 = 32;
+pub const VARIANTS: u8 = 64;
+/// Bytes of the `want` a receiver appends to its answer with VARIANTS
+pub const WANT_SIZE: usize = 6;
+pub const METADATA_SIZE: usize = 36;
+/// Bytes of nonce following the metadata of an encrypted payload
+pub const NONCE_SIZE: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+  Rgba = 0,
+  Bgra = 1,
+  Gray = 2,
+  Rgb = 3,
+}
+
+impl PixelFormat {
+  /// Name used on the command line and in the WebSocket handshake
+  pub fn name(self) -> &'static str {
+    match self {
+      PixelFormat::Rgba => "rgba",
+      PixelFormat::Bgra => "bgra",
+      PixelFormat::Gray => "gray",
+      PixelFormat::Rgb => "rgb",
+    }
+  }
+
+  pub fn bytes_per_pixel(self) -> u32 {
+    match self {
+      PixelFormat::Rgba | PixelFormat::Bgra => 4,
+      PixelFormat::Rgb => 3,
+      PixelFormat::Gray => 1,
+    }
+  }
+}
+
+impl FromStr for PixelFormat {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "rgba" => Ok(PixelFormat::Rgba),
+      "bgra" => Ok(PixelFormat::Bgra),
+      "gray" => Ok(PixelFormat::Gray),
+      "rgb" => Ok(PixelFormat::Rgb),
+      _ => Err(format!(
+        "Unknown pixel format '{}' (expected rgba, bgra, rgb or gray)",
+        s
+      )),
+    }
+  }
+}
+
+/// How each frame payload is encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+  Raw = 0,
+  Jpeg = 1,
+  Zstd = 2,
+  Delta = 3,
+  H264 = 4,
+  Rle = 5,
+}
+
+impl Codec {
+  /// Name used on the command line and in the WebSocket handshake
+  pub fn name(self) -> &'static str {
+    match self {
+      Codec::Raw => "raw",
+      Codec::Jpeg => "jpeg",
+      Codec::Zstd => "zstd",
+      Codec::Delta => "delta",
+      Codec::H264 => "h264",
+      Codec::Rle => "rle",
+    }
+  }
+}
+
+impl FromStr for Codec {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "raw" => Ok(Codec::Raw),
+      "jpeg" => Ok(Codec::Jpeg),
+      "zstd" => Ok(Codec::Zstd),
+      "delta" => Ok(Codec::Delta),
+      "h264" => Ok(Codec::H264),
+      "rle" => Ok(Codec::Rle),
+      _ => Err(format!(
+        "Unknown codec '{}' (expected raw, jpeg, zstd, delta, h264 or rle)",
+        s
+      )),
+    }
+  }
+}
+
+/// Stream description sent once per connection before any frames
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Handshake {
+  pub width: u32,
+  pub height: u32,
+  pub pixel_format: PixelFormat,
+  pub codec: Codec,
+  pub fps: u32,
+  /// Bytes per row of the pixels the receiver ends up with
+  pub stride: u32,
+  /// Agree on the pixel format with the receiver, and check it can decode
+  /// `codec`, before streaming
+  pub negotiate: bool,
+  /// Audio packets are interleaved with the frames
+  pub audio: bool,
+  /// Payloads are sealed with a pre-shared key
+  pub encrypted: bool,
+  /// Payloads carry a CRC-32
+  pub checksum: bool,
+  /// Cursor packets come before frames, which leave the cursor out
+  pub cursor: bool,
+  /// UDP frames carry parity slices
+  pub fec: bool,
+  /// Receivers may ask for another variant of the stream while negotiating
+  pub variants: bool,
+}
+
+impl Handshake {
+  pub fn to_bytes(self) -> [u8; HANDSHAKE_SIZE] {
+    let mut bytes = [0u8; HANDSHAKE_SIZE];
+    bytes[0..4].copy_from_slice(&MAGIC);
+    bytes[4] = PROTOCOL_VERSION;
+    bytes[5] = self.pixel_format as u8;
+    bytes[6] = self.codec as u8;
+    if self.negotiate || self.variants {
+      bytes[7] |= NEGOTIATE;
+    }
+    if self.variants {
+      bytes[7] |= VARIANTS;
+    }
+    if self.audio {
+      bytes[7] |= AUDIO;
+    }
+    if self.encrypted {
+      bytes[7] |= ENCRYPTED;
+    }
+    if self.checksum {
+      bytes[7] |= CHECKSUM;
+    }
+    if self.cursor {
+      bytes[7] |= CURSOR;
+    }
+    if self.fec {
+      bytes[7] |= FEC;
+    }
+    bytes[8..12].copy_from_slice(&self.width.to_le_bytes());
+    bytes[12..16].copy_from_slice(&self.height.to_le_bytes());
+    bytes[16..20].copy_from_slice(&self.fps.to_le_bytes());
+    bytes[20..24].copy_from_slice(&self.stride.to_le_bytes());
+    bytes
+  }
+
+  /// The handshake as the JSON text message that opens a WebSocket stream
+  pub fn to_json(self) -> String {
+    serde_json::json!({
+      "version": PROTOCOL_VERSION,
+      "width": self.width,
+      "height": self.height,
+      "pixel_format": self.pixel_format.name(),
+      "codec": self.codec.name(),
+      "fps": self.fps,
+      "stride": self.stride,
+      "audio": self.audio,
+      "encrypted": self.encrypted,
+      "checksum": self.checksum,
+      "cursor": self.cursor,
+    })
+    .to_string()
+  }
+}
+
+/// A `choice` or `preferred` byte naming no pixel format
+pub const NO_PIXEL_FORMAT: u8 = 0xff;
+
+/// Codecs and pixel formats one side of a negotiation supports
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Capabilities {
+  /// Bit n set for codec n
+  pub codecs: u8,
+  /// Bit n set for pixel format n
+  pub pixel_formats: u8,
+}
+
+impl Capabilities {
+  pub fn new(codecs: &[Codec], pixel_formats: &[PixelFormat]) -> Self {
+    Capabilities {
+      codecs: codecs
+        .iter()
+        .fold(0, |bits, &codec| bits | 1 << codec as u8),
+      pixel_formats: pixel_formats
+        .iter()
+        .fold(0, |bits, &format| bits | 1 << format as u8),
+    }
+  }
+
+  pub fn has_codec(self, codec: Codec) -> bool {
+    self.codecs & 1 << codec as u8 != 0
+  }
+
+  pub fn has_pixel_format(self, format: PixelFormat) -> bool {
+    self.pixel_formats & 1 << format as u8 != 0
+  }
+
+  /// What both sides support
+  pub fn intersect(self, other: Capabilities) -> Self {
+    Capabilities {
+      codecs: self.codecs & other.codecs,
+      pixel_formats: self.pixel_formats & other.pixel_formats,
+    }
+  }
+
+  pub fn to_bytes(self) -> [u8; 2] {
+    [self.codecs, self.pixel_formats]
+  }
+
+  pub fn from_bytes(bytes: [u8; 2]) -> Self {
+    Capabilities {
+      codecs: bytes[0],
+      pixel_formats: bytes[1],
+    }
+  }
+
+  /// The codecs by name, for messages
+  fn codec_names(self) -> String {
+    let names: Vec<&str> = CODECS
+      .iter()
+      .filter(|&&codec| self.has_codec(codec))
+      .map(|codec| codec.name())
+      .collect();
+    match names.is_empty() {
+      true => "none".to_string(),
+      false => names.join(", "),
+    }
+  }
+}
+
+/// A variant of the stream: what --variants encodes, or what a receiver's `want`
+/// asks for. Anything left out is whatever the stream has.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Variant {
+  pub codec: Option<Codec>,
+  /// JPEG quality, 1-100
+  pub quality: Option<u8>,
+  /// Frames are scaled down to fit within this size
+  pub size: Option<[u32; 2]>,
+}
+
+impl Variant {
+  pub fn to_bytes(self) -> [u8; WANT_SIZE] {
+    let [width, height] = self
+      .size
+      .unwrap_or_default()
+      .map(|side| side.min(u16::MAX as u32) as u16);
+    let mut bytes = [0u8; WANT_SIZE];
+    bytes[0] = self.codec.map_or(0xff, |codec| codec as u8);
+    bytes[1] = self.quality.unwrap_or(0);
+    bytes[2..4].copy_from_slice(&width.to_le_bytes());
+    bytes[4..6].copy_from_slice(&height.to_le_bytes());
+    bytes
+  }
+
+  /// Unknown codecs and sizes with a zero side count as no wish
+  pub fn from_bytes(bytes: [u8; WANT_SIZE]) -> Self {
+    let side = |offset: usize| u16::from_le_bytes([bytes[offset], bytes[offset + 1]]) as u32;
+    let size = [side(2), side(4)];
+    Variant {
+      codec: CODECS.get(bytes[0] as usize).copied(),
+      quality: (1..=100).contains(&bytes[1]).then_some(bytes[1]),
+      size: (size[0] > 0 && size[1] > 0).then_some(size),
+    }
+  }
+}
+
+impl fmt::Display for Variant {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    if let Some(codec) = self.codec {
+      write!(f, "{}", codec.name())?;
+    }
+    if let Some(quality) = self.quality {
+      write!(f, ":{}", quality)?;
+    }
+    if let Some([width, height]) = self.size {
+      write!(f, "@{}x{}", width, height)?;
+    }
+    Ok(())
+  }
+}
+
+/// `CODEC[:QUALITY][@WIDTHxHEIGHT]`, any part of which may be left out, e.g.
+/// `jpeg:60@1280x720`, `zstd` or `@640x360`
+impl FromStr for Variant {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let invalid = || {
+      format!(
+        "Invalid variant '{}' (expected CODEC[:QUALITY][@WIDTHxHEIGHT], e.g. jpeg:60@1280x720)",
+        s
+      )
+    };
+    let (rest, size) = match s.split_once('@') {
+      Some((rest, size)) => {
+        let (width, height) = size.split_once('x').ok_or_else(invalid)?;
+        match (width.parse(), height.parse()) {
+          (Ok(width @ 1..), Ok(height @ 1..)) => (rest, Some([width, height])),
+          _ => return Err(invalid()),
+        }
+      }
+      None => (s, None),
+    };
+    let (codec, quality) = match rest.split_once(':') {
+      Some((codec, quality)) => match quality.parse() {
+        Ok(quality @ 1..=100) => (codec, Some(quality)),
+        _ => return Err(invalid()),
+      },
+      None => (rest, None),
+    };
+    let codec = match codec {
+      "" => None,
+      codec => Some(codec.parse()?),
+    };
+    Ok(Variant {
+      codec,
+      quality,
+      size,
+    })
+  }
+}
+
+/// Every codec, by number
+pub const CODECS: [Codec; 6] = [
+  Codec::Raw,
+  Codec::Jpeg,
+  Codec::Zstd,
+  Codec::Delta,
+  Codec::H264,
+  Codec::Rle,
+];
+/// Every pixel format, by number
+pub const PIXEL_FORMATS: [PixelFormat; 4] = [
+  PixelFormat::Rgba,
+  PixelFormat::Bgra,
+  PixelFormat::Gray,
+  PixelFormat::Rgb,
+];
+
+/// The sender's side of a negotiation: the pixel format a stream of `codec` whose
+/// handshake named `first` uses once the receiver has answered `offer` with
+/// `answer` and `preferred`, or why the two have nothing in common
+pub fn choose_pixel_format(
+  codec: Codec,
+  first: PixelFormat,
+  offer: Capabilities,
+  answer: Capabilities,
+  preferred: u8,
+) -> Result<PixelFormat, String> {
+  let shared = offer.intersect(answer);
+  if !shared.has_codec(codec) {
+    return Err(format!(
+      "the receiver can't decode {} (it supports {})",
+      codec.name(),
+      answer.codec_names()
+    ));
+  }
+  let preferred = PIXEL_FORMATS
+    .into_iter()
+    .find(|&format| format as u8 == preferred && shared.has_pixel_format(format));
+  preferred
+    .or_else(|| {
+      [first]
+        .into_iter()
+        .chain(PIXEL_FORMATS)
+        .find(|&format| shared.has_pixel_format(format))
+    })
+    .ok_or_else(|| {
+      format!(
+        "the receiver can't show any of the pixel formats {} can be sent in",
+        codec.name()
+      )
+    })
+}
+
+/// Per-frame fields carried in the metadata block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameInfo {
+  pub width: u32,
+  pub height: u32,
+  pub seq: u64,
+  pub timestamp_ms: u64,
+  /// Payload size after decoding
+  pub raw_size: u32,
+  /// CRC-32 of the payload as sent, on streams with CHECKSUM
+  pub checksum: Option<u32>,
+  /// Set once the payload has been encrypted
+  pub nonce: Option<[u8; NONCE_SIZE]>,
+}
+
+impl FrameInfo {
+  /// The end-of-stream marker; `seq` is the number the next frame would have had
+  pub fn end_of_stream(seq: u64) -> Self {
+    FrameInfo {
+      width: 0,
+      height: 0,
+      seq,
+      timestamp_ms: 0,
+      raw_size: 0,
+      checksum: None,
+      nonce: None,
+    }
+  }
+
+  /// A repeat of the `width` x `height` frame numbered `seq`, shown again at
+  /// `timestamp_ms`
+  pub fn repeat(width: u32, height: u32, seq: u64, timestamp_ms: u64) -> Self {
+    FrameInfo {
+      width,
+      height,
+      seq,
+      timestamp_ms,
+      raw_size: 0,
+      checksum: None,
+      nonce: None,
+    }
+  }
+
+  /// Announces that frames from `seq` on are `width` x `height`, decoding to
+  /// `raw_size` bytes
+  pub fn size_change(width: u32, height: u32, seq: u64, raw_size: u32) -> Self {
+    FrameInfo {
+      width,
+      height,
+      seq,
+      timestamp_ms: 0,
+      raw_size,
+      checksum: None,
+      nonce: None,
+    }
+  }
+
+  /// Whether this metadata, read back with an empty payload, announces a size
+  /// change rather than a repeat
+  pub fn is_size_change(&self) -> bool {
+    self.raw_size != 0
+  }
+
+  /// Whether `data` is the payload this metadata was sent with, as far as the
+  /// checksum can tell. Always true without one.
+  pub fn checksum_matches(&self, data: &[u8]) -> bool {
+    self
+      .checksum
+      .is_none_or(|checksum| checksum == crc32fast::hash(data))
+  }
+
+  fn metadata(&self, total_size: usize, num_chunks: usize) -> [u8; METADATA_SIZE] {
+    let mut metadata = [0u8; METADATA_SIZE];
+    metadata[0..4].copy_from_slice(&self.width.to_le_bytes());
+    metadata[4..8].copy_from_slice(&self.height.to_le_bytes());
+    metadata[8..12].copy_from_slice(&(total_size as u32).to_le_bytes());
+    metadata[12..16].copy_from_slice(&(num_chunks as u32).to_le_bytes());
+    metadata[16..24].copy_from_slice(&self.seq.to_le_bytes());
+    metadata[24..32].copy_from_slice(&self.timestamp_ms.to_le_bytes());
+    metadata[32..36].copy_from_slice(&self.raw_size.to_le_bytes());
+    metadata
+  }
+}
+
+/// Datagram size kept under a 1500-byte Ethernet MTU after IP/UDP headers
+pub const DEFAULT_DATAGRAM_SIZE: usize = 1400;
+/// Largest payload a UDP datagram over IPv4 can carry
+pub const MAX_DATAGRAM_SIZE: usize = 65507;
+pub const DATAGRAM_HEADER_SIZE: usize = 24;
+
+/// Header bytes in front of each datagram's payload, which grows by the CRC-32 on
+/// streams with CHECKSUM and by `fec_group` on streams with FEC
+pub fn datagram_header_size(checksum: bool, fec: bool) -> usize {
+  DATAGRAM_HEADER_SIZE + if checksum { 4 } else { 0 } + if fec { 2 } else { 0 }
+}
+
+/// Write one frame: the metadata block followed by the payload split into
+/// size-prefixed chunks, then flush so buffered writers emit the whole frame.
+pub fn send_frame<W: Write>(
+  writer: &mut W,
+  info: &FrameInfo,
+  data: &[u8],
+  chunk_size: usize,
+) -> io::Result<()> {
+  let num_chunks = data.len().div_ceil(chunk_size);
+  writer.write_all(&info.metadata(data.len(), num_chunks))?;
+  if let Some(checksum) = info.checksum {
+    writer.write_all(&checksum.to_le_bytes())?;
+  }
+  if let Some(nonce) = &info.nonce {
+    writer.write_all(nonce)?;
+  }
+
+  for chunk in data.chunks(chunk_size) {
+    writer.write_all(&(chunk.len() as u32).to_le_bytes())?;
+    writer.write_all(chunk)?;
+  }
+
+  writer.flush()
+}
+
+/// Read one frame written by `send_frame` on a stream of `version` into `data`,
+/// returning its metadata, or `None` at the end-of-stream marker. Repeats and size
+/// changes are the only frames that come back with an empty `data`. `seq` and
+/// `timestamp_ms` read as 0 before version 2, and `raw_size` before version 4.
+/// `encrypted` and `checksummed` are the handshake's ENCRYPTED and CHECKSUM flags.
+pub fn read_frame<R: Read>(
+  reader: &mut R,
+  version: u8,
+  encrypted: bool,
+  checksummed: bool,
+  data: &mut Vec<u8>,
+) -> io::Result<Option<FrameInfo>> {
+  let metadata_size = match version {
+    1 => 16,
+    2 | 3 => 32,
+    _ => METADATA_SIZE,
+  };
+  let mut metadata = [0u8; METADATA_SIZE];
+  reader.read_exact(&mut metadata[..metadata_size])?;
+  let u32_at = |offset: usize| u32::from_le_bytes(metadata[offset..offset + 4].try_into().unwrap());
+  let u64_at = |offset: usize| u64::from_le_bytes(metadata[offset..offset + 8].try_into().unwrap());
+  let (total_size, num_chunks) = (u32_at(8) as usize, u32_at(12));
+  let mut info = FrameInfo {
+    width: u32_at(0),
+    height: u32_at(4),
+    seq: u64_at(16),
+    timestamp_ms: u64_at(24),
+    raw_size: u32_at(32),
+    checksum: None,
+    nonce: None,
+  };
+  // Control markers are the only blocks without a checksum or nonce
+  if total_size == 0 {
+    if info.width == 0 || version < 15 {
+      return Ok(None);
+    }
+    data.clear();
+    return Ok(Some(info));
+  }
+  if checksummed {
+    let mut checksum = [0u8; 4];
+    reader.read_exact(&mut checksum)?;
+    info.checksum = Some(u32::from_le_bytes(checksum));
+  }
+  if encrypted {
+    let mut nonce = [0u8; NONCE_SIZE];
+    reader.read_exact(&mut nonce)?;
+    info.nonce = Some(nonce);
+  }
+
+  data.clear();
+  for _ in 0..num_chunks {
+    let mut size = [0u8; 4];
+    reader.read_exact(&mut size)?;
+    let size = u32::from_le_bytes(size) as usize;
+    if data.len() + size > total_size {
+      let message = format!("chunks overflow the {}-byte frame", total_size);
+      return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+    }
+    let start = data.len();
+    data.resize(start + size, 0);
+    reader.read_exact(&mut data[start..])?;
+  }
+  if data.len() != total_size {
+    let message = format!(
+      "frame has {} bytes, metadata says {}",
+      data.len(),
+      total_size
+    );
+    return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+  }
+  Ok(Some(info))
+}
+
+/// Tell the receiver the stream is over so it can finalize whatever it was writing
+pub fn send_end_of_stream<W: Write>(writer: &mut W, seq: u64) -> io::Result<()> {
+  send_frame(writer, &FrameInfo::end_of_stream(seq), &[], 1)
+}
+
+/// One frame as a single WebSocket binary message: metadata, then the payload
+pub fn frame_message(info: &FrameInfo, data: &[u8]) -> Vec<u8> {
+  let mut message = Vec::with_capacity(METADATA_SIZE + 4 + NONCE_SIZE + data.len());
+  message.extend_from_slice(&info.metadata(data.len(), (!data.is_empty()) as usize));
+  if let Some(checksum) = info.checksum {
+    message.extend_from_slice(&checksum.to_le_bytes());
+  }
+  if let Some(nonce) = &info.nonce {
+    message.extend_from_slice(nonce);
+  }
+  message.extend_from_slice(data);
+  message
+}
+
+/// Send one frame as a burst of datagrams of at most `datagram_size` bytes, header
+/// included, on a connected UDP socket. On a stream with FEC, `fec` is the number
+/// of data slices each parity slice covers.
+pub fn send_frame_datagrams(
+  socket: &UdpSocket,
+  info: &FrameInfo,
+  data: &[u8],
+  datagram_size: usize,
+  fec: Option<u16>,
+) -> io::Result<()> {
+  let payload_size = datagram_size - datagram_header_size(info.checksum.is_some(), fec.is_some());
+  let chunk_count = data.len().div_ceil(payload_size);
+  let group = fec.unwrap_or(0) as usize;
+  let (width, height) = (info.width, info.height);
+  let parity_count = fec::parity_count(chunk_count, group);
+  if chunk_count + parity_count > u16::MAX as usize
+    || width > u16::MAX as u32
+    || height > u16::MAX as u32
+  {
+    return Err(io::Error::new(
+      io::ErrorKind::InvalidInput,
+      "frame too large for the UDP transport",
+    ));
+  }
+
+  // A repeat has no payload, so it goes out as the header alone
+  if data.is_empty() {
+    let mut datagram = [0u8; DATAGRAM_HEADER_SIZE];
+    datagram[0..4].copy_from_slice(&(info.seq as u32).to_le_bytes());
+    datagram[8..10].copy_from_slice(&(width as u16).to_le_bytes());
+    datagram[10..12].copy_from_slice(&(height as u16).to_le_bytes());
+    datagram[16..24].copy_from_slice(&info.timestamp_ms.to_le_bytes());
+    return socket.send(&datagram).map(|_| ());
+  }
+
+  let chunks: Vec<_> = data.chunks(payload_size).collect();
+  let parity: Vec<_> = (0..parity_count)
+    .map(|run| {
+      let covered = &chunks[fec::run(run, chunk_count, group)];
+      fec::xor(covered.iter().copied(), covered[0].len())
+    })
+    .collect();
+  let slices = chunks
+    .iter()
+    .copied()
+    .chain(parity.iter().map(Vec::as_slice));
+  let mut datagram = Vec::with_capacity(datagram_size);
+  for (index, chunk) in slices.enumerate() {
+    datagram.clear();
+    datagram.extend_from_slice(&(info.seq as u32).to_le_bytes());
+    datagram.extend_from_slice(&(index as u16).to_le_bytes());
+    datagram.extend_from_slice(&(chunk_count as u16).to_le_bytes());
+    datagram.extend_from_slice(&(width as u16).to_le_bytes());
+    datagram.extend_from_slice(&(height as u16).to_le_bytes());
+    datagram.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    datagram.extend_from_slice(&info.timestamp_ms.to_le_bytes());
+    // Each slice is checked on its own, so the ones that arrive can be used even
+    // if the rest of the frame doesn't
+    if info.checksum.is_some() {
+      datagram.extend_from_slice(&crc32fast::hash(chunk).to_le_bytes());
+    }
+    if let Some(group) = fec {
+      datagram.extend_from_slice(&group.to_le_bytes());
+    }
+    datagram.extend_from_slice(chunk);
+    socket.send(&datagram)?;
+  }
+
+  Ok(())
+}
+
+/// Header-only datagram marking the end of the stream
+pub fn send_end_of_stream_datagram(socket: &UdpSocket, seq: u64) -> io::Result<()> {
+  let mut datagram = [0u8; DATAGRAM_HEADER_SIZE];
+  datagram[0..4].copy_from_slice(&(seq as u32).to_le_bytes());
+  socket.send(&datagram).map(|_| ())
+}
+
+/// One datagram of `send_frame_datagrams`, or a header-only control datagram
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Datagram<'a> {
+  pub frame_id: u32,
+  pub chunk_index: u16,
+  /// 0 for the end of the stream and for repeats
+  pub chunk_count: u16,
+  pub width: u16,
+  pub height: u16,
+  pub total_size: u32,
+  pub timestamp_ms: u64,
+  pub checksum: Option<u32>,
+  /// Data slices per parity slice, 0 without FEC
+  pub fec_group: u16,
+  pub payload: &'a [u8],
+}
+
+impl Datagram<'_> {
+  pub fn is_end_of_stream(&self) -> bool {
+    self.chunk_count == 0 && (self.width, self.height) == (0, 0)
+  }
+
+  pub fn is_repeat(&self) -> bool {
+    self.chunk_count == 0 && (self.width, self.height) != (0, 0)
+  }
+
+  /// A parity slice rather than one of the frame's own
+  pub fn is_parity(&self) -> bool {
+    self.chunk_index >= self.chunk_count
+  }
+}
+
+/// Split a datagram received on a stream whose handshake had CHECKSUM and FEC set
+/// or not into its header fields and payload
+pub fn parse_datagram(bytes: &[u8], checksummed: bool, fec: bool) -> io::Result<Datagram<'_>> {
+  if bytes.len() < DATAGRAM_HEADER_SIZE {
+    return Err(io::Error::new(
+      io::ErrorKind::InvalidData,
+      format!("{}-byte datagram is shorter than its header", bytes.len()),
+    ));
+  }
+  let u16_at = |offset: usize| u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+  let u32_at = |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+  let chunk_count = u16_at(6);
+  // Control datagrams are the header alone, even with CHECKSUM or FEC
+  let payload_at = match chunk_count {
+    0 => DATAGRAM_HEADER_SIZE,
+    _ => datagram_header_size(checksummed, fec),
+  };
+  if bytes.len() < payload_at {
+    return Err(io::Error::new(
+      io::ErrorKind::InvalidData,
+      "datagram is missing its checksum or fec_group",
+    ));
+  }
+  let checksum = (chunk_count != 0 && checksummed).then(|| u32_at(DATAGRAM_HEADER_SIZE));
+  let fec_group = match chunk_count != 0 && fec {
+    true => u16_at(payload_at - 2),
+    false => 0,
+  };
+  Ok(Datagram {
+    frame_id: u32_at(0),
+    chunk_index: u16_at(4),
+    chunk_count,
+    width: u16_at(8),
+    height: u16_at(10),
+    total_size: u32_at(12),
+    timestamp_ms: u64::from_le_bytes(bytes[16..24].try_into().unwrap()),
+    checksum,
+    fec_group,
+    payload: &bytes[payload_at..],
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn info(seq: u64) -> FrameInfo {
+    FrameInfo {
+      width: 3,
+      height: 2,
+      seq,
+      timestamp_ms: 1234,
+      raw_size: 24,
+      checksum: None,
+      nonce: None,
+    }
+  }
+
+  #[test]
+  fn negotiation_prefers_the_receiver_then_the_handshake() {
+    let all = Capabilities::new(&CODECS, &PIXEL_FORMATS);
+    let offer = Capabilities::new(
+      &[Codec::Raw, Codec::Zstd],
+      &[PixelFormat::Bgra, PixelFormat::Rgba],
+    );
+    let choose = |answer, preferred| {
+      choose_pixel_format(Codec::Raw, PixelFormat::Bgra, offer, answer, preferred)
+    };
+    assert_eq!(choose(all, NO_PIXEL_FORMAT), Ok(PixelFormat::Bgra));
+    assert_eq!(choose(all, PixelFormat::Rgba as u8), Ok(PixelFormat::Rgba));
+    // A preference for something that wasn't offered is ignored
+    assert_eq!(choose(all, PixelFormat::Gray as u8), Ok(PixelFormat::Bgra));
+    let rgba_only = Capabilities::new(&[Codec::Raw], &[PixelFormat::Rgba]);
+    assert_eq!(choose(rgba_only, NO_PIXEL_FORMAT), Ok(PixelFormat::Rgba));
+  }
+
+  #[test]
+  fn negotiation_fails_without_a_common_codec_or_format() {
+    let offer = Capabilities::new(&CODECS, &[PixelFormat::Rgba]);
+    let no_h264 = Capabilities::new(&[Codec::Raw, Codec::Jpeg], &PIXEL_FORMATS);
+    let err = choose_pixel_format(
+      Codec::H264,
+      PixelFormat::Rgba,
+      offer,
+      no_h264,
+      NO_PIXEL_FORMAT,
+    );
+    assert_eq!(
+      err.unwrap_err(),
+      "the receiver can't decode h264 (it supports raw, jpeg)"
+    );
+    let gray_only = Capabilities::new(&CODECS, &[PixelFormat::Gray]);
+    assert!(choose_pixel_format(Codec::Raw, PixelFormat::Rgba, offer, gray_only, 0xff).is_err());
+  }
+
+  #[test]
+  fn variants_parse_and_round_trip() {
+    let variant: Variant = "jpeg:60@1280x720".parse().unwrap();
+    assert_eq!(
+      variant,
+      Variant {
+        codec: Some(Codec::Jpeg),
+        quality: Some(60),
+        size: Some([1280, 720]),
+      }
+    );
+    assert_eq!(Variant::from_bytes(variant.to_bytes()), variant);
+    assert_eq!("@640x360".parse::<Variant>().unwrap().codec, None);
+    assert_eq!(
+      Variant::from_bytes(Variant::default().to_bytes()),
+      Variant::default()
+    );
+    for bad in ["jpeg:0", "jpeg@640", "webp", "raw:50@0x10"] {
+      assert!(bad.parse::<Variant>().is_err(), "{} parsed", bad);
+    }
+  }
+
+  #[test]
+  fn variants_are_offered_while_negotiating() {
+    let handshake = Handshake {
+      width: 640,
+      height: 360,
+      pixel_format: PixelFormat::Bgra,
+      codec: Codec::Raw,
+      fps: 30,
+      stride: 640 * 4,
+      negotiate: false,
+      audio: false,
+      encrypted: false,
+      checksum: false,
+      cursor: false,
+      fec: false,
+      variants: true,
+    };
+    assert_eq!(handshake.to_bytes()[7], NEGOTIATE | VARIANTS);
+    let plain = Handshake {
+      variants: false,
+      ..handshake
+    };
+    assert_eq!(plain.to_bytes()[7], 0);
+  }
+
+  #[test]
+  fn frames_round_trip() {
+    let data: Vec<u8> = (0..24).collect();
+    let mut wire = Vec::new();
+    // 24 bytes in chunks of 10: two full chunks and a shorter last one
+    send_frame(&mut wire, &info(7), &data, 10).unwrap();
+    assert_eq!(wire.len(), METADATA_SIZE + 3 * 4 + data.len());
+    send_end_of_stream(&mut wire, 8).unwrap();
+
+    let mut reader = wire.as_slice();
+    let mut read = Vec::new();
+    let got = read_frame(&mut reader, PROTOCOL_VERSION, false, false, &mut read).unwrap();
+    assert_eq!(got, Some(info(7)));
+    assert_eq!(read, data);
+    assert_eq!(
+      read_frame(&mut reader, PROTOCOL_VERSION, false, false, &mut read).unwrap(),
+      None
+    );
+    assert!(reader.is_empty());
+  }
+
+  #[test]
+  fn control_markers_are_tiny_and_skip_the_checksum() {
+    let repeat = FrameInfo::repeat(3, 2, 7, 1300);
+    let mut wire = Vec::new();
+    send_frame(&mut wire, &repeat, &[], 10).unwrap();
+    assert_eq!(wire.len(), METADATA_SIZE);
+    let resized = FrameInfo::size_change(4, 2, 8, 32);
+    send_frame(&mut wire, &resized, &[], 10).unwrap();
+    send_end_of_stream(&mut wire, 8).unwrap();
+
+    let mut reader = wire.as_slice();
+    let mut read = vec![1, 2, 3];
+    let got = read_frame(&mut reader, PROTOCOL_VERSION, true, true, &mut read).unwrap();
+    assert_eq!(got, Some(repeat));
+    assert!(read.is_empty() && !repeat.is_size_change());
+    let got = read_frame(&mut reader, PROTOCOL_VERSION, true, true, &mut read).unwrap();
+    assert_eq!(got, Some(resized));
+    assert!(read.is_empty() && resized.is_size_change());
+    assert_eq!(
+      read_frame(&mut reader, PROTOCOL_VERSION, true, true, &mut read).unwrap(),
+      None
+    );
+  }
+
+  #[test]
+  fn datagrams_round_trip() {
+    let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+    sender.connect(receiver.local_addr().unwrap()).unwrap();
+    let data: Vec<u8> = (0..50).collect();
+    let sent = FrameInfo {
+      checksum: Some(crc32fast::hash(&data)),
+      ..info(7)
+    };
+    // 50 bytes, 20 to a datagram after the header and checksum
+    send_frame_datagrams(&sender, &sent, &data, DATAGRAM_HEADER_SIZE + 4 + 20, None).unwrap();
+    let repeat = FrameInfo::repeat(3, 2, 7, 1300);
+    send_frame_datagrams(&sender, &repeat, &[], 1400, None).unwrap();
+    send_end_of_stream_datagram(&sender, 8).unwrap();
+
+    let mut buf = [0u8; 1500];
+    let mut payload = Vec::new();
+    for index in 0..3 {
+      let len = receiver.recv(&mut buf).unwrap();
+      let datagram = parse_datagram(&buf[..len], true, false).unwrap();
+      assert_eq!(
+        (
+          datagram.frame_id,
+          datagram.chunk_index,
+          datagram.chunk_count
+        ),
+        (7, index, 3)
+      );
+      assert_eq!((datagram.width, datagram.height), (3, 2));
+      assert_eq!(datagram.total_size, 50);
+      // Each slice carries the checksum of its own payload
+      assert_eq!(datagram.checksum, Some(crc32fast::hash(datagram.payload)));
+      payload.extend_from_slice(datagram.payload);
+    }
+    assert_eq!(payload, data);
+
+    let len = receiver.recv(&mut buf).unwrap();
+    let repeat = parse_datagram(&buf[..len], true, false).unwrap();
+    assert!(repeat.is_repeat() && !repeat.is_end_of_stream());
+    assert_eq!((repeat.frame_id, repeat.timestamp_ms), (7, 1300));
+    let len = receiver.recv(&mut buf).unwrap();
+    assert!(parse_datagram(&buf[..len], true, false)
+      .unwrap()
+      .is_end_of_stream());
+    assert!(parse_datagram(&buf[..10], false, false).is_err());
+  }
+
+  #[test]
+  fn parity_slices_follow_the_frame() {
+    let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+    sender.connect(receiver.local_addr().unwrap()).unwrap();
+    let data: Vec<u8> = (0..50).collect();
+    // Slices of 20, 20 and 10 bytes, with one parity slice per two
+    let datagram_size = DATAGRAM_HEADER_SIZE + 2 + 20;
+    send_frame_datagrams(&sender, &info(7), &data, datagram_size, Some(2)).unwrap();
+
+    let mut buf = [0u8; 1500];
+    let mut slices = Vec::new();
+    for index in 0..5 {
+      let len = receiver.recv(&mut buf).unwrap();
+      let datagram = parse_datagram(&buf[..len], false, true).unwrap();
+      assert_eq!((datagram.chunk_index, datagram.chunk_count), (index, 3));
+      assert_eq!((datagram.fec_group, datagram.total_size), (2, 50));
+      assert_eq!(datagram.is_parity(), index >= 3);
+      slices.push(datagram.payload.to_vec());
+    }
+    assert_eq!(slices[3], fec::xor([&data[..20], &data[20..40]], 20));
+    assert_eq!(slices[4], &data[40..]);
+  }
+
+  #[test]
+  fn nonce_follows_the_metadata() {
+    let sealed = FrameInfo {
+      nonce: Some([9; NONCE_SIZE]),
+      ..info(1)
+    };
+    let mut wire = Vec::new();
+    send_frame(&mut wire, &sealed, &[5; 30], 64).unwrap();
+    assert_eq!(&wire[METADATA_SIZE..METADATA_SIZE + NONCE_SIZE], &[9; 12]);
+
+    let mut read = Vec::new();
+    let got = read_frame(
+      &mut wire.as_slice(),
+      PROTOCOL_VERSION,
+      true,
+      false,
+      &mut read,
+    )
+    .unwrap();
+    assert_eq!(got, Some(sealed));
+    assert_eq!(read, [5; 30]);
+  }
+
+  #[test]
+  fn checksum_comes_before_the_nonce() {
+    let data = [5; 30];
+    let checked = FrameInfo {
+      checksum: Some(crc32fast::hash(&data)),
+      nonce: Some([9; NONCE_SIZE]),
+      ..info(1)
+    };
+    let mut wire = Vec::new();
+    send_frame(&mut wire, &checked, &data, 64).unwrap();
+    assert_eq!(
+      &wire[METADATA_SIZE + 4..METADATA_SIZE + 4 + NONCE_SIZE],
+      &[9; 12]
+    );
+
+    let mut read = Vec::new();
+    let got = read_frame(
+      &mut wire.as_slice(),
+      PROTOCOL_VERSION,
+      true,
+      true,
+      &mut read,
+    )
+    .unwrap()
+    .unwrap();
+    assert_eq!(got, checked);
+    assert!(got.checksum_matches(&read));
+    read[0] ^= 1;
+    assert!(!got.checksum_matches(&read));
+  }
+
+  #[test]
+  fn older_versions_have_shorter_metadata() {
+    let mut wire = Vec::new();
+    send_frame(&mut wire, &info(7), &[1, 2, 3], 64).unwrap();
+    // A version 1 sender stops after num_chunks
+    let v1: Vec<u8> = [&wire[..16], &wire[METADATA_SIZE..]].concat();
+
+    let mut read = Vec::new();
+    let got = read_frame(&mut v1.as_slice(), 1, false, false, &mut read)
+      .unwrap()
+      .unwrap();
+    assert_eq!((got.width, got.height, got.seq, got.raw_size), (3, 2, 0, 0));
+    assert_eq!(read, [1, 2, 3]);
+  }
+
+  #[test]
+  fn rejects_chunks_that_disagree_with_the_metadata() {
+    let mut wire = Vec::new();
+    send_frame(&mut wire, &info(0), &[0; 8], 4).unwrap();
+    // Claim a 6-byte frame, which the second chunk overflows
+    wire[8..12].copy_from_slice(&6u32.to_le_bytes());
+    let err = read_frame(
+      &mut wire.as_slice(),
+      PROTOCOL_VERSION,
+      false,
+      false,
+      &mut Vec::new(),
+    );
+    assert_eq!(err.unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+    // A stream cut off mid-frame
+    let mut wire = Vec::new();
+    send_frame(&mut wire, &info(0), &[0; 8], 4).unwrap();
+    wire.truncate(wire.len() - 1);
+    let err = read_frame(
+      &mut wire.as_slice(),
+      PROTOCOL_VERSION,
+      false,
+      false,
+      &mut Vec::new(),
+    );
+    assert_eq!(err.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+  }
+}
@@ -0,0 +1,127 @@
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// What to do with a frame the bandwidth cap doesn't have room for yet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverLimit {
+  /// Hold the frame until it fits; the capture buffer drops stale frames meanwhile
+  Delay,
+  /// Skip the frame and send the next one that fits
+  Drop,
+}
+
+impl FromStr for OverLimit {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "delay" => Ok(OverLimit::Delay),
+      "drop" => Ok(OverLimit::Drop),
+      _ => Err(format!(
+        "Unknown --over-limit '{}' (expected delay or drop)",
+        s
+      )),
+    }
+  }
+}
+
+/// Token bucket capping egress at `rate` bytes per second. The bucket holds at most
+/// one second's worth of tokens, so short idle periods allow a burst but the long-run
+/// average stays at `rate`. A frame larger than the bucket may still go once the
+/// bucket is full; the overdraft is paid back before the next frame.
+pub struct TokenBucket {
+  rate: f64,
+  capacity: f64,
+  tokens: f64,
+  last: Instant,
+}
+
+impl TokenBucket {
+  pub fn new(rate: f64, now: Instant) -> Self {
+    TokenBucket {
+      rate,
+      capacity: rate,
+      tokens: rate,
+      last: now,
+    }
+  }
+
+  fn refill(&mut self, now: Instant) {
+    let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+    self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+    self.last = now;
+  }
+
+  // Tokens that must be available before `bytes` can go
+  fn needed(&self, bytes: usize) -> f64 {
+    (bytes as f64).min(self.capacity)
+  }
+
+  /// How long to wait before `bytes` may be sent; zero if it can go now
+  pub fn delay_for(&mut self, bytes: usize, now: Instant) -> Duration {
+    self.refill(now);
+    let missing = self.needed(bytes) - self.tokens;
+    if missing <= 0.0 {
+      return Duration::ZERO;
+    }
+    Duration::from_secs_f64(missing / self.rate)
+  }
+
+  /// Account for `bytes` sent, whether or not they fit
+  pub fn take(&mut self, bytes: usize, now: Instant) {
+    self.refill(now);
+    self.tokens -= bytes as f64;
+  }
+
+  /// Take the tokens for `bytes` if they're available, returning whether they were
+  pub fn try_take(&mut self, bytes: usize, now: Instant) -> bool {
+    if !self.delay_for(bytes, now).is_zero() {
+      return false;
+    }
+    self.take(bytes, now);
+    true
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn allows_a_burst_then_paces_to_the_rate() {
+    let start = Instant::now();
+    let mut bucket = TokenBucket::new(1000.0, start);
+
+    // A full bucket lets one second's worth through at once
+    assert!(bucket.try_take(600, start));
+    assert!(bucket.try_take(400, start));
+    assert!(!bucket.try_take(100, start));
+    assert_eq!(bucket.delay_for(100, start), Duration::from_millis(100));
+
+    // Tokens come back at the configured rate
+    let later = start + Duration::from_millis(100);
+    assert!(bucket.try_take(100, later));
+    assert!(!bucket.try_take(1, later));
+  }
+
+  #[test]
+  fn oversized_frames_go_when_full_and_are_paid_back() {
+    let start = Instant::now();
+    let mut bucket = TokenBucket::new(1000.0, start);
+
+    assert!(bucket.try_take(3000, start));
+    // 3000 bytes at 1000/s: the debt plus the next frame take 2.5s to cover
+    assert_eq!(bucket.delay_for(500, start), Duration::from_millis(2500));
+    assert!(!bucket.try_take(500, start + Duration::from_secs(2)));
+    assert!(bucket.try_take(500, start + Duration::from_millis(2500)));
+  }
+
+  #[test]
+  fn idle_time_does_not_bank_more_than_one_second() {
+    let start = Instant::now();
+    let mut bucket = TokenBucket::new(1000.0, start);
+    let later = start + Duration::from_secs(60);
+    assert!(bucket.try_take(1000, later));
+    assert!(!bucket.try_take(1, later));
+  }
+}
@@ -0,0 +1,227 @@
+// MP4 recording of the H.264 stream, used with --record.
+//
+// Each h264 payload (see video.rs) becomes one sample of a single video track. MP4
+// stores NAL units behind 4-byte big-endian lengths instead of Annex B start codes,
+// and keeps the SPS/PPS in the track header, so those come out of the samples. The
+// file only becomes playable once `finish` has written the index at the end.
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use bytes::Bytes;
+use mp4::{
+  AvcConfig, FourCC, MediaConfig, Mp4Config, Mp4Sample, Mp4Writer, TrackConfig, TrackType,
+};
+
+use crate::video::KEYFRAME;
+
+// Sample times are in milliseconds, like FrameInfo::timestamp_ms
+const TIMESCALE: u32 = 1000;
+const TRACK_ID: u32 = 1;
+
+const NAL_SPS: u8 = 7;
+const NAL_PPS: u8 = 8;
+const NAL_ACCESS_UNIT_DELIMITER: u8 = 9;
+
+pub struct Recorder {
+  path: PathBuf,
+  writer: Mp4Writer<BufWriter<File>>,
+  width: u32,
+  height: u32,
+  /// Duration given to the last sample, which has no successor to measure against
+  frame_ms: u32,
+  /// Timestamp of the first recorded frame, so the file starts at zero
+  start_ms: Option<u64>,
+  /// Held back until the next frame says how long it lasts
+  pending: Option<Mp4Sample>,
+  frames: u64,
+}
+
+impl Recorder {
+  /// Create the file up front so an unwritable path fails before streaming starts
+  pub fn create(path: &Path, width: u32, height: u32, fps: u32) -> Result<Self, String> {
+    let file =
+      File::create(path).map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+    let config = Mp4Config {
+      major_brand: FourCC { value: *b"isom" },
+      minor_version: 512,
+      compatible_brands: vec![
+        FourCC { value: *b"isom" },
+        FourCC { value: *b"iso2" },
+        FourCC { value: *b"avc1" },
+        FourCC { value: *b"mp41" },
+      ],
+      timescale: TIMESCALE,
+    };
+    let writer =
+      Mp4Writer::write_start(BufWriter::new(file), &config).map_err(|e| e.to_string())?;
+    Ok(Recorder {
+      path: path.to_path_buf(),
+      writer,
+      width,
+      height,
+      frame_ms: (TIMESCALE / fps.max(1)).max(1),
+      start_ms: None,
+      pending: None,
+      frames: 0,
+    })
+  }
+
+  /// Add one h264 payload captured at `timestamp_ms`. Frames before the first
+  /// keyframe are skipped, since nothing could decode them.
+  pub fn write(&mut self, payload: &[u8], timestamp_ms: u64) -> Result<(), String> {
+    let Some((&kind, access_unit)) = payload.split_first() else {
+      return Ok(());
+    };
+    let sample = Sample::parse(access_unit);
+
+    let start_ms = match self.start_ms {
+      Some(start_ms) => start_ms,
+      None => {
+        let (Some(sps), Some(pps)) = (sample.sps, sample.pps) else {
+          return Ok(());
+        };
+        let track = TrackConfig {
+          track_type: TrackType::Video,
+          timescale: TIMESCALE,
+          language: "und".to_string(),
+          media_conf: MediaConfig::AvcConfig(AvcConfig {
+            width: self.width as u16,
+            height: self.height as u16,
+            seq_param_set: sps.to_vec(),
+            pic_param_set: pps.to_vec(),
+          }),
+        };
+        self.writer.add_track(&track).map_err(|e| e.to_string())?;
+        *self.start_ms.insert(timestamp_ms)
+      }
+    };
+    if sample.data.is_empty() {
+      return Ok(());
+    }
+
+    let start_time = timestamp_ms.saturating_sub(start_ms);
+    if let Some(mut previous) = self.pending.take() {
+      previous.duration = start_time.saturating_sub(previous.start_time).max(1) as u32;
+      self.write_sample(previous)?;
+    }
+    self.pending = Some(Mp4Sample {
+      start_time,
+      duration: self.frame_ms,
+      rendering_offset: 0,
+      is_sync: kind == KEYFRAME,
+      bytes: Bytes::from(sample.data),
+    });
+    Ok(())
+  }
+
+  fn write_sample(&mut self, sample: Mp4Sample) -> Result<(), String> {
+    self.frames += 1;
+    self
+      .writer
+      .write_sample(TRACK_ID, &sample)
+      .map_err(|e| e.to_string())
+  }
+
+  /// Write the last frame and the index. Returns the number of frames recorded
+  /// and where.
+  pub fn finish(mut self) -> Result<(u64, PathBuf), String> {
+    if let Some(last) = self.pending.take() {
+      self.write_sample(last)?;
+    }
+    self.writer.write_end().map_err(|e| e.to_string())?;
+    Ok((self.frames, self.path))
+  }
+}
+
+/// One access unit split into what goes in the track header and the sample itself
+struct Sample<'a> {
+  sps: Option<&'a [u8]>,
+  pps: Option<&'a [u8]>,
+  /// Remaining NAL units, each behind its length
+  data: Vec<u8>,
+}
+
+impl<'a> Sample<'a> {
+  fn parse(access_unit: &'a [u8]) -> Self {
+    let mut sample = Sample {
+      sps: None,
+      pps: None,
+      data: Vec::with_capacity(access_unit.len()),
+    };
+    for nal in nal_units(access_unit) {
+      match nal[0] & 0x1f {
+        NAL_SPS => sample.sps = Some(nal),
+        NAL_PPS => sample.pps = Some(nal),
+        NAL_ACCESS_UNIT_DELIMITER => {}
+        _ => {
+          sample
+            .data
+            .extend_from_slice(&(nal.len() as u32).to_be_bytes());
+          sample.data.extend_from_slice(nal);
+        }
+      }
+    }
+    sample
+  }
+}
+
+/// The NAL units of an Annex B byte stream, without their 3- or 4-byte start codes
+fn nal_units(stream: &[u8]) -> Vec<&[u8]> {
+  let mut units = Vec::new();
+  let mut start = None;
+  let mut i = 0;
+  while i + 3 <= stream.len() {
+    if stream[i..i + 3] == [0, 0, 1] {
+      if let Some(start) = start {
+        units.push(&stream[start..i]);
+      }
+      i += 3;
+      start = Some(i);
+    } else {
+      i += 1;
+    }
+  }
+  if let Some(start) = start {
+    units.push(&stream[start..]);
+  }
+
+  // A unit never ends in a zero byte, so trailing ones belong to the next 4-byte
+  // start code
+  units
+    .into_iter()
+    .map(|unit| {
+      let end = unit.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+      &unit[..end]
+    })
+    .filter(|unit| !unit.is_empty())
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn splits_annex_b_on_both_start_code_lengths() {
+    let stream = [
+      0, 0, 0, 1, 0x67, 1, 2, 0, 0, 1, 0x68, 3, 0, 0, 0, 1, 0x65, 4, 5,
+    ];
+    assert_eq!(
+      nal_units(&stream),
+      vec![&[0x67, 1, 2][..], &[0x68, 3][..], &[0x65, 4, 5][..]]
+    );
+  }
+
+  #[test]
+  fn moves_parameter_sets_out_of_the_sample() {
+    let stream = [
+      0, 0, 0, 1, 0x09, 0xf0, 0, 0, 0, 1, 0x67, 1, 0, 0, 1, 0x68, 2, 0, 0, 1, 0x65, 7, 8, 9,
+    ];
+    let sample = Sample::parse(&stream);
+    assert_eq!(sample.sps, Some(&[0x67, 1][..]));
+    assert_eq!(sample.pps, Some(&[0x68, 2][..]));
+    assert_eq!(sample.data, vec![0, 0, 0, 4, 0x65, 7, 8, 9]);
+  }
+}
@@ -0,0 +1,267 @@
+// --loop-file: a recording played back in place of the screen, over and over. It
+// reads a Y4M file, whose header gives the frame size, or BGRA frames back to back
+// with no row padding, whose size --loop-size gives. The receiver's --out writes
+// Y4M from any stream, but --raw writes the stream's own pixel format and stride,
+// so only raw dumps of a BGRA stream, as the streamer sends it, play back right.
+// Y4M frames go to the capture thread as NV12, so --colorspace and --range say how
+// they were converted, the same as for a capturer delivering YUV.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use scap::frame::{BGRAFrame, Frame, YUVFrame};
+
+const Y4M_MAGIC: &[u8] = b"YUV4MPEG2 ";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Layout {
+  /// 4:2:0 Y, U and V planes after a `FRAME` line
+  Yuv420,
+  /// Only the Y plane, with no colour
+  Mono,
+  Bgra,
+}
+
+/// A recording opened for --loop-file
+pub struct Recording {
+  reader: BufReader<File>,
+  layout: Layout,
+  size: [u32; 2],
+  /// Where the first frame starts, to go back to at the end
+  start: u64,
+  /// Frame rate the Y4M header gives
+  fps: Option<f64>,
+  planes: Vec<u8>,
+}
+
+impl Recording {
+  /// Open `path`, a Y4M file or raw BGRA frames of `raw_size`
+  pub fn open(path: &Path, raw_size: Option<[u32; 2]>) -> Result<Self, String> {
+    let read_error = |e: io::Error| format!("Can't read {}: {}", path.display(), e);
+    let mut reader = BufReader::new(File::open(path).map_err(read_error)?);
+    let is_y4m = reader
+      .fill_buf()
+      .map_err(read_error)?
+      .starts_with(Y4M_MAGIC);
+    let (layout, size, fps) = if is_y4m {
+      let mut header = Vec::new();
+      reader.read_until(b'\n', &mut header).map_err(read_error)?;
+      let (layout, size, fps) = parse_header(&header)
+        .map_err(|e| format!("{} isn't a Y4M file we can play: {}", path.display(), e))?;
+      if raw_size.is_some() {
+        return Err(format!(
+          "{} is a Y4M file, whose header gives its size; leave out --loop-size",
+          path.display()
+        ));
+      }
+      (layout, size, fps)
+    } else {
+      let size = raw_size.ok_or_else(|| {
+        format!(
+          "{} has no Y4M header, so give the size of its raw BGRA frames with --loop-size",
+          path.display()
+        )
+      })?;
+      (Layout::Bgra, size, None)
+    };
+    let start = reader.stream_position().map_err(read_error)?;
+    let mut recording = Recording {
+      reader,
+      layout,
+      size,
+      start,
+      fps,
+      planes: Vec::new(),
+    };
+    // A file without a whole frame would have nothing to loop over
+    match recording.read_into(Vec::new()) {
+      Ok(Some(_)) => {}
+      Ok(None) => return Err(format!("{} holds no whole frame", path.display())),
+      Err(e) => return Err(read_error(e)),
+    }
+    recording.rewind().map_err(read_error)?;
+    Ok(recording)
+  }
+
+  pub fn size(&self) -> [u32; 2] {
+    self.size
+  }
+
+  /// Frame rate the file was recorded at, where it says
+  pub fn fps(&self) -> Option<f64> {
+    self.fps
+  }
+
+  /// The next frame, from the start again after the last one. `spare` is filled
+  /// in and handed over rather than allocating.
+  pub fn next_frame(&mut self, spare: Vec<u8>) -> io::Result<Frame> {
+    if let Some(frame) = self.read_into(spare)? {
+      return Ok(frame);
+    }
+    self.rewind()?;
+    self
+      .read_into(Vec::new())?
+      .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "the recording is empty"))
+  }
+
+  fn rewind(&mut self) -> io::Result<()> {
+    self.reader.seek(SeekFrom::Start(self.start)).map(|_| ())
+  }
+
+  /// Read one frame, `None` at the end of the file, a partial last frame included
+  fn read_into(&mut self, mut data: Vec<u8>) -> io::Result<Option<Frame>> {
+    let [width, height] = self.size.map(|side| side as usize);
+    let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+    if self.layout == Layout::Bgra {
+      data.resize(width * height * 4, 0);
+      if !read_whole(&mut self.reader, &mut data)? {
+        return Ok(None);
+      }
+      return Ok(Some(Frame::BGRA(BGRAFrame {
+        display_time: 0,
+        width: width as i32,
+        height: height as i32,
+        data,
+      })));
+    }
+
+    // Every frame starts with a FRAME line, which may carry parameters
+    let mut line = Vec::new();
+    if self.reader.read_until(b'\n', &mut line)? == 0 {
+      return Ok(None);
+    }
+    if !line.starts_with(b"FRAME") {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "a Y4M frame doesn't start with FRAME",
+      ));
+    }
+    data.resize(width * height, 0);
+    if !read_whole(&mut self.reader, &mut data)? {
+      return Ok(None);
+    }
+    // NV12 interleaves U and V in one plane; mono frames have neither, so get grey
+    let chroma = chroma_width * chroma_height;
+    let mut interleaved = vec![128; chroma * 2];
+    if self.layout == Layout::Yuv420 {
+      self.planes.resize(chroma * 2, 0);
+      if !read_whole(&mut self.reader, &mut self.planes)? {
+        return Ok(None);
+      }
+      let (u, v) = self.planes.split_at(chroma);
+      for (pair, (&u, &v)) in interleaved.chunks_exact_mut(2).zip(u.iter().zip(v)) {
+        pair.copy_from_slice(&[u, v]);
+      }
+    }
+    Ok(Some(Frame::YUVFrame(YUVFrame {
+      display_time: 0,
+      width: width as i32,
+      height: height as i32,
+      luminance_bytes: data,
+      luminance_stride: width as i32,
+      chrominance_bytes: interleaved,
+      chrominance_stride: (chroma_width * 2) as i32,
+    })))
+  }
+}
+
+/// Fill `buf`, or return false if the file ends first
+fn read_whole(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<bool> {
+  match reader.read_exact(buf) {
+    Ok(()) => Ok(true),
+    Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+    Err(e) => Err(e),
+  }
+}
+
+/// The layout, size and frame rate in a `YUV4MPEG2 ...` header line
+fn parse_header(line: &[u8]) -> Result<(Layout, [u32; 2], Option<f64>), String> {
+  let line = std::str::from_utf8(line).map_err(|_| "the header isn't text".to_string())?;
+  let (mut width, mut height, mut fps) = (None, None, None);
+  // Without a C parameter, frames are 4:2:0
+  let mut layout = Layout::Yuv420;
+  for param in line.split_ascii_whitespace().skip(1) {
+    let Some(tag) = param.get(..1) else {
+      continue;
+    };
+    let value = &param[1..];
+    match tag {
+      "W" => width = value.parse::<u32>().ok(),
+      "H" => height = value.parse::<u32>().ok(),
+      "F" => {
+        fps = value
+          .split_once(':')
+          .and_then(|(num, den)| Some(num.parse::<f64>().ok()? / den.parse::<f64>().ok()?))
+          .filter(|fps| fps.is_finite() && *fps > 0.0)
+      }
+      "C" => {
+        layout = match value {
+          "420" | "420jpeg" | "420paldv" | "420mpeg2" => Layout::Yuv420,
+          "mono" => Layout::Mono,
+          _ => return Err(format!("{} chroma (only 4:2:0 and mono are)", value)),
+        }
+      }
+      "I" if value != "p" => return Err("the frames are interlaced".to_string()),
+      _ => {}
+    }
+  }
+  match (width, height) {
+    (Some(width), Some(height)) if width > 0 && height > 0 => Ok((layout, [width, height], fps)),
+    _ => Err("the header has no frame size".to_string()),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::fs;
+
+  #[test]
+  fn reads_y4m_frames_as_nv12_and_loops() {
+    let path = std::env::temp_dir().join("screen-streamer-replay-test.y4m");
+    let mut file = b"YUV4MPEG2 W3 H2 F30:1 Ip A1:1 C420jpeg\n".to_vec();
+    for shade in [10, 20] {
+      file.extend_from_slice(b"FRAME\n");
+      file.extend_from_slice(&[shade; 6]);
+      file.extend_from_slice(&[shade + 1, shade + 1, shade + 2, shade + 2]);
+    }
+    // A partial frame at the end is left out
+    file.extend_from_slice(b"FRAME\n\x01\x02");
+    fs::write(&path, file).unwrap();
+
+    let mut recording = Recording::open(&path, None).unwrap();
+    assert_eq!(recording.size(), [3, 2]);
+    assert_eq!(recording.fps(), Some(30.0));
+    let shades: Vec<_> = (0..5)
+      .map(|_| match recording.next_frame(Vec::new()).unwrap() {
+        Frame::YUVFrame(frame) => {
+          assert_eq!(frame.chrominance_stride, 4);
+          assert_eq!(
+            frame.chrominance_bytes[..2],
+            [frame.luminance_bytes[0] + 1, frame.luminance_bytes[0] + 2]
+          );
+          frame.luminance_bytes[0]
+        }
+        _ => panic!("a Y4M frame came out as another layout"),
+      })
+      .collect();
+    assert_eq!(shades, [10, 20, 10, 20, 10]);
+    assert!(Recording::open(&path, Some([3, 2])).is_err());
+    let _ = fs::remove_file(&path);
+  }
+
+  #[test]
+  fn raw_files_need_a_size_and_a_whole_frame() {
+    let path = std::env::temp_dir().join("screen-streamer-replay-test.raw");
+    fs::write(&path, [0u8; 2 * 2 * 4]).unwrap();
+    assert!(Recording::open(&path, None).is_err());
+    assert!(Recording::open(&path, Some([4, 4])).is_err());
+    let mut recording = Recording::open(&path, Some([2, 2])).unwrap();
+    assert!(matches!(
+      recording.next_frame(Vec::new()),
+      Ok(Frame::BGRA(_))
+    ));
+    let _ = fs::remove_file(&path);
+  }
+}
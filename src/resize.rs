@@ -0,0 +1,164 @@
+// --scale and --width: resample captured frames to any size in software, after
+// cropping and before conversion, so the output no longer depends on the sizes
+// --resolution asks scap for. Both filters sample at pixel centres, so halving a
+// frame with bilinear averages each 2x2 block.
+
+use std::str::FromStr;
+
+/// How output pixels are taken from the source frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+  /// The closest source pixel: cheapest, but thin lines and text shimmer
+  Nearest,
+  /// A weighted blend of the four closest source pixels
+  Bilinear,
+}
+
+impl FromStr for Filter {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "nearest" => Ok(Filter::Nearest),
+      "bilinear" => Ok(Filter::Bilinear),
+      _ => Err(format!(
+        "Unknown --filter '{}' (expected nearest or bilinear)",
+        s
+      )),
+    }
+  }
+}
+
+/// Output size, relative to the frame being resized
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scale {
+  /// Multiply both sides by this factor
+  Factor(f64),
+  /// This many pixels wide, with the height keeping the aspect ratio
+  Width(u32),
+}
+
+impl Scale {
+  /// Output size for frames of `size`, rounded to even numbers so every codec
+  /// takes it
+  pub fn size(self, [width, height]: [u32; 2]) -> [u32; 2] {
+    let factor = match self {
+      Scale::Factor(factor) => factor,
+      Scale::Width(target) => target as f64 / width as f64,
+    };
+    let side = |side: u32| ((side as f64 * factor).round() as u32 & !1).max(2);
+    [side(width), side(height)]
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Resize {
+  pub scale: Scale,
+  pub filter: Filter,
+}
+
+// Bilinear weights are fixed point with this many steps between two pixels
+const WEIGHT_ONE: u32 = 256;
+
+/// Resample a BGRA frame whose rows are `stride` bytes apart to `out_width` x
+/// `out_height` tightly packed pixels in `out`
+pub fn resize_bgra_into(
+  src: &[u8],
+  [width, height]: [usize; 2],
+  stride: usize,
+  [out_width, out_height]: [usize; 2],
+  filter: Filter,
+  out: &mut Vec<u8>,
+) {
+  out.clear();
+  out.reserve(out_width * out_height * 4);
+  match filter {
+    Filter::Nearest => {
+      let columns: Vec<usize> = (0..out_width)
+        .map(|x| nearest(x, width, out_width) * 4)
+        .collect();
+      for y in 0..out_height {
+        let row = &src[nearest(y, height, out_height) * stride..];
+        for &column in &columns {
+          out.extend_from_slice(&row[column..column + 4]);
+        }
+      }
+    }
+    Filter::Bilinear => {
+      let columns: Vec<(usize, usize, u32)> = (0..out_width)
+        .map(|x| {
+          let (left, right, weight) = bilinear(x, width, out_width);
+          (left * 4, right * 4, weight)
+        })
+        .collect();
+      for y in 0..out_height {
+        let (top, bottom, down) = bilinear(y, height, out_height);
+        let (top, bottom) = (&src[top * stride..], &src[bottom * stride..]);
+        for &(left, right, across) in &columns {
+          for channel in 0..4 {
+            let blend = |row: &[u8]| {
+              row[left + channel] as u32 * (WEIGHT_ONE - across)
+                + row[right + channel] as u32 * across
+            };
+            let value = blend(top) * (WEIGHT_ONE - down) + blend(bottom) * down;
+            out.push(((value + WEIGHT_ONE * WEIGHT_ONE / 2) / (WEIGHT_ONE * WEIGHT_ONE)) as u8);
+          }
+        }
+      }
+    }
+  }
+}
+
+/// Source index whose pixel covers the centre of output pixel `i`
+fn nearest(i: usize, size: usize, out_size: usize) -> usize {
+  ((2 * i + 1) * size / (2 * out_size)).min(size - 1)
+}
+
+/// The two source indices either side of output pixel `i`'s centre, and how far
+/// towards the second it lies in 1/WEIGHT_ONE steps
+fn bilinear(i: usize, size: usize, out_size: usize) -> (usize, usize, u32) {
+  let centre = ((i as f64 + 0.5) * size as f64 / out_size as f64 - 0.5).max(0.0);
+  let first = (centre as usize).min(size - 1);
+  let weight = ((centre - first as f64) * WEIGHT_ONE as f64).round() as u32;
+  (first, (first + 1).min(size - 1), weight.min(WEIGHT_ONE))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // 4x2 with a padded stride: two 2x2 blocks
+  #[rustfmt::skip]
+  const SRC: [u8; 36] = [
+    0, 0, 0, 255,  100, 0, 0, 255,  10, 20, 30, 40,  10, 20, 30, 40,  0, 0,
+    0, 0, 0, 255,  100, 0, 0, 255,  10, 20, 30, 40,  10, 20, 30, 41,  0, 0,
+  ];
+
+  #[test]
+  fn bilinear_halving_averages_blocks() {
+    let mut out = Vec::new();
+    resize_bgra_into(&SRC, [4, 2], 18, [2, 1], Filter::Bilinear, &mut out);
+    assert_eq!(out, [50, 0, 0, 255, 10, 20, 30, 40]);
+  }
+
+  #[test]
+  fn nearest_picks_source_pixels() {
+    let mut out = Vec::new();
+    resize_bgra_into(&SRC, [4, 2], 18, [2, 1], Filter::Nearest, &mut out);
+    assert_eq!(out, [100, 0, 0, 255, 10, 20, 30, 41]);
+
+    // The same size copies the frame, minus the padding
+    resize_bgra_into(&SRC, [4, 2], 18, [4, 2], Filter::Bilinear, &mut out);
+    assert_eq!(out[..16], SRC[..16]);
+    assert_eq!(out[16..], SRC[18..34]);
+  }
+
+  #[test]
+  fn sizes_keep_the_aspect_ratio() {
+    assert_eq!(Scale::Factor(0.5).size([1920, 1080]), [960, 540]);
+    assert_eq!(Scale::Width(960).size([1920, 1080]), [960, 540]);
+    // Odd results round down to even, and nothing goes below 2
+    assert_eq!(Scale::Width(1000).size([1366, 768]), [1000, 562]);
+    assert_eq!(Scale::Factor(0.001).size([640, 480]), [2, 2]);
+  }
+}
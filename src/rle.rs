@@ -0,0 +1,134 @@
+// RLE payload layout, used by the rle codec: the frame's 4-byte pixels as a
+// sequence of packets, each starting with a header byte:
+//
+//   packet := header:u8 pixel[4]                 (header >= 128: run)
+//           | header:u8 pixel[4 * (header + 1)]  (header < 128: literal)
+//
+// A run repeats its one pixel `header - 127` times; a literal copies the
+// `header + 1` pixels that follow. Both cover 1 to 128 pixels. The payload decodes
+// to exactly `raw_size` bytes, so receivers can allocate the frame up front.
+
+pub const PIXEL_SIZE: usize = 4;
+
+const RUN: u8 = 0x80;
+const MAX_PACKET: usize = 128;
+// A run of two already beats a literal of two (5 bytes against 8)
+const MIN_RUN: usize = 2;
+
+/// Run-length encode `pixels`, whose length is a multiple of 4, into `out`
+pub fn rle_encode(pixels: &[u8], out: &mut Vec<u8>) {
+  debug_assert_eq!(pixels.len() % PIXEL_SIZE, 0);
+  out.clear();
+  let count = pixels.len() / PIXEL_SIZE;
+  let pixel = |i: usize| &pixels[i * PIXEL_SIZE..(i + 1) * PIXEL_SIZE];
+  let flush = |out: &mut Vec<u8>, start: usize, end: usize| {
+    for start in (start..end).step_by(MAX_PACKET) {
+      let len = (end - start).min(MAX_PACKET);
+      out.push(len as u8 - 1);
+      out.extend_from_slice(&pixels[start * PIXEL_SIZE..(start + len) * PIXEL_SIZE]);
+    }
+  };
+
+  let (mut i, mut literal_start) = (0, 0);
+  while i < count {
+    let mut run = 1;
+    while run < MAX_PACKET && i + run < count && pixel(i + run) == pixel(i) {
+      run += 1;
+    }
+    if run >= MIN_RUN {
+      flush(out, literal_start, i);
+      out.push(RUN | (run - 1) as u8);
+      out.extend_from_slice(pixel(i));
+      i += run;
+      literal_start = i;
+    } else {
+      i += 1;
+    }
+  }
+  flush(out, literal_start, count);
+}
+
+/// Decode an RLE payload into `out`, which must come to exactly `raw_size` bytes
+// The streamer only encodes; decoding is for the receiver and tests
+#[allow(dead_code)]
+pub fn rle_decode(payload: &[u8], raw_size: usize, out: &mut Vec<u8>) -> Result<(), String> {
+  out.clear();
+  out.reserve(raw_size);
+  let mut rest = payload;
+  while let Some((&header, body)) = rest.split_first() {
+    let run = header & RUN != 0;
+    let pixels = (header & !RUN) as usize + 1;
+    let len = if run { PIXEL_SIZE } else { pixels * PIXEL_SIZE };
+    if body.len() < len {
+      return Err("RLE payload ends inside a packet".to_string());
+    }
+    if out.len() + pixels * PIXEL_SIZE > raw_size {
+      return Err(format!("RLE payload decodes past {} bytes", raw_size));
+    }
+    let (data, next) = body.split_at(len);
+    if run {
+      for _ in 0..pixels {
+        out.extend_from_slice(data);
+      }
+    } else {
+      out.extend_from_slice(data);
+    }
+    rest = next;
+  }
+  if out.len() != raw_size {
+    return Err(format!(
+      "RLE payload decoded to {} bytes, expected {}",
+      out.len(),
+      raw_size
+    ));
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn round_trip(pixels: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    rle_encode(pixels, &mut encoded);
+    let mut decoded = Vec::new();
+    rle_decode(&encoded, pixels.len(), &mut decoded).unwrap();
+    assert_eq!(decoded, pixels);
+    encoded
+  }
+
+  #[test]
+  fn round_trips_runs_and_literals() {
+    assert!(round_trip(&[]).is_empty());
+    assert_eq!(round_trip(&[1, 2, 3, 4]), [0, 1, 2, 3, 4]);
+
+    // A flat toolbar, a few distinct pixels, then a long flat background
+    let mut pixels = [0x20u8, 0x20, 0x20, 0xff].repeat(10);
+    for i in 0..5u8 {
+      pixels.extend_from_slice(&[i, i, i, 0xff]);
+    }
+    pixels.extend([0xf0u8, 0xf0, 0xf0, 0xff].repeat(300));
+    let encoded = round_trip(&pixels);
+    // 10, 5 literal, then 128 + 128 + 44
+    assert_eq!(encoded.len(), 5 + (1 + 5 * 4) + 3 * 5);
+  }
+
+  #[test]
+  fn splits_long_literals() {
+    let pixels: Vec<u8> = (0..300u32).flat_map(u32::to_le_bytes).collect();
+    let encoded = round_trip(&pixels);
+    assert_eq!(encoded.len(), pixels.len() + 3);
+  }
+
+  #[test]
+  fn rejects_bad_payloads() {
+    let mut out = Vec::new();
+    // A literal of two pixels with only one present
+    assert!(rle_decode(&[1, 0, 0, 0, 0], 8, &mut out).is_err());
+    // A run longer than the frame
+    assert!(rle_decode(&[RUN | 3, 0, 0, 0, 0], 8, &mut out).is_err());
+    // Too short for the frame
+    assert!(rle_decode(&[RUN | 1, 0, 0, 0, 0], 12, &mut out).is_err());
+  }
+}
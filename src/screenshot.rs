@@ -0,0 +1,51 @@
+use std::path::Path;
+
+use image::{ImageFormat, RgbaImage};
+use scap::capturer::{Capturer, Options};
+use scap::frame::{Frame, FrameType};
+
+use crate::convert;
+
+/// Capture a single frame from `options` and write it to `path` as a PNG
+pub fn save(mut options: Options, path: &Path) -> Result<(u32, u32), String> {
+  // One frame isn't worth a --capture-format; BGRA is what this reads
+  options.output_type = FrameType::BGRAFrame;
+  let mut capturer =
+    Capturer::build(options).map_err(|e| format!("Failed to create capturer: {}", e))?;
+  let [width, height] = capturer.get_output_frame_size();
+  capturer.start_capture();
+
+  // Idle frames carry no pixels, so wait for the first one that does
+  let frame = loop {
+    match capturer.get_next_frame() {
+      Ok(Frame::BGRA(bgra)) if !bgra.data.is_empty() && bgra.height > 0 => break Ok(bgra),
+      Ok(Frame::BGRA(_)) => continue,
+      Ok(_) => break Err("Unexpected frame format".to_string()),
+      Err(e) => break Err(format!("Error getting frame: {:?}", e)),
+    }
+  };
+  capturer.stop_capture();
+  let bgra = frame?;
+
+  // Rows may be padded, as in the streaming path
+  let stride = bgra.data.len() / bgra.height as usize;
+  if (bgra.width as u32, bgra.height as u32) != (width, height) || stride < width as usize * 4 {
+    return Err(format!(
+      "Captured a {}x{} frame ({} bytes), expected {}x{}",
+      bgra.width,
+      bgra.height,
+      bgra.data.len(),
+      width,
+      height
+    ));
+  }
+  let mut rgba = Vec::new();
+  convert::bgra_to_rgba_strided_into(&bgra.data, width as usize, stride, &mut rgba);
+
+  let image = RgbaImage::from_raw(width, height, rgba)
+    .ok_or("Frame is smaller than its reported size".to_string())?;
+  image
+    .save_with_format(path, ImageFormat::Png)
+    .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+  Ok((width, height))
+}
@@ -0,0 +1,1790 @@
+// The capture-and-stream pipeline behind the binary, for embedding in other apps.
+// A Streamer sets up from the same Args the command line produces, then streams on
+// threads of its own until stopped, the receiver is given up on, or --duration
+// runs out; encoded frames can also be handed to a callback as they are sent.
+
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, sleep, JoinHandle};
+use std::time::{Duration, Instant};
+
+use log::{debug, error, info, warn};
+use scap::capturer::{Area, Options, Point, Size};
+use scap::Target;
+
+use crate::adaptive::QualityController;
+use crate::affinity;
+#[cfg(feature = "audio")]
+use crate::audio;
+use crate::autocodec::CodecSwitcher;
+use crate::bench;
+use crate::broadcast::Broadcaster;
+use crate::capture::{CaptureThread, Processing, Source};
+use crate::cli::Args;
+use crate::controls::Command;
+use crate::convert::Yuv;
+use crate::crypto;
+use crate::cursor::Cursor;
+use crate::delta::DeltaEncoder;
+use crate::encode::{FrameEncoder, StreamEncoder};
+use crate::events::{Event, EventKind, Events, EVENT_QUEUE_DEPTH};
+use crate::follow::{self, Follow};
+use crate::idle::IdleRate;
+use crate::logging;
+use crate::metrics::{self, Metrics, StatsSnapshot, Totals};
+use crate::mirror::{self, Feed, Mirrored};
+use crate::net::{self, Backoff, Connection, LinkOptions, Listener, Transport};
+use crate::overlay::{CursorLayer, Overlay};
+use crate::profile::{Laps, Profile, Stage};
+use crate::protocol::{self, Capabilities, Codec, FrameInfo, Handshake, PixelFormat};
+use crate::ratelimit::{OverLimit, TokenBucket};
+#[cfg(feature = "record")]
+use crate::record;
+use crate::replay::Recording;
+use crate::resize::Resize;
+use crate::screenshot;
+use crate::targets;
+use crate::tls::TlsConfig;
+use crate::variants::Ladder;
+#[cfg(feature = "h264")]
+use crate::video;
+use crate::watermark::Watermark;
+
+/// What setting up or streaming failed with
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
+
+/// Called with every encoded frame; see `Streamer::on_frame`
+pub type FrameCallback = Arc<dyn Fn(&EncodedFrame) + Send + Sync>;
+
+/// A frame as it goes out, encoded but not yet encrypted or checksummed
+pub struct EncodedFrame<'a> {
+  /// The display's index with --all-displays, otherwise 0
+  pub stream: usize,
+  pub info: &'a FrameInfo,
+  pub data: &'a [u8],
+}
+
+/// Screen capture streamed to a receiver, or served to them with --listen, exactly
+/// as the binary does for the same Args
+pub struct Streamer {
+  config: Args,
+  on_frame: Option<FrameCallback>,
+  events: Option<SyncSender<Event>>,
+  handle: Handle,
+  running: Option<JoinHandle<Result<(), Error>>>,
+  /// Each running stream's counts, for `stats`
+  stats: Vec<Arc<Metrics>>,
+  /// Sent frames for the --preview window, until `preview` takes them
+  #[cfg_attr(not(feature = "preview"), allow(dead_code))]
+  preview: Option<Receiver<Mirrored>>,
+}
+
+/// Pauses, resumes or stops a Streamer from any thread, e.g. a Ctrl-C handler
+#[derive(Clone, Default)]
+pub struct Handle {
+  interrupted: Arc<AtomicBool>,
+  /// One per running stream; --all-displays runs several
+  commands: Arc<Mutex<Vec<Sender<Command>>>>,
+}
+
+impl Handle {
+  /// Pass `command` to every stream. Quitting also stops one still being set up.
+  pub fn send(&self, command: Command) {
+    if command == Command::Quit {
+      self.interrupted.store(true, Ordering::SeqCst);
+    }
+    // Streams that have already finished just miss out
+    for tx in self.commands.lock().unwrap().iter() {
+      let _ = tx.send(command);
+    }
+  }
+
+  /// Ask every stream to stop, which they do within POLL_INTERVAL. Returns whether
+  /// that had already been asked.
+  pub fn interrupt(&self) -> bool {
+    self.interrupted.swap(true, Ordering::SeqCst)
+  }
+
+  /// Commands for one more stream
+  fn subscribe(&self) -> Receiver<Command> {
+    let (tx, rx) = mpsc::channel();
+    self.commands.lock().unwrap().push(tx);
+    rx
+  }
+}
+
+/// One stream `start` set up: its settings, what it captures and where it goes
+struct Planned {
+  args: Args,
+  source: Source,
+  server_addrs: Vec<SocketAddr>,
+  /// Which display this is with --all-displays
+  index: Option<usize>,
+}
+
+impl Streamer {
+  /// A streamer for `config`, best made with `Args::parse_from` so the settings
+  /// are checked against each other the way the command line's are
+  pub fn new(config: Args) -> Self {
+    Streamer {
+      config,
+      on_frame: None,
+      events: None,
+      handle: Handle::default(),
+      running: None,
+      stats: Vec::new(),
+      preview: None,
+    }
+  }
+
+  /// Hand every encoded frame to `callback` on the sending thread, before it goes
+  /// out. Frames keep coming while no receiver is connected, as with --record;
+  /// --constant-fps repeats aren't passed on. Set it before `start`.
+  pub fn on_frame(&mut self, callback: impl Fn(&EncodedFrame) + Send + Sync + 'static) {
+    self.on_frame = Some(Arc::new(callback));
+  }
+
+  /// Lifecycle events from every stream, for watching it without parsing logs.
+  /// Up to EVENT_QUEUE_DEPTH wait to be received; past that, newer ones are
+  /// dropped rather than holding up streaming. Call it before `start`; calling it
+  /// again leaves the earlier receiver with nothing more to receive.
+  pub fn events(&mut self) -> Receiver<Event> {
+    let (tx, rx) = mpsc::sync_channel(EVENT_QUEUE_DEPTH);
+    self.events = Some(tx);
+    rx
+  }
+
+  /// Frames sent and dropped, bytes sent, the frame rate and the receivers
+  /// connected, as of one moment, for dashboards that would otherwise parse the
+  /// stats line. It never waits on the sending thread, so it can be polled as
+  /// often as wanted. With --all-displays the streams' counts are added up; before
+  /// `start` they're all 0.
+  pub fn stats(&self) -> StatsSnapshot {
+    StatsSnapshot::sum(self.stats.iter().map(|metrics| metrics.snapshot()))
+  }
+
+  /// For pausing or stopping the streamer from elsewhere
+  pub fn handle(&self) -> Handle {
+    self.handle.clone()
+  }
+
+  /// Check the settings, the capture permission and the target, then start
+  /// streaming in the background. --list-targets and --screenshot, which don't
+  /// stream, are done by the time this returns; so is a platform that can't
+  /// capture, which is only logged.
+  pub fn start(&mut self) -> Result<(), Error> {
+    let args = &self.config;
+    // Resolve the receiver's addresses up front so a bad --host fails before capture starts
+    let server_addrs = args.server_addrs()?;
+    // Load certificates up front too, so a bad --cert or --ca fails before capture starts
+    let tls = args.tls_config()?;
+
+    let plans = self.plan(server_addrs)?;
+    if plans.is_empty() {
+      return Ok(());
+    }
+    let handle = self.handle.clone();
+    let on_frame = self.on_frame.clone();
+    let events = self.events.clone();
+    self.stats = plans.iter().map(|_| Arc::default()).collect();
+    let stats = self.stats.clone();
+    let feed = self.config.preview.then(|| {
+      let (feed, frames) = mirror::channel();
+      self.preview = Some(frames);
+      feed
+    });
+    self.running = Some(thread::spawn(move || {
+      run(plans, tls, &handle, on_frame, events, stats, feed)
+    }));
+    Ok(())
+  }
+
+  /// With --preview, show the frames being sent in a window until streaming ends
+  /// or the window is closed, which leaves streaming running. Call it after
+  /// `start` on the main thread, which macOS wants windows on; without --preview
+  /// it returns at once.
+  pub fn preview(&mut self) -> Result<(), Error> {
+    #[cfg(feature = "preview")]
+    if let Some(frames) = self.preview.take() {
+      mirror::show(frames)?;
+    }
+    Ok(())
+  }
+
+  /// Wait for streaming to end by itself, e.g. by --duration or a `Handle`
+  pub fn wait(&mut self) -> Result<(), Error> {
+    match self.running.take().map(JoinHandle::join) {
+      None => Ok(()),
+      Some(Ok(result)) => result,
+      Some(Err(_)) => Err("stream thread panicked".into()),
+    }
+  }
+
+  /// Stop streaming and wait for receivers to be told and sockets closed
+  pub fn stop(&mut self) -> Result<(), Error> {
+    self.handle.interrupt();
+    self.wait()
+  }
+
+  /// The streams to run, none if there's nothing to stream
+  fn plan(&self, server_addrs: Vec<SocketAddr>) -> Result<Vec<Planned>, Error> {
+    let args = &self.config;
+    let single = |source| {
+      vec![Planned {
+        args: args.clone(),
+        source,
+        server_addrs: server_addrs.clone(),
+        index: None,
+      }]
+    };
+
+    // The test pattern needs no capturer, so none of the checks below apply
+    if let Some([width, height]) = args.test_pattern {
+      info!("🧪 Streaming a {}x{} test pattern", width, height);
+      return Ok(single(Source::TestPattern([width, height])));
+    }
+    if let Some(path) = &args.loop_file {
+      let recording = Recording::open(path, args.loop_size)?;
+      let [width, height] = recording.size();
+      match recording.fps() {
+        Some(fps) => info!(
+          "🎞️ Looping {}, {}x{} recorded at {:.3} fps",
+          path.display(),
+          width,
+          height,
+          fps
+        ),
+        None => info!("🎞️ Looping {}, {}x{}", path.display(), width, height),
+      }
+      return Ok(single(Source::Recording(recording)));
+    }
+
+    // Check if the platform is supported
+    if !scap::is_supported() {
+      error!("❌ Platform not supported");
+      return Ok(Vec::new());
+    }
+
+    // Check if we have permission to capture screen
+    if !scap::has_permission() {
+      warn!("⚠️ Permission not granted. Requesting permission...");
+      if !scap::request_permission() && !wait_for_permission(&self.handle.interrupted) {
+        error!(
+          "❌ Permission denied. Allow screen capture for this program and run it again, \
+           or stream --test-pattern 1280x720 without it"
+        );
+        return Ok(Vec::new());
+      }
+    }
+
+    info!("✅ Platform supported and permission granted");
+
+    if args.list_targets {
+      targets::list();
+      return Ok(Vec::new());
+    }
+
+    let excluded = targets::excluded(&args.exclude);
+    for target in &excluded {
+      info!("🙈 Excluding {}", targets::name(target));
+    }
+
+    // --all-displays runs one independent stream per display, on consecutive ports
+    // from --port, so any receiver can take any of them
+    if args.all_displays {
+      let displays = targets::all_displays();
+      if displays.is_empty() {
+        return Err("No display found".into());
+      }
+      if args.port.checked_add(displays.len() as u16 - 1).is_none() {
+        return Err(
+          format!(
+            "{} displays need ports {} and up, past 65535",
+            displays.len(),
+            args.port
+          )
+          .into(),
+        );
+      }
+      let mut plans = Vec::new();
+      for (index, display) in displays.into_iter().enumerate() {
+        if let Some(crop) = &args.crop {
+          targets::check_crop(&display, crop)?;
+        }
+        let mut args = args.clone();
+        args.port += index as u16;
+        info!(
+          "🖥️ Capturing {} on port {}",
+          targets::name(&display),
+          args.port
+        );
+        // One copy of the system audio is enough; it goes with the first display
+        args.audio &= index == 0;
+        args.pin_cores = args.pin_cores.map(|first| first + 2 * index);
+        let mut server_addrs = server_addrs.clone();
+        for addr in &mut server_addrs {
+          addr.set_port(args.port);
+        }
+        plans.push(Planned {
+          source: Source::Screen(capture_options(&args, display, &excluded)),
+          args,
+          server_addrs,
+          index: Some(index),
+        });
+      }
+      return Ok(plans);
+    }
+
+    // Pick the display or window to capture
+    let target = targets::resolve(args.target.as_ref())?;
+    info!("🖥️ Capturing {}", targets::name(&target));
+    if let Some(crop) = &args.crop {
+      targets::check_crop(&target, crop)?;
+    }
+    let options = capture_options(args, target, &excluded);
+
+    // A screenshot needs only the capturer, not the capture thread or network
+    if let Some(path) = &args.screenshot {
+      let (width, height) = screenshot::save(options, path)?;
+      info!(
+        "📸 Saved {}x{} screenshot to {}",
+        width,
+        height,
+        path.display()
+      );
+      return Ok(Vec::new());
+    }
+
+    Ok(single(Source::Screen(options)))
+  }
+}
+
+/// Run every planned stream to the end, each on its own thread if there are several
+fn run(
+  plans: Vec<Planned>,
+  tls: Option<TlsConfig>,
+  handle: &Handle,
+  on_frame: Option<FrameCallback>,
+  events: Option<SyncSender<Event>>,
+  stats: Vec<Arc<Metrics>>,
+  mut preview: Option<Feed>,
+) -> Result<(), Error> {
+  let streams: Vec<_> = plans
+    .into_iter()
+    .zip(stats)
+    .map(|(plan, metrics)| {
+      let hooks = Hooks {
+        interrupted: &handle.interrupted,
+        commands: handle.subscribe(),
+        index: plan.index,
+        on_frame: on_frame.clone(),
+        events: Events::new(events.clone(), plan.index.unwrap_or(0)),
+        metrics,
+        // --preview shows a single stream
+        preview: preview.take(),
+      };
+      (plan, hooks)
+    })
+    .collect();
+  if streams.len() == 1 {
+    let (plan, hooks) = streams.into_iter().next().unwrap();
+    return stream(&plan.args, plan.source, plan.server_addrs, tls, hooks);
+  }
+
+  let failed = thread::scope(|scope| {
+    let threads: Vec<_> = streams
+      .into_iter()
+      .map(|(plan, hooks)| {
+        let tls = tls.clone();
+        scope.spawn(move || stream(&plan.args, plan.source, plan.server_addrs, tls, hooks))
+      })
+      .collect();
+    threads
+      .into_iter()
+      .enumerate()
+      .filter_map(|(index, stream)| match stream.join() {
+        Ok(Ok(())) => None,
+        Ok(Err(e)) => Some(format!("display {}: {}", index, e)),
+        Err(_) => Some(format!("display {}: stream thread panicked", index)),
+      })
+      .collect::<Vec<_>>()
+  });
+  if failed.is_empty() {
+    return Ok(());
+  }
+  Err(failed.join("; ").into())
+}
+
+/// How one stream is stopped, steered and watched from outside
+struct Hooks<'a> {
+  interrupted: &'a AtomicBool,
+  commands: Receiver<Command>,
+  /// Which display this is with --all-displays, for the logs and `on_frame`
+  index: Option<usize>,
+  on_frame: Option<FrameCallback>,
+  events: Events,
+  /// Where the stream keeps its counts
+  metrics: Arc<Metrics>,
+  preview: Option<Feed>,
+}
+
+// How often the send loop wakes up to check for Enter/Ctrl-C while no frame is ready
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// How often --profile logs its breakdown, long enough for a p99 to mean something
+const PROFILE_INTERVAL: Duration = Duration::from_secs(5);
+
+// Some systems grant capture permission outside the prompt (e.g. macOS System
+// Settings), so a refusal is re-checked this often for this long before giving up
+const PERMISSION_POLL: Duration = Duration::from_secs(2);
+const PERMISSION_WAIT: Duration = Duration::from_secs(30);
+
+/// Poll for capture permission granted after the prompt was refused, until
+/// PERMISSION_WAIT passes or the user interrupts
+fn wait_for_permission(interrupted: &AtomicBool) -> bool {
+  warn!(
+    "⏳ Waiting up to {}s for screen capture permission. Grant it in the system's \
+     privacy settings (Screen Recording on macOS); some systems need a restart of \
+     this program afterwards",
+    PERMISSION_WAIT.as_secs()
+  );
+  let deadline = Instant::now() + PERMISSION_WAIT;
+  while Instant::now() < deadline && !interrupted.load(Ordering::SeqCst) {
+    sleep(PERMISSION_POLL);
+    if scap::has_permission() {
+      info!("🔓 Screen capture permission granted");
+      return true;
+    }
+  }
+  false
+}
+
+/// Screen capture settings for `target`
+fn capture_options(args: &Args, target: Target, excluded: &[Target]) -> Options {
+  Options {
+    // The capturer is asked for the target rate and the capture thread enforces it,
+    // since not every backend honours this exactly
+    fps: args.fps,
+    target: Some(target),
+    show_cursor: args.show_cursor,
+    show_highlight: args.show_highlight,
+    excluded_targets: (!excluded.is_empty()).then(|| excluded.to_vec()),
+    output_type: args.capture_format.frame_type(),
+    output_resolution: args.resolution,
+    // --follow-cursor crops on the capture thread instead, where the region can move
+    crop_area: args.crop.clone().filter(|_| !args.follow_cursor),
+  }
+}
+
+/// Capture `source` and stream it to, or serve it at, `server_addrs` until the
+/// user quits or the connection is given up on
+fn stream(
+  args: &Args,
+  source: Source,
+  server_addrs: Vec<SocketAddr>,
+  tls: Option<TlsConfig>,
+  hooks: Hooks,
+) -> Result<(), Error> {
+  let Hooks {
+    interrupted,
+    commands,
+    index,
+    on_frame,
+    events,
+    metrics,
+    mut preview,
+  } = hooks;
+  // Prefixes the stats line so several streams can share a terminal
+  let label = index.map_or(String::new(), |index| format!("[{}] ", index));
+
+  // This thread does the sending; the capture thread pins itself as it starts
+  if let Some(first) = args.pin_cores {
+    affinity::pin_current(first + 1, "send");
+  }
+
+  // Create capturer on its own thread and get frame size
+  // --fps 0 leaves frames unpaced, so there is no frame time to hold to
+  let frame_time = (args.fps > 0).then(|| Duration::from_secs_f64(1.0 / args.fps as f64));
+  let follow = match (&args.crop, &source) {
+    (
+      Some(crop),
+      Source::Screen(Options {
+        target: Some(target),
+        ..
+      }),
+    ) if args.follow_cursor => Some(Follow::new(crop, targets::full_size(target))),
+    _ => None,
+  };
+  // The captured area in display pixels, for placing the cursor in frames
+  let area = match &source {
+    Source::Screen(Options {
+      target: Some(target),
+      crop_area,
+      ..
+    }) if args.cursor_overlay || args.cursor_layer => {
+      // Frames cover the --crop, unless --follow-cursor left the whole display
+      let [width, height] = targets::full_size(target);
+      let display = Area {
+        origin: Point { x: 0.0, y: 0.0 },
+        size: Size {
+          width: width as f64,
+          height: height as f64,
+        },
+      };
+      Some(crop_area.clone().unwrap_or(display))
+    }
+    _ => None,
+  };
+  let overlay = area
+    .as_ref()
+    .filter(|_| args.cursor_overlay)
+    .map(|area| Overlay::new(area, args.overlay_radius, args.overlay_colour));
+  // A cursor layer is no use without the cursor's position, so the capturer keeps
+  // drawing the cursor unless that can be read
+  let cursor_layer = match &area {
+    Some(area) if args.cursor_layer => match follow::cursor() {
+      Some(_) => Some(CursorLayer::new(area)),
+      None => {
+        warn!("⚠️ The cursor's position can't be read, drawing it into frames instead");
+        None
+      }
+    },
+    _ => None,
+  };
+  let mut source = source;
+  if let (Some(_), Source::Screen(options)) = (&cursor_layer, &mut source) {
+    info!("🖱️ Sending the cursor beside the frames");
+    options.show_cursor = false;
+  }
+  let cursor_packets = cursor_layer.is_some();
+  if let Source::Screen(options) = &source {
+    debug!("🔧 Capturer options: {:?}", options);
+  }
+  // --variants are encoded on the capture thread, from the same processed frames
+  let ladder = (!args.variants.is_empty())
+    .then(|| Ladder::new(&args.variants, args.pixel_format, args.quality, args.filter));
+  if ladder.is_some() {
+    let names: Vec<String> = args.variants.iter().map(ToString::to_string).collect();
+    info!("🪜 Offering receivers variants: {}", names.join(", "));
+  }
+  let capture = CaptureThread::spawn(
+    source,
+    frame_time,
+    args.pacing,
+    args.buffer_depth,
+    Processing {
+      follow,
+      resize: args.scale.map(|scale| Resize {
+        scale,
+        filter: args.filter,
+      }),
+      overlay,
+      watermark: (args.watermark.is_some() || args.timestamp).then(|| {
+        Watermark::new(
+          args.watermark.clone(),
+          args.timestamp,
+          args.watermark_corner,
+        )
+      }),
+      cursor_layer,
+      idle: frame_time
+        .filter(|_| args.half_rate_on_idle)
+        .map(|frame_time| {
+          IdleRate::new(
+            args.idle_threshold / 100.0,
+            frame_time,
+            Duration::from_secs_f64(1.0 / args.idle_fps),
+          )
+        }),
+      variants: ladder.clone(),
+    },
+    args.no_drop,
+  )?;
+  // The handshake reports the size after cropping and scaling, which is what
+  // the capturer actually delivers until the target changes size
+  let (mut width, mut height) = (capture.width, capture.height);
+
+  // Receivers get RGBA unless they accept BGRA, which skips the conversion. JPEG
+  // and H.264 decode to RGBA unless RGB or grayscale was asked for (JPEG only).
+  // Negotiating needs the reply path TCP provides; it also checks the receiver
+  // can decode the codec at all.
+  let rgba_only = matches!(args.codec, Codec::Jpeg | Codec::H264);
+  let negotiate = args.transport.is_stream();
+  let offer = match args.pixel_format {
+    Some(format @ (PixelFormat::Gray | PixelFormat::Rgb)) => format,
+    _ if rgba_only => PixelFormat::Rgba,
+    Some(format) => format,
+    None if negotiate => PixelFormat::Bgra,
+    None => PixelFormat::Rgba,
+  };
+  let offer_formats = match (args.pixel_format, offer) {
+    (None, PixelFormat::Bgra) => vec![PixelFormat::Bgra, PixelFormat::Rgba],
+    _ => vec![offer],
+  };
+  // Every codec this build can encode, though the stream only ever uses --codec
+  let codecs: Vec<Codec> = protocol::CODECS
+    .into_iter()
+    .filter(|&codec| codec != Codec::H264 || cfg!(feature = "h264"))
+    .collect();
+  let capabilities = Capabilities::new(&codecs, &offer_formats);
+
+  // System audio goes out as its own packets between frames. It's opened before
+  // connecting so the handshake can say whether to expect it.
+  #[cfg(feature = "audio")]
+  let audio = match args.audio.then(audio::AudioCapture::start) {
+    Some(Ok(audio)) => {
+      info!(
+        "🔊 Audio: {} ({} Hz, {} channels)",
+        audio.device, audio.sample_rate, audio.channels
+      );
+      Some(audio)
+    }
+    Some(Err(e)) => {
+      warn!("⚠️ Audio capture unavailable, streaming video only: {}", e);
+      None
+    }
+    None => None,
+  };
+  #[cfg(feature = "audio")]
+  let has_audio = audio.is_some();
+  #[cfg(not(feature = "audio"))]
+  let has_audio = false;
+
+  // --psk seals every payload, so only a receiver with the same key can read it
+  let cipher = args.psk.as_deref().map(crypto::Cipher::new);
+  if cipher.is_some() {
+    info!("🔑 Encrypting payloads with the pre-shared key");
+  }
+
+  // Describe the stream to the receiver once per connection
+  let mut handshake = Handshake {
+    width,
+    height,
+    pixel_format: offer,
+    codec: args.codec,
+    fps: args.fps,
+    // Padded rows are packed on the capture thread, so the wire is always tight
+    stride: width * offer.bytes_per_pixel(),
+    negotiate,
+    audio: has_audio,
+    encrypted: cipher.is_some(),
+    checksum: args.checksum,
+    cursor: cursor_packets,
+    fec: args.fec.is_some(),
+    variants: ladder.is_some(),
+  };
+
+  // --bench stops short of the network and measures capture and encoding instead
+  if let Some(duration) = args.bench {
+    let encoder = FrameEncoder {
+      codec: args.codec,
+      quality: args.quality,
+      level: args.level,
+      pixel_format: offer,
+      yuv: Yuv::new(args.colorspace, args.range),
+    };
+    let stream_encoder = stream_encoder(args, args.codec, width, height, offer)?;
+    bench::run(
+      capture,
+      encoder,
+      stream_encoder,
+      duration,
+      interrupted,
+      args.pin_cores,
+    );
+    return Ok(());
+  }
+
+  let link = LinkOptions {
+    transport: args.transport,
+    nodelay: args.nodelay,
+    connect_timeout: args.connect_timeout,
+    keepalive: args.keepalive,
+    chunk_size: args.chunk_size,
+    fec: args.fec,
+    tls,
+    path: args.path.clone(),
+  };
+  if link.tls.is_some() {
+    info!("🔒 Encrypting the stream with TLS");
+  }
+  // What the logs call the receiver: its socket file, its address, or its host
+  // name when that has several
+  let receiver = match (&args.path, &server_addrs[..]) {
+    (Some(path), _) => path.display().to_string(),
+    (None, [addr]) => addr.to_string(),
+    _ => format!("{} ({} addresses)", args.host, server_addrs.len()),
+  };
+
+  // In listen mode wait for the first receiver to dial in, then keep accepting
+  // more in the background; otherwise connect out, retrying with backoff until
+  // the receiver is up
+  let mut backoff = Backoff::new(args.max_retries);
+  let mut broadcaster = None;
+  let mut socket = None;
+  if args.listen {
+    let listener = Listener::bind(server_addrs[0], &link)?;
+    match args.transport {
+      Transport::Ws => info!("👂 Listening on ws://{}", listener.local_addr()?),
+      Transport::Mjpeg => info!("👂 Listening on http://{}/", listener.local_addr()?),
+      _ => info!("👂 Listening on {}", listener.local_addr()?),
+    }
+    let mut first = listener.accept(&handshake)?;
+    let first_variant = match &ladder {
+      Some(ladder) => {
+        let (format, variant) = first
+          .0
+          .negotiate_variant(&handshake, capabilities, ladder)?;
+        handshake.pixel_format = format;
+        variant
+      }
+      None => {
+        handshake.pixel_format = first.0.negotiate(&handshake, capabilities)?;
+        0
+      }
+    };
+    match first_variant {
+      0 => info!("✅ Receiver connected from {}", first.1),
+      variant => info!(
+        "✅ Receiver connected from {} on variant {}",
+        first.1, variant
+      ),
+    }
+    events.emit(EventKind::Connected {
+      peer: first.1.to_string(),
+    });
+    // The first receiver settles the format; later ones are just told what it is
+    handshake.negotiate = false;
+    broadcaster = Some(Broadcaster::start(
+      listener,
+      first,
+      handshake,
+      events.clone(),
+      ladder.map(|ladder| (ladder, capabilities, first_variant)),
+    ));
+  } else {
+    info!("🔌 Connecting to {} over {:?}", receiver, args.transport);
+    socket = loop {
+      let attempt = Connection::open(&server_addrs, &link, &handshake)
+        .and_then(|mut socket| Ok((socket.negotiate(&handshake, capabilities)?, socket)));
+      match attempt {
+        Ok((format, socket)) => {
+          handshake.pixel_format = format;
+          break Some(socket);
+        }
+        // The receiver answered, so connecting again would get the same answer
+        Err(e) if e.kind() == io::ErrorKind::Unsupported => {
+          error!("❌ No format in common with the receiver: {}", e);
+          events.error(format!("No format in common with the receiver: {}", e));
+          return Err(e.into());
+        }
+        Err(e) => match backoff.next_delay() {
+          Some(delay) => {
+            warn!(
+              "⚠️ Connection failed: {}. Retrying in {:.2}s...",
+              e,
+              delay.as_secs_f64()
+            );
+            sleep(delay);
+          }
+          None => {
+            error!("❌ Giving up after {} retries", backoff.attempts());
+            events.error(format!("Giving up after {} retries", backoff.attempts()));
+            return Err(e.into());
+          }
+        },
+      }
+    };
+    backoff.reset();
+    match socket.as_ref().and_then(Connection::peer_addr) {
+      // The host had several addresses, so say which one answered
+      Some(addr) if server_addrs.len() > 1 => info!("✅ Connected to server at {}", addr),
+      _ => info!("✅ Connected to server"),
+    }
+    events.emit(EventKind::Connected {
+      peer: receiver.clone(),
+    });
+    // Reconnects keep the format the stream started with
+    handshake.negotiate = false;
+  }
+  debug!(
+    "🤝 Handshake: protocol v{}, {:?}",
+    protocol::PROTOCOL_VERSION,
+    handshake
+  );
+
+  let encoder = FrameEncoder {
+    codec: args.codec,
+    quality: args.quality,
+    level: args.level,
+    pixel_format: handshake.pixel_format,
+    yuv: Yuv::new(args.colorspace, args.range),
+  };
+  let mut reconnect_at = Instant::now();
+
+  // Calculate buffer sizes based on resolution
+  let mut frame_size = (width * height * handshake.pixel_format.bytes_per_pixel()) as u64;
+  let chunk_payload = match link.transport {
+    Transport::Udp => {
+      link.chunk_size - protocol::datagram_header_size(args.checksum, args.fec.is_some())
+    }
+    _ => link.chunk_size,
+  };
+  let num_chunks = (frame_size as usize).div_ceil(chunk_payload);
+
+  match args.fps {
+    0 => info!("⚙️ Capture settings: {}x{}, unpaced", width, height),
+    fps => info!(
+      "⚙️ Capture settings: {}x{} @ {}fps (max)",
+      width, height, fps
+    ),
+  }
+  info!(
+    "📦 Frame size: {:.1}MB ({} chunks)",
+    frame_size as f64 / (1024.0 * 1024.0),
+    num_chunks
+  );
+  match link.transport {
+    Transport::Udp => debug!(
+      "📦 Chunks: datagrams of up to {} bytes, {} of them payload",
+      link.chunk_size, chunk_payload
+    ),
+    _ => debug!("📦 Chunks: up to {} bytes each", chunk_payload),
+  }
+  if let Some(group) = link.fec {
+    info!("🛟 FEC: a parity datagram for every {} of a frame's", group);
+  }
+  match encoder.codec {
+    Codec::Raw if args.auto_codec => info!("🗜️ Codec: auto, starting with raw"),
+    Codec::Raw => info!("🗜️ Codec: raw"),
+    Codec::Jpeg => match args.min_quality {
+      Some(min) => info!(
+        "🗜️ Codec: jpeg (adaptive quality {}-{})",
+        min, encoder.quality
+      ),
+      None => info!("🗜️ Codec: jpeg (quality {})", encoder.quality),
+    },
+    Codec::Zstd => info!("🗜️ Codec: zstd (level {})", encoder.level),
+    Codec::Rle => info!("🗜️ Codec: rle"),
+    Codec::Delta => info!(
+      "🗜️ Codec: delta (keyframe every {} frames)",
+      args.keyframe_interval
+    ),
+    Codec::H264 => info!(
+      "🗜️ Codec: h264 ({} kbps, keyframe every {} frames)",
+      args.bitrate, args.keyframe_interval
+    ),
+  }
+  info!(
+    "🎨 Pixel format: {:?}{}",
+    handshake.pixel_format,
+    if negotiate { " (negotiated)" } else { "" }
+  );
+  if let Some(mbps) = args.max_mbps {
+    let mode = match args.over_limit {
+      OverLimit::Delay => "delaying",
+      OverLimit::Drop => "dropping",
+    };
+    info!("🚦 Bandwidth cap: {} Mbps, {} frames over it", mbps, mode);
+  }
+  if args.no_drop {
+    info!("🐢 No-drop mode: capture slows to what the receiver takes");
+  }
+  if args.constant_fps {
+    info!("🔂 Constant FPS: repeating the last frame whenever a new one is late");
+  }
+  if args.half_rate_on_idle {
+    info!(
+      "💤 Slowing down to as few as {} fps while at most {}% of the screen changes",
+      args.idle_fps, args.idle_threshold
+    );
+  }
+
+  let mut stream_encoder = stream_encoder(args, args.codec, width, height, handshake.pixel_format)?;
+  let mut receivers = 0;
+  // A restarted frame dropped here leaves saying so to the next one sent
+  let mut restart_pending = false;
+
+  // --record keeps a copy of every encoded frame, whether or not it reaches a receiver
+  #[cfg(feature = "record")]
+  let mut recorder = match &args.record {
+    Some(path) => {
+      let recorder = record::Recorder::create(path, width, height, args.fps)?;
+      info!("⏺️ Recording to {}", path.display());
+      Some(recorder)
+    }
+    None => None,
+  };
+  #[cfg(feature = "record")]
+  let recording = recorder.is_some();
+  #[cfg(not(feature = "record"))]
+  let recording = false;
+  // Frames for an `on_frame` callback or --preview are wanted without receivers too
+  let mut keep_frames = recording || on_frame.is_some() || preview.is_some();
+
+  // --max-mbps caps what goes on the wire; frames it holds back count as dropped
+  let mut limiter = args
+    .max_mbps
+    .map(|mbps| TokenBucket::new(mbps * 1_000_000.0 / 8.0, Instant::now()));
+
+  // Trade JPEG quality for keeping up when sends start to overrun the frame time
+  let mut adaptive = args
+    .min_quality
+    .zip(frame_time)
+    .map(|(min, budget)| QualityController::new(min, args.quality, budget));
+  // --codec auto: trade CPU for bytes on the wire while sends overrun the frame time
+  let mut switcher = frame_time.filter(|_| args.auto_codec).map(|budget| {
+    let mut ladder = vec![Codec::Raw, Codec::Zstd, Codec::Jpeg];
+    if cfg!(feature = "h264") {
+      ladder.push(Codec::H264);
+    }
+    CodecSwitcher::new(ladder, budget, Instant::now())
+  });
+
+  // Running totals for the stats line, --metrics-addr and `Streamer::stats`
+  if let Some(addr) = args.metrics_addr {
+    metrics::serve(addr, metrics.clone())?;
+  }
+  // The totals as of the last stats line, which shows what changed since
+  let mut shown = metrics.snapshot();
+
+  // One copy of each frame sent this interval, however many receivers got it
+  let mut bytes_sent = 0;
+  // Capture-to-send time summed over the frames sent this interval
+  let mut latency_total = Duration::ZERO;
+  let mut peak_depth = 0;
+  let mut last_fps_print = Instant::now();
+  let mut seq: u64 = 0;
+  // --constant-fps: when capture misses a frame time, repeat the last frame sent
+  // at `repeat_at`, then once per frame time until a new one comes
+  let repeat_every = frame_time.filter(|_| args.constant_fps);
+  let mut repeat_at: Option<Instant> = None;
+  let mut repeats = 0;
+
+  // --profile: both threads time their stages into this
+  let profile = args.profile.then(|| Arc::new(Profile::default()));
+  let mut last_profile_print = Instant::now();
+
+  // Start capture
+  capture.start(encoder, args.pin_cores, profile.clone());
+  info!(
+    "{}🎥 Started capture. Type p or r and Enter to pause or resume; Enter, q or Ctrl-C to stop...",
+    label
+  );
+  info!("Streaming... ");
+  let stream_start = Instant::now();
+  #[cfg(feature = "audio")]
+  let mut audio_seq = 0;
+  let mut idle = false;
+  let mut user_paused = false;
+
+  loop {
+    // Act on keyboard commands, stopping on q, Enter or Ctrl-C
+    let mut quit = interrupted.load(Ordering::SeqCst);
+    while let Ok(command) = commands.try_recv() {
+      match command {
+        Command::Pause if !user_paused => {
+          user_paused = true;
+          info!("⏸️ Paused, type r and Enter to resume");
+        }
+        Command::Resume if user_paused => {
+          user_paused = false;
+          info!("▶️ Resumed");
+        }
+        Command::Quit => quit = true,
+        _ => {}
+      }
+    }
+    if !quit
+      && args
+        .duration
+        .is_some_and(|duration| stream_start.elapsed() >= duration)
+    {
+      info!(
+        "{}⏱️ Stopping after {:.1}s",
+        label,
+        stream_start.elapsed().as_secs_f64()
+      );
+      quit = true;
+    }
+    if !quit && args.frames.is_some_and(|frames| seq >= frames) {
+      info!("{}⏱️ Stopping after {} frames", label, seq);
+      quit = true;
+    }
+    // Capture failing for good ends the stream too; the capture thread logged why
+    if capture.failed() {
+      events.error("Capture failed".to_string());
+      break;
+    }
+    if quit {
+      break;
+    }
+
+    // While disconnected, retry once the backoff elapses. This runs whether or not
+    // frames are arriving, since capture is paused meanwhile.
+    if broadcaster.is_none() && socket.is_none() && Instant::now() >= reconnect_at {
+      match dial(
+        &server_addrs,
+        &link,
+        &mut handshake,
+        &codecs,
+        switcher.as_mut(),
+      ) {
+        Ok(new_socket) => {
+          match new_socket.peer_addr() {
+            Some(addr) if server_addrs.len() > 1 => {
+              info!("✅ Reconnected to {} at {}", receiver, addr)
+            }
+            _ => info!("✅ Reconnected to {}", receiver),
+          }
+          events.emit(EventKind::Connected {
+            peer: receiver.clone(),
+          });
+          backoff.reset();
+          socket = Some(new_socket);
+          if let Some(stream_encoder) = stream_encoder.as_mut() {
+            stream_encoder.force_keyframe();
+          }
+          // A --codec auto switch may have settled on another pixel format
+          capture.set_encoding(handshake.codec, handshake.pixel_format);
+        }
+        // As when first connecting, dialing again would get the same answer
+        Err(e) if e.kind() == io::ErrorKind::Unsupported => {
+          error!("❌ No format in common with the receiver: {}", e);
+          events.error(format!("No format in common with the receiver: {}", e));
+          break;
+        }
+        Err(e) => match backoff.next_delay() {
+          Some(delay) => {
+            warn!(
+              "⚠️ Reconnect failed: {}. Retrying in {:.2}s...",
+              e,
+              delay.as_secs_f64()
+            );
+            reconnect_at = Instant::now() + delay;
+          }
+          None => {
+            error!("❌ Giving up after {} retries", backoff.attempts());
+            events.error(format!("Giving up after {} retries", backoff.attempts()));
+            break;
+          }
+        },
+      }
+    }
+
+    let connected = broadcaster
+      .as_ref()
+      .map_or(socket.is_some() as usize, |b| b.client_count());
+    metrics.update(|stats| stats.receivers = connected as u64);
+
+    // Nobody to send to and nothing recording: stop capturing until that changes
+    if (connected == 0 && !keep_frames) != idle {
+      idle = !idle;
+      if idle {
+        info!("⏸️ No receivers, pausing capture");
+      } else {
+        info!("▶️ Receiver back, resuming capture");
+      }
+    }
+    // Receivers stay connected while paused; they just get no new frames
+    capture.set_paused(idle || user_paused);
+
+    // Forward whatever audio arrived since the last frame. A socket broken here
+    // surfaces on the next frame send, which handles the reconnect.
+    #[cfg(feature = "audio")]
+    if let Some(audio) = &audio {
+      while let Ok(buffer) = audio.buffers.try_recv() {
+        if user_paused {
+          continue;
+        }
+        let mut info = buffer.info(audio_seq, stream_start);
+        audio_seq += 1;
+        let mut data = buffer.data;
+        if let Err(e) = protect(&mut info, &mut data, cipher.as_ref(), args.checksum) {
+          error!("❌ Failed to encrypt audio: {}", e);
+          continue;
+        }
+        metrics.update(|stats| stats.bytes_sent += data.len() as u64 * connected as u64);
+        if let Some(broadcaster) = &broadcaster {
+          broadcaster.send(info, Arc::new(data));
+        } else if let Some(conn) = socket.as_mut() {
+          let _ = conn.send_frame(&info, &data);
+        }
+      }
+    }
+
+    // Wait for the capture thread to hand over the next converted frame, but not
+    // past the next repeat
+    let wait = repeat_at.map_or(POLL_INTERVAL, |at| {
+      at.saturating_duration_since(Instant::now())
+        .min(POLL_INTERVAL)
+    });
+    let Some(frame) = capture.frames.pop_timeout(wait) else {
+      if let Some((at, every)) = repeat_at.zip(repeat_every) {
+        if !idle && Instant::now() >= at {
+          // Only a header goes out, so a still screen costs next to nothing. A
+          // broken socket surfaces on the next frame send.
+          let timestamp_ms = stream_start.elapsed().as_millis() as u64;
+          let info = FrameInfo::repeat(width, height, seq - 1, timestamp_ms);
+          if let Some(broadcaster) = &broadcaster {
+            broadcaster.send(info, Arc::new(Vec::new()));
+          } else if let Some(conn) = socket.as_mut() {
+            let _ = conn.send_frame(&info, &[]);
+          }
+          repeats += 1;
+          repeat_at = Some(at + every);
+        }
+      }
+      continue;
+    };
+    // Frames still buffered behind this one show whether the sender is keeping up
+    peak_depth = peak_depth.max(capture.frames.depth());
+    events.emit(EventKind::FrameCaptured {
+      seq,
+      bytes: frame.data.len(),
+    });
+    let newly_dropped = capture.take_dropped();
+    if newly_dropped > 0 {
+      metrics.update(|stats| stats.frames_dropped += newly_dropped);
+      events.emit(EventKind::FrameDropped {
+        count: newly_dropped,
+      });
+    }
+    restart_pending |= frame.restarted;
+    // --max-lag-ms: a frame that sat too long goes stale rather than out late. It
+    // was never encoded against, so the stream encoder needs no keyframe after.
+    if args
+      .max_lag
+      .is_some_and(|max_lag| frame.captured_at.elapsed() > max_lag)
+    {
+      metrics.update(|stats| stats.frames_dropped += 1);
+      events.emit(EventKind::FrameDropped { count: 1 });
+      capture.recycle(frame.data);
+      continue;
+    }
+    // Encoded before a --codec auto switch, in what the receiver no longer expects
+    if frame.format != (handshake.codec, handshake.pixel_format) {
+      metrics.update(|stats| stats.frames_dropped += 1);
+      events.emit(EventKind::FrameDropped { count: 1 });
+      capture.recycle(frame.data);
+      continue;
+    }
+
+    // The capture target changed size: start over with everything sized for it.
+    // Reconnects get the new size in their handshake, receivers already connected
+    // get a size change marker, and listen-mode clients get theirs from their own
+    // writer thread.
+    let resized = (frame.width, frame.height) != (width, height);
+    if resized {
+      info!(
+        "{}📐 Streaming {}x{} frames from now on",
+        label, frame.width, frame.height
+      );
+      (width, height) = (frame.width, frame.height);
+      frame_size = (width * height * handshake.pixel_format.bytes_per_pixel()) as u64;
+      handshake.width = width;
+      handshake.height = height;
+      handshake.stride = width * handshake.pixel_format.bytes_per_pixel();
+      stream_encoder =
+        self::stream_encoder(args, handshake.codec, width, height, handshake.pixel_format)?;
+      // An MP4 track has one size, so the recording ends where the old size does
+      #[cfg(feature = "record")]
+      if let Some(recorder) = recorder.take() {
+        match recorder.finish() {
+          Ok((frames, path)) => warn!(
+            "⚠️ Recording stopped at the size change: {} frames in {}",
+            frames,
+            path.display()
+          ),
+          Err(e) => error!("❌ Failed to finish the recording: {}", e),
+        }
+      }
+      if let Some(conn) = socket.as_mut() {
+        let marker = FrameInfo::size_change(width, height, seq, frame_size as u32);
+        // A broken socket surfaces on the frame send right after
+        let _ = conn.send_size_change(&marker);
+      }
+    }
+
+    // The capturer was rebuilt after it stopped: numbering goes on, but the frames
+    // don't follow on from the ones before. Receivers hear of it from a size change
+    // marker, repeating the size unless it changed anyway, and the stream encoder
+    // starts over at a keyframe. UDP has no marker; the keyframe is all it gets.
+    if std::mem::take(&mut restart_pending) {
+      info!(
+        "{}🔄 Capture restarted, streaming on from frame {}",
+        label, seq
+      );
+      if !resized {
+        let marker = FrameInfo::size_change(width, height, seq, frame_size as u32);
+        if let Some(conn) = socket.as_mut() {
+          let _ = conn.send_size_change(&marker);
+        }
+        if let Some(broadcaster) = &broadcaster {
+          broadcaster.restart(marker);
+        }
+        if let Some(stream_encoder) = stream_encoder.as_mut() {
+          stream_encoder.force_keyframe();
+        }
+      }
+    }
+
+    // A receiver that just joined has no base frame to apply deltas to
+    if let (Some(stream_encoder), Some(broadcaster)) = (stream_encoder.as_mut(), &broadcaster) {
+      let count = broadcaster.client_count();
+      if count > receivers {
+        stream_encoder.force_keyframe();
+      }
+      receivers = count;
+    }
+
+    // With no receiver attached, frames are simply discarded unless kept
+    let offline = broadcaster
+      .as_ref()
+      .map_or(socket.is_none(), |b| b.client_count() == 0);
+    if offline && !keep_frames {
+      continue;
+    }
+
+    let mut info = FrameInfo {
+      width,
+      height,
+      seq,
+      timestamp_ms: frame.captured_at.duration_since(stream_start).as_millis() as u64,
+      raw_size: frame_size as u32,
+      checksum: None,
+      nonce: None,
+    };
+    let mut laps = Laps::start(profile.as_deref());
+    let mut data = match stream_encoder.as_mut() {
+      Some(stream_encoder) => {
+        let payload = stream_encoder.encode(&frame.data);
+        laps.lap(Stage::StreamEncode);
+        capture.recycle(frame.data);
+        match payload {
+          Ok(payload) => payload,
+          Err(e) => {
+            error!("❌ Failed to encode frame: {}", e);
+            events.error(format!("Failed to encode frame: {}", e));
+            continue;
+          }
+        }
+      }
+      None => frame.data,
+    };
+
+    #[cfg(feature = "record")]
+    if let Some(Err(e)) = recorder
+      .as_mut()
+      .map(|recorder| recorder.write(&data, info.timestamp_ms))
+    {
+      error!("❌ Recording stopped, the file won't play: {}", e);
+      events.error(format!("Recording stopped, the file won't play: {}", e));
+      recorder = None;
+    }
+    if let Some(on_frame) = &on_frame {
+      on_frame(&EncodedFrame {
+        stream: index.unwrap_or(0),
+        info: &info,
+        data: &data,
+      });
+    }
+    let format = (handshake.codec, handshake.pixel_format);
+    if let Some(feed) = &preview {
+      if !feed.send(&info, format, &data, frame.cursor) {
+        preview = None;
+        keep_frames = recording || on_frame.is_some();
+      }
+    }
+    if offline {
+      capture.recycle(data);
+      continue;
+    }
+
+    // --cursor-layer: where the cursor is goes out right ahead of the frame
+    if let Some((x, y)) = frame.cursor {
+      let mut info = Cursor::info(seq, info.timestamp_ms);
+      let mut data = Cursor::arrow(x, y).to_payload();
+      match protect(&mut info, &mut data, cipher.as_ref(), args.checksum) {
+        // A broken socket surfaces on the frame send right after
+        Ok(()) => {
+          metrics.update(|stats| stats.bytes_sent += data.len() as u64 * connected as u64);
+          if let Some(broadcaster) = &broadcaster {
+            broadcaster.send(info, Arc::new(data));
+          } else if let Some(conn) = socket.as_mut() {
+            let _ = conn.send_frame(&info, &data);
+          }
+        }
+        Err(e) => error!("❌ Failed to encrypt the cursor: {}", e),
+      }
+    }
+
+    // Encrypt after recording, so the file on disk stays playable
+    laps.skip();
+    let base = info;
+    if let Err(e) = protect(&mut info, &mut data, cipher.as_ref(), args.checksum) {
+      error!("❌ Failed to encrypt frame: {}", e);
+      events.error(format!("Failed to encrypt frame: {}", e));
+      capture.recycle(data);
+      continue;
+    }
+    if cipher.is_some() || args.checksum {
+      laps.lap(Stage::Seal);
+    }
+    let payload_size = data.len() as u64;
+
+    if let Some(limiter) = limiter.as_mut() {
+      // Every receiver gets its own copy in listen mode
+      let copies = broadcaster.as_ref().map_or(1, |b| b.client_count());
+      let bytes = data.len() * copies;
+      match args.over_limit {
+        OverLimit::Delay => {
+          sleep(limiter.delay_for(bytes, Instant::now()));
+          limiter.take(bytes, Instant::now());
+          laps.lap(Stage::Throttle);
+        }
+        OverLimit::Drop => {
+          if !limiter.try_take(bytes, Instant::now()) {
+            metrics.update(|stats| stats.frames_dropped += 1);
+            events.emit(EventKind::FrameDropped { count: 1 });
+            // The dropped frame was encoded against the previous one, so the next
+            // must not depend on it
+            if let Some(stream_encoder) = stream_encoder.as_mut() {
+              stream_encoder.force_keyframe();
+            }
+            capture.recycle(data);
+            continue;
+          }
+        }
+      }
+    }
+
+    if let Some(broadcaster) = &broadcaster {
+      // Fan out without blocking; laggards skip this frame on their own. The buffer
+      // is shared with every client's writer, so it isn't recycled.
+      let (sent, lagging) = broadcaster.send_frame(0, info, Arc::new(data));
+      let mut fanned_out = payload_size * sent.saturating_sub(lagging) as u64;
+      // Each variant goes to its own receivers, numbered like the main stream
+      for variant in frame.variants {
+        let mut info = FrameInfo {
+          width: variant.width,
+          height: variant.height,
+          raw_size: variant.raw_size,
+          ..base
+        };
+        let mut data = variant.data;
+        if let Err(e) = protect(&mut info, &mut data, cipher.as_ref(), args.checksum) {
+          error!("❌ Failed to encrypt frame: {}", e);
+          continue;
+        }
+        let size = data.len() as u64;
+        let (sent, lagging) = broadcaster.send_frame(variant.index, info, Arc::new(data));
+        fanned_out += size * sent.saturating_sub(lagging) as u64;
+      }
+      laps.lap(Stage::Send);
+      metrics.update(|stats| stats.bytes_sent += fanned_out);
+      // The client threads do the writing, so this measures up to the hand-off
+      latency_total += frame.captured_at.elapsed();
+      events.emit(EventKind::FrameSent {
+        seq,
+        bytes: payload_size as usize,
+        latency: frame.captured_at.elapsed(),
+      });
+      // Sends happen on the client threads; a lagging client is what overrunning
+      // the frame time looks like from here
+      if let Some(adaptive) = adaptive.as_mut() {
+        let pressure = if lagging > 0 {
+          adaptive.budget() * 2
+        } else {
+          Duration::ZERO
+        };
+        if let Some(quality) = adaptive.record(pressure) {
+          capture.set_quality(quality);
+        }
+      }
+    } else if let Some(conn) = socket.as_mut() {
+      let send_start = Instant::now();
+      let result = conn.send_frame(&info, &data);
+      laps.lap(Stage::Send);
+      if let Some(adaptive) = adaptive.as_mut() {
+        if let Some(quality) = adaptive.record(send_start.elapsed()) {
+          capture.set_quality(quality);
+        }
+      }
+      if let (Some(switcher), Ok(())) = (switcher.as_mut(), &result) {
+        switcher.record(payload_size as usize, send_start.elapsed());
+      }
+      capture.recycle(data);
+      if result.is_ok() {
+        metrics.update(|stats| stats.bytes_sent += payload_size);
+        latency_total += frame.captured_at.elapsed();
+        events.emit(EventKind::FrameSent {
+          seq,
+          bytes: payload_size as usize,
+          latency: frame.captured_at.elapsed(),
+        });
+      }
+      if let Err(e) = result {
+        error!(
+          "❌ Connection lost ({}): {:?}",
+          net::disconnect_reason(&e),
+          e
+        );
+        events.emit(EventKind::Disconnected {
+          peer: receiver.clone(),
+          reason: net::disconnect_reason(&e).to_string(),
+        });
+        // Drop the broken socket and schedule a reconnect; capture keeps running meanwhile
+        socket = None;
+        match backoff.next_delay() {
+          Some(delay) => {
+            info!("🔁 Reconnecting in {:.2}s...", delay.as_secs_f64());
+            reconnect_at = Instant::now() + delay;
+            continue;
+          }
+          None => {
+            error!("❌ Giving up after {} retries", backoff.attempts());
+            events.error(format!("Giving up after {} retries", backoff.attempts()));
+            break;
+          }
+        }
+      }
+    }
+
+    seq += 1;
+    metrics.update(|stats| stats.frames_sent += 1);
+    bytes_sent += payload_size;
+    // Half a frame time of slack, so a frame that is only slightly late isn't
+    // preceded by a repeat of the one before
+    repeat_at = repeat_every.map(|every| Instant::now() + every + every / 2);
+
+    // --codec auto: the handshake names the codec, so switching means ending this
+    // connection and dialing again, with the numbering carrying on. Receivers see
+    // one stream end and the next begin.
+    let switch = switcher
+      .as_mut()
+      .filter(|_| socket.is_some())
+      .and_then(|switcher| switcher.decide(Instant::now()));
+    if let Some(codec) = switch {
+      info!(
+        "{}🔀 Switching to --codec {} to fit the link",
+        label,
+        codec.name()
+      );
+      if let Some(mut conn) = socket.take() {
+        let _ = conn.send_end_of_stream(seq);
+      }
+      switch_handshake(&mut handshake, codec);
+      match dial(
+        &server_addrs,
+        &link,
+        &mut handshake,
+        &codecs,
+        switcher.as_mut(),
+      ) {
+        Ok(conn) => socket = Some(conn),
+        Err(e) if e.kind() == io::ErrorKind::Unsupported => {
+          error!("❌ No format in common with the receiver: {}", e);
+          events.error(format!("No format in common with the receiver: {}", e));
+          break;
+        }
+        // The reconnect above keeps trying, with the usual backoff
+        Err(e) => warn!("⚠️ Reconnecting to switch codecs failed: {}", e),
+      }
+      capture.set_encoding(handshake.codec, handshake.pixel_format);
+      stream_encoder =
+        self::stream_encoder(args, handshake.codec, width, height, handshake.pixel_format)?;
+    }
+
+    // Print FPS and stats every second
+    if last_fps_print.elapsed().as_secs() >= 1 {
+      // What changed since the last stats line, read back from what embedders see
+      let stats = metrics.snapshot();
+      let frame_count = stats.frames_sent - shown.frames_sent;
+      let dropped_frames = stats.frames_dropped - shown.frames_dropped;
+      let bytes_out = stats.bytes_sent - shown.bytes_sent;
+      let fps = frame_count as f64 / last_fps_print.elapsed().as_secs_f64();
+      metrics.update(|stats| stats.fps = fps);
+      shown = metrics.snapshot();
+      // Average time from capture until the frame's last byte was handed to the socket
+      let latency = latency_total.as_secs_f64() * 1000.0 / frame_count as f64;
+      let drop_rate = (dropped_frames as f64 / (frame_count + dropped_frames) as f64) * 100.0;
+      // Throughput is what actually went out; the raw ratio compares one copy of
+      // each frame against the same frame sent uncompressed
+      let bandwidth = bytes_out as f64 / (1024.0 * 1024.0) / last_fps_print.elapsed().as_secs_f64();
+      let of_raw = bytes_sent as f64 / (frame_count * frame_size) as f64 * 100.0;
+      let quality = adaptive
+        .as_ref()
+        .map(|adaptive| format!(" | Quality: {}", adaptive.quality()))
+        .unwrap_or_default();
+      let repeated = repeat_every
+        .map(|_| format!(" | Repeats: {}", repeats))
+        .unwrap_or_default();
+      let codec = switcher
+        .as_ref()
+        .map(|switcher| format!(" | Codec: {}", switcher.codec().name()))
+        .unwrap_or_default();
+
+      logging::stats(&format!(
+        "{}🎬 FPS: {:.1} | Latency: {:.1}ms | Dropped: {}/{} ({:.1}%) | Buffer: {}/{} | {:.1}MB/s ({:.0}% of raw){}{}{}",
+        label,
+        fps,
+        latency,
+        dropped_frames,
+        frame_count + dropped_frames,
+        drop_rate,
+        peak_depth,
+        capture.frames.capacity(),
+        bandwidth,
+        of_raw,
+        quality,
+        repeated,
+        codec
+      ));
+      if let Some(profile) = profile.as_ref() {
+        if last_profile_print.elapsed() >= PROFILE_INTERVAL {
+          if let Some(stages) = profile.report() {
+            info!("{}⏱️ Stages (mean/p99 ms): {}", label, stages);
+          }
+          last_profile_print = Instant::now();
+        }
+      }
+      // Logs and the stats line go to stderr, so stdout carries only these
+      if args.stats_json {
+        let stats = serde_json::json!({
+          "fps": fps,
+          "dropped": dropped_frames,
+          "drop_rate": drop_rate,
+          "bytes_sent": bytes_out,
+          "latency_ms": latency,
+        });
+        println!("{}", stats);
+      }
+
+      latency_total = Duration::ZERO;
+      bytes_sent = 0;
+      peak_depth = 0;
+      repeats = 0;
+      last_fps_print = Instant::now();
+    }
+  }
+
+  // Frames the capture thread dropped since the last one sent
+  metrics.update(|stats| stats.frames_dropped += capture.take_dropped());
+  let totals = Totals::from(metrics.snapshot());
+  let session = stream_start.elapsed();
+
+  // Stop Capture
+  capture.stop();
+
+  // Let receivers know the stream ended cleanly before the sockets close
+  if let Some(broadcaster) = &broadcaster {
+    broadcaster.finish(seq);
+  } else if let Some(conn) = socket.as_mut() {
+    if let Err(e) = conn.send_end_of_stream(seq) {
+      error!("❌ Failed to send end-of-stream: {:?}", e);
+    }
+  }
+
+  // The MP4 index goes at the end, so the file only plays once this has run
+  #[cfg(feature = "record")]
+  if let Some(recorder) = recorder {
+    match recorder.finish() {
+      Ok((frames, path)) => info!("💾 Recorded {} frames to {}", frames, path.display()),
+      Err(e) => error!("❌ Failed to finish the recording: {}", e),
+    }
+  }
+  info!("👋 Capture stopped");
+  if let Some(profile) = &profile {
+    info!(
+      "{}⏱️ Time per stage in µs, folded for flamegraph.pl:\n{}",
+      label,
+      profile.folded().trim_end()
+    );
+  }
+  logging::summary(&format!("{}{}", label, totals.summary(session)));
+  Ok(())
+}
+
+/// Seal `data` with `cipher` and checksum it, as every payload on a stream with
+/// --psk or --checksum goes out. The checksum covers what goes on the wire, so
+/// corruption of the ciphertext shows too.
+fn protect(
+  info: &mut FrameInfo,
+  data: &mut Vec<u8>,
+  cipher: Option<&crypto::Cipher>,
+  checksum: bool,
+) -> Result<(), String> {
+  if let Some(cipher) = cipher {
+    info.nonce = Some(cipher.seal(info, data)?);
+  }
+  if checksum {
+    info.checksum = Some(crc32fast::hash(data));
+  }
+  Ok(())
+}
+
+/// Point `handshake` at `codec` for --codec auto, asking to agree on the pixel
+/// format again: JPEG and H.264 decode to RGBA, the others can go out as BGRA
+fn switch_handshake(handshake: &mut Handshake, codec: Codec) {
+  handshake.codec = codec;
+  handshake.pixel_format = auto_formats(codec)[0];
+  handshake.stride = handshake.width * handshake.pixel_format.bytes_per_pixel();
+  handshake.negotiate = true;
+}
+
+/// Pixel formats --codec auto offers with `codec`, the preferred one first
+fn auto_formats(codec: Codec) -> &'static [PixelFormat] {
+  match codec {
+    Codec::Jpeg | Codec::H264 => &[PixelFormat::Rgba],
+    _ => &[PixelFormat::Bgra, PixelFormat::Rgba],
+  }
+}
+
+/// Connect to the receiver with `handshake`, agreeing on the pixel format if it
+/// asks to, which only --codec auto does after the first connection. A codec the
+/// receiver can't decode is left off the switcher's ladder, and the handshake
+/// tries the one before it instead, until the receiver has refused them all.
+fn dial(
+  server_addrs: &[SocketAddr],
+  link: &LinkOptions,
+  handshake: &mut Handshake,
+  codecs: &[Codec],
+  mut switcher: Option<&mut CodecSwitcher>,
+) -> io::Result<Connection> {
+  loop {
+    let offer = Capabilities::new(codecs, auto_formats(handshake.codec));
+    let attempt = Connection::open(server_addrs, link, handshake)
+      .and_then(|mut socket| Ok((socket.negotiate(handshake, offer)?, socket)));
+    match (attempt, switcher.as_deref_mut()) {
+      (Ok((format, socket)), _) => {
+        handshake.pixel_format = format;
+        handshake.stride = handshake.width * format.bytes_per_pixel();
+        handshake.negotiate = false;
+        return Ok(socket);
+      }
+      (Err(e), Some(switcher)) if e.kind() == io::ErrorKind::Unsupported => {
+        warn!(
+          "⚠️ The receiver can't take {}: {}",
+          handshake.codec.name(),
+          e
+        );
+        match switcher.refuse(Instant::now()) {
+          Some(codec) => switch_handshake(handshake, codec),
+          None => return Err(e),
+        }
+      }
+      (Err(e), _) => return Err(e),
+    }
+  }
+}
+
+/// Deltas and H.264 frames are encoded on the sending side rather than on the
+/// capture thread because each one must be relative to the frame actually sent
+/// before it, and the capture side can't know which frames the buffer dropped
+fn stream_encoder(
+  args: &Args,
+  codec: Codec,
+  width: u32,
+  height: u32,
+  pixel_format: PixelFormat,
+) -> Result<Option<StreamEncoder>, String> {
+  Ok(match codec {
+    Codec::Delta => Some(StreamEncoder::Delta(DeltaEncoder::new(
+      width,
+      height,
+      pixel_format.bytes_per_pixel(),
+      args.keyframe_interval,
+    ))),
+    #[cfg(feature = "h264")]
+    Codec::H264 => Some(StreamEncoder::H264(video::H264Encoder::new(
+      width,
+      height,
+      args.fps,
+      args.bitrate,
+      args.keyframe_interval,
+    )?)),
+    _ => None,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use std::io::{Read, Write};
+  use std::net::TcpListener;
+
+  use crate::protocol::HANDSHAKE_SIZE;
+
+  #[test]
+  fn dialing_a_receiver_that_refuses_every_codec_fails_each_time() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addrs = [listener.local_addr().unwrap()];
+    // Raw then zstd on the first dial, and zstd again on the second
+    let receiver = thread::spawn(move || {
+      for _ in 0..3 {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut handshake = [0u8; HANDSHAKE_SIZE];
+        let mut offer = [0u8; 2];
+        stream.read_exact(&mut handshake).unwrap();
+        stream.read_exact(&mut offer).unwrap();
+        stream.write_all(&[0, 0, 0xff]).unwrap();
+        stream.read_exact(&mut [0u8; 1]).unwrap();
+      }
+    });
+
+    let link = LinkOptions {
+      transport: Transport::Tcp,
+      nodelay: true,
+      connect_timeout: Duration::from_secs(1),
+      keepalive: None,
+      chunk_size: 65536,
+      fec: None,
+      tls: None,
+      path: None,
+    };
+    let codecs = [Codec::Raw, Codec::Zstd];
+    let mut switcher =
+      CodecSwitcher::new(codecs.to_vec(), Duration::from_millis(16), Instant::now());
+    let mut handshake = Handshake {
+      width: 64,
+      height: 64,
+      pixel_format: PixelFormat::Bgra,
+      codec: Codec::Raw,
+      fps: 60,
+      stride: 64 * 4,
+      negotiate: true,
+      audio: false,
+      encrypted: false,
+      checksum: false,
+      cursor: false,
+      fec: false,
+      variants: false,
+    };
+    for _ in 0..2 {
+      let attempt = dial(&addrs, &link, &mut handshake, &codecs, Some(&mut switcher));
+      assert!(attempt.is_err_and(|e| e.kind() == io::ErrorKind::Unsupported));
+      // The stats line still has a codec to show
+      assert_eq!(switcher.codec(), Codec::Zstd);
+    }
+    receiver.join().unwrap();
+  }
+
+  #[test]
+  fn handle_reaches_every_stream() {
+    let handle = Handle::default();
+    let (first, second) = (handle.subscribe(), handle.subscribe());
+    handle.send(Command::Pause);
+    assert_eq!(first.try_recv(), Ok(Command::Pause));
+    assert_eq!(second.try_recv(), Ok(Command::Pause));
+
+    // Quitting also stops streams that aren't listening for commands yet
+    handle.send(Command::Quit);
+    assert!(handle.interrupted.load(Ordering::SeqCst));
+    assert!(handle.interrupt());
+  }
+}
@@ -0,0 +1,254 @@
+use log::warn;
+use scap::capturer::{self, Area, Options};
+use scap::Target;
+
+/// Capture target requested on the command line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TargetSelector {
+  /// 0-based index into the displays, in the order listed by `--list-targets`
+  Display(usize),
+  /// Case-insensitive substring of a window title
+  Window(String),
+}
+
+pub fn name(target: &Target) -> String {
+  match target {
+    Target::Display(display) => format!("display '{}'", display.title),
+    Target::Window(window) => format!("window '{}'", window.title),
+  }
+}
+
+/// Print every display and window that can be captured as a table, with the
+/// index or title to pass to `--display`/`--window`
+pub fn list() {
+  let targets = scap::get_all_targets();
+  if targets.is_empty() {
+    println!(
+      "No displays or windows can be captured. Check that a display is connected and \
+       this program may record the screen."
+    );
+    return;
+  }
+  let rows: Vec<_> = displays(&targets)
+    .enumerate()
+    .map(|(index, target)| (format!("--display {}", index), target))
+    .chain(windows(&targets).map(|target| ("--window".to_string(), target)))
+    .map(|(select, target)| Row {
+      kind: match target {
+        Target::Display(_) => "display",
+        Target::Window(_) => "window",
+      },
+      select,
+      id: id(target),
+      size: full_size(target),
+      title: title(target).to_string(),
+    })
+    .collect();
+  print!("{}", table(&rows));
+}
+
+/// One line of `list`'s table
+struct Row {
+  kind: &'static str,
+  /// The flag that picks this target
+  select: String,
+  id: u32,
+  /// Zero where the platform can't tell before capturing
+  size: [u32; 2],
+  title: String,
+}
+
+fn table(rows: &[Row]) -> String {
+  let size = |row: &Row| match row.size {
+    [0, _] | [_, 0] => "?".to_string(),
+    [width, height] => format!("{}x{}", width, height),
+  };
+  let cells: Vec<[String; 5]> = rows
+    .iter()
+    .map(|row| {
+      [
+        row.kind.to_string(),
+        row.select.clone(),
+        row.id.to_string(),
+        size(row),
+        row.title.clone(),
+      ]
+    })
+    .collect();
+  let header = ["TYPE", "SELECT", "ID", "SIZE", "TITLE"].map(String::from);
+  let mut widths = [0; 5];
+  for row in std::iter::once(&header).chain(&cells) {
+    for (width, cell) in widths.iter_mut().zip(row) {
+      *width = (*width).max(cell.chars().count());
+    }
+  }
+  let mut out = String::new();
+  for row in std::iter::once(&header).chain(&cells) {
+    // The title is last, so it isn't padded and can be as long as it likes
+    let line: Vec<_> = row
+      .iter()
+      .zip(widths)
+      .map(|(cell, width)| format!("{:width$}", cell, width = width))
+      .collect();
+    out.push_str(line.join("  ").trim_end());
+    out.push('\n');
+  }
+  out
+}
+
+/// Find the requested target, or the first display when none was asked for
+pub fn resolve(selector: Option<&TargetSelector>) -> Result<Target, String> {
+  let targets = scap::get_all_targets();
+  match selector {
+    None => displays(&targets)
+      .next()
+      .cloned()
+      .ok_or("No display found".to_string()),
+    Some(TargetSelector::Display(index)) => displays(&targets).nth(*index).cloned().ok_or(format!(
+      "No display {} ({} available, see --list-targets)",
+      index,
+      displays(&targets).count()
+    )),
+    Some(TargetSelector::Window(pattern)) => targets
+      .iter()
+      .find(|t| window_matches(t, pattern))
+      .cloned()
+      .ok_or(format!(
+        "No window title contains '{}' (see --list-targets)",
+        pattern
+      )),
+  }
+}
+
+/// `target` as the system lists it now, to build a new capturer for after the old
+/// one stopped: the same id if that's still there, otherwise the same kind and
+/// title, as a display that was unplugged and plugged back in may get a new id
+pub fn refresh(target: &Target) -> Option<Target> {
+  let targets = scap::get_all_targets();
+  let same_kind = |t: &&Target| std::mem::discriminant(*t) == std::mem::discriminant(target);
+  let found = targets
+    .iter()
+    .filter(same_kind)
+    .find(|t| id(t) == id(target))
+    .or_else(|| {
+      targets
+        .iter()
+        .filter(same_kind)
+        .find(|t| title(t) == title(target))
+    });
+  found.cloned()
+}
+
+/// Every display, in the order listed by `--list-targets`
+pub fn all_displays() -> Vec<Target> {
+  displays(&scap::get_all_targets()).cloned().collect()
+}
+
+/// Find every window whose title contains one of `titles`. Titles that match
+/// nothing are reported and skipped, so a closed window doesn't stop the stream.
+pub fn excluded(titles: &[String]) -> Vec<Target> {
+  let targets = scap::get_all_targets();
+  let mut excluded = Vec::new();
+  for title in titles {
+    let before = excluded.len();
+    excluded.extend(targets.iter().filter(|t| window_matches(t, title)).cloned());
+    if excluded.len() == before {
+      warn!(
+        "⚠️ No window title contains '{}', nothing excluded for it",
+        title
+      );
+    }
+  }
+  excluded
+}
+
+/// The full, unscaled size of `target`, which --crop is measured against
+pub fn full_size(target: &Target) -> [u32; 2] {
+  capturer::get_output_frame_size(&Options {
+    target: Some(target.clone()),
+    ..Default::default()
+  })
+}
+
+/// Check that `crop` lies within the full, unscaled size of `target`
+pub fn check_crop(target: &Target, crop: &Area) -> Result<(), String> {
+  let [width, height] = full_size(target);
+  let (right, bottom) = (
+    crop.origin.x + crop.size.width,
+    crop.origin.y + crop.size.height,
+  );
+  if right > width as f64 || bottom > height as f64 {
+    return Err(format!(
+      "--crop {},{},{},{} extends past the {}x{} {}",
+      crop.origin.x,
+      crop.origin.y,
+      crop.size.width,
+      crop.size.height,
+      width,
+      height,
+      name(target)
+    ));
+  }
+  Ok(())
+}
+
+fn displays(targets: &[Target]) -> impl Iterator<Item = &Target> {
+  targets.iter().filter(|t| matches!(t, Target::Display(_)))
+}
+
+fn windows(targets: &[Target]) -> impl Iterator<Item = &Target> {
+  targets.iter().filter(|t| matches!(t, Target::Window(_)))
+}
+
+// Window titles match on a case-insensitive substring
+fn window_matches(target: &Target, pattern: &str) -> bool {
+  matches!(target, Target::Window(w) if w.title.to_lowercase().contains(&pattern.to_lowercase()))
+}
+
+fn title(target: &Target) -> &str {
+  match target {
+    Target::Display(display) => &display.title,
+    Target::Window(window) => &window.title,
+  }
+}
+
+fn id(target: &Target) -> u32 {
+  match target {
+    Target::Display(display) => display.id,
+    Target::Window(window) => window.id,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn lines_up_the_table() {
+    let row = |kind, select: &str, id, size, title: &str| Row {
+      kind,
+      select: select.to_string(),
+      id,
+      size,
+      title: title.to_string(),
+    };
+    let rows = [
+      row(
+        "display",
+        "--display 0",
+        1,
+        [2560, 1440],
+        "Built-in Retina Display",
+      ),
+      row("window", "--window", 4821, [0, 0], "Terminal — zsh"),
+    ];
+    assert_eq!(
+      table(&rows),
+      "\
+TYPE     SELECT       ID    SIZE       TITLE
+display  --display 0  1     2560x1440  Built-in Retina Display
+window   --window     4821  ?          Terminal — zsh
+"
+    );
+  }
+}
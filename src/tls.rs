@@ -0,0 +1,147 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::Arc;
+
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::{
+  ClientConfig, ClientConnection, RootCertStore, ServerConfig, ServerConnection, StreamOwned,
+};
+
+/// Which end of a TLS session we are, with everything needed to start one
+#[derive(Clone)]
+pub enum TlsConfig {
+  /// Connecting out: the peer's certificate must chain to a trusted root and name
+  /// `server_name`
+  Client {
+    config: Arc<ClientConfig>,
+    server_name: ServerName<'static>,
+  },
+  /// Accepting: present our own certificate
+  Server(Arc<ServerConfig>),
+}
+
+impl TlsConfig {
+  /// Trust the PEM certificates in `ca`, or the Mozilla root set without one, and
+  /// expect a certificate for `host` (a DNS name or IP address)
+  pub fn client(host: &str, ca: Option<&Path>) -> Result<TlsConfig, String> {
+    let mut roots = RootCertStore::empty();
+    match ca {
+      Some(path) => {
+        for cert in load_certs(path)? {
+          roots
+            .add(cert)
+            .map_err(|e| format!("Bad certificate in {}: {}", path.display(), e))?;
+        }
+      }
+      None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+    }
+    let config = ClientConfig::builder()
+      .with_root_certificates(roots)
+      .with_no_client_auth();
+
+    let host = host
+      .strip_prefix('[')
+      .and_then(|h| h.strip_suffix(']'))
+      .unwrap_or(host);
+    let server_name = ServerName::try_from(host.to_string())
+      .map_err(|_| format!("'{}' can't be checked against a TLS certificate", host))?;
+    Ok(TlsConfig::Client {
+      config: Arc::new(config),
+      server_name,
+    })
+  }
+
+  /// Serve the PEM certificate chain in `cert` with the private key in `key`
+  pub fn server(cert: &Path, key: &Path) -> Result<TlsConfig, String> {
+    let certs = load_certs(cert)?;
+    let key = load_key(key)?;
+    let config = ServerConfig::builder()
+      .with_no_client_auth()
+      .with_single_cert(certs, key)
+      .map_err(|e| format!("Certificate and key don't work together: {}", e))?;
+    Ok(TlsConfig::Server(Arc::new(config)))
+  }
+
+  /// Start a session on `stream`. The TLS handshake runs on the first read or write.
+  pub fn wrap(&self, stream: TcpStream) -> io::Result<TlsStream> {
+    Ok(match self {
+      TlsConfig::Client {
+        config,
+        server_name,
+      } => {
+        let conn =
+          ClientConnection::new(config.clone(), server_name.clone()).map_err(io::Error::other)?;
+        TlsStream::Client(StreamOwned::new(conn, stream))
+      }
+      TlsConfig::Server(config) => {
+        let conn = ServerConnection::new(config.clone()).map_err(io::Error::other)?;
+        TlsStream::Server(StreamOwned::new(conn, stream))
+      }
+    })
+  }
+}
+
+/// A TCP stream behind TLS, readable and writable like the plain one
+pub enum TlsStream {
+  Client(StreamOwned<ClientConnection, TcpStream>),
+  Server(StreamOwned<ServerConnection, TcpStream>),
+}
+
+impl TlsStream {
+  /// The underlying socket, for options such as read timeouts
+  pub fn socket(&self) -> &TcpStream {
+    match self {
+      TlsStream::Client(stream) => &stream.sock,
+      TlsStream::Server(stream) => &stream.sock,
+    }
+  }
+}
+
+impl Read for TlsStream {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    match self {
+      TlsStream::Client(stream) => stream.read(buf),
+      TlsStream::Server(stream) => stream.read(buf),
+    }
+  }
+}
+
+impl Write for TlsStream {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    match self {
+      TlsStream::Client(stream) => stream.write(buf),
+      TlsStream::Server(stream) => stream.write(buf),
+    }
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    match self {
+      TlsStream::Client(stream) => stream.flush(),
+      TlsStream::Server(stream) => stream.flush(),
+    }
+  }
+}
+
+fn open(path: &Path) -> Result<BufReader<File>, String> {
+  File::open(path)
+    .map(BufReader::new)
+    .map_err(|e| format!("Failed to open {}: {}", path.display(), e))
+}
+
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, String> {
+  let certs = rustls_pemfile::certs(&mut open(path)?)
+    .collect::<Result<Vec<_>, _>>()
+    .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+  if certs.is_empty() {
+    return Err(format!("No PEM certificates in {}", path.display()));
+  }
+  Ok(certs)
+}
+
+fn load_key(path: &Path) -> Result<PrivateKeyDer<'static>, String> {
+  rustls_pemfile::private_key(&mut open(path)?)
+    .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?
+    .ok_or(format!("No PEM private key in {}", path.display()))
+}
@@ -0,0 +1,335 @@
+// --variants: in listen mode, up to MAX_VARIANTS other variants of the stream are
+// encoded beside it, each with its own codec, JPEG quality and size, so a viewer
+// on the LAN can take raw frames while one across the internet takes small JPEGs.
+// Receivers say what they'd rather have in the handshake and get the closest of the
+// main stream and these. The capture thread encodes a variant only while some
+// receiver is on it, so unwatched ones cost nothing. Variants are only codecs whose
+// frames stand alone, as there is one encode for every receiver of a variant,
+// however many frames each of them skipped.
+
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+
+use crate::encode::FrameEncoder;
+use crate::protocol::{Capabilities, Codec, Handshake, PixelFormat, Variant};
+use crate::resize::{self, Filter};
+
+/// The most variants beside the main stream, which bounds the extra encoding
+pub const MAX_VARIANTS: usize = 3;
+
+/// Codecs a variant can use
+pub const VARIANT_CODECS: [Codec; 4] = [Codec::Raw, Codec::Zstd, Codec::Jpeg, Codec::Rle];
+
+/// One variant, ready to encode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rung {
+  pub codec: Codec,
+  pub quality: u8,
+  pub pixel_format: PixelFormat,
+  /// Frames are scaled down to fit within this
+  pub bound: Option<[u32; 2]>,
+}
+
+/// A frame of one variant, encoded on the capture thread
+pub struct VariantFrame {
+  /// Which variant, from 1; 0 is the main stream
+  pub index: usize,
+  pub width: u32,
+  pub height: u32,
+  /// Payload size after decoding
+  pub raw_size: u32,
+  pub data: Vec<u8>,
+}
+
+/// The variants a listen-mode stream offers, shared by the capture thread and
+/// the broadcaster
+#[derive(Debug, Clone)]
+pub struct Ladder {
+  rungs: Vec<Rung>,
+  /// JPEG quality of the main stream, to compare against
+  main_quality: u8,
+  filter: Filter,
+  /// Bit i - 1 is set while variant i has a receiver
+  wanted: Arc<AtomicU8>,
+}
+
+impl Ladder {
+  /// Variants as --variants gives them; ones without a quality take `quality`,
+  /// the main stream's. Pixels are RGBA unless --pixel-format asks for others
+  /// the codec can carry.
+  pub fn new(
+    variants: &[Variant],
+    pixel_format: Option<PixelFormat>,
+    quality: u8,
+    filter: Filter,
+  ) -> Self {
+    let rungs = variants
+      .iter()
+      .map(|variant| {
+        let codec = variant.codec.unwrap_or(Codec::Raw);
+        let pixel_format = match (codec, pixel_format) {
+          (Codec::Jpeg, Some(format @ (PixelFormat::Gray | PixelFormat::Rgb))) => format,
+          (Codec::Jpeg, _) => PixelFormat::Rgba,
+          (Codec::Rle, Some(format)) if format.bytes_per_pixel() != 4 => PixelFormat::Rgba,
+          (_, format) => format.unwrap_or(PixelFormat::Rgba),
+        };
+        Rung {
+          codec,
+          quality: variant.quality.unwrap_or(quality),
+          pixel_format,
+          bound: variant.size,
+        }
+      })
+      .collect();
+    Ladder {
+      rungs,
+      main_quality: quality,
+      filter,
+      wanted: Arc::new(AtomicU8::new(0)),
+    }
+  }
+
+  pub fn rungs(&self) -> &[Rung] {
+    &self.rungs
+  }
+
+  /// Note which variants have receivers, from the variant of each
+  pub fn set_wanted(&self, variants: impl IntoIterator<Item = usize>) {
+    let mask = variants
+      .into_iter()
+      .filter(|&index| index > 0)
+      .fold(0, |mask, index| mask | 1 << (index - 1));
+    self.wanted.store(mask, Ordering::Relaxed);
+  }
+
+  /// What a receiver of variant `index` is told, for a stream described by `main`
+  pub fn handshake(&self, index: usize, main: &Handshake) -> Handshake {
+    let mut handshake = Handshake {
+      negotiate: false,
+      variants: false,
+      ..*main
+    };
+    if let Some(rung) = index.checked_sub(1).map(|rung| self.rungs[rung]) {
+      let [width, height] = fit([main.width, main.height], rung.bound);
+      handshake.width = width;
+      handshake.height = height;
+      handshake.codec = rung.codec;
+      handshake.pixel_format = rung.pixel_format;
+      handshake.stride = width * rung.pixel_format.bytes_per_pixel();
+    }
+    handshake
+  }
+
+  /// The variant closest to `want` a receiver that decodes `answer` can take, 0
+  /// being the main stream `main`, which is only a choice when `main_ok`. A wish
+  /// for a codec outweighs one for a size, which outweighs one for a quality;
+  /// without any, the main stream wins.
+  pub fn pick(
+    &self,
+    main: &Handshake,
+    main_ok: bool,
+    answer: Capabilities,
+    want: Variant,
+  ) -> Option<usize> {
+    // Index, codec, quality and size of each variant the receiver can decode
+    let main_size = [main.width, main.height];
+    let mut candidates: Vec<(usize, Codec, u8, [u32; 2])> = main_ok
+      .then_some((0, main.codec, self.main_quality, main_size))
+      .into_iter()
+      .chain(
+        self
+          .rungs
+          .iter()
+          .enumerate()
+          .filter(|(_, r)| answer.has_codec(r.codec) && answer.has_pixel_format(r.pixel_format))
+          .map(|(rung, r)| (rung + 1, r.codec, r.quality, fit(main_size, r.bound))),
+      )
+      .collect();
+    if let Some(codec) = want.codec {
+      if candidates.iter().any(|candidate| candidate.1 == codec) {
+        candidates.retain(|candidate| candidate.1 == codec);
+      }
+    }
+    let area = |[width, height]: [u32; 2]| width as u64 * height as u64;
+    if let Some([max_width, max_height]) = want.size {
+      let fits = |size: [u32; 2]| size[0] <= max_width && size[1] <= max_height;
+      if candidates.iter().any(|candidate| fits(candidate.3)) {
+        candidates.retain(|candidate| fits(candidate.3));
+      } else if let Some(smallest) = candidates.iter().map(|candidate| area(candidate.3)).min() {
+        candidates.retain(|candidate| area(candidate.3) == smallest);
+      }
+    }
+    // Only JPEG loses detail; every other codec counts as full quality
+    let quality = |codec: Codec, quality: u8| if codec == Codec::Jpeg { quality } else { 100 };
+    candidates
+      .into_iter()
+      .min_by_key(|&(index, codec, q, size)| {
+        let larger = if want.size.is_some() {
+          u64::MAX - area(size)
+        } else {
+          0
+        };
+        let off = want
+          .quality
+          .map_or(0, |want| quality(codec, q).abs_diff(want));
+        (larger, off, index)
+      })
+      .map(|candidate| candidate.0)
+  }
+
+  /// Encode a BGRA frame whose rows are `stride` bytes apart as every variant
+  /// that has a receiver, with the main stream's `encoder` settings otherwise
+  pub fn encode(
+    &self,
+    bgra: &[u8],
+    [width, height]: [u32; 2],
+    stride: usize,
+    encoder: &FrameEncoder,
+  ) -> Result<Vec<VariantFrame>, String> {
+    let wanted = self.wanted.load(Ordering::Relaxed);
+    let mut frames = Vec::new();
+    for (rung, r) in self.rungs.iter().enumerate() {
+      if wanted & 1 << rung == 0 {
+        continue;
+      }
+      let [out_width, out_height] = fit([width, height], r.bound);
+      let (pixels, pixels_stride) = if [out_width, out_height] == [width, height] {
+        (bgra.to_vec(), stride)
+      } else {
+        let mut resized = Vec::new();
+        resize::resize_bgra_into(
+          bgra,
+          [width as usize, height as usize],
+          stride,
+          [out_width as usize, out_height as usize],
+          self.filter,
+          &mut resized,
+        );
+        (resized, out_width as usize * 4)
+      };
+      let encoder = FrameEncoder {
+        codec: r.codec,
+        quality: r.quality,
+        pixel_format: r.pixel_format,
+        ..*encoder
+      };
+      let mut data = Vec::new();
+      encoder.encode(pixels, out_width, out_height, pixels_stride, &mut data)?;
+      frames.push(VariantFrame {
+        index: rung + 1,
+        width: out_width,
+        height: out_height,
+        raw_size: out_width * out_height * r.pixel_format.bytes_per_pixel(),
+        data,
+      });
+    }
+    Ok(frames)
+  }
+}
+
+/// `size` scaled down to fit within `bound`, keeping its aspect ratio, to even
+/// sides like --scale's; sizes that already fit are kept
+pub fn fit([width, height]: [u32; 2], bound: Option<[u32; 2]>) -> [u32; 2] {
+  let Some([max_width, max_height]) = bound else {
+    return [width, height];
+  };
+  if width <= max_width && height <= max_height {
+    return [width, height];
+  }
+  let (width, height) = (width as u64, height as u64);
+  let (max_width, max_height) = (max_width as u64, max_height as u64);
+  // Whichever side reaches its limit first sets the scale
+  let [width, height] = if width * max_height >= height * max_width {
+    [max_width, height * max_width / width]
+  } else {
+    [width * max_height / height, max_height]
+  };
+  [width, height].map(|side| (side as u32 & !1).max(2))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::protocol::{CODECS, PIXEL_FORMATS};
+
+  fn main_stream() -> Handshake {
+    Handshake {
+      width: 1920,
+      height: 1080,
+      pixel_format: PixelFormat::Bgra,
+      codec: Codec::Raw,
+      fps: 30,
+      stride: 1920 * 4,
+      negotiate: true,
+      audio: false,
+      encrypted: false,
+      checksum: false,
+      cursor: false,
+      fec: false,
+      variants: true,
+    }
+  }
+
+  fn ladder(variants: &[&str]) -> Ladder {
+    let variants: Vec<Variant> = variants.iter().map(|v| v.parse().unwrap()).collect();
+    Ladder::new(&variants, None, 80, Filter::Bilinear)
+  }
+
+  #[test]
+  fn fits_within_the_bound_keeping_the_aspect() {
+    assert_eq!(fit([1920, 1080], Some([1280, 1280])), [1280, 720]);
+    assert_eq!(fit([1920, 1080], Some([640, 200])), [354, 200]);
+    assert_eq!(fit([800, 600], Some([1280, 720])), [800, 600]);
+    assert_eq!(fit([800, 600], None), [800, 600]);
+  }
+
+  #[test]
+  fn picks_the_closest_variant_the_receiver_decodes() {
+    let ladder = ladder(&["jpeg:40@640x360", "jpeg:90@1280x720", "zstd"]);
+    let main = main_stream();
+    let all = Capabilities::new(&CODECS, &PIXEL_FORMATS);
+    let pick = |answer, want: &str| ladder.pick(&main, true, answer, want.parse().unwrap());
+
+    assert_eq!(pick(all, ""), Some(0));
+    assert_eq!(pick(all, "zstd"), Some(3));
+    assert_eq!(pick(all, "jpeg"), Some(1));
+    assert_eq!(pick(all, "jpeg:85"), Some(2));
+    // The largest that fits, or else the smallest there is
+    assert_eq!(pick(all, "@1280x800"), Some(2));
+    assert_eq!(pick(all, "@320x200"), Some(1));
+    // A codec nothing is sent in doesn't rule the others out
+    assert_eq!(pick(all, "rle@700x400"), Some(1));
+
+    // Without the main stream, whatever is left
+    let jpeg_only = Capabilities::new(&[Codec::Jpeg], &[PixelFormat::Rgba]);
+    let zstd: Variant = "zstd".parse().unwrap();
+    assert_eq!(ladder.pick(&main, false, jpeg_only, zstd), Some(1));
+    let rle_only = Capabilities::new(&[Codec::Rle], &[PixelFormat::Rgba]);
+    assert_eq!(ladder.pick(&main, false, rle_only, zstd), None);
+  }
+
+  #[test]
+  fn encodes_only_the_variants_with_receivers() {
+    let ladder = ladder(&["raw@2x2", "zstd"]);
+    let encoder = FrameEncoder {
+      codec: Codec::Raw,
+      quality: 80,
+      level: 1,
+      pixel_format: PixelFormat::Bgra,
+      yuv: Default::default(),
+    };
+    let bgra = vec![7; 4 * 4 * 4];
+    assert!(ladder
+      .encode(&bgra, [4, 4], 16, &encoder)
+      .unwrap()
+      .is_empty());
+    ladder.set_wanted([0, 1, 1]);
+    let frames = ladder.encode(&bgra, [4, 4], 16, &encoder).unwrap();
+    assert_eq!(frames.len(), 1);
+    assert_eq!(
+      (frames[0].index, frames[0].width, frames[0].raw_size),
+      (1, 2, 16)
+    );
+    assert_eq!(frames[0].data, [7; 16]);
+  }
+}
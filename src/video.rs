@@ -0,0 +1,77 @@
+// H.264 payload layout, used by the h264 codec:
+//
+//   payload := kind:u8 access_unit
+//
+// `kind` is 0 when the access unit starts with an IDR picture a decoder can
+// start from, and 1 otherwise. `access_unit` is the Annex B byte stream (NAL
+// units behind 00 00 00 01 start codes) for exactly one frame; IDR frames carry
+// the SPS/PPS in front of the picture. Like deltas, every other frame depends on
+// the ones before it, so after a gap in `seq` a receiver should wait for the next
+// keyframe.
+
+use openh264::encoder::{BitRate, Encoder, EncoderConfig, FrameRate, FrameType};
+use openh264::formats::YUVBuffer;
+use openh264::OpenH264API;
+
+pub const KEYFRAME: u8 = 0;
+const INTER: u8 = 1;
+
+/// openh264 wrapper fed with YUV420 frames from the capture thread
+pub struct H264Encoder {
+  encoder: Encoder,
+  width: usize,
+  height: usize,
+  keyframe_interval: u32,
+  since_keyframe: u32,
+}
+
+impl H264Encoder {
+  /// `bitrate_kbps` is the target rate the encoder aims for; `keyframe_interval`
+  /// is the number of frames between IDR frames
+  pub fn new(
+    width: u32,
+    height: u32,
+    fps: u32,
+    bitrate_kbps: u32,
+    keyframe_interval: u32,
+  ) -> Result<Self, String> {
+    if !width.is_multiple_of(2) || !height.is_multiple_of(2) {
+      return Err(format!(
+        "H.264 needs even dimensions, got {}x{}",
+        width, height
+      ));
+    }
+    let config = EncoderConfig::new()
+      .bitrate(BitRate::from_bps(bitrate_kbps.saturating_mul(1000)))
+      .max_frame_rate(FrameRate::from_hz(fps as f32));
+    let encoder = Encoder::with_api_config(OpenH264API::from_source(), config)
+      .map_err(|e| format!("Failed to create H.264 encoder: {}", e))?;
+    Ok(H264Encoder {
+      encoder,
+      width: width as usize,
+      height: height as usize,
+      keyframe_interval,
+      since_keyframe: keyframe_interval,
+    })
+  }
+
+  /// Make the next frame an IDR frame, e.g. when a receiver (re)joins mid-stream
+  pub fn force_keyframe(&mut self) {
+    self.since_keyframe = self.keyframe_interval;
+  }
+
+  /// Encode one tightly packed YUV420 frame
+  pub fn encode(&mut self, yuv: &[u8]) -> Result<Vec<u8>, String> {
+    if self.since_keyframe >= self.keyframe_interval {
+      self.encoder.force_intra_frame();
+    }
+    let frame = YUVBuffer::from_vec(yuv.to_vec(), self.width, self.height);
+    let bitstream = self.encoder.encode(&frame).map_err(|e| e.to_string())?;
+
+    let keyframe = matches!(bitstream.frame_type(), FrameType::IDR);
+    let mut out = vec![if keyframe { KEYFRAME } else { INTER }];
+    bitstream.write_vec(&mut out);
+    self.since_keyframe = if keyframe { 1 } else { self.since_keyframe + 1 };
+    Ok(out)
+  }
+}
@@ -0,0 +1,298 @@
+// --watermark and --timestamp: a line of text burned into a corner of every frame
+// after conversion and before encoding, for provenance in shared recordings. The
+// 5x7 bitmap font covers digits, letters (drawn in capitals) and common
+// punctuation; anything else comes out as '?'. Each dot is a square of pixels
+// that grows with the frame height, white on a half-dark box so the text reads
+// on any background. Only the box's own pixels are touched, so the cost stays a
+// few thousand pixel writes a frame.
+
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const GLYPH_WIDTH: usize = 5;
+const GLYPH_HEIGHT: usize = 7;
+// Glyphs are set one dot apart
+const CELL_WIDTH: usize = GLYPH_WIDTH + 1;
+// Dots of box around the text, and of space between the box and the frame edges
+const PADDING: usize = 2;
+const MARGIN: usize = 4;
+// One pixel per dot up to this many rows of frame, two up to twice as many, ...
+const ROWS_PER_SCALE: u32 = 360;
+
+/// Where in the frame the text goes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Corner {
+  TopLeft,
+  TopRight,
+  BottomLeft,
+  BottomRight,
+}
+
+impl FromStr for Corner {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "top-left" => Ok(Corner::TopLeft),
+      "top-right" => Ok(Corner::TopRight),
+      "bottom-left" => Ok(Corner::BottomLeft),
+      "bottom-right" => Ok(Corner::BottomRight),
+      _ => Err(format!(
+        "Unknown --watermark-corner '{}' (expected top-left, top-right, bottom-left or bottom-right)",
+        s
+      )),
+    }
+  }
+}
+
+/// Text burned into a corner of each frame
+pub struct Watermark {
+  text: Option<String>,
+  timestamp: bool,
+  corner: Corner,
+  /// What the last frame showed, rebuilt when the clock passes another second
+  line: String,
+  second: Option<u64>,
+}
+
+impl Watermark {
+  /// Show `text`, the current UTC time if `timestamp` is set, or both in `corner`
+  pub fn new(text: Option<String>, timestamp: bool, corner: Corner) -> Self {
+    Watermark {
+      text,
+      timestamp,
+      corner,
+      line: String::new(),
+      second: None,
+    }
+  }
+
+  /// Draw the text into a BGRA frame of `size` pixels whose rows are `stride` bytes
+  /// apart
+  pub fn draw(&mut self, bgra: &mut [u8], stride: usize, size: [u32; 2]) {
+    let now = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .map_or(0, |elapsed| elapsed.as_secs());
+    if self.second != Some(now) {
+      self.second = Some(now);
+      self.line = match (&self.text, self.timestamp) {
+        (Some(text), true) => format!("{}  {}", text, utc(now)),
+        (Some(text), false) => text.clone(),
+        (None, _) => utc(now),
+      };
+    }
+    draw_line(bgra, stride, size, &self.line, self.corner);
+  }
+}
+
+/// `secs` after the Unix epoch as `YYYY-MM-DD HH:MM:SS UTC`
+fn utc(secs: u64) -> String {
+  let (days, time) = (secs / 86400, secs % 86400);
+  // Days to a civil date, after Howard Hinnant's days_from_civil inverse, with
+  // years starting on 1 March so the leap day comes last
+  let days = days as i64 + 719_468;
+  let era = days.div_euclid(146_097);
+  let day_of_era = days.rem_euclid(146_097);
+  let year_of_era =
+    (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+  let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+  let month_index = (5 * day_of_year + 2) / 153;
+  let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+  let month = if month_index < 10 {
+    month_index + 3
+  } else {
+    month_index - 9
+  };
+  let year = year_of_era + era * 400 + (month <= 2) as i64;
+  format!(
+    "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+    year,
+    month,
+    day,
+    time / 3600,
+    time / 60 % 60,
+    time % 60
+  )
+}
+
+/// Draw `line` on its box in `corner`, clipped to the frame
+fn draw_line(
+  bgra: &mut [u8],
+  stride: usize,
+  [width, height]: [u32; 2],
+  line: &str,
+  corner: Corner,
+) {
+  let (width, height) = (width as usize, height as usize);
+  let scale = (height as u32 / ROWS_PER_SCALE + 1) as usize;
+  let box_width = (line.chars().count() * CELL_WIDTH - 1 + 2 * PADDING) * scale;
+  let box_height = (GLYPH_HEIGHT + 2 * PADDING) * scale;
+  let margin = MARGIN * scale;
+  let place = |size: usize, extent: usize, far: bool| {
+    if far {
+      size.saturating_sub(extent + margin)
+    } else {
+      margin.min(size)
+    }
+  };
+  let left = place(
+    width,
+    box_width,
+    matches!(corner, Corner::TopRight | Corner::BottomRight),
+  );
+  let top = place(
+    height,
+    box_height,
+    matches!(corner, Corner::BottomLeft | Corner::BottomRight),
+  );
+  let (right, bottom) = (
+    (left + box_width).min(width),
+    (top + box_height).min(height),
+  );
+
+  // Halve what's behind the box, then set the glyphs' dots to white
+  for y in top..bottom {
+    for pixel in bgra[y * stride + left * 4..y * stride + right * 4].chunks_exact_mut(4) {
+      for channel in &mut pixel[..3] {
+        *channel /= 2;
+      }
+    }
+  }
+  let origin = (left + PADDING * scale, top + PADDING * scale);
+  for (index, c) in line.chars().enumerate() {
+    let glyph = glyph(c);
+    let glyph_left = origin.0 + index * CELL_WIDTH * scale;
+    for (row, bits) in glyph.iter().enumerate() {
+      for column in (0..GLYPH_WIDTH).filter(|column| bits >> (GLYPH_WIDTH - 1 - column) & 1 == 1) {
+        let x0 = glyph_left + column * scale;
+        let y0 = origin.1 + row * scale;
+        for y in y0..(y0 + scale).min(bottom) {
+          for x in x0..(x0 + scale).min(right) {
+            bgra[y * stride + x * 4..][..3].fill(255);
+          }
+        }
+      }
+    }
+  }
+}
+
+/// The glyph for `c`, each row's dots in the low five bits, leftmost first
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+  let c = c.to_ascii_uppercase();
+  match c {
+    ' '..='_' => FONT[c as usize - ' ' as usize],
+    _ => FONT['?' as usize - ' ' as usize],
+  }
+}
+
+// ' ' through '_' in ASCII order
+#[rustfmt::skip]
+const FONT: [[u8; GLYPH_HEIGHT]; 64] = [
+  [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // ' '
+  [0x04, 0x04, 0x04, 0x04, 0x04, 0x00, 0x04], // !
+  [0x0a, 0x0a, 0x00, 0x00, 0x00, 0x00, 0x00], // "
+  [0x0a, 0x0a, 0x1f, 0x0a, 0x1f, 0x0a, 0x0a], // #
+  [0x04, 0x0f, 0x14, 0x0e, 0x05, 0x1e, 0x04], // $
+  [0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03], // %
+  [0x0c, 0x12, 0x14, 0x08, 0x15, 0x12, 0x0d], // &
+  [0x04, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00], // '
+  [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02], // (
+  [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08], // )
+  [0x00, 0x04, 0x15, 0x0e, 0x15, 0x04, 0x00], // *
+  [0x00, 0x04, 0x04, 0x1f, 0x04, 0x04, 0x00], // +
+  [0x00, 0x00, 0x00, 0x00, 0x0c, 0x04, 0x08], // ,
+  [0x00, 0x00, 0x00, 0x1f, 0x00, 0x00, 0x00], // -
+  [0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x0c], // .
+  [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00], // /
+  [0x0e, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0e], // 0
+  [0x04, 0x0c, 0x04, 0x04, 0x04, 0x04, 0x0e], // 1
+  [0x0e, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1f], // 2
+  [0x1f, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0e], // 3
+  [0x02, 0x06, 0x0a, 0x12, 0x1f, 0x02, 0x02], // 4
+  [0x1f, 0x10, 0x1e, 0x01, 0x01, 0x11, 0x0e], // 5
+  [0x06, 0x08, 0x10, 0x1e, 0x11, 0x11, 0x0e], // 6
+  [0x1f, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08], // 7
+  [0x0e, 0x11, 0x11, 0x0e, 0x11, 0x11, 0x0e], // 8
+  [0x0e, 0x11, 0x11, 0x0f, 0x01, 0x02, 0x0c], // 9
+  [0x00, 0x0c, 0x0c, 0x00, 0x0c, 0x0c, 0x00], // :
+  [0x00, 0x0c, 0x0c, 0x00, 0x0c, 0x04, 0x08], // ;
+  [0x02, 0x04, 0x08, 0x10, 0x08, 0x04, 0x02], // <
+  [0x00, 0x00, 0x1f, 0x00, 0x1f, 0x00, 0x00], // =
+  [0x08, 0x04, 0x02, 0x01, 0x02, 0x04, 0x08], // >
+  [0x0e, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04], // ?
+  [0x0e, 0x11, 0x01, 0x0d, 0x15, 0x15, 0x0e], // @
+  [0x0e, 0x11, 0x11, 0x1f, 0x11, 0x11, 0x11], // A
+  [0x1e, 0x11, 0x11, 0x1e, 0x11, 0x11, 0x1e], // B
+  [0x0e, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0e], // C
+  [0x1c, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1c], // D
+  [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x1f], // E
+  [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x10], // F
+  [0x0e, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0f], // G
+  [0x11, 0x11, 0x11, 0x1f, 0x11, 0x11, 0x11], // H
+  [0x0e, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0e], // I
+  [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0c], // J
+  [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11], // K
+  [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1f], // L
+  [0x11, 0x1b, 0x15, 0x15, 0x11, 0x11, 0x11], // M
+  [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11], // N
+  [0x0e, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e], // O
+  [0x1e, 0x11, 0x11, 0x1e, 0x10, 0x10, 0x10], // P
+  [0x0e, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0d], // Q
+  [0x1e, 0x11, 0x11, 0x1e, 0x14, 0x12, 0x11], // R
+  [0x0f, 0x10, 0x10, 0x0e, 0x01, 0x01, 0x1e], // S
+  [0x1f, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04], // T
+  [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e], // U
+  [0x11, 0x11, 0x11, 0x11, 0x11, 0x0a, 0x04], // V
+  [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0a], // W
+  [0x11, 0x11, 0x0a, 0x04, 0x0a, 0x11, 0x11], // X
+  [0x11, 0x11, 0x11, 0x0a, 0x04, 0x04, 0x04], // Y
+  [0x1f, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1f], // Z
+  [0x0e, 0x08, 0x08, 0x08, 0x08, 0x08, 0x0e], // [
+  [0x00, 0x10, 0x08, 0x04, 0x02, 0x01, 0x00], // \
+  [0x0e, 0x02, 0x02, 0x02, 0x02, 0x02, 0x0e], // ]
+  [0x04, 0x0a, 0x11, 0x00, 0x00, 0x00, 0x00], // ^
+  [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1f], // _
+];
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn formats_utc_dates() {
+    assert_eq!(utc(0), "1970-01-01 00:00:00 UTC");
+    assert_eq!(utc(951_868_799), "2000-02-29 23:59:59 UTC");
+    assert_eq!(utc(1_792_000_000), "2026-10-14 17:46:40 UTC");
+  }
+
+  #[test]
+  fn draws_on_a_box_in_the_corner() {
+    // 64x16 mid-gray frame; one dot per pixel below 360 rows
+    let (width, height) = (64, 16);
+    let mut frame = vec![200u8; width * height * 4];
+    draw_line(&mut frame, width * 4, [64, 16], "i-", Corner::BottomRight);
+    let pixel = |x: usize, y: usize| frame[(y * width + x) * 4];
+    // The box is 2 cells less the trailing gap plus padding: 15x11, ending 4 pixels
+    // from the right and bottom edges
+    let (left, top) = (64 - 4 - 15, 16 - 4 - 11);
+    assert_eq!(pixel(left - 1, top), 200);
+    assert_eq!(pixel(left, top), 100);
+    assert_eq!(pixel(left + 14, top + 10), 100);
+    assert_eq!(pixel(left + 15, top + 10), 200);
+    // 'I' has a bar across its top row, lowercase drawn as a capital
+    let text = (left + PADDING, top + PADDING);
+    assert_eq!(pixel(text.0, text.1), 100);
+    assert_eq!(pixel(text.0 + 1, text.1), 255);
+    // '-' is a bar across the middle row of the next cell
+    assert_eq!(pixel(text.0 + CELL_WIDTH, text.1 + 3), 255);
+    // Alpha is left alone
+    assert_eq!(frame[(text.1 * width + text.0 + 1) * 4 + 3], 200);
+  }
+
+  #[test]
+  fn clips_to_small_frames() {
+    let mut frame = vec![0u8; 8 * 4 * 4];
+    draw_line(&mut frame, 8 * 4, [8, 4], "WATERMARK", Corner::TopLeft);
+    draw_line(&mut frame, 8 * 4, [8, 4], "WATERMARK", Corner::BottomRight);
+  }
+}
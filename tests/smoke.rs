@@ -0,0 +1,330 @@
+// End-to-end smoke tests: the streamer binary sends --test-pattern frames to the
+// receiver binary over localhost, and every frame the receiver writes out with
+// --out --raw must be the pattern frame it shows, byte for byte. Nothing here
+// needs a display or capture permission, so they run on any OS in CI; the Unix
+// socket one only where there are Unix sockets.
+
+use std::fs::{self, File};
+use std::net::{TcpListener, UdpSocket};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use screen_streamer::pattern::{self, COUNTER_BITS, COUNTER_CELL};
+
+// Wide enough for the whole counter, so every frame says which one it is
+const WIDTH: u32 = (COUNTER_BITS * COUNTER_CELL) as u32;
+const HEIGHT: u32 = 48;
+const FRAMES: u64 = 30;
+
+// Far more than a run takes, so a hung binary fails the test instead of CI
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// One run's files, in Cargo's scratch directory for integration tests
+struct Run {
+  dir: PathBuf,
+}
+
+impl Run {
+  fn new(name: &str) -> Self {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("smoke-{}", name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    Run { dir }
+  }
+
+  fn path(&self, file: &str) -> PathBuf {
+    self.dir.join(file)
+  }
+
+  /// Start `binary` with its log going to a file named after it. Its stdin stays
+  /// open while the `Child` lives, as the streamer stops at the end of it.
+  fn spawn(&self, binary: &str, args: &[String]) -> Child {
+    let log = File::create(self.path(&format!("{}.log", name(binary)))).unwrap();
+    Command::new(binary)
+      .args(args)
+      .stdin(Stdio::piped())
+      .stdout(Stdio::null())
+      .stderr(log)
+      .spawn()
+      .unwrap()
+  }
+
+  /// Wait for `child` to exit successfully, killing it once TIMEOUT is up
+  fn finish(&self, mut child: Child, binary: &str) {
+    let deadline = Instant::now() + TIMEOUT;
+    let status = loop {
+      if let Some(status) = child.try_wait().unwrap() {
+        break status;
+      }
+      if Instant::now() >= deadline {
+        let _ = child.kill();
+        let _ = child.wait();
+        panic!(
+          "{} didn't exit in time:\n{}",
+          name(binary),
+          self.log(binary)
+        );
+      }
+      sleep(Duration::from_millis(20));
+    };
+    assert!(
+      status.success(),
+      "{} exited with {}:\n{}",
+      name(binary),
+      status,
+      self.log(binary)
+    );
+  }
+
+  fn log(&self, binary: &str) -> String {
+    fs::read_to_string(self.path(&format!("{}.log", name(binary)))).unwrap_or_default()
+  }
+}
+
+fn name(binary: &str) -> &str {
+  binary.rsplit(['/', '\\']).next().unwrap_or(binary)
+}
+
+/// Stream FRAMES pattern frames from the streamer with `streamer` options to a
+/// receiver with `receiver` options, returning the frames it wrote
+fn stream(name: &str, streamer: &[&str], receiver: &[&str]) -> Vec<u8> {
+  let size = format!("{}x{}", WIDTH, HEIGHT);
+  let mut args = vec!["--test-pattern", &size];
+  args.extend(streamer);
+  stream_in(&Run::new(name), &args, receiver)
+}
+
+/// Stream FRAMES frames in `run`, from whatever source `streamer` picks
+fn stream_in(run: &Run, streamer: &[&str], receiver: &[&str]) -> Vec<u8> {
+  let out = run.path("frames.raw");
+  let streamer_bin = env!("CARGO_BIN_EXE_screen-streamer");
+  let receiver_bin = env!("CARGO_BIN_EXE_receiver");
+
+  let mut receiver_args: Vec<String> = receiver.iter().map(|arg| arg.to_string()).collect();
+  receiver_args.extend([
+    "--out".to_string(),
+    out.display().to_string(),
+    "--raw".to_string(),
+  ]);
+  let receiving = run.spawn(receiver_bin, &receiver_args);
+  // A TCP or Unix streamer retries until the receiver listens, but a UDP one
+  // sends its handshake once, so give the receiver a head start
+  sleep(Duration::from_millis(500));
+
+  let mut streamer_args: Vec<String> = ["--frames".to_string(), FRAMES.to_string()].into();
+  streamer_args.extend(streamer.iter().map(|arg| arg.to_string()));
+  let streaming = run.spawn(streamer_bin, &streamer_args);
+  run.finish(streaming, streamer_bin);
+  run.finish(receiving, receiver_bin);
+  fs::read(&out).unwrap()
+}
+
+/// The frame number the pattern drew into a BGRA or RGBA `frame`
+fn counter(frame: &[u8]) -> u64 {
+  (0..COUNTER_BITS).fold(0, |counter, bit| {
+    let white = frame[bit * COUNTER_CELL * 4] == 255;
+    counter << 1 | white as u64
+  })
+}
+
+/// Check `frames` hold FRAMES pattern frames one after another, each exactly
+/// as drawn, and return their numbers. `swap` says red and blue trade places,
+/// as in RGBA.
+fn check(frames: &[u8], swap: bool) -> Vec<u64> {
+  let size = (WIDTH * HEIGHT * 4) as usize;
+  assert_eq!(frames.len() % size, 0, "partial frame written");
+  let mut expected = Vec::new();
+  let numbers: Vec<_> = frames
+    .chunks_exact(size)
+    .map(|frame| {
+      let number = counter(frame);
+      pattern::render_into(number, WIDTH, HEIGHT, &mut expected);
+      if swap {
+        for pixel in expected.chunks_exact_mut(4) {
+          pixel.swap(0, 2);
+        }
+      }
+      assert!(frame == expected, "frame {} arrived changed", number);
+      number
+    })
+    .collect();
+  assert_eq!(numbers.len() as u64, FRAMES);
+  assert!(
+    numbers.windows(2).all(|pair| pair[0] < pair[1]),
+    "frames out of order: {:?}",
+    numbers
+  );
+  numbers
+}
+
+fn free_tcp_port() -> String {
+  let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+  listener.local_addr().unwrap().port().to_string()
+}
+
+fn free_udp_port() -> String {
+  let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+  socket.local_addr().unwrap().port().to_string()
+}
+
+/// Over TCP with --no-drop, every frame drawn goes out, so they arrive numbered
+/// from 0 without a gap
+fn tcp(name: &str, streamer: &[&str], swap: bool) {
+  let port = free_tcp_port();
+  let mut args = vec!["--port", &port, "--fps", "0", "--no-drop"];
+  args.extend(streamer);
+  let frames = stream(name, &args, &["--port", &port]);
+  assert_eq!(check(&frames, swap), (0..FRAMES).collect::<Vec<_>>());
+}
+
+#[test]
+fn tcp_raw_bgra() {
+  tcp("tcp-raw-bgra", &["--pixel-format", "bgra"], false);
+}
+
+#[test]
+fn tcp_raw_rgba() {
+  tcp("tcp-raw-rgba", &["--pixel-format", "rgba"], true);
+}
+
+#[test]
+fn tcp_lossless_codecs() {
+  for codec in ["zstd", "delta", "rle"] {
+    tcp(
+      &format!("tcp-{}", codec),
+      &["--pixel-format", "bgra", "--codec", codec],
+      false,
+    );
+  }
+}
+
+// UDP can't hold capture up for the sender, so a frame may be skipped, but the
+// ones that go out must all arrive intact
+#[test]
+fn udp_raw_bgra() {
+  let port = free_udp_port();
+  let frames = stream(
+    "udp-raw-bgra",
+    &[
+      "--port",
+      &port,
+      "--transport",
+      "udp",
+      "--pixel-format",
+      "bgra",
+    ],
+    &["--port", &port, "--udp"],
+  );
+  check(&frames, false);
+}
+
+// Parity slices and per-slice checksums change the datagram header, not the frames
+#[test]
+fn udp_fec_checksummed() {
+  let port = free_udp_port();
+  let frames = stream(
+    "udp-fec",
+    &[
+      "--port",
+      &port,
+      "--transport",
+      "udp",
+      "--pixel-format",
+      "bgra",
+      "--fec",
+      "0.25",
+      "--checksum",
+    ],
+    &["--port", &port, "--udp"],
+  );
+  check(&frames, false);
+}
+
+// A recording of the first few pattern frames, as the receiver wrote them, comes
+// back over and over with --loop-file
+#[test]
+fn loop_file_replays_a_recording() {
+  let port = free_tcp_port();
+  let recorded = &stream(
+    "loop-record",
+    &["--port", &port, "--pixel-format", "bgra"],
+    &["--port", &port],
+  )[..];
+  let run = Run::new("loop-replay");
+  let size = (WIDTH * HEIGHT * 4) as usize;
+  let recording = run.path("recording.raw");
+  fs::write(&recording, &recorded[..4 * size]).unwrap();
+  let frames = stream_in(
+    &run,
+    &[
+      "--loop-file",
+      recording.to_str().unwrap(),
+      "--loop-size",
+      &format!("{}x{}", WIDTH, HEIGHT),
+      "--port",
+      &port,
+      "--fps",
+      "0",
+      "--no-drop",
+      "--pixel-format",
+      "bgra",
+    ],
+    &["--port", &port],
+  );
+  assert_eq!(frames.len(), FRAMES as usize * size);
+  for (index, frame) in frames.chunks_exact(size).enumerate() {
+    let looped = &recorded[index % 4 * size..][..size];
+    assert!(
+      frame == looped,
+      "frame {} isn't recorded frame {}",
+      index,
+      index % 4
+    );
+  }
+}
+
+// A receiver that dials a listening streamer and asks for zstd gets that variant,
+// lossless, rather than the main stream's JPEG
+#[test]
+fn listen_variant_picked_by_the_receiver() {
+  let port = free_tcp_port();
+  let frames = stream(
+    "listen-variant",
+    &[
+      "--port",
+      &port,
+      "--listen",
+      "--codec",
+      "jpeg",
+      "--variants",
+      "jpeg:30@64x16,zstd",
+    ],
+    &["--port", &port, "--connect", "--want", "zstd"],
+  );
+  check(&frames, true);
+}
+
+#[cfg(unix)]
+#[test]
+fn unix_socket_raw_bgra() {
+  let path = Run::new("unix-socket").path("receiver.sock");
+  let path = path.to_str().unwrap();
+  let frames = stream(
+    "unix-raw-bgra",
+    &[
+      "--transport",
+      "unix",
+      "--path",
+      path,
+      "--fps",
+      "0",
+      "--no-drop",
+      "--pixel-format",
+      "bgra",
+    ],
+    &["--path", path],
+  );
+  assert_eq!(check(&frames, false), (0..FRAMES).collect::<Vec<_>>());
+}